@@ -0,0 +1,39 @@
+//! Benchmarks of reading and writing documents dominated by sixbit node
+//! names, run with `cargo bench -p kbinxml --bench sixbit`.
+
+use bytes::Bytes;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use kbinxml::{Node, Value};
+
+/// A document of many small nodes with names of every length up to 32
+/// characters, so much of the work is encoding and decoding the names.
+fn names_document() -> Node {
+    const CHARS: &str = "0123456789:ABCDEFGHIJKLMNOPQRSTUVWXYZ_abcdefghijklmnopqrstuvwxyz";
+
+    let children = (0..2048)
+        .map(|i| {
+            let len = 1 + i % 32;
+            let start = i % (CHARS.len() - len);
+            let name = &CHARS[start..start + len];
+
+            Node::with_attrs_value(name, &[("id", "1")], Value::U8(i as u8))
+        })
+        .collect::<Vec<_>>();
+
+    Node::with_nodes("root", children)
+}
+
+fn bench_names(c: &mut Criterion) {
+    let node = names_document();
+    let binary = Bytes::from(kbinxml::to_binary(&node).unwrap());
+
+    c.bench_function("sixbit_encode_names", |b| {
+        b.iter(|| kbinxml::to_binary(black_box(&node)).unwrap())
+    });
+    c.bench_function("sixbit_decode_names", |b| {
+        b.iter(|| kbinxml::from_binary(black_box(binary.clone())).unwrap())
+    });
+}
+
+criterion_group!(benches, bench_names);
+criterion_main!(benches);
@@ -0,0 +1,15 @@
+#![no_main]
+
+use kbinxml::{Document, Limits};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if kbinxml::is_binary_xml(data) {
+        return;
+    }
+
+    if let Ok(document) = Document::parse_untrusted(data, Limits::default()) {
+        let _ = document.to_bytes();
+        let _ = document.to_xml();
+    }
+});
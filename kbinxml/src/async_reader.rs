@@ -0,0 +1,159 @@
+use std::io::Cursor;
+
+use bytes::Bytes;
+use snafu::ResultExt;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::encoding_type::EncodingType;
+use crate::error::{KbinError, Result};
+use crate::node::NodeCollection;
+use crate::options::{Endianness, ReaderOptions};
+use crate::reader::{
+    check_data_len, DataBufferLength, DataBufferRead, HeaderRead, NodeBufferRead, Reader,
+    ReaderError,
+};
+
+/// Reads binary XML documents from an asynchronous source.
+///
+/// Only the header and the two sections declared by the header are read from
+/// the source, so the source may continue with other data after a document.
+pub struct AsyncReader<R> {
+    inner: R,
+    options: ReaderOptions,
+}
+
+impl<R> AsyncReader<R>
+where
+    R: AsyncRead + Unpin,
+{
+    pub fn new(inner: R) -> Self {
+        Self::with_options(inner, ReaderOptions::default())
+    }
+
+    pub fn with_options(inner: R, options: ReaderOptions) -> Self {
+        Self { inner, options }
+    }
+
+    #[inline]
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Read the header, node buffer, and data buffer of the next document
+    /// and return a `Reader` over them.
+    pub async fn read_reader(&mut self) -> std::result::Result<Reader, ReaderError> {
+        let mut header = [0; 8];
+        self.inner
+            .read_exact(&mut header)
+            .await
+            .context(HeaderRead)?;
+        let (compression, encoding, len_node) = Reader::read_header(&mut Cursor::new(header))?;
+
+        // The byte order cannot be detected without reading ahead
+        let endianness = self.options.endianness.unwrap_or_default();
+        let len_node = match endianness {
+            Endianness::Big => len_node,
+            Endianness::Little => len_node.swap_bytes(),
+        };
+
+        let mut node_buf = vec![0; len_node as usize];
+        self.inner
+            .read_exact(&mut node_buf)
+            .await
+            .context(NodeBufferRead { len_node })?;
+
+        let mut len_data = [0; 4];
+        self.inner
+            .read_exact(&mut len_data)
+            .await
+            .context(DataBufferLength)?;
+        let len_data = endianness
+            .read_u32(&mut &len_data[..])
+            .context(DataBufferLength)?;
+        info!("len_data: {0} (0x{0:x})", len_data);
+        check_data_len(&self.options, len_data)?;
+
+        let mut data_buf = vec![0; len_data as usize];
+        self.inner
+            .read_exact(&mut data_buf)
+            .await
+            .context(DataBufferRead { len_data })?;
+
+        Ok(Reader::from_parts(
+            compression,
+            encoding,
+            endianness,
+            self.options.clone(),
+            Bytes::from(node_buf),
+            Bytes::from(data_buf),
+        ))
+    }
+
+    /// Read the next document into a `NodeCollection`, equivalent to
+    /// `kbinxml::from_binary` for asynchronous sources.
+    pub async fn read_node_collection(&mut self) -> Result<(NodeCollection, EncodingType)> {
+        let mut reader = self.read_reader().await?;
+        let collection =
+            NodeCollection::from_iter(&mut reader).ok_or(KbinError::NoNodeCollection)?;
+        let encoding = reader.encoding();
+
+        Ok((collection, encoding))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use tokio::runtime::Builder;
+
+    use crate::options::ReaderOptions;
+    use crate::reader::{Reader, ReaderError};
+
+    use super::AsyncReader;
+
+    const TESTCASES: &[u8] = include_bytes!("../../testcases_out.kbin");
+
+    #[test]
+    fn test_matches_sync_reader() {
+        let runtime = Builder::new_current_thread().build().unwrap();
+        let mut reader = AsyncReader::new(TESTCASES);
+        let result = runtime
+            .block_on(reader.read_node_collection())
+            .expect("Failed to read node collection");
+
+        let expected = crate::from_binary(Bytes::from(TESTCASES)).unwrap();
+        assert_eq!(result, expected);
+        assert!(reader.into_inner().is_empty());
+    }
+
+    #[test]
+    fn test_data_limit() {
+        let len_node = u32::from_be_bytes([TESTCASES[4], TESTCASES[5], TESTCASES[6], TESTCASES[7]]);
+        let start = 8 + len_node as usize;
+        let len_data = u32::from_be_bytes([
+            TESTCASES[start],
+            TESTCASES[start + 1],
+            TESTCASES[start + 2],
+            TESTCASES[start + 3],
+        ]);
+        let mut options = ReaderOptions::builder();
+        options.max_data_len(len_data as usize - 1);
+        let options = options.build();
+
+        let runtime = Builder::new_current_thread().build().unwrap();
+        let mut reader = AsyncReader::with_options(TESTCASES, options.clone());
+        assert!(matches!(
+            runtime.block_on(reader.read_reader()),
+            Err(ReaderError::DataLimit { .. })
+        ));
+        assert!(matches!(
+            Reader::with_options(Bytes::from(TESTCASES), options),
+            Err(ReaderError::DataLimit { .. })
+        ));
+    }
+}
@@ -0,0 +1,319 @@
+//! A container of named documents, for games that pack related kbin files
+//! together.
+//!
+//! A bundle starts with the magic `KBNB` and the number of entries as a big
+//! endian `u32`, followed by a table with one record per entry:
+//!
+//! - the length of the name as a big endian `u16`, then the name in UTF-8
+//! - the offset of the data from the start of the bundle as a big endian
+//!   `u32`
+//! - the length of the data as a big endian `u32`
+//!
+//! The data of the entries follows the table, each entry aligned to four
+//! bytes. Entries are usually binary or text XML documents, but any data can
+//! be stored.
+
+use std::convert::TryFrom;
+use std::str;
+
+use byteorder::{BigEndian, ByteOrder};
+use bytes::Bytes;
+use indexmap::IndexMap;
+use snafu::Snafu;
+
+use crate::document::Document;
+use crate::error::{KbinError, Result};
+
+const MAGIC: &[u8; 4] = b"KBNB";
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
+pub enum BundleError {
+    #[snafu(display("Input is not a bundle"))]
+    InvalidMagic,
+
+    #[snafu(display("Bundle is truncated at offset {}", offset))]
+    Truncated { offset: usize },
+
+    #[snafu(display("Entry name at offset {} is not valid UTF-8", offset))]
+    InvalidName {
+        offset: usize,
+        source: str::Utf8Error,
+    },
+
+    #[snafu(display("Duplicate entry `{}`", name))]
+    DuplicateEntry { name: String },
+
+    #[snafu(display(
+        "Data of entry `{}` ({} bytes at offset {}) is outside of the bundle",
+        name,
+        len,
+        offset
+    ))]
+    EntryOutOfBounds {
+        name: String,
+        offset: usize,
+        len: usize,
+    },
+
+    #[snafu(display("Entry `{}` not found", name))]
+    MissingEntry { name: String },
+
+    #[snafu(display("Entry name `{}` is longer than {} bytes", name, u16::MAX))]
+    NameTooLong { name: String },
+
+    #[snafu(display("Bundle is larger than 4 GiB"))]
+    TooLarge,
+}
+
+/// Reads the entry table, failing with `Truncated` at its end.
+struct Cursor<'a> {
+    input: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> std::result::Result<&'a [u8], BundleError> {
+        let offset = self.offset;
+        let data = self
+            .input
+            .get(offset..offset + len)
+            .ok_or(BundleError::Truncated { offset })?;
+        self.offset += len;
+
+        Ok(data)
+    }
+
+    fn u16(&mut self) -> std::result::Result<u16, BundleError> {
+        self.take(2).map(BigEndian::read_u16)
+    }
+
+    fn u32(&mut self) -> std::result::Result<u32, BundleError> {
+        self.take(4).map(BigEndian::read_u32)
+    }
+}
+
+/// Named documents packed into one container, in insertion order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Bundle {
+    entries: IndexMap<String, Bytes>,
+}
+
+impl Bundle {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `input` starts with the magic of a bundle.
+    #[inline]
+    pub fn is_bundle(input: &[u8]) -> bool {
+        input.starts_with(MAGIC)
+    }
+
+    /// Read the entry table of a bundle. The data of the entries is not
+    /// copied.
+    pub fn parse(input: Bytes) -> std::result::Result<Self, BundleError> {
+        if !Self::is_bundle(&input) {
+            return Err(BundleError::InvalidMagic);
+        }
+
+        let mut cursor = Cursor {
+            input: &input,
+            offset: MAGIC.len(),
+        };
+        let count = cursor.u32()?;
+
+        // Every record takes at least 10 bytes, so a count larger than the
+        // input allows is not trusted for the allocation
+        let mut entries = IndexMap::with_capacity((count as usize).min(input.len() / 10));
+        for _ in 0..count {
+            let name_len = cursor.u16()? as usize;
+            let name_offset = cursor.offset;
+            let name = str::from_utf8(cursor.take(name_len)?).map_err(|source| {
+                BundleError::InvalidName {
+                    offset: name_offset,
+                    source,
+                }
+            })?;
+            let offset = cursor.u32()? as usize;
+            let len = cursor.u32()? as usize;
+
+            if offset.checked_add(len).is_none_or(|end| end > input.len()) {
+                return Err(BundleError::EntryOutOfBounds {
+                    name: name.to_owned(),
+                    offset,
+                    len,
+                });
+            }
+
+            let data = input.slice(offset..offset + len);
+            if entries.insert(name.to_owned(), data).is_some() {
+                return Err(BundleError::DuplicateEntry {
+                    name: name.to_owned(),
+                });
+            }
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Add an entry, returning the data of the entry it replaced.
+    pub fn insert<K, V>(&mut self, name: K, data: V) -> Option<Bytes>
+    where
+        K: Into<String>,
+        V: Into<Bytes>,
+    {
+        self.entries.insert(name.into(), data.into())
+    }
+
+    /// Add a document as an entry, written with its options.
+    pub fn insert_document<K>(&mut self, name: K, document: &Document) -> Result<Option<Bytes>>
+    where
+        K: Into<String>,
+    {
+        Ok(self.insert(name, document.to_bytes()?))
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Bytes> {
+        self.entries.shift_remove(name)
+    }
+
+    #[inline]
+    pub fn get(&self, name: &str) -> Option<&Bytes> {
+        self.entries.get(name)
+    }
+
+    /// Read the entry with the given name as a binary or text XML document.
+    pub fn document(&self, name: &str) -> Result<Document> {
+        let data = self.get(name).ok_or_else(|| BundleError::MissingEntry {
+            name: name.to_owned(),
+        })?;
+
+        Document::parse(data)
+    }
+
+    /// The names of the entries in order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Bytes)> {
+        self.entries
+            .iter()
+            .map(|(name, data)| (name.as_str(), data))
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Write the bundle.
+    pub fn to_bytes(&self) -> std::result::Result<Vec<u8>, BundleError> {
+        let to_u32 = |n: usize| u32::try_from(n).map_err(|_| BundleError::TooLarge);
+
+        let table_len = self
+            .entries
+            .keys()
+            .map(|name| 2 + name.len() + 4 + 4)
+            .sum::<usize>();
+        let mut offset = MAGIC.len() + 4 + table_len;
+
+        let mut output = Vec::with_capacity(offset);
+        output.extend_from_slice(MAGIC);
+        output.extend_from_slice(&to_u32(self.entries.len())?.to_be_bytes());
+
+        let mut offsets = Vec::with_capacity(self.entries.len());
+        for (name, data) in &self.entries {
+            let name_len = u16::try_from(name.len())
+                .map_err(|_| BundleError::NameTooLong { name: name.clone() })?;
+
+            // Align the data of every entry to four bytes
+            offset = (offset + 3) & !3;
+            offsets.push(offset);
+
+            output.extend_from_slice(&name_len.to_be_bytes());
+            output.extend_from_slice(name.as_bytes());
+            output.extend_from_slice(&to_u32(offset)?.to_be_bytes());
+            output.extend_from_slice(&to_u32(data.len())?.to_be_bytes());
+
+            offset += data.len();
+        }
+        to_u32(offset)?;
+
+        for (data, offset) in self.entries.values().zip(offsets) {
+            output.resize(offset, 0);
+            output.extend_from_slice(data);
+        }
+
+        Ok(output)
+    }
+}
+
+impl From<BundleError> for KbinError {
+    #[inline]
+    fn from(source: BundleError) -> Self {
+        KbinError::Bundle { source }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::document::Document;
+    use crate::node::Node;
+    use crate::options::Options;
+    use crate::value::Value;
+
+    use super::{Bundle, BundleError};
+
+    #[test]
+    fn test_bundle() {
+        let music = Node::with_nodes("music", vec![Node::with_value("id", Value::U32(1))]);
+        let collection = crate::NodeCollection::from_node(&music).unwrap();
+
+        let mut bundle = Bundle::new();
+        bundle
+            .insert_document("music.kbin", &Document::new(collection, Options::default()))
+            .unwrap();
+        bundle.insert("notes.xml", &b"<notes __type=\"str\">a</notes>"[..]);
+        bundle.insert("raw", vec![1, 2, 3]);
+
+        let data = bundle.to_bytes().unwrap();
+        assert!(Bundle::is_bundle(&data));
+
+        let read = Bundle::parse(Bytes::from(data.clone())).unwrap();
+        assert_eq!(read, bundle);
+        assert_eq!(
+            read.names().collect::<Vec<_>>(),
+            ["music.kbin", "notes.xml", "raw"]
+        );
+
+        let document = read.document("music.kbin").unwrap();
+        assert!(document.header.is_some());
+        assert_eq!(Node::from_collection(document.root()).unwrap(), music);
+        let notes = read.document("notes.xml").unwrap();
+        assert_eq!(
+            notes.root().base().value().unwrap(),
+            Value::String("a".into())
+        );
+        assert!(read.document("missing").is_err());
+
+        // The entry table must stay within the input
+        assert!(matches!(
+            Bundle::parse(Bytes::from(data[..20].to_vec())),
+            Err(BundleError::Truncated { .. })
+        ));
+        assert!(matches!(
+            Bundle::parse(Bytes::from(&b"KBN"[..])),
+            Err(BundleError::InvalidMagic)
+        ));
+    }
+}
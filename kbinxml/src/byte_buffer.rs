@@ -2,12 +2,13 @@ use std::cmp::max;
 use std::io::{self, Cursor, Seek, SeekFrom, Write};
 use std::ops::{Deref, DerefMut};
 
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{BigEndian, WriteBytesExt};
 use bytes::Bytes;
 use snafu::{ResultExt, Snafu};
 
 use crate::encoding_type::{EncodingError, EncodingType};
 use crate::node_types::StandardType;
+use crate::options::Endianness;
 
 #[derive(Debug, Snafu)]
 pub enum ByteBufferError {
@@ -71,7 +72,7 @@ pub enum ByteBufferError {
 }
 
 /// Remove trailing null bytes, used for the `String` type
-pub(crate) fn strip_trailing_null_bytes<'a>(data: &'a [u8]) -> &'a [u8] {
+pub(crate) fn strip_trailing_null_bytes(data: &[u8]) -> &[u8] {
     let len = data.len();
 
     if len == 0 {
@@ -91,29 +92,63 @@ pub(crate) fn strip_trailing_null_bytes<'a>(data: &'a [u8]) -> &'a [u8] {
     }
 }
 
+/// The alignment used when none is given, shared by the node buffer and most
+/// data buffers
+const DEFAULT_ALIGNMENT: u64 = 4;
+
 pub struct ByteBufferRead {
     cursor: Cursor<Bytes>,
     buffer: Bytes,
     offset_1: usize,
     offset_2: usize,
+    alignment: u64,
+    endianness: Endianness,
+
+    /// The offset of the data returned by the last read
+    last_offset: usize,
 }
 
 pub struct ByteBufferWrite {
     buffer: Cursor<Vec<u8>>,
     offset_1: u64,
     offset_2: u64,
+    alignment: u64,
+    endianness: Endianness,
 }
 
 impl ByteBufferRead {
     pub fn new(buffer: Bytes) -> Self {
+        Self::with_alignment(buffer, DEFAULT_ALIGNMENT)
+    }
+
+    pub fn with_alignment(buffer: Bytes, alignment: u64) -> Self {
         Self {
             cursor: Cursor::new(buffer.clone()),
             buffer,
             offset_1: 0,
             offset_2: 0,
+            alignment,
+            endianness: Endianness::Big,
+            last_offset: 0,
         }
     }
 
+    /// Read length prefixes in the given byte order.
+    pub fn set_endianness(&mut self, endianness: Endianness) {
+        self.endianness = endianness;
+    }
+
+    /// The offset within the buffer of the data returned by the last read
+    #[inline]
+    pub fn last_offset(&self) -> usize {
+        self.last_offset
+    }
+
+    /// Returns `true` if every byte of the buffer has been read
+    pub fn is_empty(&self) -> bool {
+        self.cursor.position() >= self.buffer.len() as u64
+    }
+
     #[inline]
     fn data_buf_offset(&self) -> usize {
         // Position is not the index of the previously read byte, it is the current
@@ -140,6 +175,7 @@ impl ByteBufferRead {
         // To avoid an allocation of a `Vec` here, the raw input byte array is used
         let start = self.data_buf_offset();
         let end = self.check_read_size(start, size)?;
+        self.last_offset = start;
 
         let data = self.buffer.slice(start..end);
         trace!(
@@ -157,7 +193,10 @@ impl ByteBufferRead {
     }
 
     pub fn buf_read(&mut self) -> Result<Bytes, ByteBufferError> {
-        let size = self.cursor.read_u32::<BigEndian>().context(ReadSize)?;
+        let size = self
+            .endianness
+            .read_u32(&mut self.cursor)
+            .context(ReadSize)?;
         debug!(
             "buf_read => index: {}, size: {}",
             self.cursor.position(),
@@ -178,21 +217,26 @@ impl ByteBufferRead {
     }
 
     pub fn get_aligned(&mut self, node_type: StandardType) -> Result<Bytes, ByteBufferError> {
-        if self.offset_1 % 4 == 0 {
+        self.get_aligned_size(node_type.size * node_type.count)
+    }
+
+    /// Like `get_aligned`, for a value of `size` bytes.
+    pub fn get_aligned_size(&mut self, size: usize) -> Result<Bytes, ByteBufferError> {
+        if self.offset_1.is_multiple_of(4) {
             self.offset_1 = self.data_buf_offset();
         }
-        if self.offset_2 % 4 == 0 {
+        if self.offset_2.is_multiple_of(4) {
             self.offset_2 = self.data_buf_offset();
         }
 
         let old_pos = self.data_buf_offset();
-        let size = node_type.size * node_type.count;
         trace!("get_aligned => old_pos: {}, size: {}", old_pos, size);
 
         let (check_old, data) = match size {
             1 => {
                 let end = self.check_read_size(self.offset_1, 1)?;
                 let data = self.buffer.slice(self.offset_1..end);
+                self.last_offset = self.offset_1;
                 self.offset_1 += 1;
 
                 (true, data)
@@ -200,13 +244,14 @@ impl ByteBufferRead {
             2 => {
                 let end = self.check_read_size(self.offset_2, 2)?;
                 let data = self.buffer.slice(self.offset_2..end);
+                self.last_offset = self.offset_2;
                 self.offset_2 += 2;
 
                 (true, data)
             },
             size => {
                 let data = self
-                    .buf_read_size(size as usize)
+                    .buf_read_size(size)
                     .map_err(Box::new)
                     .context(ReadAligned { size })?;
                 self.realign_reads(None)?;
@@ -234,15 +279,23 @@ impl ByteBufferRead {
         Ok(data)
     }
 
+    /// Continue reading at `position`, which must be aligned, forgetting the
+    /// slots of 1 and 2 byte values read before.
+    pub fn reset_position(&mut self, position: u64) {
+        self.cursor.set_position(position);
+        self.offset_1 = position as usize;
+        self.offset_2 = position as usize;
+    }
+
     pub fn realign_reads(&mut self, size: Option<u64>) -> Result<(), ByteBufferError> {
-        let size = size.unwrap_or(4);
+        let size = size.unwrap_or(self.alignment);
         trace!(
             "realign_reads => position: {}, size: {}",
             self.cursor.position(),
             size
         );
 
-        while self.cursor.position() % size > 0 {
+        while !self.cursor.position().is_multiple_of(size) {
             self.cursor
                 .seek(SeekFrom::Current(1))
                 .context(SeekForward { size: 1usize })?;
@@ -255,13 +308,24 @@ impl ByteBufferRead {
 
 impl ByteBufferWrite {
     pub fn new(buffer: Vec<u8>) -> Self {
+        Self::with_alignment(buffer, DEFAULT_ALIGNMENT)
+    }
+
+    pub fn with_alignment(buffer: Vec<u8>, alignment: u64) -> Self {
         Self {
             buffer: Cursor::new(buffer),
             offset_1: 0,
             offset_2: 0,
+            alignment,
+            endianness: Endianness::Big,
         }
     }
 
+    /// Write length prefixes in the given byte order.
+    pub fn set_endianness(&mut self, endianness: Endianness) {
+        self.endianness = endianness;
+    }
+
     pub fn into_inner(self) -> Vec<u8> {
         self.buffer.into_inner()
     }
@@ -277,8 +341,8 @@ impl ByteBufferWrite {
     }
 
     pub fn buf_write(&mut self, data: &[u8]) -> Result<(), ByteBufferError> {
-        self.buffer
-            .write_u32::<BigEndian>(data.len() as u32)
+        self.endianness
+            .write_u32(&mut self.buffer, data.len() as u32)
             .context(WriteLength { len: data.len() })?;
         debug!(
             "buf_write => index: {}, size: {}",
@@ -317,15 +381,25 @@ impl ByteBufferWrite {
         node_type: StandardType,
         data: &[u8],
     ) -> Result<(), ByteBufferError> {
-        if self.offset_1 % 4 == 0 {
+        self.write_aligned_size(node_type, node_type.size * node_type.count, data)
+    }
+
+    /// Like `write_aligned`, for a value of `size` bytes. `node_type` is only
+    /// used for errors.
+    pub fn write_aligned_size(
+        &mut self,
+        node_type: StandardType,
+        size: usize,
+        data: &[u8],
+    ) -> Result<(), ByteBufferError> {
+        if self.offset_1.is_multiple_of(4) {
             self.offset_1 = self.data_buf_offset();
         }
-        if self.offset_2 % 4 == 0 {
+        if self.offset_2.is_multiple_of(4) {
             self.offset_2 = self.data_buf_offset();
         }
 
         let old_pos = self.data_buf_offset();
-        let size = node_type.size * node_type.count;
         trace!(
             "write_aligned => old_pos: {}, size: {}, data: 0x{:02x?}",
             old_pos,
@@ -344,7 +418,7 @@ impl ByteBufferWrite {
         let check_old = match size {
             1 => {
                 // Make room for new DWORD
-                if self.offset_1 % 4 == 0 {
+                if self.offset_1.is_multiple_of(4) {
                     self.buffer
                         .write_u32::<BigEndian>(0)
                         .context(WritePadding { size: 4usize })?;
@@ -364,7 +438,7 @@ impl ByteBufferWrite {
             },
             2 => {
                 // Make room for new DWORD
-                if self.offset_2 % 4 == 0 {
+                if self.offset_2.is_multiple_of(4) {
                     self.buffer
                         .write_u32::<BigEndian>(0)
                         .context(WritePadding { size: 4usize })?;
@@ -420,14 +494,14 @@ impl ByteBufferWrite {
     }
 
     pub fn realign_writes(&mut self, size: Option<u64>) -> Result<(), ByteBufferError> {
-        let size = size.unwrap_or(4);
+        let size = size.unwrap_or(self.alignment);
         trace!(
             "realign_writes => position: {}, size: {}",
             self.buffer.position(),
             size
         );
 
-        while self.buffer.position() % size > 0 {
+        while !self.buffer.position().is_multiple_of(size) {
             self.buffer
                 .write_u8(0)
                 .context(WritePadding { size: 1usize })?;
@@ -0,0 +1,651 @@
+//! Structural comparison of node trees and round-trip checks for documents.
+//!
+//! `compare` reports every difference between two trees with the same paths
+//! used by `search`, and `compare_with_options` ignores small differences in
+//! float and time values written by other implementations. `round_trip` reads
+//! a binary or text document, writes it back in the same format, and reports
+//! whether the output is byte-identical and whether it reads back to the same
+//! tree. `find_duplicate_siblings` reports runs of identical sibling nodes,
+//! which some exporters write by accident, and `merge_duplicate_siblings`
+//! keeps one node of each run.
+
+use std::fmt;
+
+use bytes::Bytes;
+
+use crate::encoding_type::EncodingType;
+use crate::error::KbinError;
+use crate::header::Header;
+use crate::node::Node;
+use crate::options::{Options, ReaderOptions};
+use crate::value::{Value, ValueArray};
+
+/// A single difference between two node trees. `path` is the path of the
+/// node on the left side.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Difference {
+    Key {
+        path: String,
+        left: String,
+        right: String,
+    },
+    Value {
+        path: String,
+        left: Option<Value>,
+        right: Option<Value>,
+    },
+    Attribute {
+        path: String,
+        key: String,
+        left: Option<String>,
+        right: Option<String>,
+    },
+    /// Both nodes have the same attributes, but in a different order.
+    AttributeOrder { path: String },
+    /// A child exists on only one side. The key is the name of that child.
+    Child {
+        path: String,
+        left: Option<String>,
+        right: Option<String>,
+    },
+}
+
+impl Difference {
+    pub fn path(&self) -> &str {
+        match self {
+            Difference::Key { path, .. } |
+            Difference::Value { path, .. } |
+            Difference::Attribute { path, .. } |
+            Difference::AttributeOrder { path } |
+            Difference::Child { path, .. } => path,
+        }
+    }
+}
+
+struct OptionDisplay<'a, T>(&'a Option<T>);
+
+impl<T: fmt::Display> fmt::Display for OptionDisplay<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(value) => write!(f, "`{}`", value),
+            None => f.write_str("(none)"),
+        }
+    }
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Difference::Key { path, left, right } => {
+                write!(f, "{}: key `{}` != `{}`", path, left, right)
+            },
+            Difference::Value { path, left, right } => write!(
+                f,
+                "{}: value {} != {}",
+                path,
+                OptionDisplay(left),
+                OptionDisplay(right)
+            ),
+            Difference::Attribute {
+                path,
+                key,
+                left,
+                right,
+            } => write!(
+                f,
+                "{}: attribute `{}` {} != {}",
+                path,
+                key,
+                OptionDisplay(left),
+                OptionDisplay(right)
+            ),
+            Difference::AttributeOrder { path } => write!(f, "{}: attribute order differs", path),
+            Difference::Child { path, left, right } => write!(
+                f,
+                "{}: child {} != {}",
+                path,
+                OptionDisplay(left),
+                OptionDisplay(right)
+            ),
+        }
+    }
+}
+
+/// Tolerances for `compare_with_options`. Values are compared exactly by
+/// default.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CompareOptions {
+    absolute_epsilon: f64,
+    relative_epsilon: f64,
+    time_skew: u32,
+}
+
+impl CompareOptions {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Treat float and double components that differ by at most `epsilon`
+    /// as equal.
+    pub fn absolute_epsilon(&mut self, epsilon: f64) -> &mut Self {
+        self.absolute_epsilon = epsilon;
+        self
+    }
+
+    /// Treat float and double components that differ by at most `epsilon`
+    /// times the larger of their magnitudes as equal.
+    pub fn relative_epsilon(&mut self, epsilon: f64) -> &mut Self {
+        self.relative_epsilon = epsilon;
+        self
+    }
+
+    /// Treat time values that differ by at most `seconds` as equal.
+    pub fn time_skew(&mut self, seconds: u32) -> &mut Self {
+        self.time_skew = seconds;
+        self
+    }
+
+    fn floats_match(&self, left: f64, right: f64) -> bool {
+        let difference = (left - right).abs();
+
+        left == right ||
+            difference <= self.absolute_epsilon ||
+            difference <= self.relative_epsilon * left.abs().max(right.abs())
+    }
+
+    fn values_match(&self, left: Option<&Value>, right: Option<&Value>) -> bool {
+        match (left, right) {
+            _ if left == right => true,
+            (Some(Value::Time(left)), Some(Value::Time(right))) => {
+                left.abs_diff(*right) <= self.time_skew
+            },
+            (Some(left), Some(right)) if left.standard_type() == right.standard_type() => {
+                match (float_components(left), float_components(right)) {
+                    (Some(left), Some(right)) => {
+                        left.len() == right.len() &&
+                            left
+                                .iter()
+                                .zip(&right)
+                                .all(|(&left, &right)| self.floats_match(left, right))
+                    },
+                    _ => false,
+                }
+            },
+            _ => false,
+        }
+    }
+}
+
+/// The components of a float or double value, or `None` for other types.
+fn float_components(value: &Value) -> Option<Vec<f64>> {
+    let components = match value {
+        Value::Float(n) => vec![f64::from(*n)],
+        Value::Double(n) => vec![*n],
+        Value::Float2(v) => v.iter().copied().map(f64::from).collect(),
+        Value::Float3(v) => v.iter().copied().map(f64::from).collect(),
+        Value::Float4(v) => v.iter().copied().map(f64::from).collect(),
+        Value::Double2(v) => v.to_vec(),
+        Value::Double3(v) => v.to_vec(),
+        Value::Double4(v) => v.to_vec(),
+        Value::Array(values) => match values {
+            ValueArray::Float(v) => v.iter().copied().map(f64::from).collect(),
+            ValueArray::Double(v) => v.clone(),
+            ValueArray::Float2(v) => v.iter().flatten().copied().map(f64::from).collect(),
+            ValueArray::Float3(v) => v.iter().flatten().copied().map(f64::from).collect(),
+            ValueArray::Float4(v) => v.iter().flatten().copied().map(f64::from).collect(),
+            ValueArray::Double2(v) => v.iter().flatten().copied().collect(),
+            ValueArray::Double3(v) => v.iter().flatten().copied().collect(),
+            ValueArray::Double4(v) => v.iter().flatten().copied().collect(),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    Some(components)
+}
+
+/// Compare two node trees, returning every difference found. Children are
+/// matched by position.
+#[inline]
+pub fn compare(left: &Node, right: &Node) -> Vec<Difference> {
+    compare_with_options(left, right, &CompareOptions::default())
+}
+
+/// Like `compare`, but values within the tolerances of `options` are not
+/// reported.
+pub fn compare_with_options(left: &Node, right: &Node, options: &CompareOptions) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    compare_node(
+        &format!("/{}", left.key()),
+        left,
+        right,
+        options,
+        &mut differences,
+    );
+
+    differences
+}
+
+fn compare_node(
+    path: &str,
+    left: &Node,
+    right: &Node,
+    options: &CompareOptions,
+    differences: &mut Vec<Difference>,
+) {
+    if left.key() != right.key() {
+        differences.push(Difference::Key {
+            path: path.to_owned(),
+            left: left.key().to_owned(),
+            right: right.key().to_owned(),
+        });
+    }
+
+    if !options.values_match(left.value(), right.value()) {
+        differences.push(Difference::Value {
+            path: path.to_owned(),
+            left: left.value().cloned(),
+            right: right.value().cloned(),
+        });
+    }
+
+    let empty = Default::default();
+    let left_attrs = left.attributes().unwrap_or(&empty);
+    let right_attrs = right.attributes().unwrap_or(&empty);
+    for (key, value) in left_attrs {
+        let other = right_attrs.get(key);
+        if other != Some(value) {
+            differences.push(Difference::Attribute {
+                path: path.to_owned(),
+                key: key.clone(),
+                left: Some(value.clone()),
+                right: other.cloned(),
+            });
+        }
+    }
+    for (key, value) in right_attrs {
+        if !left_attrs.contains_key(key) {
+            differences.push(Difference::Attribute {
+                path: path.to_owned(),
+                key: key.clone(),
+                left: None,
+                right: Some(value.clone()),
+            });
+        }
+    }
+    if left_attrs == right_attrs && !left_attrs.keys().eq(right_attrs.keys()) {
+        differences.push(Difference::AttributeOrder {
+            path: path.to_owned(),
+        });
+    }
+
+    let left_children = left.children().map(Vec::as_slice).unwrap_or_default();
+    let right_children = right.children().map(Vec::as_slice).unwrap_or_default();
+    let segments = child_segments(left_children);
+    for (i, segment) in segments.iter().enumerate() {
+        let child_path = format!("{}/{}", path, segment);
+        match right_children.get(i) {
+            Some(right_child) => compare_node(
+                &child_path,
+                &left_children[i],
+                right_child,
+                options,
+                differences,
+            ),
+            None => differences.push(Difference::Child {
+                path: path.to_owned(),
+                left: Some(left_children[i].key().to_owned()),
+                right: None,
+            }),
+        }
+    }
+    for child in right_children.iter().skip(left_children.len()) {
+        differences.push(Difference::Child {
+            path: path.to_owned(),
+            left: None,
+            right: Some(child.key().to_owned()),
+        });
+    }
+}
+
+/// The `Node` counterpart of `search::child_segments`.
+fn child_segments(children: &[Node]) -> Vec<String> {
+    children
+        .iter()
+        .enumerate()
+        .map(|(i, child)| {
+            let key = child.key();
+            let total = children.iter().filter(|other| other.key() == key).count();
+
+            if total > 1 {
+                let index = children[..i]
+                    .iter()
+                    .filter(|other| other.key() == key)
+                    .count();
+                format!("{}[{}]", key, index)
+            } else {
+                key.to_owned()
+            }
+        })
+        .collect()
+}
+
+/// A run of consecutive sibling nodes that are identical, including the
+/// order of their attributes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DuplicateRun {
+    /// The path of the first node of the run
+    pub path: String,
+
+    /// The number of nodes in the run, at least two
+    pub count: usize,
+}
+
+impl fmt::Display for DuplicateRun {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} identical siblings", self.path, self.count)
+    }
+}
+
+/// Whether two trees would be written to the same bytes.
+fn identical(left: &Node, right: &Node) -> bool {
+    let left_children = left.children().map(Vec::as_slice).unwrap_or_default();
+    let right_children = right.children().map(Vec::as_slice).unwrap_or_default();
+
+    left.key() == right.key() &&
+        left.value() == right.value() &&
+        left.attributes().into_iter().flatten().eq(right.attributes().into_iter().flatten()) &&
+        left_children.len() == right_children.len() &&
+        left_children
+            .iter()
+            .zip(right_children)
+            .all(|(left, right)| identical(left, right))
+}
+
+/// The start and length of every run of identical nodes in `children`.
+fn duplicate_runs(children: &[Node]) -> Vec<(usize, usize)> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    while start < children.len() {
+        let len = 1 + children[start + 1..]
+            .iter()
+            .take_while(|child| identical(&children[start], child))
+            .count();
+        if len > 1 {
+            runs.push((start, len));
+        }
+        start += len;
+    }
+
+    runs
+}
+
+fn find_duplicates(path: &str, node: &Node, runs: &mut Vec<DuplicateRun>) {
+    let children = node.children().map(Vec::as_slice).unwrap_or_default();
+    let segments = child_segments(children);
+    let duplicates = duplicate_runs(children);
+
+    for (i, child) in children.iter().enumerate() {
+        let child_path = format!("{}/{}", path, segments[i]);
+        match duplicates.iter().find(|(start, len)| (*start..start + len).contains(&i)) {
+            Some((start, len)) if *start == i => runs.push(DuplicateRun {
+                path: child_path.clone(),
+                count: *len,
+            }),
+
+            // The other nodes of a run have the same duplicates as the first
+            Some(_) => continue,
+            None => {},
+        };
+
+        find_duplicates(&child_path, child, runs);
+    }
+}
+
+/// Find every run of consecutive identical siblings in a tree. Duplicates
+/// below the nodes of a run are reported once, for its first node.
+pub fn find_duplicate_siblings(node: &Node) -> Vec<DuplicateRun> {
+    let mut runs = Vec::new();
+    find_duplicates(&format!("/{}", node.key()), node, &mut runs);
+
+    runs
+}
+
+fn merge_duplicates(node: &mut Node) {
+    if let Some(children) = node.children_mut() {
+        for (start, len) in duplicate_runs(children).into_iter().rev() {
+            children.drain(start + 1..start + len);
+        }
+        for child in children {
+            merge_duplicates(child);
+        }
+    }
+}
+
+/// Remove all but the first node of every run of consecutive identical
+/// siblings, returning the runs as `find_duplicate_siblings` reports them.
+pub fn merge_duplicate_siblings(node: &mut Node) -> Vec<DuplicateRun> {
+    let runs = find_duplicate_siblings(node);
+    if !runs.is_empty() {
+        merge_duplicates(node);
+    }
+
+    runs
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Binary,
+    Text,
+}
+
+/// The result of reading a document and writing it back in the same format.
+#[derive(Clone, Debug)]
+pub struct RoundTrip {
+    pub format: Format,
+    pub encoding: EncodingType,
+
+    pub input_len: usize,
+    pub output_len: usize,
+
+    /// Whether the written document is byte-for-byte equal to the input
+    pub identical: bool,
+
+    /// Differences between the tree read from the input and the tree read
+    /// back from the output
+    pub differences: Vec<Difference>,
+}
+
+impl RoundTrip {
+    /// Returns `true` if the output reads back to the same tree. Text XML
+    /// rarely round-trips byte-for-byte because of formatting, so
+    /// `identical` is not considered.
+    pub fn is_ok(&self) -> bool {
+        self.differences.is_empty() && (self.identical || self.format == Format::Text)
+    }
+}
+
+impl fmt::Display for RoundTrip {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:?} ({:?}): {} bytes in, {} bytes out, {}, {} difference(s)",
+            self.format,
+            self.encoding,
+            self.input_len,
+            self.output_len,
+            if self.identical {
+                "identical"
+            } else {
+                "not identical"
+            },
+            self.differences.len()
+        )?;
+
+        for difference in &self.differences {
+            write!(f, "\n  {}", difference)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Read a binary or text XML document, write it back in the same format, and
+/// compare the result. Binary documents are written with the compression and
+/// encoding from their header.
+pub fn round_trip(input: &[u8]) -> Result<RoundTrip, KbinError> {
+    let (format, encoding, before, output) = if crate::is_binary_xml(input) {
+        let header = Header::parse(input)?;
+        let (collection, encoding) =
+            crate::from_binary_with_options(ReaderOptions::default(), Bytes::from(input.to_vec()))?;
+        let options = Options::new(header.compression, header.encoding);
+        let output = crate::to_binary_with_options(options, &collection)?;
+
+        (Format::Binary, encoding, collection.as_node()?, output)
+    } else {
+        let (collection, encoding) = crate::from_text_xml(input)?;
+        let output = crate::to_text_xml(&collection)?;
+
+        (Format::Text, encoding, collection.as_node()?, output)
+    };
+
+    let (after, _) = match format {
+        Format::Binary => {
+            crate::from_binary_with_options(ReaderOptions::default(), Bytes::from(output.clone()))?
+        },
+        Format::Text => crate::from_text_xml(&output)?,
+    };
+
+    Ok(RoundTrip {
+        format,
+        encoding,
+        input_len: input.len(),
+        output_len: output.len(),
+        identical: input == &output[..],
+        differences: compare(&before, &after.as_node()?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare() {
+        let left = Node::with(
+            "root",
+            &[("a", "1"), ("b", "2")],
+            vec![
+                Node::with_value("item", Value::S32(1)),
+                Node::with_value("item", Value::S32(2)),
+            ],
+        );
+        assert!(compare(&left, &left).is_empty());
+
+        let mut right = left.clone();
+        right.set_attr("a", "3");
+        right.children_mut().unwrap()[1].set_value(Some(Value::S32(5)));
+        right.append_child(Node::new("extra"));
+
+        let differences = compare(&left, &right);
+        assert_eq!(differences.len(), 3);
+        assert_eq!(differences[0].path(), "/root");
+        assert_eq!(differences[1].path(), "/root/item[1]");
+        assert_eq!(differences[2].to_string(), "/root: child (none) != `extra`");
+    }
+
+    #[test]
+    fn test_compare_tolerance() {
+        let left = Node::with_nodes(
+            "root",
+            vec![
+                Node::with_value("f", Value::Float(0.1)),
+                Node::with_value("d", Value::Double2([1000.0, -2.0])),
+                Node::with_value("v", Value::Array(ValueArray::Float(vec![1.0, 2.0]))),
+                Node::with_value("t", Value::Time(1_600_000_000)),
+            ],
+        );
+        let right = Node::with_nodes(
+            "root",
+            vec![
+                Node::with_value("f", Value::Float(0.100_000_1)),
+                Node::with_value("d", Value::Double2([1000.001, -2.0])),
+                Node::with_value("v", Value::Array(ValueArray::Float(vec![1.0, 2.000_001]))),
+                Node::with_value("t", Value::Time(1_600_000_001)),
+            ],
+        );
+        assert_eq!(compare(&left, &right).len(), 4);
+
+        let mut options = CompareOptions::new();
+        options.absolute_epsilon(1e-5);
+        let differences = compare_with_options(&left, &right, &options);
+        let paths: Vec<_> = differences.iter().map(Difference::path).collect();
+        assert_eq!(paths, ["/root/d", "/root/t"]);
+
+        options.relative_epsilon(1e-5).time_skew(1);
+        assert!(compare_with_options(&left, &right, &options).is_empty());
+
+        // Values of different types are never equal
+        let double = Node::with_value("f", Value::Double(0.1));
+        let float = Node::with_value("f", Value::Float(0.1));
+        assert_eq!(compare_with_options(&double, &float, &options).len(), 1);
+    }
+
+    #[test]
+    fn test_duplicate_siblings() {
+        let item = |id| Node::with_attrs("item", &[("id", id), ("n", "1")]);
+        let mut node = Node::with_nodes(
+            "root",
+            vec![
+                item("1"),
+                item("1"),
+                item("1"),
+                item("2"),
+                Node::with_attrs("item", &[("n", "1"), ("id", "2")]),
+                Node::with_nodes(
+                    "list",
+                    vec![
+                        Node::with_value("a", Value::U8(1)),
+                        Node::with_value("a", Value::U8(1)),
+                    ],
+                ),
+            ],
+        );
+
+        let runs = find_duplicate_siblings(&node);
+        assert_eq!(
+            runs,
+            [
+                DuplicateRun {
+                    path: "/root/item[0]".into(),
+                    count: 3
+                },
+                DuplicateRun {
+                    path: "/root/list/a[0]".into(),
+                    count: 2
+                },
+            ]
+        );
+        assert_eq!(runs[0].to_string(), "/root/item[0]: 3 identical siblings");
+
+        assert_eq!(merge_duplicate_siblings(&mut node), runs);
+        let children = node.children().unwrap();
+        assert_eq!(children.len(), 4);
+        assert_eq!(children[3].children().unwrap().len(), 1);
+        assert!(find_duplicate_siblings(&node).is_empty());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data = include_bytes!("../../testcases_out.kbin");
+        let report = round_trip(data).unwrap();
+        assert_eq!(report.format, Format::Binary);
+        assert!(report.is_ok(), "{}", report);
+
+        let text = include_bytes!("../../testcases_out.xml");
+        let report = round_trip(text).unwrap();
+        assert_eq!(report.format, Format::Text);
+        assert!(report.is_ok(), "{}", report);
+    }
+}
@@ -3,8 +3,9 @@ use std::fmt;
 
 use crate::{SIG_COMPRESSED, SIG_UNCOMPRESSED};
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub enum CompressionType {
+    #[default]
     Compressed,
     Uncompressed,
 }
@@ -29,12 +30,6 @@ impl CompressionType {
     }
 }
 
-impl Default for CompressionType {
-    fn default() -> Self {
-        CompressionType::Compressed
-    }
-}
-
 impl fmt::Display for UnknownCompression {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Unknown compression type: 0x{:x}", self.0)
@@ -0,0 +1,147 @@
+use std::fmt;
+
+use rustc_hex::{FromHex, ToHex};
+
+use crate::ARRAY_MASK;
+
+/// A node type id that is not one of the standard types, such as the private
+/// type codes used by some game series.
+///
+/// Nodes of a registered type are read into and written from
+/// `Value::Unknown`. Unlike other unknown types, single values are stored
+/// inline in the data buffer with `size * count` bytes, following the same
+/// packing rules as the standard types: one and two byte values share
+/// four byte blocks, larger values are aligned on their own. Arrays are
+/// length-prefixed like arrays of standard types. Data is kept in the byte
+/// order of the document.
+///
+/// In text XML, the type is written as `__type="<name>"` with the text
+/// returned by `emit`, and read back with `parse`. Both default to hex.
+#[derive(Clone, Copy)]
+pub struct CustomType {
+    pub id: u8,
+    pub name: &'static str,
+
+    /// The size of each element in bytes
+    pub size: usize,
+
+    /// The number of elements of a single value
+    pub count: usize,
+
+    /// Parse the text of a node into its data, `None` if the text is invalid
+    pub parse: fn(&str) -> Option<Vec<u8>>,
+
+    /// Format the data of a node as text
+    pub emit: fn(&[u8]) -> String,
+}
+
+fn parse_hex(text: &str) -> Option<Vec<u8>> {
+    text.from_hex().ok()
+}
+
+fn emit_hex(data: &[u8]) -> String {
+    data.to_hex()
+}
+
+impl CustomType {
+    pub fn new(id: u8, name: &'static str, size: usize, count: usize) -> Self {
+        Self {
+            id: id & !ARRAY_MASK,
+            name,
+            size,
+            count,
+            parse: parse_hex,
+            emit: emit_hex,
+        }
+    }
+
+    /// The number of bytes of a single value.
+    #[inline]
+    pub fn value_size(&self) -> usize {
+        self.size * self.count
+    }
+
+    /// Find the registered type of the raw type byte `type_id`, which may
+    /// include the array flag.
+    pub(crate) fn find(types: &[CustomType], type_id: u8) -> Option<&CustomType> {
+        let id = type_id & !ARRAY_MASK;
+        types.iter().find(|custom| custom.id == id)
+    }
+
+    pub(crate) fn find_by_name<'a>(types: &'a [CustomType], name: &str) -> Option<&'a CustomType> {
+        types.iter().find(|custom| custom.name == name)
+    }
+}
+
+impl fmt::Debug for CustomType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CustomType")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("size", &self.size)
+            .field("count", &self.count)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::node::Node;
+    use crate::options::{Options, ReaderOptions, TextXmlOptions};
+    use crate::value::Value;
+
+    use super::CustomType;
+
+    #[test]
+    fn test_custom_type() {
+        let mut custom_type = CustomType::new(0x3c, "pos", 1, 2);
+        custom_type.parse = |text| text.split(',').map(|n| n.parse().ok()).collect();
+        custom_type.emit = |data| format!("{},{}", data[0], data[1]);
+
+        let node = Node::with_nodes(
+            "root",
+            vec![
+                Node::with_value("a", Value::U8(7)),
+                Node::with_value(
+                    "b",
+                    Value::Unknown {
+                        type_id: 0x3c,
+                        data: vec![1, 2],
+                    },
+                ),
+                Node::with_value("c", Value::U8(8)),
+            ],
+        );
+
+        let mut options = Options::builder();
+        options.custom_type(custom_type);
+        let binary = crate::to_binary_with_options(options.build(), &node).unwrap();
+
+        // The value shares its block with the one byte values
+        // The value is packed like a two byte standard type, not length-prefixed
+        assert_eq!(
+            &binary[binary.len() - 12..],
+            &[0, 0, 0, 8, 7, 8, 0, 0, 1, 2, 0, 0]
+        );
+
+        let mut options = ReaderOptions::builder();
+        options.custom_type(custom_type);
+        let (collection, _) =
+            crate::from_binary_with_options(options.build(), Bytes::from(binary)).unwrap();
+        assert_eq!(Node::from_collection(&collection).unwrap(), node);
+
+        let mut options = TextXmlOptions::builder();
+        options.custom_type(custom_type);
+        let text = crate::to_text_xml_with_options(options.build(), &collection).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains("<b __type=\"pos\">1,2</b>"), "{}", text);
+
+        let mut options = ReaderOptions::builder();
+        options.custom_type(custom_type);
+        let (read, _) =
+            crate::from_text_xml_with_options(options.build(), text.as_bytes()).unwrap();
+        assert_eq!(Node::from_collection(&read).unwrap(), node);
+    }
+}
@@ -0,0 +1,188 @@
+use bytes::Bytes;
+
+use crate::encoding_type::EncodingType;
+use crate::header::Header;
+use crate::node::NodeCollection;
+use crate::error::{KbinError, Result};
+use crate::options::{Limits, Options, ReaderOptions};
+use crate::progress::Progress;
+use crate::reader::Reader;
+
+/// A document read from binary or text XML, along with the options needed to
+/// write it back the way it was read.
+#[derive(Clone, Debug)]
+pub struct Document {
+    /// The header of a binary document, `None` for text XML
+    pub header: Option<Header>,
+    pub collection: NodeCollection,
+
+    options: Options,
+}
+
+impl Document {
+    /// Read a binary or text XML document.
+    pub fn parse(input: &[u8]) -> Result<Self> {
+        Self::parse_with_options(ReaderOptions::default(), input)
+    }
+
+    /// Like `parse`, with options for the reader. Binary documents keep their
+    /// compression, encoding, alignment, and byte order for `to_bytes`. Text
+    /// XML documents keep their encoding.
+    pub fn parse_with_options(options: ReaderOptions, input: &[u8]) -> Result<Self> {
+        if !crate::is_binary_xml(input) {
+            let (collection, encoding) = crate::from_text_xml_with_options(options, input)?;
+
+            return Ok(Self {
+                header: None,
+                collection,
+                options: Options::with_encoding(encoding),
+            });
+        }
+
+        let header = Header::parse(input)?;
+        let deny_multiple_roots = options.deny_multiple_roots;
+        let keep_partial_tree = options.keep_partial_tree;
+        let mut reader = Reader::with_options(Bytes::from(input.to_vec()), options)?;
+        let collection = crate::read_binary_root(
+            &mut reader,
+            &mut Progress::none(),
+            deny_multiple_roots,
+            keep_partial_tree,
+        )?;
+
+        let mut options = Options::builder();
+        options
+            .compression(reader.compression())
+            .encoding(reader.encoding())
+            .alignment(reader.alignment())
+            .endianness(reader.endianness());
+
+        Ok(Self {
+            header: Some(header),
+            collection,
+            options: options.build(),
+        })
+    }
+
+    /// Read a document from an untrusted source, failing as soon as it
+    /// exceeds one of `limits` instead of allocating for it.
+    pub fn parse_untrusted(input: &[u8], limits: Limits) -> Result<Self> {
+        if input.len() > limits.max_input_len {
+            return Err(KbinError::InputLimit {
+                len: input.len(),
+                max_input_len: limits.max_input_len,
+            });
+        }
+
+        Self::parse_with_options(limits.reader_options(), input)
+    }
+
+    /// Wrap a tree to be written with `options`.
+    pub fn new(collection: NodeCollection, options: Options) -> Self {
+        Self {
+            header: None,
+            collection,
+            options,
+        }
+    }
+
+    #[inline]
+    pub fn root(&self) -> &NodeCollection {
+        &self.collection
+    }
+
+    #[inline]
+    pub fn root_mut(&mut self) -> &mut NodeCollection {
+        &mut self.collection
+    }
+
+    #[inline]
+    pub fn encoding(&self) -> EncodingType {
+        self.options.encoding
+    }
+
+    /// The options `to_bytes` writes with.
+    #[inline]
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    #[inline]
+    pub fn set_options(&mut self, options: Options) {
+        self.options = options;
+    }
+
+    /// Write the document as binary XML.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        crate::to_binary_with_options(self.options.clone(), &self.collection)
+    }
+
+    /// Write the document as text XML.
+    pub fn to_xml(&self) -> Result<Vec<u8>> {
+        crate::to_text_xml(&self.collection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compression_type::CompressionType;
+    use crate::encoding_type::EncodingType;
+    use crate::options::Limits;
+
+    use super::Document;
+
+    #[test]
+    fn test_document() {
+        let data = include_bytes!("../../testcases_out.kbin");
+
+        let mut document = Document::parse(data).unwrap();
+        let header = document.header.unwrap();
+        assert_eq!(header.compression, CompressionType::Compressed);
+        assert_eq!(document.encoding(), EncodingType::SHIFT_JIS);
+        assert_eq!(document.to_bytes().unwrap(), &data[..]);
+
+        let text = document.to_xml().unwrap();
+        let from_text = Document::parse(&text).unwrap();
+        assert!(from_text.header.is_none());
+        assert_eq!(
+            from_text.root().as_node().unwrap(),
+            document.root().as_node().unwrap()
+        );
+
+        document.root_mut().children_mut().clear();
+        let root = Document::parse(&document.to_bytes().unwrap()).unwrap();
+        assert!(root.root().children().is_empty());
+    }
+
+    #[test]
+    fn test_parse_untrusted() {
+        let data = include_bytes!("../../testcases_out.kbin");
+        let text = include_bytes!("../../testcases_out.xml");
+
+        for input in [&data[..], &text[..]].iter() {
+            assert!(Document::parse_untrusted(input, Limits::default()).is_ok());
+
+            let limits = [
+                Limits {
+                    max_input_len: 16,
+                    ..Limits::default()
+                },
+                Limits {
+                    max_depth: 1,
+                    ..Limits::default()
+                },
+                Limits {
+                    max_array_len: 1,
+                    ..Limits::default()
+                },
+                Limits {
+                    max_string_len: 1,
+                    ..Limits::default()
+                },
+            ];
+            for limits in limits.iter() {
+                assert!(Document::parse_untrusted(input, *limits).is_err());
+            }
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! Conversion from a `roxmltree` document, for applications that already
+//! parsed the text XML into a DOM.
+//!
+//! The source text of an element is read with `TextXmlReader`, so elements
+//! are interpreted exactly as `from_text_xml` does, including the `__type`,
+//! `__count`, `__size`, and `__type_id` attributes and the escaping of other
+//! attribute keys that collide with them. `roxmltree` documents are
+//! read-only, so the reverse direction is `to_text_xml` followed by
+//! `roxmltree::Document::parse`.
+
+use std::convert::TryFrom;
+
+use crate::error::KbinError;
+use crate::node::{Node, NodeCollection};
+use crate::text_reader::TextXmlReader;
+
+/// Read the source text of `element` with the text reader. The text is
+/// already decoded, so the collection uses UTF-8.
+fn read_element(element: roxmltree::Node<'_, '_>) -> Result<NodeCollection, KbinError> {
+    if !element.is_element() {
+        return Err(KbinError::InvalidState);
+    }
+
+    let text = &element.document().input_text()[element.range()];
+    let mut reader = TextXmlReader::new(text.as_bytes());
+
+    reader
+        .as_node_collection()?
+        .ok_or(KbinError::NoNodeCollection)
+}
+
+impl Node {
+    /// Convert an element and its descendants.
+    pub fn from_roxmltree(element: roxmltree::Node<'_, '_>) -> Result<Self, KbinError> {
+        read_element(element)?.as_node()
+    }
+}
+
+impl TryFrom<&roxmltree::Document<'_>> for Node {
+    type Error = KbinError;
+
+    /// Convert the root element of a document.
+    fn try_from(document: &roxmltree::Document<'_>) -> Result<Self, Self::Error> {
+        Node::from_roxmltree(document.root_element())
+    }
+}
+
+impl NodeCollection {
+    /// Convert the root element of a document. Strings in the document are
+    /// already decoded, so the collection uses UTF-8.
+    pub fn from_roxmltree(document: &roxmltree::Document<'_>) -> Result<Self, KbinError> {
+        read_element(document.root_element())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_roxmltree() {
+        let text = include_str!("../../testcases_out.xml");
+        let document = roxmltree::Document::parse(text).unwrap();
+
+        let (expected, _) = crate::from_text_xml(text.as_bytes()).unwrap();
+        let expected = expected.as_node().unwrap();
+        assert_eq!(Node::try_from(&document).unwrap(), expected);
+
+        let collection = NodeCollection::from_roxmltree(&document).unwrap();
+        assert_eq!(collection.as_node().unwrap(), expected);
+
+        // Elements below the root are read on their own
+        let element = document.root_element().first_element_child().unwrap();
+        let child = &expected.children().unwrap()[0];
+        assert_eq!(&Node::from_roxmltree(element).unwrap(), child);
+
+        let document = roxmltree::Document::parse("<a __type=\"s32\" __count=\"2\"/>").unwrap();
+        assert!(Node::try_from(&document).is_err());
+    }
+}
@@ -1,13 +1,17 @@
+use std::borrow::Cow;
 use std::fmt;
+use std::str;
 use std::string::FromUtf8Error;
 
 /// The `encoding_rs` crate uses the following to describe their counterparts:
 ///
 /// `SHIFT_JIS`    => `WINDOWS_31J`
 /// `WINDOWS_1252` => `ISO-8859-1`
-use encoding_rs::{Encoding, EUC_JP, SHIFT_JIS, UTF_8, WINDOWS_1252};
+use encoding_rs::{EncoderResult, Encoding, EUC_JP, SHIFT_JIS, UTF_8, WINDOWS_1252};
 use snafu::{ResultExt, Snafu};
 
+use crate::error::KbinError;
+
 #[derive(Debug, Snafu)]
 pub enum EncodingError {
     #[snafu(display("Unknown encoding"))]
@@ -33,22 +37,17 @@ pub enum EncodingError {
 }
 
 #[allow(non_camel_case_types)]
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub enum EncodingType {
     None,
     ASCII,
     ISO_8859_1,
     EUC_JP,
+    #[default]
     SHIFT_JIS,
     UTF_8,
 }
 
-impl Default for EncodingType {
-    fn default() -> Self {
-        EncodingType::SHIFT_JIS
-    }
-}
-
 impl fmt::Display for EncodingType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let encoding = match *self {
@@ -85,7 +84,7 @@ impl EncodingType {
             e if e == EUC_JP => Ok(EncodingType::EUC_JP),
             e if e == SHIFT_JIS => Ok(EncodingType::SHIFT_JIS),
             e if e == UTF_8 => Ok(EncodingType::UTF_8),
-            _ => return Err(EncodingError::UnknownEncoding),
+            _ => Err(EncodingError::UnknownEncoding),
         }
     }
 
@@ -117,6 +116,18 @@ impl EncodingType {
         }
     }
 
+    /// Every supported encoding maps bytes up to 0x7F to the same characters
+    /// as ASCII, so pure ASCII input is already valid UTF-8 and skips
+    /// `encoding_rs` entirely.
+    #[inline]
+    fn ascii_str(input: &[u8]) -> Option<&str> {
+        if input.is_ascii() {
+            str::from_utf8(input).ok()
+        } else {
+            None
+        }
+    }
+
     fn decode_ascii(input: &[u8]) -> Result<String, EncodingError> {
         // ASCII only goes up to 0x7F
         match input.iter().position(|&ch| ch >= 0x80) {
@@ -136,6 +147,7 @@ impl EncodingType {
     fn decode_with_encoding(
         encoding: &'static Encoding,
         input: &[u8],
+        lenient: bool,
     ) -> Result<String, EncodingError> {
         let (output, actual, character_replaced) = encoding.decode(input);
 
@@ -149,7 +161,7 @@ impl EncodingType {
         // implementation will include invalid characters.
         if encoding != actual {
             Err(EncodingError::MismatchedDecode { actual })
-        } else if !character_replaced || encoding == SHIFT_JIS {
+        } else if !character_replaced || (lenient && encoding == SHIFT_JIS) {
             Ok(output.into_owned())
         } else {
             Err(EncodingError::UnmappableCharacters)
@@ -179,15 +191,19 @@ impl EncodingType {
     /// `EncodingType::SHIFT_JIS` will ignore invalid characters because Konami's
     /// implementation will include invalid characters.
     pub fn decode_bytes(&self, input: &[u8]) -> Result<String, EncodingError> {
+        if let Some(text) = Self::ascii_str(input) {
+            return Ok(text.to_owned());
+        }
+
         match *self {
             EncodingType::None | EncodingType::UTF_8 => {
                 String::from_utf8(input.to_vec()).context(InvalidUtf8)
             },
 
             EncodingType::ASCII => Self::decode_ascii(input),
-            EncodingType::ISO_8859_1 => Self::decode_with_encoding(WINDOWS_1252, input),
-            EncodingType::EUC_JP => Self::decode_with_encoding(EUC_JP, input),
-            EncodingType::SHIFT_JIS => Self::decode_with_encoding(SHIFT_JIS, input),
+            EncodingType::ISO_8859_1 => Self::decode_with_encoding(WINDOWS_1252, input, true),
+            EncodingType::EUC_JP => Self::decode_with_encoding(EUC_JP, input, true),
+            EncodingType::SHIFT_JIS => Self::decode_with_encoding(SHIFT_JIS, input, true),
         }
     }
 
@@ -196,14 +212,7 @@ impl EncodingType {
     /// A `Some` value indicates the encoding should be used from the `encoding`
     /// crate. A `None` value indicates Rust's own UTF-8 handling should be used.
     pub fn encode_bytes(&self, input: &str) -> Result<Vec<u8>, EncodingError> {
-        let mut result = match *self {
-            EncodingType::None | EncodingType::UTF_8 => input.as_bytes().to_vec(),
-
-            EncodingType::ASCII => Self::encode_ascii(input)?,
-            EncodingType::ISO_8859_1 => Self::encode_with_encoding(WINDOWS_1252, input)?,
-            EncodingType::EUC_JP => Self::encode_with_encoding(EUC_JP, input)?,
-            EncodingType::SHIFT_JIS => Self::encode_with_encoding(SHIFT_JIS, input)?,
-        };
+        let mut result = self.encode_raw(input)?;
 
         // Add trailing null byte
         result.reserve_exact(1);
@@ -211,4 +220,229 @@ impl EncodingType {
 
         Ok(result)
     }
+
+    fn encode_raw(&self, input: &str) -> Result<Vec<u8>, EncodingError> {
+        if input.is_ascii() {
+            return Ok(input.as_bytes().to_vec());
+        }
+
+        match *self {
+            EncodingType::None | EncodingType::UTF_8 => Ok(input.as_bytes().to_vec()),
+
+            EncodingType::ASCII => Self::encode_ascii(input),
+            EncodingType::ISO_8859_1 => Self::encode_with_encoding(WINDOWS_1252, input),
+            EncodingType::EUC_JP => Self::encode_with_encoding(EUC_JP, input),
+            EncodingType::SHIFT_JIS => Self::encode_with_encoding(SHIFT_JIS, input),
+        }
+    }
+
+    /// Encode a string without the trailing null byte used in binary
+    /// documents.
+    pub fn encode(&self, input: &str) -> Result<Vec<u8>, KbinError> {
+        self.encode_raw(input).map_err(Into::into)
+    }
+
+    /// The length of `input` encoded with `encode`, counted without
+    /// allocating the encoded string.
+    pub fn encoded_len(&self, input: &str) -> Result<usize, KbinError> {
+        if input.is_ascii() {
+            return Ok(input.len());
+        }
+
+        let encoding = match *self {
+            EncodingType::None | EncodingType::UTF_8 => return Ok(input.len()),
+            EncodingType::ASCII => {
+                return match input.as_bytes().iter().position(|&ch| ch >= 0x80) {
+                    Some(index) => Err(EncodingError::InvalidAscii { index }.into()),
+                    None => Ok(input.len()),
+                };
+            },
+            EncodingType::ISO_8859_1 => WINDOWS_1252,
+            EncodingType::EUC_JP => EUC_JP,
+            EncodingType::SHIFT_JIS => SHIFT_JIS,
+        };
+
+        let mut encoder = encoding.new_encoder();
+        let mut buf = [0; 256];
+        let mut input = input;
+        let mut len = 0;
+        loop {
+            let (result, read, written) =
+                encoder.encode_from_utf8_without_replacement(input, &mut buf, true);
+            len += written;
+            input = &input[read..];
+
+            match result {
+                EncoderResult::InputEmpty => return Ok(len),
+                EncoderResult::OutputFull => {},
+                EncoderResult::Unmappable(_) => {
+                    return Err(EncodingError::UnmappableCharacters.into());
+                },
+            };
+        }
+    }
+
+    /// Encode a text XML document, writing characters that cannot be
+    /// represented as decimal numeric character references.
+    pub(crate) fn encode_with_char_refs(&self, input: &str) -> Vec<u8> {
+        let encoding = match *self {
+            EncodingType::None | EncodingType::UTF_8 => return input.as_bytes().to_vec(),
+            EncodingType::ASCII => {
+                let mut output = Vec::with_capacity(input.len());
+                for ch in input.chars() {
+                    if ch.is_ascii() {
+                        output.push(ch as u8);
+                    } else {
+                        output.extend_from_slice(format!("&#{};", u32::from(ch)).as_bytes());
+                    }
+                }
+
+                return output;
+            },
+            EncodingType::ISO_8859_1 => WINDOWS_1252,
+            EncodingType::EUC_JP => EUC_JP,
+            EncodingType::SHIFT_JIS => SHIFT_JIS,
+        };
+
+        // `encoding_rs` replaces unmappable characters with references
+        let (output, _, _) = encoding.encode(input);
+        output.into_owned()
+    }
+
+    /// Decode bytes without a trailing null byte.
+    ///
+    /// Unlike `decode_bytes`, invalid `EncodingType::SHIFT_JIS` sequences are
+    /// an error instead of being replaced.
+    pub fn decode(&self, input: &[u8]) -> Result<String, KbinError> {
+        if let Some(text) = Self::ascii_str(input) {
+            return Ok(text.to_owned());
+        }
+
+        let result = match *self {
+            EncodingType::None | EncodingType::UTF_8 => {
+                String::from_utf8(input.to_vec()).context(InvalidUtf8)
+            },
+
+            EncodingType::ASCII => Self::decode_ascii(input),
+            EncodingType::ISO_8859_1 => Self::decode_with_encoding(WINDOWS_1252, input, false),
+            EncodingType::EUC_JP => Self::decode_with_encoding(EUC_JP, input, false),
+            EncodingType::SHIFT_JIS => Self::decode_with_encoding(SHIFT_JIS, input, false),
+        };
+
+        result.map_err(Into::into)
+    }
+
+    /// Like `decode_bytes`, but borrows `input` when it is pure ASCII or, for
+    /// UTF-8 documents, valid UTF-8, which is the case for most names and
+    /// values in game data.
+    pub fn decode_borrowed<'a>(&self, input: &'a [u8]) -> Result<Cow<'a, str>, EncodingError> {
+        let borrowed = match *self {
+            EncodingType::None | EncodingType::UTF_8 => str::from_utf8(input).ok(),
+            _ => Self::ascii_str(input),
+        };
+
+        match borrowed {
+            Some(text) => Ok(Cow::Borrowed(text)),
+            None => self.decode_bytes(input).map(Cow::Owned),
+        }
+    }
+
+    /// Guess the encoding of some bytes. Trailing null bytes are ignored.
+    ///
+    /// Input without bytes above 0x7F is `ASCII`, valid UTF-8 is `UTF_8`, and
+    /// otherwise whichever of `SHIFT_JIS` and `EUC_JP` decodes without errors
+    /// is used. Input valid in both is decoded as the one producing fewer
+    /// characters, since the overlap is mostly half-width katakana in
+    /// Shift-JIS. Anything else is `ISO_8859_1`, which can decode every byte.
+    pub fn detect(input: &[u8]) -> Self {
+        let end = input
+            .iter()
+            .rposition(|&ch| ch != 0)
+            .map_or(0, |index| index + 1);
+        let input = &input[..end];
+
+        if input.is_ascii() {
+            return EncodingType::ASCII;
+        }
+        if std::str::from_utf8(input).is_ok() {
+            return EncodingType::UTF_8;
+        }
+
+        let shift_jis = Self::decode_with_encoding(SHIFT_JIS, input, false);
+        let euc_jp = Self::decode_with_encoding(EUC_JP, input, false);
+        match (shift_jis, euc_jp) {
+            (Ok(shift_jis), Ok(euc_jp)) => {
+                if shift_jis.chars().count() < euc_jp.chars().count() {
+                    EncodingType::SHIFT_JIS
+                } else {
+                    EncodingType::EUC_JP
+                }
+            },
+            (Ok(_), Err(_)) => EncodingType::SHIFT_JIS,
+            (Err(_), Ok(_)) => EncodingType::EUC_JP,
+            (Err(_), Err(_)) => EncodingType::ISO_8859_1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let text = "\u{30c6}\u{30b9}\u{30c8} test";
+        for encoding in &[
+            EncodingType::SHIFT_JIS,
+            EncodingType::EUC_JP,
+            EncodingType::UTF_8,
+        ] {
+            let bytes = encoding.encode(text).unwrap();
+            assert_eq!(bytes.last(), Some(&b't'));
+            assert_eq!(encoding.decode(&bytes).unwrap(), text);
+            assert_eq!(EncodingType::detect(&bytes), *encoding);
+        }
+
+        for encoding in &[
+            EncodingType::SHIFT_JIS,
+            EncodingType::EUC_JP,
+            EncodingType::UTF_8,
+            EncodingType::ISO_8859_1,
+        ] {
+            let text = "\u{ff83}\u{30c6} ".repeat(100);
+            match encoding.encode(&text) {
+                Ok(bytes) => assert_eq!(encoding.encoded_len(&text).unwrap(), bytes.len()),
+                Err(_) => assert!(encoding.encoded_len(&text).is_err()),
+            };
+        }
+
+        assert!(EncodingType::ASCII.encode(text).is_err());
+        assert_eq!(EncodingType::detect(b"test\0"), EncodingType::ASCII);
+        assert!(EncodingType::SHIFT_JIS.decode(&[0x82]).is_err());
+        assert!(EncodingType::SHIFT_JIS.decode_bytes(&[0x82]).is_ok());
+    }
+
+    #[test]
+    fn test_ascii_fast_path() {
+        let sjis = EncodingType::SHIFT_JIS.encode("\u{30c6}").unwrap();
+        for encoding in &[
+            EncodingType::ASCII,
+            EncodingType::SHIFT_JIS,
+            EncodingType::EUC_JP,
+            EncodingType::ISO_8859_1,
+        ] {
+            let text = encoding.decode_borrowed(b"music_id").unwrap();
+            assert!(matches!(text, Cow::Borrowed("music_id")));
+            assert_eq!(encoding.encode("music_id").unwrap(), b"music_id");
+            assert_eq!(encoding.encoded_len("music_id").unwrap(), 8);
+        }
+
+        let text = EncodingType::SHIFT_JIS.decode_borrowed(&sjis).unwrap();
+        assert!(matches!(text, Cow::Owned(ref text) if text == "\u{30c6}"));
+        assert!(matches!(
+            EncodingType::UTF_8.decode_borrowed("\u{30c6}".as_bytes()),
+            Ok(Cow::Borrowed("\u{30c6}"))
+        ));
+        assert!(EncodingType::ASCII.decode_borrowed(&sjis).is_err());
+    }
 }
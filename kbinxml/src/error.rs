@@ -1,4 +1,5 @@
 use std::error::Error;
+use std::fmt;
 use std::io;
 use std::num::{ParseFloatError, ParseIntError};
 use std::result::Result as StdResult;
@@ -7,8 +8,12 @@ use quick_xml::Error as QuickXmlError;
 use rustc_hex::FromHexError;
 use snafu::Snafu;
 
+use crate::bundle::BundleError;
 use crate::byte_buffer::ByteBufferError;
 use crate::encoding_type::EncodingError;
+#[cfg(any(feature = "rmpv", feature = "cbor4ii", feature = "serde_json"))]
+use crate::interchange::InterchangeError;
+use crate::node::{NodeCollection, NodePath};
 use crate::node_types::StandardType;
 use crate::reader::ReaderError;
 use crate::sixbit::SixbitError;
@@ -75,6 +80,29 @@ pub enum KbinError {
     #[snafu(display("Value mismatch, expected an array, but found {:?}", value))]
     ExpectedValueArray { value: Value },
 
+    #[snafu(display("Unable to convert {:?} to {}", value, node_type))]
+    InvalidValueConversion {
+        node_type: StandardType,
+        value: Value,
+    },
+
+    #[snafu(display("Time is out of range for a kbin time value"))]
+    TimeOutOfRange,
+
+    #[snafu(display("Arithmetic overflow for value of type {}", node_type))]
+    ArithmeticOverflow { node_type: StandardType },
+
+    #[cfg(feature = "exact-floats")]
+    #[snafu(display(
+        "Formatted {} value `{}` does not parse back to the same bits",
+        node_type,
+        text
+    ))]
+    InexactFloat {
+        node_type: StandardType,
+        text: String,
+    },
+
     #[snafu(display("Invalid input for boolean: {}", input))]
     InvalidBooleanInput { input: u8 },
 
@@ -84,6 +112,73 @@ pub enum KbinError {
     #[snafu(display("Invalid state"))]
     InvalidState,
 
+    #[snafu(display("Node `{}` does not have a value", key))]
+    NoNodeValue { key: String },
+
+    #[snafu(display("Child node `{}` not found", key))]
+    ChildNotFound { key: String },
+
+    #[snafu(display("Attribute `{}` not found", key))]
+    AttributeNotFound { key: String },
+
+    #[snafu(display("Error at `{}`: {}", path, source))]
+    AtPath {
+        path: NodePath,
+        source: Box<KbinError>,
+    },
+
+    /// Returned instead of `source` by readers with the `keep_partial_tree`
+    /// option when reading fails after the root node was started.
+    #[snafu(display("Failed to read the whole document: {}", source))]
+    PartialTree {
+        collection: Box<NodeCollection>,
+        last_path: Option<NodePath>,
+        source: Box<KbinError>,
+    },
+
+    #[snafu(display("No node found at path `{}`", path))]
+    InvalidNodePath { path: String },
+
+    #[snafu(display(
+        "Input of {} bytes exceeds the limit of {} bytes",
+        len,
+        max_input_len
+    ))]
+    InputLimit { len: usize, max_input_len: usize },
+
+    #[snafu(display("Invalid localization table entry on line {}", line))]
+    InvalidLocalizationLine { line: usize },
+
+    #[snafu(display("Invalid patch operation `{}` at path `{}`", op, path))]
+    InvalidPatchOperation { path: String, op: &'static str },
+
+    #[snafu(display("Child index {} out of range for node with {} children", index, len))]
+    ChildIndexOutOfRange { index: usize, len: usize },
+
+    #[snafu(display("Array index {} out of range for array with {} elements", index, len))]
+    ArrayIndexOutOfRange { index: usize, len: usize },
+
+    #[snafu(display("Duplicate attribute `{}`", key))]
+    DuplicateAttribute { key: String },
+
+    #[snafu(display("Duplicate child `{}`", key))]
+    DuplicateChild { key: String },
+
+    #[snafu(display("Invalid flattened path `{}`", path))]
+    InvalidFlatPath { path: String },
+
+    #[snafu(display("Node with an empty name, which text XML needs a placeholder name for"))]
+    EmptyNodeName,
+
+    #[snafu(display("Writing only the children of a node is not supported by this type"))]
+    FragmentUnsupported,
+
+    #[snafu(display("Unable to parse attribute `{}`", key))]
+    AttributeParse {
+        key: String,
+        source: Box<dyn Error + Send + Sync>,
+    },
+
     #[snafu(display("Failed to handle byte buffer operation"))]
     ByteBuffer {
         #[snafu(backtrace)]
@@ -120,8 +215,25 @@ pub enum KbinError {
         source: TextReaderError,
     },
 
+    #[snafu(display("Failed to handle bundle"))]
+    Bundle {
+        #[snafu(backtrace)]
+        source: BundleError,
+    },
+
+    #[cfg(any(feature = "rmpv", feature = "cbor4ii", feature = "serde_json"))]
+    #[snafu(display("Failed to convert MessagePack or CBOR"))]
+    Interchange {
+        #[snafu(backtrace)]
+        source: InterchangeError,
+    },
+
     #[snafu(display("Error handling XML"))]
     XmlError { source: QuickXmlError },
+
+    #[cfg(feature = "regex")]
+    #[snafu(display("Invalid search pattern"))]
+    InvalidRegex { source: regex::Error },
 }
 
 impl From<ByteBufferError> for KbinError {
@@ -166,9 +278,280 @@ impl From<TextReaderError> for KbinError {
     }
 }
 
+#[cfg(any(feature = "rmpv", feature = "cbor4ii", feature = "serde_json"))]
+impl From<InterchangeError> for KbinError {
+    #[inline]
+    fn from(source: InterchangeError) -> Self {
+        KbinError::Interchange { source }
+    }
+}
+
 impl From<QuickXmlError> for KbinError {
     #[inline]
     fn from(source: QuickXmlError) -> Self {
         KbinError::XmlError { source }
     }
 }
+
+/// A machine-readable category of a `KbinError`, for example to map errors
+/// to status codes. New kinds may be added, but the kind of an existing
+/// error does not change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The input is not a valid document
+    Malformed,
+
+    /// The input exceeds one of the limits set in the reader options
+    LimitExceeded,
+
+    /// Text could not be converted to or from the encoding of the document
+    Encoding,
+
+    /// A node, attribute, or value has another type than expected
+    TypeMismatch,
+
+    /// Text could not be parsed as a value
+    ValueParse,
+
+    /// A value cannot be represented, such as an out of range time
+    InvalidValue,
+
+    /// A node, attribute, value, or entry that was asked for does not exist
+    NotFound,
+
+    /// An argument or a tree to be written is invalid
+    InvalidArgument,
+
+    /// Writing the output failed
+    Io,
+
+    /// A bug in this crate
+    Internal,
+}
+
+impl ErrorKind {
+    /// A stable snake case name of the kind, such as `limit_exceeded`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::Malformed => "malformed",
+            ErrorKind::LimitExceeded => "limit_exceeded",
+            ErrorKind::Encoding => "encoding",
+            ErrorKind::TypeMismatch => "type_mismatch",
+            ErrorKind::ValueParse => "value_parse",
+            ErrorKind::InvalidValue => "invalid_value",
+            ErrorKind::NotFound => "not_found",
+            ErrorKind::InvalidArgument => "invalid_argument",
+            ErrorKind::Io => "io",
+            ErrorKind::Internal => "internal",
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl ReaderError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            ReaderError::DepthLimit { .. } |
+            ReaderError::NodeLimit { .. } |
+            ReaderError::DataLimit { .. } |
+            ReaderError::ArrayLimit { .. } |
+            ReaderError::StringLimit { .. } => ErrorKind::LimitExceeded,
+            ReaderError::InvalidEncoding { .. } |
+            ReaderError::MismatchedEncoding |
+            ReaderError::NodeSixbitName { .. } => ErrorKind::Encoding,
+            _ => ErrorKind::Malformed,
+        }
+    }
+}
+
+impl TextReaderError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            TextReaderError::DepthLimit { .. } |
+            TextReaderError::NodeLimit { .. } |
+            TextReaderError::DataLimit { .. } |
+            TextReaderError::ArrayLimit { .. } |
+            TextReaderError::StringLimit { .. } => ErrorKind::LimitExceeded,
+            TextReaderError::InvalidEncoding { .. } |
+            TextReaderError::DecodeText { .. } |
+            TextReaderError::Utf8 { .. } => ErrorKind::Encoding,
+            TextReaderError::InvalidCustomValue { .. } | TextReaderError::NonFiniteFloat { .. } => {
+                ErrorKind::ValueParse
+            },
+            TextReaderError::ValueDecode { source, .. } |
+            TextReaderError::ValueEncode { source, .. } => source.kind(),
+            TextReaderError::AtPosition { source, .. } => source.kind(),
+            TextReaderError::NoNodeData => ErrorKind::Internal,
+            _ => ErrorKind::Malformed,
+        }
+    }
+}
+
+impl WriterError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            WriterError::NodeSixbitName { .. } | WriterError::NodeUncompressedNameEncode { .. } => {
+                ErrorKind::Encoding
+            },
+            WriterError::ValueEncode { source, .. } |
+            WriterError::DefinitionKey { source, .. } |
+            WriterError::DefinitionValue { source, .. } => source.kind(),
+            WriterError::UnexpectedValueArray { .. } | WriterError::ExpectedValueArray { .. } => {
+                ErrorKind::TypeMismatch
+            },
+            WriterError::UncompressedNameLength { .. } |
+            WriterError::NoNodeKey |
+            WriterError::NoNodeValue |
+            WriterError::NoOpenNode { .. } |
+            WriterError::MultipleRoots |
+            WriterError::UnclosedNodes { .. } => ErrorKind::InvalidArgument,
+            _ => ErrorKind::Io,
+        }
+    }
+}
+
+impl KbinError {
+    /// The category of this error. Errors wrapped with their location, such
+    /// as `AtPath`, have the kind of the wrapped error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            KbinError::DataConvert { .. } |
+            KbinError::NoNodeCollection |
+            KbinError::SizeMismatch { .. } |
+            KbinError::InvalidLocalizationLine { .. } |
+            KbinError::ByteBuffer { .. } |
+            KbinError::XmlError { .. } => ErrorKind::Malformed,
+            KbinError::StringParse { .. } |
+            KbinError::StringParseInt { .. } |
+            KbinError::StringParseFloat { .. } |
+            KbinError::HexError { .. } |
+            KbinError::InvalidBooleanInput { .. } |
+            KbinError::AttributeParse { .. } => ErrorKind::ValueParse,
+            KbinError::TypeMismatch { .. } |
+            KbinError::ValueTypeMismatch { .. } |
+            KbinError::ExpectedValueArray { .. } |
+            KbinError::InvalidValueConversion { .. } |
+            KbinError::InvalidNodeType { .. } => ErrorKind::TypeMismatch,
+            KbinError::TimeOutOfRange | KbinError::ArithmeticOverflow { .. } => {
+                ErrorKind::InvalidValue
+            },
+            #[cfg(feature = "exact-floats")]
+            KbinError::InexactFloat { .. } => ErrorKind::InvalidValue,
+            KbinError::InvalidState => ErrorKind::Internal,
+            KbinError::NoNodeValue { .. } |
+            KbinError::ChildNotFound { .. } |
+            KbinError::AttributeNotFound { .. } |
+            KbinError::InvalidNodePath { .. } |
+            KbinError::ChildIndexOutOfRange { .. } |
+            KbinError::ArrayIndexOutOfRange { .. } => ErrorKind::NotFound,
+            KbinError::AtPath { source, .. } | KbinError::PartialTree { source, .. } => {
+                source.kind()
+            },
+            KbinError::InputLimit { .. } => ErrorKind::LimitExceeded,
+            KbinError::InvalidPatchOperation { .. } |
+            KbinError::DuplicateAttribute { .. } |
+            KbinError::DuplicateChild { .. } |
+            KbinError::InvalidFlatPath { .. } |
+            KbinError::EmptyNodeName |
+            KbinError::FragmentUnsupported => ErrorKind::InvalidArgument,
+            KbinError::Encoding { .. } | KbinError::Sixbit { .. } => ErrorKind::Encoding,
+            KbinError::Reader { source } => source.kind(),
+            KbinError::Writer { source } => source.kind(),
+            KbinError::TextReader { source } => source.kind(),
+            KbinError::Bundle { source } => match source {
+                BundleError::MissingEntry { .. } => ErrorKind::NotFound,
+                BundleError::NameTooLong { .. } | BundleError::TooLarge => {
+                    ErrorKind::InvalidArgument
+                },
+                _ => ErrorKind::Malformed,
+            },
+            #[cfg(any(feature = "rmpv", feature = "cbor4ii", feature = "serde_json"))]
+            KbinError::Interchange { source } => match source {
+                InterchangeError::InvalidValue { .. } => ErrorKind::InvalidValue,
+                _ => ErrorKind::Malformed,
+            },
+            #[cfg(feature = "regex")]
+            KbinError::InvalidRegex { .. } => ErrorKind::InvalidArgument,
+        }
+    }
+
+    /// The path of the node the error happened at, if known.
+    pub fn path(&self) -> Option<&NodePath> {
+        match self {
+            KbinError::AtPath { path, .. } => Some(path),
+            KbinError::PartialTree { last_path, .. } => last_path.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// The byte offset in text XML input the error happened at, if known.
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            KbinError::TextReader {
+                source: TextReaderError::AtPosition { offset, .. },
+            } => Some(*offset),
+            KbinError::AtPath { source, .. } | KbinError::PartialTree { source, .. } => {
+                source.offset()
+            },
+            _ => None,
+        }
+    }
+
+    /// The expected and actual node type of type mismatches, where the actual
+    /// type is known.
+    pub fn type_mismatch(&self) -> Option<(StandardType, Option<StandardType>)> {
+        match self {
+            KbinError::TypeMismatch { expected, found } => Some((*expected, Some(*found))),
+            KbinError::ValueTypeMismatch { node_type, value } |
+            KbinError::InvalidValueConversion { node_type, value } => {
+                Some((*node_type, Some(value.standard_type())))
+            },
+            KbinError::InvalidNodeType { node_type } => Some((*node_type, None)),
+            KbinError::AtPath { source, .. } | KbinError::PartialTree { source, .. } => {
+                source.type_mismatch()
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::node::Node;
+    use crate::node_types::StandardType;
+    use crate::options::ReaderOptions;
+    use crate::value::Value;
+
+    use super::ErrorKind;
+
+    #[test]
+    fn test_error_kinds() {
+        let node = Node::with_value("a", Value::U8(1));
+        let err = node.value_as::<u32>().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+        assert_eq!(err.kind().code(), "type_mismatch");
+        assert_eq!(
+            err.type_mismatch().map(|(expected, _)| expected),
+            Some(StandardType::U32)
+        );
+
+        let err = crate::from_text_xml(b"<a __type=\"u8\">x</a>").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ValueParse);
+        assert_eq!(err.offset(), Some(15));
+
+        let mut options = ReaderOptions::builder();
+        options.max_depth(1);
+        let err =
+            crate::from_text_xml_with_options(options.build(), b"<a><b><c/></b></a>").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::LimitExceeded);
+
+        let err = Node::new("a").child_value_str("b").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,297 @@
+//! Transformations applied while a `Node` tree is written.
+//!
+//! `Node::events` walks a tree as a sequence of `Event`s without copying it.
+//! The adapters in `EventsExt` change the sequence on the fly, and
+//! `StreamWriter::write_events` writes the result, so simple edits of large
+//! trees do not need a modified copy of the tree:
+//!
+//! ```
+//! use kbinxml::events::EventsExt;
+//! use kbinxml::{Node, StreamWriter};
+//!
+//! let node = Node::with_nodes("root", vec![Node::new("debug"), Node::new("music")]);
+//!
+//! let events = node
+//!     .events()
+//!     .strip_nodes(|key, _| key == "debug")
+//!     .rename_keys(|key| if key == "music" { Some("song".into()) } else { None })
+//!     .root_attribute("version", "2");
+//!
+//! let mut writer = StreamWriter::new();
+//! writer.write_events(events).unwrap();
+//! let binary = writer.finish().unwrap();
+//! ```
+
+use std::borrow::Cow;
+use std::slice;
+
+use indexmap::map::Iter as AttributeIter;
+
+use crate::node::Node;
+use crate::value::Value;
+
+/// One step of writing a node tree. Every `Start` is followed by the
+/// attributes of the node, then the events of its children, then an `End`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event<'a> {
+    Start {
+        key: Cow<'a, str>,
+        value: Option<&'a Value>,
+    },
+    Attribute {
+        key: Cow<'a, str>,
+        value: Cow<'a, str>,
+    },
+    End,
+}
+
+struct Frame<'a> {
+    attributes: Option<AttributeIter<'a, String, String>>,
+    children: slice::Iter<'a, Node>,
+}
+
+/// Iterator over the events of a `Node` tree. Created by `Node::events`.
+pub struct Events<'a> {
+    root: Option<&'a Node>,
+    stack: Vec<Frame<'a>>,
+}
+
+impl<'a> Events<'a> {
+    fn start(&mut self, node: &'a Node) -> Event<'a> {
+        self.stack.push(Frame {
+            attributes: node.attributes().map(|attributes| attributes.iter()),
+            children: node
+                .children()
+                .map(|children| children.iter())
+                .unwrap_or_default(),
+        });
+
+        Event::Start {
+            key: Cow::Borrowed(node.key()),
+            value: node.value(),
+        }
+    }
+}
+
+impl<'a> Iterator for Events<'a> {
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(root) = self.root.take() {
+            return Some(self.start(root));
+        }
+
+        let frame = self.stack.last_mut()?;
+        if let Some((key, value)) = frame.attributes.as_mut().and_then(Iterator::next) {
+            return Some(Event::Attribute {
+                key: Cow::Borrowed(key),
+                value: Cow::Borrowed(value),
+            });
+        }
+
+        match frame.children.next() {
+            Some(child) => Some(self.start(child)),
+            None => {
+                self.stack.pop();
+                Some(Event::End)
+            },
+        }
+    }
+}
+
+impl Node {
+    /// Iterate over the events of writing this node and its descendants.
+    pub fn events(&self) -> Events<'_> {
+        Events {
+            root: Some(self),
+            stack: Vec::new(),
+        }
+    }
+}
+
+/// Leaves out nodes matching a predicate along with their attributes and
+/// descendants. Created by `EventsExt::strip_nodes`.
+pub struct StripNodes<I, P> {
+    inner: I,
+    predicate: P,
+
+    /// The number of open nodes being left out
+    depth: usize,
+}
+
+impl<'a, I, P> Iterator for StripNodes<I, P>
+where
+    I: Iterator<Item = Event<'a>>,
+    P: FnMut(&str, Option<&Value>) -> bool,
+{
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let event = self.inner.next()?;
+
+            match (&event, self.depth) {
+                (Event::Start { key, value }, 0) => {
+                    if !(self.predicate)(key, *value) {
+                        return Some(event);
+                    }
+                    self.depth = 1;
+                },
+                (_, 0) => return Some(event),
+                (Event::Start { .. }, _) => self.depth += 1,
+                (Event::End, _) => self.depth -= 1,
+                (Event::Attribute { .. }, _) => {},
+            };
+        }
+    }
+}
+
+/// Renames nodes. Created by `EventsExt::rename_keys`.
+pub struct RenameKeys<I, F> {
+    inner: I,
+    rename: F,
+}
+
+impl<'a, I, F> Iterator for RenameKeys<I, F>
+where
+    I: Iterator<Item = Event<'a>>,
+    F: FnMut(&str) -> Option<String>,
+{
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.inner.next()? {
+            Event::Start { key, value } => {
+                let key = match (self.rename)(&key) {
+                    Some(renamed) => Cow::Owned(renamed),
+                    None => key,
+                };
+
+                Some(Event::Start { key, value })
+            },
+            event => Some(event),
+        }
+    }
+}
+
+/// Adds an attribute to the root node. Created by
+/// `EventsExt::root_attribute`.
+pub struct RootAttribute<'a, I> {
+    inner: I,
+    attribute: Option<Event<'a>>,
+
+    /// Whether the start of the root node has been returned
+    started: bool,
+}
+
+impl<'a, I> Iterator for RootAttribute<'a, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            self.started = true;
+        } else if let Some(attribute) = self.attribute.take() {
+            return Some(attribute);
+        }
+
+        self.inner.next()
+    }
+}
+
+/// Adapters for iterators of `Event`s.
+pub trait EventsExt<'a>: Iterator<Item = Event<'a>> + Sized {
+    /// Leave out every node for which `predicate` returns `true` when called
+    /// with its key and value, along with its attributes and descendants.
+    fn strip_nodes<P>(self, predicate: P) -> StripNodes<Self, P>
+    where
+        P: FnMut(&str, Option<&Value>) -> bool,
+    {
+        StripNodes {
+            inner: self,
+            predicate,
+            depth: 0,
+        }
+    }
+
+    /// Rename every node for which `rename` returns a new key.
+    fn rename_keys<F>(self, rename: F) -> RenameKeys<Self, F>
+    where
+        F: FnMut(&str) -> Option<String>,
+    {
+        RenameKeys {
+            inner: self,
+            rename,
+        }
+    }
+
+    /// Add an attribute to the root node, before its other attributes.
+    fn root_attribute<K, V>(self, key: K, value: V) -> RootAttribute<'a, Self>
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        RootAttribute {
+            inner: self,
+            attribute: Some(Event::Attribute {
+                key: Cow::Owned(key.into()),
+                value: Cow::Owned(value.into()),
+            }),
+            started: false,
+        }
+    }
+}
+
+impl<'a, I> EventsExt<'a> for I where I: Iterator<Item = Event<'a>> {}
+
+#[cfg(test)]
+mod tests {
+    use crate::node::Node;
+    use crate::value::Value;
+    use crate::writer::StreamWriter;
+
+    use super::EventsExt;
+
+    #[test]
+    fn test_events() {
+        let node = Node::with(
+            "root",
+            &[("id", "1")],
+            vec![
+                Node::with_nodes("debug", vec![Node::with_value("debug", Value::U8(1))]),
+                Node::with_attrs_value("music", &[("a", "b")], Value::S32(2)),
+                Node::with_value("flag", Value::Boolean(true)),
+            ],
+        );
+
+        let mut writer = StreamWriter::new();
+        writer.write_events(node.events()).unwrap();
+        assert_eq!(writer.finish().unwrap(), crate::to_binary(&node).unwrap());
+
+        let events = node
+            .events()
+            .strip_nodes(|key, value| key == "debug" || value == Some(&Value::Boolean(true)))
+            .rename_keys(|key| {
+                if key == "music" {
+                    Some("song".into())
+                } else {
+                    None
+                }
+            })
+            .root_attribute("version", "2");
+        let mut writer = StreamWriter::new();
+        writer.write_events(events).unwrap();
+
+        let expected = Node::with(
+            "root",
+            &[("version", "2"), ("id", "1")],
+            vec![Node::with_attrs_value("song", &[("a", "b")], Value::S32(2))],
+        );
+        assert_eq!(
+            writer.finish().unwrap(),
+            crate::to_binary(&expected).unwrap()
+        );
+    }
+}
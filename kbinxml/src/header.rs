@@ -0,0 +1,77 @@
+use std::io::Cursor;
+
+use byteorder::{BigEndian, ReadBytesExt};
+
+use crate::compression_type::CompressionType;
+use crate::encoding_type::EncodingType;
+use crate::error::Result;
+use crate::reader::Reader;
+
+/// The header at the start of a binary XML document and the lengths of its
+/// node and data sections.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub signature: u8,
+    pub compression: CompressionType,
+    pub encoding: EncodingType,
+
+    /// Length of the node section in bytes
+    pub node_len: u32,
+
+    /// Length of the data section in bytes, `None` if the input ends before
+    /// the data section length
+    pub data_len: Option<u32>,
+}
+
+impl Header {
+    /// The number of bytes needed to read everything except `data_len`.
+    pub const LEN: usize = 8;
+
+    /// Parse the header from the start of `input` without reading any nodes.
+    /// Only the first `Header::LEN` bytes are required.
+    pub fn parse(input: &[u8]) -> Result<Self> {
+        let mut cursor = Cursor::new(input);
+        let (compression, encoding, node_len) = Reader::read_header(&mut cursor)?;
+
+        let data_len_offset = Self::LEN + node_len as usize;
+        let data_len = input
+            .get(data_len_offset..)
+            .and_then(|mut rest| rest.read_u32::<BigEndian>().ok());
+
+        Ok(Self {
+            signature: input[0],
+            compression,
+            encoding,
+            node_len,
+            data_len,
+        })
+    }
+
+    /// The total length of the document described by this header, if the
+    /// data section length is known.
+    pub fn total_len(&self) -> Option<usize> {
+        self.data_len
+            .map(|data_len| Self::LEN + self.node_len as usize + 4 + data_len as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let data = include_bytes!("../../testcases_out.kbin");
+
+        let header = Header::parse(data).unwrap();
+        assert_eq!(header.compression, CompressionType::Compressed);
+        assert_eq!(header.encoding, EncodingType::SHIFT_JIS);
+        assert_eq!(header.total_len(), Some(data.len()));
+
+        let header = Header::parse(&data[..Header::LEN]).unwrap();
+        assert_eq!(header.data_len, None);
+
+        assert!(Header::parse(b"<?xml version").is_err());
+        assert!(Header::parse(&data[..4]).is_err());
+    }
+}
@@ -0,0 +1,114 @@
+use cbor4ii::core::dec::Decode;
+use cbor4ii::core::enc::Encode;
+use cbor4ii::core::utils::{BufWriter, SliceReader};
+use cbor4ii::core::Value as CborValue;
+use snafu::ResultExt;
+
+use crate::encoding_type::EncodingType;
+use crate::error::KbinError;
+use crate::interchange::{
+    item_to_node, node_to_item, CborDecode, CborEncode, InterchangeError, Item,
+};
+use crate::node::NodeCollection;
+
+fn to_cbor_value(item: Item) -> CborValue {
+    match item {
+        Item::Nil => CborValue::Null,
+        Item::Bool(b) => CborValue::Bool(b),
+        Item::Int(n) => CborValue::Integer(n.into()),
+        Item::UInt(n) => CborValue::Integer(n.into()),
+        Item::F32(n) => CborValue::Float(n.into()),
+        Item::F64(n) => CborValue::Float(n),
+        Item::Str(text) => CborValue::Text(text),
+        Item::Bin(data) => CborValue::Bytes(data),
+        Item::Array(items) => CborValue::Array(items.into_iter().map(to_cbor_value).collect()),
+        Item::Map(fields) => CborValue::Map(
+            fields
+                .into_iter()
+                .map(|(key, item)| (CborValue::Text(key), to_cbor_value(item)))
+                .collect(),
+        ),
+    }
+}
+
+fn from_cbor_value(value: CborValue) -> Result<Item, InterchangeError> {
+    let item = match value {
+        CborValue::Null => Item::Nil,
+        CborValue::Bool(b) => Item::Bool(b),
+        CborValue::Integer(n) if n >= 0 && n <= i128::from(u64::MAX) => Item::UInt(n as u64),
+        CborValue::Integer(n) if n >= i128::from(i64::MIN) && n < 0 => Item::Int(n as i64),
+        CborValue::Integer(_) => {
+            return Err(InterchangeError::UnexpectedItem {
+                expected: "an integer in the range of a 64-bit integer",
+            })
+        },
+        // CBOR does not keep the precision of floats, `item_to_value` narrows
+        // them again using the node type
+        CborValue::Float(n) => Item::F64(n),
+        CborValue::Text(text) => Item::Str(text),
+        CborValue::Bytes(data) => Item::Bin(data),
+        CborValue::Array(values) => Item::Array(
+            values
+                .into_iter()
+                .map(from_cbor_value)
+                .collect::<Result<_, _>>()?,
+        ),
+        CborValue::Map(entries) => Item::Map(
+            entries
+                .into_iter()
+                .map(|(key, value)| match key {
+                    CborValue::Text(key) => Ok((key, from_cbor_value(value)?)),
+                    _ => Err(InterchangeError::UnexpectedItem {
+                        expected: "a string key",
+                    }),
+                })
+                .collect::<Result<_, _>>()?,
+        ),
+        _ => {
+            return Err(InterchangeError::UnexpectedItem {
+                expected: "an untagged value",
+            })
+        },
+    };
+
+    Ok(item)
+}
+
+impl NodeCollection {
+    /// Convert the collection to CBOR, keeping the type of every value.
+    pub fn to_cbor(&self) -> Result<Vec<u8>, KbinError> {
+        let item = node_to_item(&self.as_node()?)?;
+
+        let mut writer = BufWriter::new(Vec::new());
+        to_cbor_value(item)
+            .encode(&mut writer)
+            .context(CborEncode)?;
+
+        Ok(writer.into_inner())
+    }
+
+    /// Read a collection written by `to_cbor`. Strings in the input are
+    /// already decoded, so the collection uses UTF-8.
+    pub fn from_cbor(input: &[u8]) -> Result<Self, KbinError> {
+        let mut reader = SliceReader::new(input);
+        let value = CborValue::decode(&mut reader).context(CborDecode)?;
+        let node = item_to_node(from_cbor_value(value)?)?;
+
+        NodeCollection::from_node_with_encoding(&node, EncodingType::UTF_8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cbor_round_trip() {
+        let (collection, _) =
+            crate::from_binary(include_bytes!("../../../testcases_out.kbin")[..].into()).unwrap();
+
+        let output = collection.to_cbor().unwrap();
+        let decoded = NodeCollection::from_cbor(&output).unwrap();
+        assert_eq!(decoded.as_node().unwrap(), collection.as_node().unwrap());
+    }
+}
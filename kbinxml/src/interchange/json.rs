@@ -0,0 +1,131 @@
+use serde_json::{Map, Number, Value as JsonValue};
+
+use crate::error::KbinError;
+use crate::interchange::{item_to_value, value_to_item, Item};
+use crate::node_types::StandardType;
+use crate::value::Value;
+
+/// Convert a float through its shortest text, so `150.1f32` becomes
+/// `150.1` rather than the exact value of the nearest double.
+fn float_to_json(n: f32) -> JsonValue {
+    n.to_string()
+        .parse()
+        .ok()
+        .and_then(Number::from_f64)
+        .map_or(JsonValue::Null, JsonValue::Number)
+}
+
+fn to_json_value(item: Item) -> JsonValue {
+    match item {
+        Item::Nil => JsonValue::Null,
+        Item::Bool(b) => JsonValue::Bool(b),
+        Item::Int(n) => JsonValue::from(n),
+        Item::UInt(n) => JsonValue::from(n),
+        Item::F32(n) => float_to_json(n),
+        Item::F64(n) => Number::from_f64(n).map_or(JsonValue::Null, JsonValue::Number),
+        Item::Str(text) => JsonValue::String(text),
+        Item::Bin(data) => JsonValue::String(Value::Binary(data).to_string()),
+        Item::Array(items) => JsonValue::Array(items.into_iter().map(to_json_value).collect()),
+        Item::Map(fields) => JsonValue::Object(
+            fields
+                .into_iter()
+                .map(|(key, item)| (key, to_json_value(item)))
+                .collect::<Map<_, _>>(),
+        ),
+    }
+}
+
+fn from_json_value(value: &JsonValue) -> Item {
+    match value {
+        JsonValue::Null => Item::Nil,
+        JsonValue::Bool(b) => Item::Bool(*b),
+        JsonValue::Number(n) => match (n.as_u64(), n.as_i64(), n.as_f64()) {
+            (Some(n), _, _) => Item::UInt(n),
+            (None, Some(n), _) => Item::Int(n),
+            (None, None, n) => Item::F64(n.unwrap_or(f64::NAN)),
+        },
+        JsonValue::String(text) => Item::Str(text.clone()),
+        JsonValue::Array(values) => Item::Array(values.iter().map(from_json_value).collect()),
+        JsonValue::Object(fields) => Item::Map(
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), from_json_value(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// Numbers, booleans and strings become their JSON counterparts, tuples are
+/// arrays of numbers or booleans and arrays are arrays of elements. Binary
+/// data is a hex string and `ip4` addresses are dotted strings, as in text
+/// XML. Floats that are not finite become `null`.
+impl From<Value> for JsonValue {
+    fn from(value: Value) -> JsonValue {
+        // Only strings and binary data fail to convert to scalars, and they
+        // are converted before
+        match value_to_item(&value) {
+            Ok(item) => to_json_value(item),
+            Err(_) => JsonValue::Null,
+        }
+    }
+}
+
+impl Value {
+    /// Read a value of `node_type` from the JSON produced by converting a
+    /// `Value` to a `serde_json::Value`. Arrays of values and tuples are
+    /// told apart by their nesting, so `[1, 2]` is an array of two `u8`
+    /// values but a single `2u8` value.
+    pub fn from_json(node_type: StandardType, json: &JsonValue) -> Result<Value, KbinError> {
+        match (node_type, json) {
+            (StandardType::Binary, JsonValue::String(text)) => {
+                Value::from_string(node_type, text, false, 0)
+            },
+            (node_type, json) => item_to_value(node_type, None, from_json_value(json)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    use crate::value::ValueArray;
+
+    #[test]
+    fn test_json_values() {
+        let values = [
+            (Value::U8(7), json!(7)),
+            (Value::S64(-2), json!(-2)),
+            (Value::Float(150.1), json!(150.1)),
+            (Value::Boolean(true), json!(true)),
+            (Value::String("name".into()), json!("name")),
+            (Value::Ip4([127, 0, 0, 1].into()), json!("127.0.0.1")),
+            (Value::Binary(vec![0, 255]), json!("00ff")),
+            (Value::S16_3([-1, 0, 1]), json!([-1, 0, 1])),
+            (
+                Value::Array(ValueArray::U8_2(vec![[1, 2], [3, 4]])),
+                json!([[1, 2], [3, 4]]),
+            ),
+            (
+                Value::Array(ValueArray::Float(vec![0.5, 1.25])),
+                json!([0.5, 1.25]),
+            ),
+        ];
+
+        for (value, json) in values.iter() {
+            assert_eq!(JsonValue::from(value.clone()), *json);
+
+            let node_type = match value {
+                Value::Array(values) => values.standard_type(),
+                value => value.standard_type(),
+            };
+            assert_eq!(Value::from_json(node_type, json).unwrap(), *value);
+        }
+
+        assert_eq!(JsonValue::from(Value::Double(f64::NAN)), JsonValue::Null);
+        assert!(Value::from_json(StandardType::U8, &json!(256)).is_err());
+        assert!(Value::from_json(StandardType::U8_2, &json!([1, 2, 3])).is_err());
+    }
+}
@@ -0,0 +1,408 @@
+//! Conversion of node collections to and from MessagePack and CBOR.
+//!
+//! Every node becomes a map with these keys, in this order:
+//!
+//! - `name`: the node name
+//! - `type`: the kbin type name of the value, such as `s32` or `3u8`, only
+//!   present for nodes with a value
+//! - `type_id`: the raw type byte, only present for unknown types
+//! - `value`: the value, only present for nodes with a value
+//! - `attrs`: a map of attribute names to values, only present if the node
+//!   has attributes
+//! - `children`: an array of child nodes, only present if the node has
+//!   children
+//!
+//! Numbers, booleans, strings, and binary data use the native types of the
+//! format. Tuples are arrays of numbers or booleans, arrays are arrays of
+//! elements, and `ip4` addresses are strings. Values are never converted to
+//! text, so floats and binary data round trip exactly.
+//!
+//! Single values also convert to and from `serde_json::Value` the same way,
+//! except that binary data is a hex string.
+
+use std::net::Ipv4Addr;
+
+use snafu::{OptionExt, ResultExt, Snafu};
+
+use crate::error::KbinError;
+use crate::node::Node;
+use crate::node_types::{StandardType, UnknownKbinType};
+use crate::value::Value;
+
+#[cfg(feature = "cbor4ii")]
+mod cbor;
+#[cfg(feature = "serde_json")]
+mod json;
+#[cfg(feature = "rmpv")]
+mod msgpack;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
+pub enum InterchangeError {
+    #[cfg(feature = "rmpv")]
+    #[snafu(display("Failed to write MessagePack"))]
+    MsgpackEncode { source: rmpv::encode::Error },
+
+    #[cfg(feature = "rmpv")]
+    #[snafu(display("Failed to read MessagePack"))]
+    MsgpackDecode { source: rmpv::decode::Error },
+
+    #[cfg(feature = "cbor4ii")]
+    #[snafu(display("Failed to write CBOR"))]
+    CborEncode {
+        source: cbor4ii::core::error::EncodeError<std::collections::TryReserveError>,
+    },
+
+    #[cfg(feature = "cbor4ii")]
+    #[snafu(display("Failed to read CBOR"))]
+    CborDecode {
+        source: cbor4ii::core::error::DecodeError<std::convert::Infallible>,
+    },
+
+    #[snafu(display("Unsupported item in input, expected {}", expected))]
+    UnexpectedItem { expected: &'static str },
+
+    #[snafu(display("Node is missing the `{}` field", field))]
+    MissingField { field: &'static str },
+
+    #[snafu(display("Invalid node type in input"))]
+    InvalidType { source: UnknownKbinType },
+
+    #[snafu(display("Invalid value for node type {}", node_type))]
+    InvalidValue { node_type: StandardType },
+}
+
+/// The data model shared by both formats.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Item {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    F32(f32),
+    F64(f64),
+    Str(String),
+    Bin(Vec<u8>),
+    Array(Vec<Item>),
+    Map(Vec<(String, Item)>),
+}
+
+fn scalar_to_item(value: &Value) -> Result<Item, KbinError> {
+    let item = match *value {
+        Value::S8(n) => Item::Int(n.into()),
+        Value::S16(n) => Item::Int(n.into()),
+        Value::S32(n) => Item::Int(n.into()),
+        Value::S64(n) => Item::Int(n),
+        Value::U8(n) => Item::UInt(n.into()),
+        Value::U16(n) => Item::UInt(n.into()),
+        Value::U32(n) | Value::Time(n) => Item::UInt(n.into()),
+        Value::U64(n) => Item::UInt(n),
+        Value::Float(n) => Item::F32(n),
+        Value::Double(n) => Item::F64(n),
+        Value::Boolean(b) => Item::Bool(b),
+        Value::Ip4(addr) => Item::Str(addr.to_string()),
+        ref value => {
+            return Err(KbinError::InvalidNodeType {
+                node_type: value.standard_type(),
+            })
+        },
+    };
+
+    Ok(item)
+}
+
+/// Convert a scalar or tuple value, splitting tuples into their elements.
+fn element_to_item(value: &Value) -> Result<Item, KbinError> {
+    let node_type = value.standard_type();
+    if node_type.count == 1 {
+        return scalar_to_item(value);
+    }
+
+    let element_type = node_type.element_type();
+    let bytes = value.to_bytes()?;
+    let items = bytes
+        .chunks(element_type.size)
+        .map(|chunk| {
+            Value::from_standard_type(element_type, false, chunk)?
+                .ok_or(KbinError::InvalidState)
+                .and_then(|value| scalar_to_item(&value))
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(Item::Array(items))
+}
+
+fn value_to_item(value: &Value) -> Result<Item, KbinError> {
+    let item = match value {
+        Value::String(text) | Value::Attribute(text) => Item::Str(text.clone()),
+        Value::Binary(data) | Value::Unknown { data, .. } => Item::Bin(data.clone()),
+        Value::Array(values) => {
+            let items = (0..values.len())
+                .filter_map(|index| values.get(index))
+                .map(|value| element_to_item(&value))
+                .collect::<Result<_, _>>()?;
+
+            Item::Array(items)
+        },
+        value => element_to_item(value)?,
+    };
+
+    Ok(item)
+}
+
+#[cfg_attr(not(any(feature = "rmpv", feature = "cbor4ii")), allow(dead_code))]
+pub(crate) fn node_to_item(node: &Node) -> Result<Item, KbinError> {
+    let mut fields = vec![("name".to_owned(), Item::Str(node.key().to_owned()))];
+
+    if let Some(value) = node.value() {
+        let node_type = match value {
+            Value::Array(values) => values.standard_type(),
+            value => value.standard_type(),
+        };
+        fields.push(("type".to_owned(), Item::Str(node_type.name.to_owned())));
+
+        if let Value::Unknown { type_id, .. } = value {
+            fields.push(("type_id".to_owned(), Item::UInt((*type_id).into())));
+        }
+
+        fields.push(("value".to_owned(), value_to_item(value)?));
+    }
+
+    if let Some(attrs) = node.attributes().filter(|attrs| !attrs.is_empty()) {
+        let attrs = attrs
+            .iter()
+            .map(|(key, value)| (key.clone(), Item::Str(value.clone())))
+            .collect();
+        fields.push(("attrs".to_owned(), Item::Map(attrs)));
+    }
+
+    if let Some(children) = node.children().filter(|children| !children.is_empty()) {
+        let children = children
+            .iter()
+            .map(node_to_item)
+            .collect::<Result<_, _>>()?;
+        fields.push(("children".to_owned(), Item::Array(children)));
+    }
+
+    Ok(Item::Map(fields))
+}
+
+fn item_to_scalar(node_type: StandardType, item: Item) -> Result<Value, KbinError> {
+    let value = match item {
+        Item::Int(n) => Value::S64(n),
+        Item::UInt(n) => Value::U64(n),
+        Item::F32(n) => Value::Float(n),
+        Item::F64(n) => Value::Double(n),
+        Item::Bool(b) => Value::Boolean(b),
+        Item::Str(text) if node_type == StandardType::Ip4 => {
+            let addr = text
+                .parse::<Ipv4Addr>()
+                .ok()
+                .context(InvalidValue { node_type })?;
+
+            return Ok(Value::Ip4(addr));
+        },
+        _ => return Err(InterchangeError::InvalidValue { node_type }.into()),
+    };
+
+    value.retype(node_type)
+}
+
+fn item_to_tuple(node_type: StandardType, item: Item) -> Result<Value, KbinError> {
+    let items = match item {
+        Item::Array(items) if items.len() == node_type.count => items,
+        _ => return Err(InterchangeError::InvalidValue { node_type }.into()),
+    };
+
+    let element_type = node_type.element_type();
+    let mut bytes = Vec::with_capacity(node_type.size * node_type.count);
+    for item in items {
+        item_to_scalar(element_type, item)?.to_bytes_into(&mut bytes)?;
+    }
+
+    Value::from_standard_type(node_type, false, &bytes)?.ok_or(KbinError::InvalidState)
+}
+
+fn item_to_element(node_type: StandardType, item: Item) -> Result<Value, KbinError> {
+    if node_type.count == 1 {
+        item_to_scalar(node_type, item)
+    } else {
+        item_to_tuple(node_type, item)
+    }
+}
+
+/// Whether `items` is the value of a single tuple rather than an array.
+fn is_tuple(node_type: StandardType, items: &[Item]) -> bool {
+    node_type.count > 1 &&
+        items.len() == node_type.count &&
+        items.iter().all(|item| !matches!(item, Item::Array(_)))
+}
+
+fn item_to_value(
+    node_type: StandardType,
+    type_id: Option<u8>,
+    item: Item,
+) -> Result<Value, KbinError> {
+    let value = match (node_type, item) {
+        (StandardType::String, Item::Str(text)) => Value::String(text),
+        (StandardType::Attribute, Item::Str(text)) => Value::Attribute(text),
+        (StandardType::Binary, Item::Bin(data)) => Value::Binary(data),
+        (StandardType::Unknown, Item::Bin(data)) => Value::Unknown {
+            type_id: type_id.context(MissingField { field: "type_id" })?,
+            data,
+        },
+        (StandardType::NodeStart, _) | (StandardType::NodeEnd, _) | (StandardType::FileEnd, _) => {
+            return Err(KbinError::InvalidNodeType { node_type })
+        },
+        (_, Item::Array(items)) if !is_tuple(node_type, &items) => {
+            let values = items
+                .into_iter()
+                .map(|item| item_to_element(node_type, item))
+                .collect::<Result<_, _>>()?;
+
+            Value::array(node_type, values)?
+        },
+        (_, item) => item_to_element(node_type, item)?,
+    };
+
+    Ok(value)
+}
+
+#[cfg_attr(not(any(feature = "rmpv", feature = "cbor4ii")), allow(dead_code))]
+fn item_to_text(item: Item) -> Result<String, InterchangeError> {
+    match item {
+        Item::Str(text) => Ok(text),
+        _ => Err(InterchangeError::UnexpectedItem {
+            expected: "a string",
+        }),
+    }
+}
+
+#[cfg_attr(not(any(feature = "rmpv", feature = "cbor4ii")), allow(dead_code))]
+pub(crate) fn item_to_node(item: Item) -> Result<Node, KbinError> {
+    let fields = match item {
+        Item::Map(fields) => fields,
+        _ => {
+            return Err(InterchangeError::UnexpectedItem {
+                expected: "a node map",
+            }
+            .into())
+        },
+    };
+
+    let mut name = None;
+    let mut node_type = None;
+    let mut type_id = None;
+    let mut value = None;
+    let mut attrs = Vec::new();
+    let mut children = Vec::new();
+
+    for (key, item) in fields {
+        match (key.as_str(), item) {
+            ("name", item) => name = Some(item_to_text(item)?),
+            ("type", item) => {
+                let name = item_to_text(item)?;
+                node_type = Some(StandardType::from_name(&name).context(InvalidType)?);
+            },
+            ("type_id", Item::UInt(id)) if id <= u64::from(u8::MAX) => type_id = Some(id as u8),
+            ("value", item) => value = Some(item),
+            ("attrs", Item::Map(items)) => attrs = items,
+            ("children", Item::Array(items)) => children = items,
+            _ => {
+                return Err(InterchangeError::UnexpectedItem {
+                    expected: "a node field",
+                }
+                .into())
+            },
+        };
+    }
+
+    let mut node = Node::new(name.context(MissingField { field: "name" })?);
+
+    match (node_type, value) {
+        (Some(node_type), Some(item)) => {
+            node.set_value(Some(item_to_value(node_type, type_id, item)?));
+        },
+        (None, None) => {},
+        (None, Some(_)) => return Err(InterchangeError::MissingField { field: "type" }.into()),
+        (Some(_), None) => return Err(InterchangeError::MissingField { field: "value" }.into()),
+    };
+
+    for (key, item) in attrs {
+        node.set_attr(key, item_to_text(item)?);
+    }
+
+    for item in children {
+        node.append_child(item_to_node(item)?);
+    }
+
+    Ok(node)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::value::ValueArray;
+
+    #[test]
+    fn test_item_round_trip() {
+        let mut node = Node::with_attrs("music", &[("id", "1")]);
+        node.append_child(Node::with_value("title", Value::String("name".into())));
+        node.append_child(Node::with_value("bpm", Value::Float(150.1)));
+        node.append_child(Node::with_value("pos", Value::S16_3([-1, 0, 1])));
+        node.append_child(Node::with_value("vec", Value::Vs16([-1; 8])));
+        node.append_child(Node::with_value("mask", Value::Vb([true; 16])));
+        node.append_child(Node::with_value(
+            "ids",
+            Value::Array(ValueArray::U8_2(vec![[1, 2], [3, 4]])),
+        ));
+        node.append_child(Node::with_value(
+            "flags",
+            Value::Array(ValueArray::Boolean(vec![true, false])),
+        ));
+        node.append_child(Node::with_value("addr", Value::Ip4([127, 0, 0, 1].into())));
+        node.append_child(Node::with_value("data", Value::Binary(vec![0, 255])));
+        node.append_child(Node::with_value(
+            "raw",
+            Value::Unknown {
+                type_id: 60,
+                data: vec![1, 2, 3],
+            },
+        ));
+
+        let item = node_to_item(&node).unwrap();
+        assert_eq!(item_to_node(item).unwrap(), node);
+
+        let item = Item::Map(vec![
+            ("name".into(), Item::Str("a".into())),
+            ("type".into(), Item::Str("u8".into())),
+            ("value".into(), Item::UInt(256)),
+        ]);
+        assert!(item_to_node(item).is_err());
+    }
+
+    #[test]
+    fn test_attribute_and_child_with_same_name() {
+        let mut node = Node::with_attrs("music", &[("id", "1")]);
+        node.append_child(Node::with_value("id", Value::S32(2)));
+
+        let item = node_to_item(&node).unwrap();
+        let fields = match &item {
+            Item::Map(fields) => fields,
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            fields[1],
+            (
+                "attrs".to_owned(),
+                Item::Map(vec![("id".to_owned(), Item::Str("1".into()))])
+            )
+        );
+
+        let decoded = item_to_node(item).unwrap();
+        assert_eq!(decoded.attr("id"), Some("1"));
+        assert_eq!(decoded.child_value_as::<i32>("id").unwrap(), 2);
+        assert_eq!(decoded, node);
+    }
+}
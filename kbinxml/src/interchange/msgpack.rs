@@ -0,0 +1,120 @@
+use rmpv::Value as MsgpackValue;
+use snafu::ResultExt;
+
+use crate::encoding_type::EncodingType;
+use crate::error::KbinError;
+use crate::interchange::{
+    item_to_node, node_to_item, InterchangeError, Item, MsgpackDecode, MsgpackEncode,
+};
+use crate::node::NodeCollection;
+
+fn to_msgpack_value(item: Item) -> MsgpackValue {
+    match item {
+        Item::Nil => MsgpackValue::Nil,
+        Item::Bool(b) => MsgpackValue::Boolean(b),
+        Item::Int(n) => MsgpackValue::from(n),
+        Item::UInt(n) => MsgpackValue::from(n),
+        Item::F32(n) => MsgpackValue::F32(n),
+        Item::F64(n) => MsgpackValue::F64(n),
+        Item::Str(text) => MsgpackValue::from(text),
+        Item::Bin(data) => MsgpackValue::Binary(data),
+        Item::Array(items) => {
+            MsgpackValue::Array(items.into_iter().map(to_msgpack_value).collect())
+        },
+        Item::Map(fields) => MsgpackValue::Map(
+            fields
+                .into_iter()
+                .map(|(key, item)| (MsgpackValue::from(key), to_msgpack_value(item)))
+                .collect(),
+        ),
+    }
+}
+
+fn from_msgpack_value(value: MsgpackValue) -> Result<Item, InterchangeError> {
+    let item = match value {
+        MsgpackValue::Nil => Item::Nil,
+        MsgpackValue::Boolean(b) => Item::Bool(b),
+        MsgpackValue::Integer(n) => match (n.as_u64(), n.as_i64()) {
+            (Some(n), _) => Item::UInt(n),
+            (None, Some(n)) => Item::Int(n),
+            (None, None) => unreachable!("MessagePack integers fit in u64 or i64"),
+        },
+        MsgpackValue::F32(n) => Item::F32(n),
+        MsgpackValue::F64(n) => Item::F64(n),
+        MsgpackValue::String(text) => match text.into_str() {
+            Some(text) => Item::Str(text),
+            None => {
+                return Err(InterchangeError::UnexpectedItem {
+                    expected: "UTF-8 text",
+                })
+            },
+        },
+        MsgpackValue::Binary(data) => Item::Bin(data),
+        MsgpackValue::Array(values) => Item::Array(
+            values
+                .into_iter()
+                .map(from_msgpack_value)
+                .collect::<Result<_, _>>()?,
+        ),
+        MsgpackValue::Map(entries) => Item::Map(
+            entries
+                .into_iter()
+                .map(|(key, value)| match key {
+                    MsgpackValue::String(key) => match key.into_str() {
+                        Some(key) => Ok((key, from_msgpack_value(value)?)),
+                        None => Err(InterchangeError::UnexpectedItem {
+                            expected: "UTF-8 text",
+                        }),
+                    },
+                    _ => Err(InterchangeError::UnexpectedItem {
+                        expected: "a string key",
+                    }),
+                })
+                .collect::<Result<_, _>>()?,
+        ),
+        MsgpackValue::Ext(..) => {
+            return Err(InterchangeError::UnexpectedItem {
+                expected: "a value other than an extension type",
+            })
+        },
+    };
+
+    Ok(item)
+}
+
+impl NodeCollection {
+    /// Convert the collection to MessagePack, keeping the type of every value.
+    pub fn to_msgpack(&self) -> Result<Vec<u8>, KbinError> {
+        let item = node_to_item(&self.as_node()?)?;
+
+        let mut output = Vec::new();
+        rmpv::encode::write_value(&mut output, &to_msgpack_value(item)).context(MsgpackEncode)?;
+
+        Ok(output)
+    }
+
+    /// Read a collection written by `to_msgpack`. Strings in the input are
+    /// already decoded, so the collection uses UTF-8.
+    pub fn from_msgpack(input: &[u8]) -> Result<Self, KbinError> {
+        let mut input = input;
+        let value = rmpv::decode::read_value(&mut input).context(MsgpackDecode)?;
+        let node = item_to_node(from_msgpack_value(value)?)?;
+
+        NodeCollection::from_node_with_encoding(&node, EncodingType::UTF_8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_msgpack_round_trip() {
+        let (collection, _) =
+            crate::from_binary(include_bytes!("../../../testcases_out.kbin")[..].into()).unwrap();
+
+        let output = collection.to_msgpack().unwrap();
+        let decoded = NodeCollection::from_msgpack(&output).unwrap();
+        assert_eq!(decoded.as_node().unwrap(), collection.as_node().unwrap());
+    }
+}
@@ -1,44 +1,88 @@
-#![cfg_attr(test, feature(test))]
+#![cfg_attr(all(test, feature = "nightly"), feature(test))]
 
-#[macro_use]
-extern crate lazy_static;
+// With the `tracing` feature the same logging macros emit `tracing` events,
+// which are recorded inside the spans of the nodes being read or written
+#[cfg(not(feature = "tracing"))]
 #[macro_use]
 extern crate log;
+#[cfg(feature = "tracing")]
+#[macro_use]
+extern crate tracing;
+
+#[cfg(feature = "memory-report")]
+use std::mem;
 
 use bytes::Bytes;
 
+#[cfg(feature = "tokio")]
+mod async_reader;
 mod byte_buffer;
+pub mod bundle;
+pub mod compare;
 mod compression_type;
+mod custom_type;
+mod document;
+#[cfg(feature = "roxmltree")]
+mod dom;
 mod encoding_type;
 mod error;
+pub mod events;
+mod header;
+#[cfg(any(feature = "rmpv", feature = "cbor4ii", feature = "serde_json"))]
+mod interchange;
+pub mod localization;
+#[cfg(feature = "memory-report")]
+mod memory_report;
 mod node;
 mod node_types;
+mod offset_map;
 mod options;
 mod printer;
+mod progress;
 mod reader;
+pub mod search;
 mod sixbit;
 mod text_reader;
 mod to_text_xml;
+pub mod tokens;
 mod types;
 mod value;
 mod writer;
 
 use crate::error::Result;
+use crate::reader::{ProgressReader, ReaderError};
 use crate::text_reader::TextXmlReader;
 use crate::to_text_xml::TextXmlWriter;
 
 // Public exports
+#[cfg(feature = "tokio")]
+pub use crate::async_reader::AsyncReader;
 pub use crate::compression_type::CompressionType;
+pub use crate::custom_type::CustomType;
+pub use crate::document::Document;
 pub use crate::encoding_type::EncodingType;
-pub use crate::error::KbinError;
-pub use crate::node::{Node, NodeCollection};
+pub use crate::error::{ErrorKind, KbinError};
+pub use crate::header::Header;
+#[cfg(feature = "memory-report")]
+pub use crate::memory_report::{HeapSize, MemoryReport};
+pub use crate::node::{
+    apply_patch, sanitize, Change, ChangeLog, ChildrenNamed, Descendants, FlatEntry, Flatten,
+    FromAttrs, IterValues, JournaledNode, MergeStrategy, Node, NodeCollection, NodeId, NodePath,
+    Patch, PatchOp, PathSegment, PatchValue, SanitizeRules, ToAttrs, Violation, ViolationKind,
+};
 pub use crate::node_types::StandardType;
-pub use crate::options::{Options, OptionsBuilder};
+pub use crate::offset_map::{OffsetEntry, OffsetMap};
+pub use crate::options::{
+    Alignment, ArrayCountPolicy, AttributeOrder, Endianness, Limits, OptionalValuePolicy, Options,
+    OptionsBuilder, ReaderOptions, ReaderOptionsBuilder, TextXmlOptions, TextXmlOptionsBuilder,
+    TimeFormat, TypeNameDialect,
+};
 pub use crate::printer::Printer;
-pub use crate::reader::Reader;
-pub use crate::to_text_xml::ToTextXml;
+pub use crate::progress::{Progress, ProgressPhase, ProgressSink};
+pub use crate::reader::{Reader, RecoveredError, RecoveryKind};
+pub use crate::to_text_xml::{TextXmlStreamWriter, ToTextXml};
 pub use crate::value::{Value, ValueArray};
-pub use crate::writer::{Writeable, Writer};
+pub use crate::writer::{StreamWriter, WriteContext, Writeable, Writer};
 
 const SIGNATURE: u8 = 0xA0;
 
@@ -61,6 +105,112 @@ pub fn from_binary(input: Bytes) -> Result<(NodeCollection, EncodingType)> {
     Ok((collection, encoding))
 }
 
+/// Unlike `from_binary`, an error encountered while reading the nodes is
+/// returned rather than ending the document early.
+pub fn from_binary_with_options(
+    options: ReaderOptions,
+    input: Bytes,
+) -> Result<(NodeCollection, EncodingType)> {
+    let deny_multiple_roots = options.deny_multiple_roots;
+    let keep_partial_tree = options.keep_partial_tree;
+    let mut reader = Reader::with_options(input, options)?;
+    let collection = read_binary_root(
+        &mut reader,
+        &mut Progress::none(),
+        deny_multiple_roots,
+        keep_partial_tree,
+    )?;
+    let encoding = reader.encoding();
+
+    Ok((collection, encoding))
+}
+
+/// Like `from_binary_with_options`, also reporting the elements read and the
+/// bytes of the input consumed to `progress`.
+pub fn from_binary_with_progress(
+    options: ReaderOptions,
+    input: Bytes,
+    progress: &mut dyn ProgressSink,
+) -> Result<(NodeCollection, EncodingType)> {
+    let mut progress = Progress::new(progress, ProgressPhase::Read);
+    let (collection, encoding) = read_binary_with_progress(options, input, &mut progress)?;
+
+    Ok((collection, encoding))
+}
+
+fn read_binary_with_progress(
+    options: ReaderOptions,
+    input: Bytes,
+    progress: &mut Progress<'_>,
+) -> Result<(NodeCollection, EncodingType)> {
+    let deny_multiple_roots = options.deny_multiple_roots;
+    let keep_partial_tree = options.keep_partial_tree;
+    let len = input.len();
+    let mut reader = Reader::with_options(input, options)?;
+    let collection =
+        read_binary_root(&mut reader, progress, deny_multiple_roots, keep_partial_tree)?;
+    let encoding = reader.encoding();
+    progress.finish(len);
+
+    Ok((collection, encoding))
+}
+
+/// Read the root node of a binary document, failing on the first error.
+fn read_binary_root(
+    reader: &mut Reader,
+    progress: &mut Progress<'_>,
+    deny_multiple_roots: bool,
+    keep_partial_tree: bool,
+) -> Result<NodeCollection> {
+    let collection = NodeCollection::from_iter(&mut ProgressReader::new(reader, progress));
+    if let Some(e) = reader.take_error() {
+        return Err(match collection {
+            Some(collection) if keep_partial_tree => partial_tree(collection, e.into()),
+            _ => e.into(),
+        });
+    }
+    let collection = collection.ok_or(KbinError::NoNodeCollection)?;
+
+    // The root node is followed by the end of file marker
+    if deny_multiple_roots && reader.read_node_type()?.0 != StandardType::FileEnd {
+        return Err(ReaderError::MultipleRoots.into());
+    }
+    if reader.options().deny_trailing_data {
+        reader.check_trailing_data()?;
+    }
+
+    Ok(collection)
+}
+
+/// Like `from_binary`, also estimating the memory held by the input and the
+/// resulting tree.
+#[cfg(feature = "memory-report")]
+pub fn from_binary_with_report(
+    input: Bytes,
+) -> Result<(NodeCollection, EncodingType, MemoryReport)> {
+    let document = input.len();
+    let (collection, encoding) = from_binary(input)?;
+    let report = MemoryReport {
+        document,
+        buffers: 0,
+        tree: mem::size_of::<NodeCollection>() + collection.heap_size(),
+    };
+
+    Ok((collection, encoding, report))
+}
+
+/// Read as much of a damaged binary document as possible, returning the
+/// errors that were skipped along with the salvaged nodes.
+pub fn from_binary_recovering(
+    input: Bytes,
+) -> Result<(NodeCollection, EncodingType, Vec<RecoveredError>)> {
+    let mut reader = Reader::recovering(input)?;
+    let collection = NodeCollection::from_iter(&mut reader).ok_or(KbinError::NoNodeCollection)?;
+    let encoding = reader.encoding();
+
+    Ok((collection, encoding, reader.take_recovered_errors()))
+}
+
 pub fn from_text_xml(input: &[u8]) -> Result<(NodeCollection, EncodingType)> {
     let mut reader = TextXmlReader::new(input);
     let collection = reader
@@ -71,6 +221,78 @@ pub fn from_text_xml(input: &[u8]) -> Result<(NodeCollection, EncodingType)> {
     Ok((collection, encoding))
 }
 
+pub fn from_text_xml_with_options(
+    options: ReaderOptions,
+    input: &[u8],
+) -> Result<(NodeCollection, EncodingType)> {
+    let keep_partial_tree = options.keep_partial_tree;
+    let mut reader = TextXmlReader::with_options(input, options);
+
+    read_text_xml(&mut reader, keep_partial_tree)
+}
+
+/// Like `from_text_xml_with_options`, also reporting the elements read and
+/// the bytes of the input consumed to `progress`.
+pub fn from_text_xml_with_progress(
+    options: ReaderOptions,
+    input: &[u8],
+    progress: &mut dyn ProgressSink,
+) -> Result<(NodeCollection, EncodingType)> {
+    let keep_partial_tree = options.keep_partial_tree;
+    let mut reader = TextXmlReader::with_options(input, options);
+    reader.set_progress(Progress::new(progress, ProgressPhase::Read));
+    let result = read_text_xml(&mut reader, keep_partial_tree)?;
+    reader.finish_progress();
+
+    Ok(result)
+}
+
+fn read_text_xml(
+    reader: &mut TextXmlReader,
+    keep_partial_tree: bool,
+) -> Result<(NodeCollection, EncodingType)> {
+    let collection = match reader.as_node_collection() {
+        Ok(collection) => collection.ok_or(KbinError::NoNodeCollection)?,
+        Err(e) => {
+            return Err(match reader.take_partial_tree() {
+                Some(collection) if keep_partial_tree => partial_tree(collection, e.into()),
+                _ => e.into(),
+            });
+        },
+    };
+    let encoding = reader.encoding();
+
+    Ok((collection, encoding))
+}
+
+/// Wrap an error with the nodes read before it and the path of the last one
+/// in document order.
+fn partial_tree(collection: NodeCollection, source: KbinError) -> KbinError {
+    let mut indexes = Vec::new();
+    let mut current = &collection;
+    while let Some(child) = current.children().back() {
+        indexes.push(current.children().len() - 1);
+        current = child;
+    }
+    let last_path = NodePath::from_indexes(&collection, &indexes).ok();
+
+    KbinError::PartialTree {
+        collection: Box::new(collection),
+        last_path,
+        source: Box::new(source),
+    }
+}
+
+/// Read every top-level element of a text XML document. Binary XML always has
+/// a single root node, so write each collection separately.
+pub fn from_text_xml_roots(input: &[u8]) -> Result<(Vec<NodeCollection>, EncodingType)> {
+    let mut reader = TextXmlReader::new(input);
+    let collections = reader.as_node_collections()?;
+    let encoding = reader.encoding();
+
+    Ok((collections, encoding))
+}
+
 pub fn from_bytes(input: Bytes) -> Result<(NodeCollection, EncodingType)> {
     if is_binary_xml(&input) {
         from_binary(input)
@@ -100,10 +322,170 @@ where
     writer.to_binary(input).map_err(Into::into)
 }
 
+/// Like `to_binary_with_options`, also reporting the elements written and the
+/// size of the output so far to `progress`.
+pub fn to_binary_with_progress<T>(
+    options: Options,
+    input: &T,
+    progress: &mut dyn ProgressSink,
+) -> Result<Vec<u8>>
+where
+    T: Writeable,
+{
+    let mut writer = Writer::with_options(options);
+    writer
+        .to_binary_with_progress(input, progress)
+        .map_err(Into::into)
+}
+
+/// Like `to_binary_with_options`, also estimating the memory held by the
+/// input tree, the node and data buffers, and the output.
+#[cfg(feature = "memory-report")]
+pub fn to_binary_with_report<T>(options: Options, input: &T) -> Result<(Vec<u8>, MemoryReport)>
+where
+    T: Writeable + HeapSize,
+{
+    let mut writer = Writer::with_options(options);
+    let mut context = WriteContext::new();
+    let output = writer.to_binary_with_context(&mut context, input)?;
+    let report = MemoryReport {
+        document: output.len(),
+        buffers: context.capacity(),
+        tree: mem::size_of::<T>() + input.heap_size(),
+    };
+
+    Ok((output, report))
+}
+
+/// Convert a binary document to UTF-8 text XML, also returning the encoding
+/// of the binary document.
+pub fn binary_to_xml(input: &[u8]) -> Result<(Vec<u8>, EncodingType)> {
+    let (collection, encoding) = from_slice(input)?;
+    let output = to_text_xml(&collection)?;
+
+    Ok((output, encoding))
+}
+
+/// Convert a text XML document to a binary document written with `options`.
+/// Use `Options::with_encoding` with the encoding returned by `binary_to_xml`
+/// to convert a document back to the encoding it was read in.
+pub fn xml_to_binary(input: &[u8], options: Options) -> Result<Vec<u8>> {
+    let (collection, _) = from_text_xml(input)?;
+
+    to_binary_with_options(options, &collection)
+}
+
+/// Like `binary_to_xml`, reporting the read phase and then the write phase to
+/// `progress`.
+pub fn binary_to_xml_with_progress(
+    input: &[u8],
+    progress: &mut dyn ProgressSink,
+) -> Result<(Vec<u8>, EncodingType)> {
+    let mut progress = Progress::new(progress, ProgressPhase::Read);
+    let input = Bytes::from(input.to_vec());
+    let (collection, encoding) =
+        read_binary_with_progress(ReaderOptions::default(), input, &mut progress)?;
+
+    let mut progress = progress.into_phase(ProgressPhase::Write);
+    let mut writer = TextXmlStreamWriter::new(&collection, Vec::new())?;
+    while !writer.write_nodes(progress::REPORT_INTERVAL)? {
+        let written = writer.written();
+        progress.report(written, writer.get_mut().len());
+    }
+    let written = writer.written();
+    let output = writer.finish()?;
+    progress.report(written, output.len());
+
+    Ok((output, encoding))
+}
+
+/// Like `xml_to_binary`, reporting the read phase and then the write phase to
+/// `progress`.
+pub fn xml_to_binary_with_progress(
+    input: &[u8],
+    options: Options,
+    progress: &mut dyn ProgressSink,
+) -> Result<Vec<u8>> {
+    let mut reader = TextXmlReader::new(input);
+    reader.set_progress(Progress::new(progress, ProgressPhase::Read));
+    let (collection, _) = read_text_xml(&mut reader, false)?;
+    let mut progress = reader.finish_progress().into_phase(ProgressPhase::Write);
+
+    let mut writer = Writer::with_options(options);
+    let mut context = WriteContext::new();
+    let output = writer.write_with_context(&mut context, &collection, &mut progress)?;
+    progress.finish(output.len());
+
+    Ok(output)
+}
+
 pub fn to_text_xml<T>(input: &T) -> Result<Vec<u8>>
 where
     T: ToTextXml,
 {
     let writer = TextXmlWriter::new();
-    writer.to_text_xml(input)
+    writer.into_text_xml(input)
+}
+
+pub fn to_text_xml_with_options<T>(options: TextXmlOptions, input: &T) -> Result<Vec<u8>>
+where
+    T: ToTextXml,
+{
+    let writer = TextXmlWriter::with_options(options);
+    writer.into_text_xml(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{EncodingType, Node, Options, ProgressPhase, ReaderOptions, Value};
+
+    #[test]
+    fn test_conversion() {
+        let node = Node::with_nodes(
+            "root",
+            vec![Node::with_value("name", Value::String("テスト".into()))],
+        );
+        let options = Options::with_encoding(EncodingType::SHIFT_JIS);
+        let data = crate::to_binary_with_options(options.clone(), &node).unwrap();
+
+        let (text, encoding) = crate::binary_to_xml(&data).unwrap();
+        assert_eq!(encoding, EncodingType::SHIFT_JIS);
+        assert!(text.starts_with(b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>"));
+        assert_eq!(crate::xml_to_binary(&text, options).unwrap(), data);
+    }
+
+    #[test]
+    fn test_progress() {
+        let children = (0..2000).map(|i| Node::with_value("i", Value::U16(i)));
+        let node = Node::with_nodes("root", children.collect::<Vec<_>>());
+
+        let mut reports = Vec::new();
+        let mut sink = |phase, items, bytes| reports.push((phase, items, bytes));
+        let data = crate::to_binary_with_progress(Options::default(), &node, &mut sink).unwrap();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].1, 1024);
+        assert_eq!(reports[1], (ProgressPhase::Write, 2001, data.len()));
+
+        let mut reports = Vec::new();
+        let mut sink = |phase, items, bytes| reports.push((phase, items, bytes));
+        let (text, _) = crate::binary_to_xml_with_progress(&data, &mut sink).unwrap();
+        assert_eq!(reports.len(), 4);
+        assert_eq!(reports[1], (ProgressPhase::Read, 2001, data.len()));
+        assert_eq!(reports[3], (ProgressPhase::Write, 2001, text.len()));
+        assert!(reports[0].2 < data.len() && reports[2].2 < text.len());
+
+        let mut reports = Vec::new();
+        let mut sink = |phase, items, bytes| reports.push((phase, items, bytes));
+        let options = ReaderOptions::default();
+        let result = crate::from_text_xml_with_progress(options, &text, &mut sink);
+        let (collection, _) = result.unwrap();
+        assert_eq!(collection.children().len(), 2000);
+        assert_eq!(reports.last(), Some(&(ProgressPhase::Read, 2001, text.len())));
+
+        let mut count = 0;
+        let mut sink = |_, _, _| count += 1;
+        let binary = crate::xml_to_binary_with_progress(&text, Options::default(), &mut sink);
+        assert_eq!(binary.unwrap(), data);
+        assert_eq!(count, 4);
+    }
 }
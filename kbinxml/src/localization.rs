@@ -0,0 +1,177 @@
+//! Extract the string values of a document into a flat table for translation
+//! and apply an edited table back.
+//!
+//! Both operate on a `NodeCollection`, so every node other than the edited
+//! strings keeps its original bytes when the collection is written again.
+//! Entries are addressed with the same paths used by `search`.
+
+use bytes::Bytes;
+
+use crate::error::KbinError;
+use crate::node::{NodeCollection, NodeData};
+use crate::node_types::StandardType;
+use crate::value::Value;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub path: String,
+    pub text: String,
+}
+
+/// Extract every `String` value in document order.
+pub fn extract(collection: &NodeCollection) -> Result<Vec<Entry>, KbinError> {
+    collection
+        .filter_by_type(StandardType::String)
+        .map(|item| {
+            let (path, definition) = item?;
+            let text = definition.value()?.as_string()?;
+
+            Ok(Entry { path, text })
+        })
+        .collect()
+}
+
+/// Replace the `String` values named by `entries`, returning the number of
+/// values that changed. Entries whose text is unchanged leave the original
+/// bytes untouched. Nothing is changed if any entry is invalid.
+pub fn apply(collection: &mut NodeCollection, entries: &[Entry]) -> Result<usize, KbinError> {
+    // Validate every entry before changing anything
+    let mut updates = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let target = find(collection, &entry.path)?;
+        let definition = target.base();
+        if definition.node_type != StandardType::String {
+            return Err(KbinError::TypeMismatch {
+                expected: StandardType::String,
+                found: definition.node_type,
+            });
+        }
+
+        if definition.value()? != Value::String(entry.text.clone()) {
+            let data = definition.encoding().encode_bytes(&entry.text)?;
+            updates.push((entry.path.as_str(), Bytes::from(data)));
+        }
+    }
+
+    let count = updates.len();
+    for (path, data) in updates {
+        let target = find_mut(collection, path)?;
+        if let NodeData::Some {
+            ref mut value_data, ..
+        } = target.base_mut().data_mut()
+        {
+            *value_data = data;
+        }
+    }
+
+    Ok(count)
+}
+
+fn invalid_path(path: &str) -> KbinError {
+    KbinError::InvalidNodePath {
+        path: path.to_owned(),
+    }
+}
+
+fn find<'a>(collection: &'a NodeCollection, path: &str) -> Result<&'a NodeCollection, KbinError> {
+    collection
+        .get_path(&path.parse()?)
+        .ok_or_else(|| invalid_path(path))
+}
+
+fn find_mut<'a>(
+    collection: &'a mut NodeCollection,
+    path: &str,
+) -> Result<&'a mut NodeCollection, KbinError> {
+    collection
+        .get_path_mut(&path.parse()?)
+        .ok_or_else(|| invalid_path(path))
+}
+
+/// Write entries as tab separated `path` and `text` lines. Tabs, newlines,
+/// and backslashes in the text are escaped with a backslash.
+pub fn to_tsv(entries: &[Entry]) -> String {
+    let mut output = String::new();
+    for entry in entries {
+        output.push_str(&entry.path);
+        output.push('\t');
+        for c in entry.text.chars() {
+            match c {
+                '\t' => output.push_str("\\t"),
+                '\n' => output.push_str("\\n"),
+                '\r' => output.push_str("\\r"),
+                '\\' => output.push_str("\\\\"),
+                c => output.push(c),
+            };
+        }
+        output.push('\n');
+    }
+
+    output
+}
+
+/// Parse the format written by `to_tsv`. Empty lines are ignored.
+pub fn from_tsv(input: &str) -> Result<Vec<Entry>, KbinError> {
+    let mut entries = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+
+        let invalid_line = || KbinError::InvalidLocalizationLine { line: i + 1 };
+        let (path, escaped) = line.split_once('\t').ok_or_else(invalid_line)?;
+
+        let mut text = String::with_capacity(escaped.len());
+        let mut chars = escaped.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                text.push(c);
+                continue;
+            }
+
+            match chars.next() {
+                Some('t') => text.push('\t'),
+                Some('n') => text.push('\n'),
+                Some('r') => text.push('\r'),
+                Some('\\') => text.push('\\'),
+                _ => return Err(invalid_line()),
+            };
+        }
+
+        entries.push(Entry {
+            path: path.to_owned(),
+            text,
+        });
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_apply() {
+        let data = include_bytes!("../../testcases_out.kbin");
+        let (mut collection, _) = crate::from_slice(data).unwrap();
+
+        let mut entries = extract(&collection).unwrap();
+        assert!(!entries.is_empty());
+        assert_eq!(from_tsv(&to_tsv(&entries)).unwrap(), entries);
+
+        assert_eq!(apply(&mut collection, &entries).unwrap(), 0);
+        assert_eq!(crate::to_binary(&collection).unwrap(), &data[..]);
+
+        entries[0].text = "翻訳\tされた".to_owned();
+        let table = from_tsv(&to_tsv(&entries[..1])).unwrap();
+        assert_eq!(apply(&mut collection, &table).unwrap(), 1);
+        assert_eq!(extract(&collection).unwrap(), entries);
+
+        let invalid = Entry {
+            path: "/missing".to_owned(),
+            text: String::new(),
+        };
+        assert!(apply(&mut collection, &[invalid]).is_err());
+    }
+}
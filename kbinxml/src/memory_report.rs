@@ -0,0 +1,152 @@
+//! Size estimates for the memory held while reading and writing binary
+//! documents, for capacity planning. Enabled with the `memory-report`
+//! feature.
+//!
+//! The sizes are estimates computed from the lengths and capacities of the
+//! buffers involved, not measurements from the allocator. Allocator overhead
+//! and the stack are not included.
+
+use std::mem;
+
+use crate::node::{Key, Node, NodeCollection, NodeData, NodeDefinition};
+use crate::value::Value;
+
+/// Estimated sizes of the buffers alive at the end of each phase of reading
+/// or writing a binary document. Created by `from_binary_with_report` and
+/// `to_binary_with_report`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryReport {
+    /// Length of the binary document, the input when reading and the output
+    /// when writing
+    pub document: usize,
+
+    /// Capacity of the node and data buffers filled while writing. Reading
+    /// slices values out of the document instead, so this is `0` there.
+    pub buffers: usize,
+
+    /// Estimated heap size of the node tree, the result when reading and the
+    /// input when writing. Key and value bytes are counted even if they are
+    /// shared with the document.
+    pub tree: usize,
+}
+
+impl MemoryReport {
+    /// Upper bound for the peak heap usage. The document, the buffers, and
+    /// the tree are all alive at once when the document is assembled.
+    #[inline]
+    pub fn peak(&self) -> usize {
+        self.document + self.buffers + self.tree
+    }
+}
+
+/// Estimate of the heap memory owned by a value, not including the size of
+/// the value itself.
+pub trait HeapSize {
+    fn heap_size(&self) -> usize;
+}
+
+impl HeapSize for NodeDefinition {
+    fn heap_size(&self) -> usize {
+        match self.data() {
+            NodeData::Some { key, value_data } => {
+                let key = match key {
+                    Key::Compressed { data, .. } | Key::Uncompressed { data, .. } => data.len(),
+                };
+
+                key + value_data.len()
+            },
+            NodeData::Comment(text) | NodeData::ProcessingInstruction(text) => text.capacity(),
+            NodeData::None => 0,
+        }
+    }
+}
+
+impl HeapSize for NodeCollection {
+    fn heap_size(&self) -> usize {
+        let attributes = self.attributes();
+        let children = self.children();
+
+        attributes.capacity() * mem::size_of::<NodeDefinition>() +
+            children.capacity() * mem::size_of::<NodeCollection>() +
+            self.base().heap_size() +
+            attributes.iter().map(HeapSize::heap_size).sum::<usize>() +
+            children.iter().map(HeapSize::heap_size).sum::<usize>()
+    }
+}
+
+impl HeapSize for Value {
+    fn heap_size(&self) -> usize {
+        match self {
+            Value::String(s) | Value::Attribute(s) => s.capacity(),
+            Value::Binary(data) | Value::Unknown { data, .. } => data.capacity(),
+            Value::Array(values) => {
+                let node_type = values.standard_type();
+                values.len() * node_type.size * node_type.count
+            },
+            _ => 0,
+        }
+    }
+}
+
+impl HeapSize for Node {
+    fn heap_size(&self) -> usize {
+        let mut size = self.key().len() +
+            self.value().map_or(0, HeapSize::heap_size) +
+            self.namespace().map_or(0, str::len);
+
+        if let Some(attributes) = self.attributes() {
+            // Each entry stores its hash next to the key and value, and the
+            // hash table stores the index of the entry
+            let entry = 2 * mem::size_of::<String>() + 2 * mem::size_of::<usize>();
+            size += attributes.capacity() * entry;
+            size += attributes
+                .iter()
+                .map(|(key, value)| key.capacity() + value.capacity())
+                .sum::<usize>();
+        }
+
+        if let Some(children) = self.children() {
+            size += children.capacity() * mem::size_of::<Node>();
+            size += children.iter().map(HeapSize::heap_size).sum::<usize>();
+        }
+
+        size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::node::{Node, NodeCollection};
+    use crate::options::Options;
+    use crate::value::Value;
+
+    use super::HeapSize;
+
+    #[test]
+    fn test_memory_report() {
+        let node = Node::with_nodes(
+            "root",
+            (0..100)
+                .map(|i| Node::with_attrs_value("song", &[("id", "1")], Value::U32(i)))
+                .collect::<Vec<_>>(),
+        );
+
+        let (binary, written) = crate::to_binary_with_report(Options::default(), &node).unwrap();
+        assert_eq!(written.document, binary.len());
+        assert!(written.buffers >= binary.len() - 12);
+        assert!(written.tree > 100 * std::mem::size_of::<Node>());
+
+        let (collection, _, read) = crate::from_binary_with_report(Bytes::from(binary)).unwrap();
+        assert_eq!(read.document, written.document);
+        assert_eq!(read.buffers, 0);
+        assert!(read.tree > 100 * std::mem::size_of::<NodeCollection>());
+        assert_eq!(read.peak(), read.document + read.tree);
+
+        // Growing the tree grows the estimate
+        let mut larger = collection.clone();
+        larger.children_mut().push_back(collection);
+        assert!(larger.heap_size() > read.tree);
+    }
+}
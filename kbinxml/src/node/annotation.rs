@@ -0,0 +1,136 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::panic::RefUnwindSafe;
+use std::sync::Arc;
+
+use crate::node::Node;
+
+type Annotation = dyn Any + Send + Sync + RefUnwindSafe;
+type AnnotationMap = HashMap<TypeId, Arc<Annotation>>;
+
+fn downcast<T>(annotation: Arc<Annotation>) -> Option<Arc<T>>
+where
+    T: Any + Send + Sync,
+{
+    let annotation: Arc<dyn Any + Send + Sync> = annotation;
+    annotation.downcast().ok()
+}
+
+/// The annotations attached to a node, one per type. Annotations are not
+/// part of the document, so they are ignored when comparing nodes.
+#[derive(Clone, Default)]
+pub(crate) struct Annotations(Option<Box<AnnotationMap>>);
+
+impl PartialEq for Annotations {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Node {
+    /// The annotation of type `T` attached to this node, if any.
+    ///
+    /// Annotations let analysis passes attach computed data such as hashes
+    /// or validation results to the nodes they describe, so the data moves
+    /// with the node when the tree is changed. They are shared by clones of
+    /// a node, but are never written to binary or text XML and are ignored
+    /// when comparing nodes. Annotation types must be `RefUnwindSafe`, so
+    /// nodes can be used across `catch_unwind`.
+    pub fn annotation<T>(&self) -> Option<&T>
+    where
+        T: Any + Send + Sync + RefUnwindSafe,
+    {
+        let annotation: &(dyn Any + Send + Sync) =
+            &**self.annotations.0.as_ref()?.get(&TypeId::of::<T>())?;
+        annotation.downcast_ref()
+    }
+
+    /// Attach an annotation of type `T` to this node, returning the previous
+    /// one of the same type.
+    pub fn set_annotation<T>(&mut self, annotation: T) -> Option<Arc<T>>
+    where
+        T: Any + Send + Sync + RefUnwindSafe,
+    {
+        self.annotations
+            .0
+            .get_or_insert_with(Default::default)
+            .insert(TypeId::of::<T>(), Arc::new(annotation))
+            .and_then(downcast)
+    }
+
+    /// Remove the annotation of type `T` from this node.
+    pub fn remove_annotation<T>(&mut self) -> Option<Arc<T>>
+    where
+        T: Any + Send + Sync + RefUnwindSafe,
+    {
+        self.annotations
+            .0
+            .as_mut()?
+            .remove(&TypeId::of::<T>())
+            .and_then(downcast)
+    }
+
+    /// Remove every annotation from this node and its descendants.
+    pub fn clear_annotations(&mut self) {
+        self.annotations.0 = None;
+
+        for child in self.children_iter_mut() {
+            child.clear_annotations();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{RefUnwindSafe, UnwindSafe};
+
+    use crate::node::{Node, NodeCollection};
+    use crate::value::Value;
+
+    #[derive(Debug, PartialEq)]
+    struct Checked(bool);
+
+    #[test]
+    fn test_annotations() {
+        let mut node = Node::with_nodes("root", vec![Node::with_value("a", Value::U8(1))]);
+        let original = node.clone();
+
+        node.set_annotation(Checked(false));
+        node.set_annotation(7u32);
+        assert_eq!(
+            node.set_annotation(Checked(true)).as_deref(),
+            Some(&Checked(false))
+        );
+        assert_eq!(node.annotation::<Checked>(), Some(&Checked(true)));
+        assert_eq!(node.annotation::<u32>(), Some(&7));
+        assert_eq!(node.annotation::<u8>(), None);
+        assert_eq!(node, original);
+
+        let child = node.children_iter_mut().next().unwrap();
+        child.set_annotation(1u8);
+        let clone = node.clone();
+        assert_eq!(
+            clone.children_iter().next().unwrap().annotation::<u8>(),
+            Some(&1)
+        );
+
+        assert_eq!(node.remove_annotation::<u32>().as_deref(), Some(&7));
+        assert_eq!(node.annotation::<u32>(), None);
+
+        node.clear_annotations();
+        assert_eq!(node.annotation::<Checked>(), None);
+        assert_eq!(
+            node.children_iter().next().unwrap().annotation::<u8>(),
+            None
+        );
+        assert_eq!(clone.annotation::<u32>(), Some(&7));
+    }
+
+    #[test]
+    fn test_unwind_safe() {
+        fn assert_unwind_safe<T: UnwindSafe + RefUnwindSafe>() {}
+
+        assert_unwind_safe::<Node>();
+        assert_unwind_safe::<NodeCollection>();
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Display;
+use std::hash::BuildHasher;
+use std::str::FromStr;
+
+use indexmap::IndexMap;
+
+use crate::error::KbinError;
+use crate::node::Node;
+
+/// A flat record read from the attributes of a node, such as
+/// `<music id="1" title="..."/>`. Implementations usually read each field
+/// with `attr_parsed` or `attr_parsed_opt`, so parse errors name the
+/// attribute.
+pub trait FromAttrs: Sized {
+    fn from_attrs(node: &Node) -> Result<Self, KbinError>;
+}
+
+/// A flat record written as the attributes of a node, usually with
+/// `set_attr_display` for each field.
+pub trait ToAttrs {
+    fn to_attrs(&self, node: &mut Node);
+}
+
+impl FromAttrs for IndexMap<String, String> {
+    fn from_attrs(node: &Node) -> Result<Self, KbinError> {
+        Ok(node.attributes().cloned().unwrap_or_default())
+    }
+}
+
+impl ToAttrs for IndexMap<String, String> {
+    fn to_attrs(&self, node: &mut Node) {
+        for (key, value) in self {
+            node.set_attr(key.as_str(), value.as_str());
+        }
+    }
+}
+
+impl<S> FromAttrs for HashMap<String, String, S>
+where
+    S: BuildHasher + Default,
+{
+    fn from_attrs(node: &Node) -> Result<Self, KbinError> {
+        Ok(node
+            .attributes()
+            .into_iter()
+            .flatten()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+}
+
+impl<S> ToAttrs for HashMap<String, String, S> {
+    fn to_attrs(&self, node: &mut Node) {
+        for (key, value) in self {
+            node.set_attr(key.as_str(), value.as_str());
+        }
+    }
+}
+
+/// Tuples of records read from and write to the same node, so a record of
+/// attributes shared by many nodes, such as a header, can be combined with
+/// the fields of each node instead of repeating them.
+macro_rules! tuple_records {
+    ($($name:ident: $index:tt),+) => {
+        impl<$($name),+> FromAttrs for ($($name,)+)
+        where
+            $($name: FromAttrs),+
+        {
+            fn from_attrs(node: &Node) -> Result<Self, KbinError> {
+                Ok(($($name::from_attrs(node)?,)+))
+            }
+        }
+
+        impl<$($name),+> ToAttrs for ($($name,)+)
+        where
+            $($name: ToAttrs),+
+        {
+            fn to_attrs(&self, node: &mut Node) {
+                $(self.$index.to_attrs(node);)+
+            }
+        }
+    };
+}
+
+tuple_records!(A: 0, B: 1);
+tuple_records!(A: 0, B: 1, C: 2);
+
+impl Node {
+    /// Like `attr_parsed`, but `None` if the node has no attribute with the
+    /// given key, for optional fields.
+    pub fn attr_parsed_opt<T>(&self, key: &str) -> Result<Option<T>, KbinError>
+    where
+        T: FromStr,
+        T::Err: Error + Send + Sync + 'static,
+    {
+        match self.attr(key) {
+            Some(_) => self.attr_parsed(key).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Set the attribute with the given key to the `Display` text of `value`,
+    /// which `attr_parsed` reads back for the standard library types.
+    pub fn set_attr_display<K, V>(&mut self, key: K, value: V) -> Option<String>
+    where
+        K: Into<String>,
+        V: Display,
+    {
+        self.set_attr(key, value.to_string())
+    }
+
+    /// Read a record from the attributes of this node.
+    #[inline]
+    pub fn attrs_into<T>(&self) -> Result<T, KbinError>
+    where
+        T: FromAttrs,
+    {
+        T::from_attrs(self)
+    }
+
+    /// Write a record to the attributes of this node. Attributes the record
+    /// does not set are kept.
+    #[inline]
+    pub fn set_attrs_from<T>(&mut self, record: &T)
+    where
+        T: ToAttrs + ?Sized,
+    {
+        record.to_attrs(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indexmap::IndexMap;
+
+    use crate::error::KbinError;
+    use crate::node::Node;
+
+    use super::{FromAttrs, ToAttrs};
+
+    #[derive(Debug, PartialEq)]
+    struct Music {
+        id: u32,
+        title: String,
+        bpm: Option<f32>,
+    }
+
+    impl FromAttrs for Music {
+        fn from_attrs(node: &Node) -> Result<Self, KbinError> {
+            Ok(Self {
+                id: node.attr_parsed("id")?,
+                title: node.attr_parsed("title")?,
+                bpm: node.attr_parsed_opt("bpm")?,
+            })
+        }
+    }
+
+    impl ToAttrs for Music {
+        fn to_attrs(&self, node: &mut Node) {
+            node.set_attr_display("id", self.id);
+            node.set_attr("title", self.title.as_str());
+            if let Some(bpm) = self.bpm {
+                node.set_attr_display("bpm", bpm);
+            }
+        }
+    }
+
+    #[test]
+    fn test_attrs_records() {
+        let music = Music {
+            id: 1,
+            title: "a".into(),
+            bpm: Some(150.5),
+        };
+        let mut node = Node::with_attrs("music", &[("kind", "2")]);
+        node.set_attrs_from(&music);
+        assert_eq!(node.attr("bpm"), Some("150.5"));
+        assert_eq!(node.attr("kind"), Some("2"));
+
+        let data = crate::to_binary(&node).unwrap();
+        let (collection, _) = crate::from_binary(data.into()).unwrap();
+        let read = Node::from_collection(&collection).unwrap();
+        assert_eq!(read.attrs_into::<Music>().unwrap(), music);
+
+        let attrs = read.attrs_into::<IndexMap<String, String>>().unwrap();
+        assert_eq!(
+            attrs.keys().collect::<Vec<_>>(),
+            ["kind", "id", "title", "bpm"]
+        );
+
+        node.remove_attr("bpm");
+        node.set_attr("id", "x");
+        assert!(matches!(
+            node.attrs_into::<Music>(),
+            Err(KbinError::AttributeParse { ref key, .. }) if key == "id"
+        ));
+        node.set_attr("id", "1");
+        assert_eq!(node.attrs_into::<Music>().unwrap().bpm, None);
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Header {
+        version: u8,
+    }
+
+    impl FromAttrs for Header {
+        fn from_attrs(node: &Node) -> Result<Self, KbinError> {
+            Ok(Self {
+                version: node.attr_parsed("version")?,
+            })
+        }
+    }
+
+    impl ToAttrs for Header {
+        fn to_attrs(&self, node: &mut Node) {
+            node.set_attr_display("version", self.version);
+        }
+    }
+
+    #[test]
+    fn test_attrs_tuple_records() {
+        let record = (
+            Header { version: 2 },
+            Music {
+                id: 1,
+                title: "a".into(),
+                bpm: None,
+            },
+        );
+        let mut node = Node::new("music");
+        node.set_attrs_from(&record);
+        assert_eq!(
+            node.attributes().unwrap().keys().collect::<Vec<_>>(),
+            ["version", "id", "title"]
+        );
+        assert_eq!(node.attrs_into::<(Header, Music)>().unwrap(), record);
+
+        node.remove_attr("version");
+        assert!(matches!(
+            node.attrs_into::<(Header, Music)>(),
+            Err(KbinError::AttributeNotFound { ref key }) if key == "version"
+        ));
+    }
+}
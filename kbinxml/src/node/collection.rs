@@ -1,10 +1,17 @@
-use std::collections::VecDeque;
+use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
 use std::fmt;
 use std::iter::Iterator;
+use std::marker::PhantomData;
+use std::slice;
+use std::sync::OnceLock;
 
+use crate::encoding_type::EncodingType;
 use crate::error::KbinError;
-use crate::node::{Node, NodeDefinition};
+use crate::node::{Node, NodeDefinition, PathError};
 use crate::node_types::StandardType;
+use crate::search::FilterByType;
 use crate::value::Value;
 
 fn parse_index(s: &str) -> Option<usize> {
@@ -14,12 +21,119 @@ fn parse_index(s: &str) -> Option<usize> {
     s.parse().ok()
 }
 
+/// Map of child keys to the positions of the children with that key, built
+/// on the first lookup by name. It is not part of the value of a collection,
+/// so it is dropped on clone and ignored by comparisons.
+#[derive(Default)]
+struct ChildIndex(OnceLock<HashMap<String, Vec<usize>>>);
+
+impl ChildIndex {
+    fn get(&self, children: &VecDeque<NodeCollection>) -> &HashMap<String, Vec<usize>> {
+        self.0.get_or_init(|| {
+            let mut index: HashMap<String, Vec<usize>> = HashMap::new();
+
+            // Children with keys that cannot be decoded are never indexed
+            for (i, child) in children.iter().enumerate() {
+                if let Ok(Some(key)) = child.base.key() {
+                    index.entry(key).or_default().push(i);
+                }
+            }
+
+            index
+        })
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        self.0.take();
+    }
+}
+
+impl Clone for ChildIndex {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl PartialEq for ChildIndex {
+    #[inline]
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for ChildIndex {}
+
 /// A collection of node definitions (`NodeDefinition`)
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Eq, PartialEq)]
 pub struct NodeCollection {
     base: NodeDefinition,
     attributes: VecDeque<NodeDefinition>,
     children: VecDeque<NodeCollection>,
+    index: ChildIndex,
+}
+
+/// Iterator over the children of a `NodeCollection` with a given key, in
+/// document order. Created by `NodeCollection::children_named`.
+pub struct ChildrenNamed<'a> {
+    children: &'a VecDeque<NodeCollection>,
+    positions: slice::Iter<'a, usize>,
+}
+
+impl<'a> Iterator for ChildrenNamed<'a> {
+    type Item = &'a NodeCollection;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let position = *self.positions.next()?;
+        self.children.get(position)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.positions.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for ChildrenNamed<'a> {}
+
+/// Iterator over the values of a `NodeCollection` and its descendants that
+/// convert to `T`, in document order. Created by
+/// `NodeCollection::iter_values`.
+pub struct IterValues<'a, T> {
+    stack: Vec<&'a NodeCollection>,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<'a, T> Iterator for IterValues<'a, T>
+where
+    T: for<'b> TryFrom<&'b Value, Error = KbinError>,
+{
+    type Item = Result<T, KbinError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let collection = self.stack.pop()?;
+            let children = collection.children.iter().rev();
+            self.stack
+                .extend(children.filter(|child| !child.base.is_markup()));
+
+            if collection.base.node_type == StandardType::NodeStart {
+                continue;
+            }
+
+            let value = match collection.base.value() {
+                Ok(value) => value,
+                Err(e) => return Some(Err(e)),
+            };
+            match T::try_from(&value) {
+                Ok(value) => return Some(Ok(value)),
+                Err(KbinError::ValueTypeMismatch { .. }) => {},
+                Err(e) => return Some(Err(e)),
+            };
+        }
+    }
 }
 
 impl NodeCollection {
@@ -28,6 +142,7 @@ impl NodeCollection {
             base,
             attributes: VecDeque::with_capacity(0),
             children: VecDeque::with_capacity(0),
+            index: ChildIndex::default(),
         }
     }
 
@@ -36,18 +151,42 @@ impl NodeCollection {
             base,
             attributes,
             children: VecDeque::with_capacity(0),
+            index: ChildIndex::default(),
         }
     }
 
+    /// Convert a `Node` tree, encoding names and strings as Shift-JIS like
+    /// the default writer options.
+    #[inline]
+    pub fn from_node(node: &Node) -> Result<Self, KbinError> {
+        Self::from_node_with_encoding(node, EncodingType::SHIFT_JIS)
+    }
+
+    /// Convert a `Node` tree, encoding names and strings with `encoding`.
+    pub fn from_node_with_encoding(node: &Node, encoding: EncodingType) -> Result<Self, KbinError> {
+        let base = NodeDefinition::from_key_value(encoding, node.key(), node.value())?;
+
+        let mut attributes = VecDeque::new();
+        if let Some(attrs) = node.attributes() {
+            for (key, value) in attrs {
+                attributes.push_back(NodeDefinition::attribute(encoding, key, value)?);
+            }
+        }
+
+        let mut collection = Self::with_attributes(base, attributes);
+        for child in node.children_iter() {
+            let child = Self::from_node_with_encoding(child, encoding)?;
+            collection.children.push_back(child);
+        }
+
+        Ok(collection)
+    }
+
     pub fn from_iter<I>(iter: &mut I) -> Option<NodeCollection>
     where
         I: Iterator<Item = NodeDefinition>,
     {
-        let base = if let Some(def) = iter.next() {
-            def
-        } else {
-            return None;
-        };
+        let base = iter.next()?;
 
         NodeCollection::from_iter_base(base, iter)
     }
@@ -59,18 +198,14 @@ impl NodeCollection {
         let mut attributes = VecDeque::new();
         let mut children = VecDeque::new();
 
-        loop {
-            if let Some(def) = iter.next() {
-                match def.node_type {
-                    StandardType::Attribute => attributes.push_back(def),
-                    StandardType::NodeEnd | StandardType::FileEnd => break,
-                    _ => match NodeCollection::from_iter_base(def, iter) {
-                        Some(child) => children.push_back(child),
-                        None => return None,
-                    },
-                }
-            } else {
-                break;
+        while let Some(def) = iter.next() {
+            match def.node_type {
+                StandardType::Attribute => attributes.push_back(def),
+                StandardType::NodeEnd | StandardType::FileEnd => break,
+                _ => match NodeCollection::from_iter_base(def, iter) {
+                    Some(child) => children.push_back(child),
+                    None => return None,
+                },
             }
         }
 
@@ -78,6 +213,7 @@ impl NodeCollection {
             base,
             attributes,
             children,
+            index: ChildIndex::default(),
         })
     }
 
@@ -101,17 +237,83 @@ impl NodeCollection {
         &mut self.attributes
     }
 
+    /// Iterate over every definition of the given type in this collection and
+    /// its descendants, without converting to a `Node`.
+    #[inline]
+    pub fn filter_by_type(&self, node_type: StandardType) -> FilterByType<'_> {
+        FilterByType::new(self, node_type)
+    }
+
+    /// Append an attribute, encoded with the encoding of the base definition.
+    pub fn push_attribute(&mut self, key: &str, value: &str) -> Result<(), KbinError> {
+        let attribute = NodeDefinition::attribute(self.base.encoding(), key, value)?;
+        self.attributes.push_back(attribute);
+
+        Ok(())
+    }
+
     #[inline]
     pub fn children(&self) -> &VecDeque<NodeCollection> {
         &self.children
     }
 
+    /// Mutable access to the children. This drops the index used by
+    /// `children_named`, which is rebuilt on the next lookup.
     #[inline]
     pub fn children_mut(&mut self) -> &mut VecDeque<NodeCollection> {
+        self.index.clear();
         &mut self.children
     }
 
+    /// Sort the children with the given comparison, like
+    /// `Node::sort_children_by`. The sort is stable.
+    pub fn sort_children_by<F>(&mut self, compare: F)
+    where
+        F: FnMut(&NodeCollection, &NodeCollection) -> Ordering,
+    {
+        self.children_mut().make_contiguous().sort_by(compare);
+    }
+
+    /// Iterate over the children with the given key, in document order.
+    ///
+    /// The first call builds an index of every child key, so repeated
+    /// lookups on large flat collections do not scan all the children.
+    /// Children with keys that cannot be decoded never match.
+    pub fn children_named<'a>(&'a self, key: &str) -> ChildrenNamed<'a> {
+        let positions = self
+            .index
+            .get(&self.children)
+            .get(key)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+
+        ChildrenNamed {
+            children: &self.children,
+            positions: positions.iter(),
+        }
+    }
+
+    /// Iterate over the values of this node and its descendants that convert
+    /// to `T`, such as every `u32` value with `iter_values::<u32>()`. Values
+    /// of other types are skipped, values that fail to decode are returned
+    /// as errors.
+    pub fn iter_values<T>(&self) -> IterValues<'_, T>
+    where
+        T: for<'b> TryFrom<&'b Value, Error = KbinError>,
+    {
+        IterValues {
+            stack: vec![self],
+            marker: PhantomData,
+        }
+    }
+
+    /// Convert to a `Node`, decoding every value. Errors are wrapped in
+    /// `KbinError::AtPath` with the path of the node that failed.
     pub fn as_node(&self) -> Result<Node, KbinError> {
+        self.as_node_inner().map_err(|e| e.into_error(self))
+    }
+
+    fn as_node_inner(&self) -> Result<Node, PathError> {
         let mut node = self.base.as_node()?;
 
         for attr in &self.attributes {
@@ -124,8 +326,10 @@ impl NodeCollection {
             }
         }
 
-        for child in &self.children {
-            node.append_child(child.as_node()?);
+        for (i, child) in self.children.iter().enumerate() {
+            if !child.base.is_markup() {
+                node.append_child(child.as_node_inner().map_err(|e| e.child(i))?);
+            }
         }
 
         Ok(node)
@@ -139,17 +343,10 @@ impl NodeCollection {
 
         for token in pointer {
             let target_opt = if let Some(index) = parse_index(token) {
-                target.children().get(index)
+                let children = target.children().iter();
+                children.filter(|child| !child.base.is_markup()).nth(index)
             } else {
-                target.children().iter().find(|ref child| {
-                    child
-                        .base()
-                        .key()
-                        .ok()
-                        .and_then(|x| x)
-                        .expect("key not parseable") ==
-                        *token
-                })
+                target.children_named(token).next()
             };
 
             if let Some(t) = target_opt {
@@ -192,6 +389,25 @@ where
     }
 }
 
+impl fmt::Debug for NodeCollection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NodeCollection")
+            .field("base", &self.base)
+            .field("attributes", &self.attributes)
+            .field("children", &self.children)
+            .finish()
+    }
+}
+
+impl TryFrom<&Node> for NodeCollection {
+    type Error = KbinError;
+
+    #[inline]
+    fn try_from(node: &Node) -> Result<Self, Self::Error> {
+        Self::from_node(node)
+    }
+}
+
 impl fmt::Display for NodeCollection {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut d = f.debug_struct("NodeCollection");
@@ -1,4 +1,7 @@
+use std::borrow::Cow;
 use std::fmt;
+use std::ops::Deref;
+use std::str;
 
 use bytes::Bytes;
 
@@ -7,8 +10,9 @@ use crate::encoding_type::EncodingType;
 use crate::error::KbinError;
 use crate::node::Node;
 use crate::node_types::StandardType;
-use crate::sixbit::{Sixbit, SixbitSize};
+use crate::sixbit::{Sixbit, SixbitName, SixbitSize};
 use crate::value::Value;
+use crate::ARRAY_MASK;
 
 #[derive(Clone, Eq)]
 pub enum Key {
@@ -18,21 +22,93 @@ pub enum Key {
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum NodeData {
-    Some { key: Key, value_data: Bytes },
+    Some {
+        key: Key,
+        value_data: Bytes,
+    },
+
+    /// The text of an XML comment, kept by the text reader with
+    /// `preserve_comments`
+    Comment(String),
+
+    /// The content of an XML processing instruction such as
+    /// `<?xml-stylesheet href="a.xsl"?>`, kept like comments
+    ProcessingInstruction(String),
+
     None,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq)]
 pub struct NodeDefinition {
     encoding: EncodingType,
     pub node_type: StandardType,
     pub is_array: bool,
 
+    /// The raw type byte for `StandardType::Unknown` definitions
+    unknown_type_id: u8,
+
+    /// The `__count` of an array node read from text XML, if it differs from
+    /// the number of values. Not compared by `PartialEq`, since it does not
+    /// change the value of the node.
+    declared_count: Option<usize>,
+
     data: NodeData,
 }
 
+/// A decoded node name that only allocates when the name is stored in an
+/// encoding other than UTF-8 or ASCII.
+///
+/// Sixbit names are kept inline, since boxing them would allocate anyway.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub enum KeyName<'a> {
+    Sixbit(SixbitName),
+    Borrowed(&'a str),
+    Owned(String),
+}
+
+impl KeyName<'_> {
+    pub fn into_string(self) -> String {
+        match self {
+            KeyName::Owned(name) => name,
+            name => name.to_owned(),
+        }
+    }
+}
+
+impl Deref for KeyName<'_> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        match self {
+            KeyName::Sixbit(name) => name,
+            KeyName::Borrowed(name) => name,
+            KeyName::Owned(name) => name,
+        }
+    }
+}
+
+impl fmt::Display for KeyName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self)
+    }
+}
+
 impl Key {
-    fn to_string(&self) -> Result<String, KbinError> {
+    /// Decode the name without allocating where possible.
+    pub fn name(&self) -> Result<KeyName<'_>, KbinError> {
+        match self {
+            Key::Compressed { ref size, ref data } => Sixbit::decode(data, *size)
+                .map(KeyName::Sixbit)
+                .map_err(Into::into),
+            Key::Uncompressed { encoding, ref data } => match encoding.decode_borrowed(data)? {
+                Cow::Borrowed(name) => Ok(KeyName::Borrowed(name)),
+                Cow::Owned(name) => Ok(KeyName::Owned(name)),
+            },
+        }
+    }
+
+    pub(crate) fn to_string(&self) -> Result<String, KbinError> {
         match self {
             Key::Compressed { ref size, ref data } => {
                 Sixbit::unpack(data, *size).map_err(Into::into)
@@ -50,6 +126,8 @@ impl NodeDefinition {
             encoding,
             node_type,
             is_array,
+            unknown_type_id: 0,
+            declared_count: None,
             data: NodeData::None,
         }
     }
@@ -64,10 +142,126 @@ impl NodeDefinition {
             encoding,
             node_type,
             is_array,
+            unknown_type_id: 0,
+            declared_count: None,
             data,
         }
     }
 
+    /// Construct a definition for a node type this crate does not know about.
+    /// `type_id` is the raw type byte, including the array flag, and is
+    /// written back verbatim.
+    pub fn unknown(encoding: EncodingType, type_id: u8, data: NodeData) -> Self {
+        Self {
+            encoding,
+            node_type: StandardType::Unknown,
+            is_array: type_id & ARRAY_MASK == ARRAY_MASK,
+            unknown_type_id: type_id,
+            declared_count: None,
+            data,
+        }
+    }
+
+    /// Construct the definition of an XML comment, which is only written to
+    /// text XML. Binary documents and `Node` trees leave it out.
+    pub fn comment<T>(text: T) -> Self
+    where
+        T: Into<String>,
+    {
+        let data = NodeData::Comment(text.into());
+        Self::with_data(EncodingType::UTF_8, StandardType::NodeStart, false, data)
+    }
+
+    /// Construct the definition of an XML processing instruction, which is
+    /// written like comments.
+    pub fn processing_instruction<T>(content: T) -> Self
+    where
+        T: Into<String>,
+    {
+        let data = NodeData::ProcessingInstruction(content.into());
+        Self::with_data(EncodingType::UTF_8, StandardType::NodeStart, false, data)
+    }
+
+    /// Whether this is a comment or processing instruction rather than a
+    /// node.
+    pub fn is_markup(&self) -> bool {
+        matches!(
+            self.data,
+            NodeData::Comment(_) | NodeData::ProcessingInstruction(_)
+        )
+    }
+
+    /// Construct an `Attribute` definition, encoding the key and value with
+    /// `encoding`.
+    pub fn attribute(encoding: EncodingType, key: &str, value: &str) -> Result<Self, KbinError> {
+        let key = Self::encode_key(encoding, key)?;
+        let data = NodeData::Some {
+            key,
+            value_data: Bytes::from(encoding.encode_bytes(value)?),
+        };
+
+        Ok(Self::with_data(
+            encoding,
+            StandardType::Attribute,
+            false,
+            data,
+        ))
+    }
+
+    fn encode_key(encoding: EncodingType, key: &str) -> Result<Key, KbinError> {
+        // Keys are stored without the trailing null byte
+        let mut data = encoding.encode_bytes(key)?;
+        data.pop();
+
+        Ok(Key::Uncompressed {
+            encoding,
+            data: Bytes::from(data),
+        })
+    }
+
+    /// Construct a definition with the key and value of a `Node`, or a
+    /// `NodeStart` definition if there is no value. Strings are encoded with
+    /// `encoding`.
+    pub(crate) fn from_key_value(
+        encoding: EncodingType,
+        key: &str,
+        value: Option<&Value>,
+    ) -> Result<Self, KbinError> {
+        let key = Self::encode_key(encoding, key)?;
+
+        let (node_type, is_array, value_data) = match value {
+            None => (StandardType::NodeStart, false, Vec::new()),
+            Some(Value::String(text)) => {
+                (StandardType::String, false, encoding.encode_bytes(text)?)
+            },
+            Some(Value::Attribute(text)) => {
+                (StandardType::Attribute, false, encoding.encode_bytes(text)?)
+            },
+            Some(Value::Binary(data)) => (StandardType::Binary, false, data.clone()),
+            Some(Value::Unknown { type_id, data }) => {
+                let data = NodeData::Some {
+                    key,
+                    value_data: Bytes::from(data.clone()),
+                };
+
+                return Ok(Self::unknown(encoding, *type_id, data));
+            },
+            Some(Value::Array(values)) => {
+                let mut data = Vec::new();
+                values.to_bytes_into(&mut data)?;
+
+                (values.standard_type(), true, data)
+            },
+            Some(value) => (value.standard_type(), false, value.to_bytes()?),
+        };
+        let data = NodeData::Some {
+            key,
+            value_data: Bytes::from(value_data),
+        };
+
+        Ok(Self::with_data(encoding, node_type, is_array, data))
+    }
+
     #[inline]
     pub fn encoding(&self) -> EncodingType {
         self.encoding
@@ -78,20 +272,55 @@ impl NodeDefinition {
         (self.node_type, self.is_array)
     }
 
+    /// The raw type byte, including the array flag, written for this definition.
+    pub fn type_id(&self) -> u8 {
+        match self.node_type {
+            StandardType::Unknown => self.unknown_type_id,
+            node_type if self.is_array => node_type as u8 | ARRAY_MASK,
+            node_type => node_type as u8,
+        }
+    }
+
+    /// The `__count` written for this array node in text XML when it differs
+    /// from the number of values. Only set by the text XML reader with
+    /// `ArrayCountPolicy::Preserve`.
+    ///
+    /// The text written with this count no longer matches the values, so it
+    /// can only be read again with `ArrayCountPolicy::Preserve` or a policy
+    /// that adjusts the values. Binary documents have no such count, so it is
+    /// lost when writing them.
     #[inline]
-    pub fn data<'a>(&'a self) -> &'a NodeData {
+    pub fn declared_count(&self) -> Option<usize> {
+        self.declared_count
+    }
+
+    #[inline]
+    pub fn set_declared_count(&mut self, count: Option<usize>) {
+        self.declared_count = count;
+    }
+
+    #[inline]
+    pub fn data(&self) -> &NodeData {
         &self.data
     }
 
     #[inline]
-    pub fn data_mut<'a>(&'a mut self) -> &'a mut NodeData {
+    pub fn data_mut(&mut self) -> &mut NodeData {
         &mut self.data
     }
 
+    /// Like `key`, but without allocating where possible.
+    pub fn key_name(&self) -> Result<Option<KeyName<'_>>, KbinError> {
+        match self.data {
+            NodeData::Some { ref key, .. } => key.name().map(Some),
+            _ => Ok(None),
+        }
+    }
+
     pub fn key(&self) -> Result<Option<String>, KbinError> {
         match self.data {
             NodeData::Some { ref key, .. } => key.to_string().map(Some),
-            NodeData::None => Ok(None),
+            _ => Ok(None),
         }
     }
 
@@ -107,6 +336,12 @@ impl NodeDefinition {
                 let value = self.encoding.decode_bytes(data)?;
                 Ok(Value::String(value))
             },
+            (StandardType::Unknown, NodeData::Some { ref value_data, .. }) => {
+                Ok(Value::Unknown {
+                    type_id: self.unknown_type_id,
+                    data: value_data.to_vec(),
+                })
+            },
             (node_type, NodeData::Some { ref value_data, .. }) => {
                 let value = Value::from_standard_type(node_type, self.is_array, value_data)?;
                 match value {
@@ -114,14 +349,14 @@ impl NodeDefinition {
                     None => Err(KbinError::InvalidNodeType { node_type }),
                 }
             },
-            (node_type, NodeData::None) => Err(KbinError::InvalidNodeType { node_type }),
+            (node_type, _) => Err(KbinError::InvalidNodeType { node_type }),
         }
     }
 
-    pub fn value_bytes<'a>(&'a self) -> Option<&'a [u8]> {
+    pub fn value_bytes(&self) -> Option<&[u8]> {
         match self.data {
             NodeData::Some { ref value_data, .. } => Some(value_data),
-            NodeData::None => None,
+            _ => None,
         }
     }
 
@@ -142,11 +377,21 @@ impl NodeDefinition {
                 let value = self.value()?;
                 Ok(Node::with_value(key, value))
             },
-            (node_type, NodeData::None) => Err(KbinError::InvalidNodeType { node_type }),
+            (node_type, _) => Err(KbinError::InvalidNodeType { node_type }),
         }
     }
 }
 
+impl PartialEq for NodeDefinition {
+    fn eq(&self, other: &NodeDefinition) -> bool {
+        self.encoding == other.encoding &&
+            self.node_type == other.node_type &&
+            self.is_array == other.is_array &&
+            self.unknown_type_id == other.unknown_type_id &&
+            self.data == other.data
+    }
+}
+
 impl PartialEq for Key {
     fn eq(&self, other: &Key) -> bool {
         match (self.to_string(), other.to_string()) {
@@ -217,6 +462,12 @@ impl fmt::Display for NodeDefinition {
                 };
                 d.field("value_data", &value_data);
             },
+            NodeData::Comment(ref text) => {
+                d.field("comment", text);
+            },
+            NodeData::ProcessingInstruction(ref content) => {
+                d.field("processing_instruction", content);
+            },
             NodeData::None => {},
         };
 
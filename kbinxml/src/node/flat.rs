@@ -0,0 +1,370 @@
+use std::collections::VecDeque;
+
+use bytes::Bytes;
+
+use crate::error::KbinError;
+use crate::node::{Node, NodeCollection, NodeData, NodeDefinition};
+use crate::node_types::StandardType;
+use crate::search::child_segments;
+use crate::value::Value;
+
+/// A value or attribute listed by `NodeCollection::flatten`, with the type
+/// and data as stored in the document, for loading into a database.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FlatEntry {
+    /// Path of the node, such as `/root/music[1]/info/title`, or of the
+    /// attribute, such as `/root/music[1]/@id`, as used by `search`.
+    pub path: String,
+    pub standard_type: StandardType,
+    pub is_array: bool,
+    /// The value as written in text XML.
+    pub value_string: String,
+    /// The value data as stored in the binary document. Strings and
+    /// attributes include their trailing null byte.
+    pub raw_bytes: Bytes,
+}
+
+impl FlatEntry {
+    fn new(path: String, definition: &NodeDefinition) -> Result<Self, KbinError> {
+        let (standard_type, is_array) = definition.node_type_tuple();
+        let value = match definition.value()? {
+            Value::String(text) | Value::Attribute(text) => text,
+            value => value.to_string(),
+        };
+        let raw_bytes = match definition.data() {
+            NodeData::Some { value_data, .. } => value_data.clone(),
+            _ => Bytes::new(),
+        };
+
+        Ok(Self {
+            path,
+            standard_type,
+            is_array,
+            value_string: value,
+            raw_bytes,
+        })
+    }
+}
+
+/// Iterator over the values and attributes of a `NodeCollection` and its
+/// descendants, in document order. Created by `NodeCollection::flatten`.
+pub struct Flatten<'a> {
+    stack: Vec<(String, &'a NodeCollection)>,
+    pending: VecDeque<Result<FlatEntry, KbinError>>,
+}
+
+impl<'a> Flatten<'a> {
+    fn new(collection: &'a NodeCollection) -> Self {
+        let mut stack = Vec::new();
+        let mut pending = VecDeque::new();
+        match collection.base().key() {
+            Ok(key) => stack.push((format!("/{}", key.unwrap_or_default()), collection)),
+            Err(e) => pending.push_back(Err(e)),
+        };
+
+        Self { stack, pending }
+    }
+
+    fn visit(&mut self, path: String, collection: &'a NodeCollection) {
+        if collection.base().node_type != StandardType::NodeStart {
+            self.pending
+                .push_back(FlatEntry::new(path.clone(), collection.base()));
+        }
+        for attribute in collection.attributes() {
+            let entry = attribute.key().and_then(|key| {
+                let path = format!("{}/@{}", path, key.unwrap_or_default());
+                FlatEntry::new(path, attribute)
+            });
+            self.pending.push_back(entry);
+        }
+
+        match child_segments(collection) {
+            Ok(segments) => {
+                for (segment, child) in segments.into_iter().rev() {
+                    self.stack.push((format!("{}/{}", path, segment), child));
+                }
+            },
+            Err(e) => self.pending.push_back(Err(e)),
+        };
+    }
+}
+
+impl<'a> Iterator for Flatten<'a> {
+    type Item = Result<FlatEntry, KbinError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.pending.pop_front() {
+                return Some(item);
+            }
+
+            let (path, collection) = self.stack.pop()?;
+            self.visit(path, collection);
+        }
+    }
+}
+
+impl NodeCollection {
+    /// List the values and attributes of this collection and all collections
+    /// below it with their types, text and raw data, in document order.
+    ///
+    /// Unlike `Node::flatten`, paths use the notation of `search`: siblings
+    /// sharing a name get a zero-based index in brackets, such as
+    /// `/root/music[0]/info/title`, and attributes are listed as
+    /// `/root/music[0]/@id`. Nodes without a value and comments are not
+    /// listed.
+    #[inline]
+    pub fn flatten(&self) -> Flatten<'_> {
+        Flatten::new(self)
+    }
+}
+
+/// Whether several of `children` are named `key`, since only names shared by
+/// siblings get an index.
+fn needs_index(children: &[Node], key: &str) -> bool {
+    children.iter().filter(|child| child.key == key).nth(1).is_some()
+}
+
+fn flatten_into(node: &Node, path: &str, pairs: &mut Vec<(String, Value)>) {
+    if let Some(value) = node.value() {
+        pairs.push((path.to_owned(), value.clone()));
+    }
+    if let Some(attributes) = node.attributes() {
+        for (key, value) in attributes {
+            pairs.push((
+                format!("{}@{}", path, key),
+                Value::Attribute(value.clone()),
+            ));
+        }
+    }
+    if let Some(children) = node.children() {
+        let mut indexes: Vec<(&str, usize)> = Vec::new();
+        for child in children {
+            let child_path = if needs_index(children, &child.key) {
+                let index = match indexes.iter_mut().find(|(key, _)| *key == child.key) {
+                    Some((_, index)) => {
+                        *index += 1;
+                        *index
+                    },
+                    None => {
+                        indexes.push((&child.key, 0));
+                        0
+                    },
+                };
+
+                format!("{}.{}.{}", path, child.key, index)
+            } else {
+                format!("{}.{}", path, child.key)
+            };
+
+            flatten_into(child, &child_path, pairs);
+        }
+    }
+}
+
+/// The child of `node` named `key` at `index` among the children with that
+/// name, appending empty nodes up to it if there are not enough.
+fn child_at<'a>(node: &'a mut Node, key: &str, index: usize) -> &'a mut Node {
+    let children = node.children.get_or_insert_with(Vec::new);
+    let mut positions = children
+        .iter()
+        .enumerate()
+        .filter(|(_, child)| child.key == key)
+        .map(|(position, _)| position);
+    let position = match positions.nth(index) {
+        Some(position) => position,
+        None => {
+            let count = children.iter().filter(|child| child.key == key).count();
+            for _ in count..=index {
+                children.push(Node::new(key));
+            }
+
+            children.len() - 1
+        },
+    };
+
+    &mut children[position]
+}
+
+impl Node {
+    /// List the values and attributes of this node and all nodes below it as
+    /// pairs of a dotted path and a value, in document order, for tabular
+    /// output.
+    ///
+    /// A path starts with the key of this node, such as
+    /// `root.music.3.info.title`. Siblings sharing a name get a zero-based
+    /// index after the name, which is left out when the name is unique, like
+    /// the indexes of `NodePath`. Attributes are listed as
+    /// `root.music.3@id` with `Value::Attribute` values. Nodes without a
+    /// value, attributes or children are not listed.
+    pub fn flatten(&self) -> Vec<(String, Value)> {
+        let mut pairs = Vec::new();
+        flatten_into(self, &self.key, &mut pairs);
+
+        pairs
+    }
+
+    /// Build a tree from the pairs listed by `flatten`. All paths have to
+    /// start with the same root key.
+    ///
+    /// Nodes are created as the paths name them, so indexes that are skipped
+    /// get empty nodes. Attribute values other than `Value::Attribute` and
+    /// `Value::String` are stored as their text. A segment made of digits is
+    /// read as an index, so node names made of digits cannot be represented.
+    pub fn from_flat_pairs<I, K>(pairs: I) -> Result<Node, KbinError>
+    where
+        I: IntoIterator<Item = (K, Value)>,
+        K: AsRef<str>,
+    {
+        let mut root: Option<Node> = None;
+
+        for (path, value) in pairs {
+            let path = path.as_ref();
+            let invalid_path = || KbinError::InvalidFlatPath {
+                path: path.to_owned(),
+            };
+
+            let (node_path, attribute) = match path.split_once('@') {
+                Some((node_path, key)) if !key.is_empty() => (node_path, Some(key)),
+                Some(_) => return Err(invalid_path()),
+                None => (path, None),
+            };
+            let mut segments = node_path.split('.').peekable();
+            let root_key = segments.next().filter(|key| !key.is_empty());
+            let root_key = root_key.ok_or_else(invalid_path)?;
+
+            let mut node = match root {
+                Some(ref mut root) if root.key == root_key => root,
+                Some(_) => return Err(invalid_path()),
+                None => root.get_or_insert_with(|| Node::new(root_key)),
+            };
+            while let Some(key) = segments.next() {
+                let is_index = |segment: &str| segment.bytes().all(|b| b.is_ascii_digit());
+                if key.is_empty() || is_index(key) {
+                    return Err(invalid_path());
+                }
+                let index = match segments.peek() {
+                    Some(segment) if is_index(segment) => {
+                        let index = segment.parse().map_err(|_| invalid_path())?;
+                        segments.next();
+                        index
+                    },
+                    _ => 0,
+                };
+
+                node = child_at(node, key, index);
+            }
+
+            match attribute {
+                Some(key) => {
+                    let value = match value {
+                        Value::Attribute(text) | Value::String(text) => text,
+                        value => value.to_string(),
+                    };
+                    node.set_attr(key, value);
+                },
+                None => {
+                    node.set_value(Some(value));
+                },
+            };
+        }
+
+        root.ok_or(KbinError::NoNodeCollection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::KbinError;
+    use crate::node::Node;
+    use crate::node_types::StandardType;
+    use crate::value::{Value, ValueArray};
+
+    #[test]
+    fn test_flatten() {
+        let music = |id: &str, title: &str| {
+            let mut node = Node::with_attrs("music", &[("id", id)]);
+            node.append_child(Node::with_nodes(
+                "info",
+                vec![Node::with_value("title", Value::String(title.into()))],
+            ));
+            node
+        };
+        let node = Node::with_nodes(
+            "root",
+            vec![
+                music("1", "a"),
+                music("2", "b"),
+                Node::with_value("count", Value::U32(2)),
+            ],
+        );
+
+        let pairs = node.flatten();
+        let paths: Vec<_> = pairs.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "root.music.0@id",
+                "root.music.0.info.title",
+                "root.music.1@id",
+                "root.music.1.info.title",
+                "root.count",
+            ]
+        );
+        assert_eq!(pairs[3].1, Value::String("b".into()));
+        assert_eq!(Node::from_flat_pairs(pairs).unwrap(), node);
+
+        let node = Node::from_flat_pairs(vec![
+            ("root.item.1", Value::U8(1)),
+            ("root.item.1@id", Value::U32(5)),
+        ])
+        .unwrap();
+        assert_eq!(node.children().unwrap().len(), 2);
+        assert_eq!(node.children().unwrap()[1].attr("id"), Some("5"));
+
+        for path in &["root.1", "other.a", "root..a", "root@", ""] {
+            assert!(matches!(
+                Node::from_flat_pairs(vec![("root.a", Value::U8(1)), (path, Value::U8(1))]),
+                Err(KbinError::InvalidFlatPath { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_collection_flatten() {
+        let mut music = Node::with_attrs("music", &[("id", "1")]);
+        music.append_child(Node::with_value("title", Value::String("a".into())));
+        let node = Node::with_nodes(
+            "root",
+            vec![
+                music,
+                Node::with_attrs("music", &[("id", "2")]),
+                Node::with_value("scores", Value::Array(ValueArray::U16(vec![1, 2]))),
+            ],
+        );
+        let data = crate::to_binary(&node).unwrap();
+        let (collection, _) = crate::from_slice(&data).unwrap();
+
+        let entries = collection.flatten().collect::<Result<Vec<_>, _>>().unwrap();
+        let paths: Vec<_> = entries.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "/root/music[0]/@id",
+                "/root/music[0]/title",
+                "/root/music[1]/@id",
+                "/root/scores",
+            ]
+        );
+
+        assert_eq!(entries[0].standard_type, StandardType::Attribute);
+        assert_eq!(entries[0].value_string, "1");
+        assert_eq!(&entries[0].raw_bytes[..], b"1\0");
+        assert_eq!(entries[1].standard_type, StandardType::String);
+        assert_eq!(entries[1].value_string, "a");
+        assert_eq!(entries[3].standard_type, StandardType::U16);
+        assert!(entries[3].is_array);
+        assert_eq!(entries[3].value_string, "1 2");
+        assert_eq!(&entries[3].raw_bytes[..], [0, 1, 0, 2]);
+    }
+}
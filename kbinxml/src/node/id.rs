@@ -0,0 +1,141 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::node::Node;
+
+/// The next id to assign. Zero marks an `IdSlot` without an id, so ids start
+/// at one.
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// An identifier of a `Node` that is unique within the process and stays the
+/// same while the node is moved around or its siblings change. Ids are
+/// assigned in the order they are first asked for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(u64);
+
+/// The id of a node, assigned by the first call to `Node::id` so creating and
+/// cloning nodes does not touch the global counter. Cloning a node creates a
+/// new node, so the clone starts without an id. Ids are not part of the
+/// document, so every slot compares equal and nodes compare by content.
+#[derive(Debug, Default)]
+pub(crate) struct IdSlot(AtomicU64);
+
+impl IdSlot {
+    fn get(&self) -> NodeId {
+        let id = self.0.load(Ordering::Relaxed);
+        if id != 0 {
+            return NodeId(id);
+        }
+
+        // Another thread may assign an id between the load and the store
+        let next = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        match self
+            .0
+            .compare_exchange(0, next, Ordering::Relaxed, Ordering::Relaxed)
+        {
+            Ok(_) => NodeId(next),
+            Err(id) => NodeId(id),
+        }
+    }
+
+    /// Whether the slot holds `id`, without assigning one.
+    #[inline]
+    fn is(&self, id: NodeId) -> bool {
+        self.0.load(Ordering::Relaxed) == id.0
+    }
+}
+
+impl Clone for IdSlot {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl PartialEq for IdSlot {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Node {
+    /// The id of this node, assigned the first time it is asked for.
+    ///
+    /// Ids can be used to refer to nodes from outside of the tree, for example
+    /// in indices or annotation tables, because they do not depend on the
+    /// position of the node. A clone of a node and its descendants get new
+    /// ids.
+    #[inline]
+    pub fn id(&self) -> NodeId {
+        self.id.get()
+    }
+
+    /// Find this node or the descendant with the id `id`.
+    pub fn find_by_id(&self, id: NodeId) -> Option<&Node> {
+        if self.id.is(id) {
+            return Some(self);
+        }
+
+        self.children_iter().find_map(|child| child.find_by_id(id))
+    }
+
+    /// Find this node or the descendant with the id `id` for modification.
+    pub fn find_by_id_mut(&mut self, id: NodeId) -> Option<&mut Node> {
+        if self.id.is(id) {
+            return Some(self);
+        }
+
+        self.children_iter_mut()
+            .find_map(|child| child.find_by_id_mut(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::node::Node;
+    use crate::value::Value;
+
+    #[test]
+    fn test_node_ids() {
+        let mut node = Node::with_nodes(
+            "root",
+            vec![Node::new("a"), Node::with_value("b", Value::U8(1))],
+        );
+        let a = node.children().unwrap()[0].id();
+        let b = node.children().unwrap()[1].id();
+        assert_ne!(a, b);
+        assert_ne!(node.id(), a);
+
+        // Ids stay with nodes moved within the tree
+        let children = node.children_mut().unwrap();
+        children.insert(0, Node::new("c"));
+        children.swap(1, 2);
+        assert_eq!(node.children().unwrap()[2].id(), a);
+        assert_eq!(node.find_by_id(b).unwrap().key(), "b");
+
+        node.find_by_id_mut(a)
+            .unwrap()
+            .set_value(Some(Value::U8(2)));
+        assert_eq!(node.child_value_u8("a").unwrap(), 2);
+
+        let clone = node.clone();
+        assert_eq!(clone, node);
+        assert_ne!(clone.id(), node.id());
+        assert!(clone.find_by_id(a).is_none());
+    }
+
+    #[test]
+    fn test_lazy_node_ids() {
+        let node = Node::with_nodes("root", vec![Node::new("a"), Node::new("b")]);
+        let clone = node.clone();
+
+        // Ids are assigned when first asked for, not when nodes are created
+        let b = clone.children().unwrap()[1].id();
+        let a = node.children().unwrap()[0].id();
+        assert!(b < a);
+        assert_eq!(node.children().unwrap()[0].id(), a);
+
+        // Searching does not assign ids to the nodes it passes
+        assert!(node.find_by_id(b).is_none());
+        let later = Node::new("c").id();
+        assert!(later < node.children().unwrap()[1].id());
+    }
+}
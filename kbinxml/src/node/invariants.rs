@@ -0,0 +1,146 @@
+use std::fmt;
+
+use crate::node::{Node, NodePath};
+use crate::value::Value;
+
+/// A problem found by `Node::check_invariants`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViolationKind {
+    /// The node has an empty name. Only compressed binary documents can
+    /// hold those, see `TextXmlOptionsBuilder::empty_name`.
+    EmptyKey,
+
+    /// The node is a default node, usually left behind by `mem::take` on a
+    /// child that was meant to be moved out of the tree.
+    DefaultNode,
+
+    /// An attribute has an empty key.
+    EmptyAttributeKey,
+
+    /// The value of the node is a `Value::Attribute`, which is only used for
+    /// attribute values and cannot be written as a node value.
+    AttributeValue,
+}
+
+impl fmt::Display for ViolationKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ViolationKind::EmptyKey => "empty node name",
+            ViolationKind::DefaultNode => "default node",
+            ViolationKind::EmptyAttributeKey => "empty attribute key",
+            ViolationKind::AttributeValue => "attribute value used as node value",
+        })
+    }
+}
+
+/// A violation of the invariants of a tree and the path of the node it was
+/// found at.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Violation {
+    pub path: NodePath,
+    pub kind: ViolationKind,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.kind)
+    }
+}
+
+fn check_node(node: &Node, path: &NodePath, violations: &mut Vec<Violation>) {
+    let mut push = |kind| {
+        violations.push(Violation {
+            path: path.clone(),
+            kind,
+        })
+    };
+
+    if *node == Node::default() {
+        push(ViolationKind::DefaultNode);
+    } else if node.key.is_empty() {
+        push(ViolationKind::EmptyKey);
+    }
+    if let Some(Value::Attribute(_)) = node.value {
+        push(ViolationKind::AttributeValue);
+    }
+    if let Some(attributes) = node.attributes() {
+        if attributes.contains_key("") {
+            push(ViolationKind::EmptyAttributeKey);
+        }
+    }
+
+    if let Some(children) = node.children() {
+        for (i, child) in children.iter().enumerate() {
+            let key = &child.key;
+            let total = children.iter().filter(|other| other.key == *key).count();
+            let index = children[..i]
+                .iter()
+                .filter(|other| other.key == *key)
+                .count();
+            let child_path = path.child(key.as_str(), Some(index).filter(|_| total > 1));
+
+            check_node(child, &child_path, violations);
+        }
+    }
+}
+
+impl Node {
+    /// Check this node and all nodes below it for states that the crate
+    /// never creates, but that code changing a tree by hand can leave
+    /// behind, returning every violation found in document order.
+    ///
+    /// Meant for debug builds and tests of applications that change trees
+    /// heavily. Applications that use empty names on purpose can filter out
+    /// `ViolationKind::EmptyKey`. Array values always hold values of a single
+    /// type, so they need no check.
+    pub fn check_invariants(&self) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        check_node(self, &NodePath::root(self.key.as_str()), &mut violations);
+
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+
+    use crate::node::Node;
+    use crate::value::Value;
+
+    use super::ViolationKind;
+
+    #[test]
+    fn test_check_invariants() {
+        let mut node = Node::with_nodes(
+            "root",
+            vec![
+                Node::with_value("item", Value::U8(1)),
+                Node::with_value("item", Value::U8(2)),
+                Node::with_attrs("info", &[("id", "1")]),
+            ],
+        );
+        assert!(node.check_invariants().is_empty());
+
+        let children = node.children_mut().unwrap();
+        let _moved = mem::take(&mut children[1]);
+        children[2].set_attr("", "x");
+        children[2].append_child(Node::with_value("", Value::Attribute("a".into())));
+
+        let violations: Vec<_> = node
+            .check_invariants()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            violations,
+            [
+                "/root/: default node",
+                "/root/info: empty attribute key",
+                "/root/info/: empty node name",
+                "/root/info/: attribute value used as node value",
+            ]
+        );
+        assert_eq!(node.check_invariants()[0].kind, ViolationKind::DefaultNode);
+    }
+}
@@ -0,0 +1,575 @@
+use crate::error::KbinError;
+use crate::node::patch::{Patch, PatchOp, PatchValue};
+use crate::node::Node;
+use crate::value::Value;
+
+/// A single mutation recorded by a `ChangeLog`.
+///
+/// Nodes are addressed by the indices of the children leading to them from
+/// the root, so `[]` is the root itself and `[0, 2]` is the third child of the
+/// root's first child.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    SetValue {
+        path: Vec<usize>,
+        old: Option<Value>,
+        new: Option<Value>,
+    },
+
+    /// Set (`new` is `Some`) or remove (`new` is `None`) an attribute.
+    /// `index` is the position of the attribute within the node's attributes.
+    SetAttr {
+        path: Vec<usize>,
+        key: String,
+        index: usize,
+        old: Option<String>,
+        new: Option<String>,
+    },
+
+    InsertChild {
+        path: Vec<usize>,
+        index: usize,
+        node: Node,
+    },
+
+    RemoveChild {
+        path: Vec<usize>,
+        index: usize,
+        node: Node,
+    },
+}
+
+/// Undo/redo history of changes made through a `JournaledNode`.
+#[derive(Clone, Debug, Default)]
+pub struct ChangeLog {
+    applied: Vec<Change>,
+    undone: Vec<Change>,
+}
+
+/// Editing handle that applies mutations to a node tree and records them in
+/// a `ChangeLog`.
+pub struct JournaledNode<'a> {
+    root: &'a mut Node,
+    log: &'a mut ChangeLog,
+}
+
+fn format_path(path: &[usize]) -> String {
+    path.iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The path of the node at `path` in the form used by `Patch`.
+fn pointer_path(path: &[usize]) -> String {
+    path.iter().map(|index| format!("/{}", index)).collect()
+}
+
+fn node_at<'a>(root: &'a Node, path: &[usize]) -> Result<&'a Node, KbinError> {
+    let mut target = root;
+
+    for &index in path {
+        target = target
+            .children()
+            .and_then(|children| children.get(index))
+            .ok_or_else(|| KbinError::InvalidNodePath {
+                path: format_path(path),
+            })?;
+    }
+
+    Ok(target)
+}
+
+fn node_at_mut<'a>(root: &'a mut Node, path: &[usize]) -> Result<&'a mut Node, KbinError> {
+    let mut target = root;
+
+    for &index in path {
+        target = target
+            .children
+            .as_mut()
+            .and_then(|children| children.get_mut(index))
+            .ok_or_else(|| KbinError::InvalidNodePath {
+                path: format_path(path),
+            })?;
+    }
+
+    Ok(target)
+}
+
+fn insert_attr(node: &mut Node, key: &str, index: usize, value: &str) {
+    let attributes = node.attributes.get_or_insert_with(Default::default);
+    let (current, _) = attributes.insert_full(key.to_owned(), value.to_owned());
+
+    if current != index && index < attributes.len() {
+        attributes.move_index(current, index);
+    }
+}
+
+fn remove_attr(node: &mut Node, key: &str) {
+    if let Some(attributes) = node.attributes.as_mut() {
+        attributes.shift_remove(key);
+    }
+}
+
+fn insert_child(node: &mut Node, index: usize, child: Node) -> Result<(), KbinError> {
+    let children = node.children.get_or_insert_with(Default::default);
+
+    if index > children.len() {
+        return Err(KbinError::ChildIndexOutOfRange {
+            index,
+            len: children.len(),
+        });
+    }
+    children.insert(index, child);
+
+    Ok(())
+}
+
+fn remove_child(node: &mut Node, index: usize) -> Result<Node, KbinError> {
+    let len = node.children.as_ref().map_or(0, Vec::len);
+
+    match node.children.as_mut() {
+        Some(children) if index < len => Ok(children.remove(index)),
+        _ => Err(KbinError::ChildIndexOutOfRange { index, len }),
+    }
+}
+
+impl Change {
+    fn apply(&self, root: &mut Node) -> Result<(), KbinError> {
+        match self {
+            Change::SetValue { path, new, .. } => {
+                node_at_mut(root, path)?.value = new.clone();
+            },
+            Change::SetAttr {
+                path,
+                key,
+                index,
+                new,
+                ..
+            } => {
+                let node = node_at_mut(root, path)?;
+                match new {
+                    Some(value) => insert_attr(node, key, *index, value),
+                    None => remove_attr(node, key),
+                };
+            },
+            Change::InsertChild { path, index, node } => {
+                insert_child(node_at_mut(root, path)?, *index, node.clone())?;
+            },
+            Change::RemoveChild { path, index, .. } => {
+                remove_child(node_at_mut(root, path)?, *index)?;
+            },
+        };
+
+        Ok(())
+    }
+
+    fn revert(&self, root: &mut Node) -> Result<(), KbinError> {
+        match self {
+            Change::SetValue { path, old, .. } => {
+                node_at_mut(root, path)?.value = old.clone();
+            },
+            Change::SetAttr {
+                path,
+                key,
+                index,
+                old,
+                ..
+            } => {
+                let node = node_at_mut(root, path)?;
+                match old {
+                    Some(value) => insert_attr(node, key, *index, value),
+                    None => remove_attr(node, key),
+                };
+            },
+            Change::InsertChild { path, index, .. } => {
+                remove_child(node_at_mut(root, path)?, *index)?;
+            },
+            Change::RemoveChild { path, index, node } => {
+                insert_child(node_at_mut(root, path)?, *index, node.clone())?;
+            },
+        };
+
+        Ok(())
+    }
+
+    fn to_node(&self) -> Node {
+        match self {
+            Change::SetValue { path, new, .. } => {
+                let mut node = Node::with_attrs("set_value", &[("path", &format_path(path))]);
+                node.set_value(new.clone());
+                node
+            },
+            Change::SetAttr {
+                path,
+                key,
+                new: Some(value),
+                ..
+            } => Node::with_attrs(
+                "set_attr",
+                &[("path", &format_path(path)), ("key", key), ("value", value)],
+            ),
+            Change::SetAttr {
+                path,
+                key,
+                new: None,
+                ..
+            } => Node::with_attrs("remove_attr", &[("path", &format_path(path)), ("key", key)]),
+            Change::InsertChild { path, index, node } => Node::with(
+                "insert_child",
+                &[("path", &format_path(path)), ("index", &index.to_string())],
+                vec![node.clone()],
+            ),
+            Change::RemoveChild { path, index, .. } => Node::with_attrs(
+                "remove_child",
+                &[("path", &format_path(path)), ("index", &index.to_string())],
+            ),
+        }
+    }
+
+    fn to_op(&self) -> PatchOp {
+        match self {
+            Change::SetValue { path, new, .. } => PatchOp::Replace {
+                path: pointer_path(path),
+                value: PatchValue::Value(new.clone()),
+            },
+            Change::SetAttr {
+                path,
+                key,
+                old,
+                new: Some(value),
+                ..
+            } => {
+                let path = format!("{}/@{}", pointer_path(path), key);
+                let value = PatchValue::Attribute(value.clone());
+
+                if old.is_some() {
+                    PatchOp::Replace { path, value }
+                } else {
+                    PatchOp::Add { path, value }
+                }
+            },
+            Change::SetAttr {
+                path,
+                key,
+                new: None,
+                ..
+            } => PatchOp::Remove {
+                path: format!("{}/@{}", pointer_path(path), key),
+            },
+            Change::InsertChild { path, index, node } => PatchOp::Add {
+                path: format!("{}/{}", pointer_path(path), index),
+                value: PatchValue::Node(node.clone()),
+            },
+            Change::RemoveChild { path, index, .. } => PatchOp::Remove {
+                path: format!("{}/{}", pointer_path(path), index),
+            },
+        }
+    }
+}
+
+impl ChangeLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start an editing session on `root`, recording every change made
+    /// through the returned handle.
+    pub fn edit<'a>(&'a mut self, root: &'a mut Node) -> JournaledNode<'a> {
+        JournaledNode { root, log: self }
+    }
+
+    /// The changes that are currently applied, oldest first.
+    #[inline]
+    pub fn changes(&self) -> &[Change] {
+        &self.applied
+    }
+
+    #[inline]
+    pub fn can_undo(&self) -> bool {
+        !self.applied.is_empty()
+    }
+
+    #[inline]
+    pub fn can_redo(&self) -> bool {
+        !self.undone.is_empty()
+    }
+
+    /// Revert the most recent change. Returns `false` if there was nothing to
+    /// undo.
+    pub fn undo(&mut self, root: &mut Node) -> Result<bool, KbinError> {
+        let change = match self.applied.pop() {
+            Some(change) => change,
+            None => return Ok(false),
+        };
+
+        if let Err(e) = change.revert(root) {
+            self.applied.push(change);
+            return Err(e);
+        }
+        self.undone.push(change);
+
+        Ok(true)
+    }
+
+    /// Reapply the most recently undone change. Returns `false` if there was
+    /// nothing to redo.
+    pub fn redo(&mut self, root: &mut Node) -> Result<bool, KbinError> {
+        let change = match self.undone.pop() {
+            Some(change) => change,
+            None => return Ok(false),
+        };
+
+        if let Err(e) = change.apply(root) {
+            self.undone.push(change);
+            return Err(e);
+        }
+        self.applied.push(change);
+
+        Ok(true)
+    }
+
+    /// Export the applied changes as a `patch` node with one child per
+    /// change, suitable for writing with `to_binary` or `to_text_xml`.
+    pub fn to_patch(&self) -> Node {
+        Node::with_nodes(
+            "patch",
+            self.applied.iter().map(Change::to_node).collect::<Vec<_>>(),
+        )
+    }
+
+    /// Export the applied changes as a `Patch` that can be applied to a copy
+    /// of the original tree with `apply_patch`.
+    pub fn to_patch_ops(&self) -> Patch {
+        let mut patch = Patch::new();
+        for change in &self.applied {
+            patch.push(change.to_op());
+        }
+
+        patch
+    }
+
+    fn record(&mut self, root: &mut Node, change: Change) -> Result<(), KbinError> {
+        change.apply(root)?;
+        self.applied.push(change);
+        self.undone.clear();
+
+        Ok(())
+    }
+}
+
+impl<'a> JournaledNode<'a> {
+    /// Read-only access to the tree being edited.
+    #[inline]
+    pub fn root(&self) -> &Node {
+        self.root
+    }
+
+    pub fn set_value(
+        &mut self,
+        path: &[usize],
+        value: Option<Value>,
+    ) -> Result<Option<Value>, KbinError> {
+        let old = node_at(self.root, path)?.value().cloned();
+        let change = Change::SetValue {
+            path: path.to_vec(),
+            old: old.clone(),
+            new: value,
+        };
+        self.log.record(self.root, change)?;
+
+        Ok(old)
+    }
+
+    pub fn set_attr<K, V>(
+        &mut self,
+        path: &[usize],
+        key: K,
+        value: V,
+    ) -> Result<Option<String>, KbinError>
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let key = key.into();
+        let node = node_at(self.root, path)?;
+        let existing = node
+            .attributes()
+            .and_then(|attributes| attributes.get_full(&key))
+            .map(|(index, _, value)| (index, value.clone()));
+        let (index, old) = match existing {
+            Some((index, value)) => (index, Some(value)),
+            None => (
+                node.attributes().map_or(0, |attributes| attributes.len()),
+                None,
+            ),
+        };
+
+        let change = Change::SetAttr {
+            path: path.to_vec(),
+            key,
+            index,
+            old: old.clone(),
+            new: Some(value.into()),
+        };
+        self.log.record(self.root, change)?;
+
+        Ok(old)
+    }
+
+    pub fn remove_attr(&mut self, path: &[usize], key: &str) -> Result<Option<String>, KbinError> {
+        let existing = node_at(self.root, path)?
+            .attributes()
+            .and_then(|attributes| attributes.get_full(key))
+            .map(|(index, _, value)| (index, value.clone()));
+        let (index, old) = match existing {
+            Some(existing) => existing,
+            None => return Ok(None),
+        };
+
+        let change = Change::SetAttr {
+            path: path.to_vec(),
+            key: key.to_owned(),
+            index,
+            old: Some(old.clone()),
+            new: None,
+        };
+        self.log.record(self.root, change)?;
+
+        Ok(Some(old))
+    }
+
+    pub fn insert_child(
+        &mut self,
+        path: &[usize],
+        index: usize,
+        node: Node,
+    ) -> Result<(), KbinError> {
+        let change = Change::InsertChild {
+            path: path.to_vec(),
+            index,
+            node,
+        };
+
+        self.log.record(self.root, change)
+    }
+
+    pub fn append_child(&mut self, path: &[usize], node: Node) -> Result<(), KbinError> {
+        let index = node_at(self.root, path)?.children().map_or(0, Vec::len);
+
+        self.insert_child(path, index, node)
+    }
+
+    pub fn remove_child(&mut self, path: &[usize], index: usize) -> Result<Node, KbinError> {
+        let parent = node_at(self.root, path)?;
+        let len = parent.children().map_or(0, Vec::len);
+        let node = parent
+            .children()
+            .and_then(|children| children.get(index))
+            .cloned()
+            .ok_or(KbinError::ChildIndexOutOfRange { index, len })?;
+
+        let change = Change::RemoveChild {
+            path: path.to_vec(),
+            index,
+            node: node.clone(),
+        };
+        self.log.record(self.root, change)?;
+
+        Ok(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::node::patch::apply_patch;
+
+    use super::*;
+
+    #[test]
+    fn test_undo_redo() {
+        let original = Node::with(
+            "root",
+            &[("a", "1"), ("b", "2"), ("c", "3")],
+            vec![Node::with_value("child", Value::U8(1))],
+        );
+        let mut root = original.clone();
+        let mut log = ChangeLog::new();
+
+        {
+            let mut editor = log.edit(&mut root);
+            editor.set_value(&[0], Some(Value::U8(2))).unwrap();
+            editor.remove_attr(&[], "a").unwrap();
+            editor.set_attr(&[], "d", "4").unwrap();
+            editor.append_child(&[], Node::new("extra")).unwrap();
+            editor.remove_child(&[], 0).unwrap();
+        }
+        let edited = root.clone();
+        assert_eq!(log.changes().len(), 5);
+        assert_eq!(edited.attr("b"), Some("2"));
+        assert_eq!(edited.children().unwrap()[0].key(), "extra");
+
+        while log.undo(&mut root).unwrap() {}
+        assert_eq!(root, original);
+        assert!(!log.can_undo());
+
+        while log.redo(&mut root).unwrap() {}
+        assert_eq!(root, edited);
+
+        let patch = log.to_patch();
+        let keys: Vec<_> = patch.children_iter().map(Node::key).collect();
+        assert_eq!(
+            keys,
+            [
+                "set_value",
+                "remove_attr",
+                "set_attr",
+                "insert_child",
+                "remove_child"
+            ]
+        );
+        assert!(crate::to_binary(&patch).is_ok());
+    }
+
+    #[test]
+    fn test_patch_ops() {
+        let original = Node::with(
+            "root",
+            &[("a", "1"), ("b", "2")],
+            vec![Node::with_nodes(
+                "child",
+                vec![Node::with_value("value", Value::U8(1))],
+            )],
+        );
+        let mut root = original.clone();
+        let mut log = ChangeLog::new();
+
+        {
+            let mut editor = log.edit(&mut root);
+            editor.set_value(&[0, 0], Some(Value::U8(2))).unwrap();
+            editor.set_attr(&[], "a", "3").unwrap();
+            editor.set_attr(&[0], "id", "4").unwrap();
+            editor.remove_attr(&[], "b").unwrap();
+            editor.append_child(&[0], Node::new("extra")).unwrap();
+            editor.remove_child(&[0], 0).unwrap();
+        }
+
+        let patch = log.to_patch_ops();
+        let ops: Vec<_> = patch.ops().iter().map(ToString::to_string).collect();
+        assert_eq!(
+            ops,
+            [
+                "replace /0/0",
+                "replace /@a",
+                "add /0/@id",
+                "remove /@b",
+                "add /0/1",
+                "remove /0/0"
+            ]
+        );
+
+        let mut replayed = original.clone();
+        apply_patch(&mut replayed, &patch).unwrap();
+        assert_eq!(replayed, root);
+    }
+}
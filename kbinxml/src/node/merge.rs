@@ -0,0 +1,227 @@
+use crate::node::Node;
+
+/// How `Node::merge` combines another tree into a node.
+///
+/// Every strategy copies the attributes of the other node, replacing those
+/// with the same key. All but `Attributes` also copy its value, if it has
+/// one. The strategies differ in how the children are combined.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeStrategy<'a> {
+    /// Replace each matching child with the child of the other node. Children
+    /// without a match are appended.
+    Overwrite { match_attribute: Option<&'a str> },
+
+    /// Append every child of the other node.
+    Append,
+
+    /// Merge each matching child with the child of the other node using the
+    /// same strategy. Children without a match are appended.
+    Deep { match_attribute: Option<&'a str> },
+
+    /// Like `Deep`, but only attributes are copied. Values are kept and
+    /// children without a match are skipped, so a document of attribute
+    /// tweaks cannot add nodes or change anything else.
+    Attributes { match_attribute: Option<&'a str> },
+}
+
+impl MergeStrategy<'_> {
+    #[inline]
+    fn is_attributes(&self) -> bool {
+        matches!(self, MergeStrategy::Attributes { .. })
+    }
+}
+
+/// Find the first child of `children` that has not been matched yet and has
+/// the key of `other`. With `match_attribute`, a child of `other` that has
+/// the attribute only matches children with the same attribute value.
+fn find_match(
+    children: &[Node],
+    matched: &[bool],
+    other: &Node,
+    match_attribute: Option<&str>,
+) -> Option<usize> {
+    let attribute = match_attribute.and_then(|key| Some((key, other.attr(key)?)));
+
+    children
+        .iter()
+        .zip(matched)
+        .position(|(child, &matched)| {
+            !matched &&
+                child.key == other.key &&
+                attribute.is_none_or(|(key, value)| child.attr(key) == Some(value))
+        })
+}
+
+impl Node {
+    /// Merge `other` into this node, for example to layer a patch document
+    /// over a base document. The key of this node is kept.
+    ///
+    /// Children are matched by key in order, so the second `item` child of
+    /// `other` matches the second `item` child of this node. With a
+    /// `match_attribute` such as `id`, children that have the attribute are
+    /// matched by its value instead.
+    pub fn merge(&mut self, other: &Node, strategy: MergeStrategy<'_>) {
+        if let Some(attributes) = &other.attributes {
+            let own = self.attributes.get_or_insert_with(Default::default);
+            for (key, value) in attributes {
+                own.insert(key.clone(), value.clone());
+            }
+        }
+
+        if let Some(value) = other.value.as_ref().filter(|_| !strategy.is_attributes()) {
+            self.value = Some(value.clone());
+        }
+
+        let other_children = match &other.children {
+            Some(children) if !children.is_empty() => children,
+            _ => return,
+        };
+
+        // The other strategies add at least one child here, but `Attributes`
+        // never adds children, so there is nothing to merge into
+        let children = match &mut self.children {
+            None if strategy.is_attributes() => return,
+            children => children.get_or_insert_with(Default::default),
+        };
+
+        let match_attribute = match strategy {
+            MergeStrategy::Append => {
+                children.extend(other_children.iter().cloned());
+                return;
+            },
+            MergeStrategy::Overwrite { match_attribute } |
+            MergeStrategy::Deep { match_attribute } |
+            MergeStrategy::Attributes { match_attribute } => match_attribute,
+        };
+
+        let mut matched = vec![false; children.len()];
+        for other_child in other_children {
+            match find_match(children, &matched, other_child, match_attribute) {
+                Some(index) => {
+                    matched[index] = true;
+
+                    if let MergeStrategy::Overwrite { .. } = strategy {
+                        children[index] = other_child.clone();
+                    } else {
+                        children[index].merge(other_child, strategy);
+                    }
+                },
+                None if strategy.is_attributes() => {},
+                None => {
+                    matched.push(true);
+                    children.push(other_child.clone());
+                },
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::node::Node;
+    use crate::value::Value;
+
+    use super::MergeStrategy;
+
+    #[test]
+    fn test_merge() {
+        let base = Node::with(
+            "data",
+            &[("version", "1")],
+            vec![
+                Node::with("item", &[("id", "1")], vec![Node::with_value("a", Value::U8(1))]),
+                Node::with("item", &[("id", "2")], vec![Node::with_value("a", Value::U8(2))]),
+            ],
+        );
+        let patch = Node::with(
+            "patch",
+            &[("version", "2")],
+            vec![
+                Node::with("item", &[("id", "2")], vec![Node::with_value("b", Value::U8(3))]),
+                Node::with_attrs("item", &[("id", "3")]),
+            ],
+        );
+
+        let mut node = base.clone();
+        node.merge(&patch, MergeStrategy::Append);
+        assert_eq!(node.key(), "data");
+        assert_eq!(node.attr("version"), Some("2"));
+        assert_eq!(node.children().unwrap().len(), 4);
+
+        let mut node = base.clone();
+        node.merge(
+            &patch,
+            MergeStrategy::Overwrite {
+                match_attribute: None,
+            },
+        );
+        let children = node.children().unwrap();
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[0], patch.children().unwrap()[0]);
+        assert_eq!(children[1], patch.children().unwrap()[1]);
+
+        let mut node = base.clone();
+        node.merge(
+            &patch,
+            MergeStrategy::Deep {
+                match_attribute: Some("id"),
+            },
+        );
+        let children = node.children().unwrap();
+        assert_eq!(children.len(), 3);
+        assert_eq!(children[0], base.children().unwrap()[0]);
+        assert_eq!(
+            children[1],
+            Node::with(
+                "item",
+                &[("id", "2")],
+                vec![
+                    Node::with_value("a", Value::U8(2)),
+                    Node::with_value("b", Value::U8(3)),
+                ],
+            )
+        );
+        assert_eq!(children[2].attr("id"), Some("3"));
+    }
+
+    #[test]
+    fn test_merge_attributes() {
+        let base = Node::with(
+            "data",
+            &[("version", "1")],
+            vec![
+                Node::with_attrs("item", &[("id", "1"), ("price", "100")]),
+                Node::with("item", &[("id", "2")], vec![Node::with_value("a", Value::U8(2))]),
+            ],
+        );
+        let patch = Node::with(
+            "patch",
+            &[("version", "2")],
+            vec![
+                Node::with(
+                    "item",
+                    &[("id", "2"), ("price", "50")],
+                    vec![Node::with_value("a", Value::U8(9)), Node::new("b")],
+                ),
+                Node::with_attrs("item", &[("id", "3")]),
+                Node::with("item", &[("id", "1")], vec![Node::new("c")]),
+            ],
+        );
+
+        let mut node = base.clone();
+        node.merge(
+            &patch,
+            MergeStrategy::Attributes {
+                match_attribute: Some("id"),
+            },
+        );
+        assert_eq!(node.attr("version"), Some("2"));
+        let children = node.children().unwrap();
+        assert_eq!(children.len(), 2);
+        // No empty list of children is added to nodes without children
+        assert_eq!(children[0], base.children().unwrap()[0]);
+        assert!(children[0].children().is_none());
+        assert_eq!(children[1].attr("price"), Some("50"));
+        assert_eq!(children[1].children(), base.children().unwrap()[1].children());
+    }
+}
@@ -1,16 +1,48 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::error::Error;
 use std::fmt;
 use std::iter::IntoIterator;
 use std::mem;
+use std::str::FromStr;
 
 use indexmap::IndexMap;
 
+use crate::error::KbinError;
+use crate::node_types::StandardType;
+use crate::options::OptionalValuePolicy;
 use crate::value::Value;
 
+use self::annotation::Annotations;
+use self::id::IdSlot;
+use self::namespace::NamespaceTag;
+
+mod annotation;
+mod attrs;
 mod collection;
 mod definition;
+mod flat;
+mod id;
+mod invariants;
+mod journal;
+mod merge;
+mod namespace;
+mod patch;
+mod path;
+mod sanitize;
 
-pub use self::collection::NodeCollection;
+pub use self::attrs::{FromAttrs, ToAttrs};
+pub use self::collection::{ChildrenNamed, IterValues, NodeCollection};
 pub use self::definition::{Key, NodeData, NodeDefinition};
+pub use self::flat::{FlatEntry, Flatten};
+pub use self::id::NodeId;
+pub use self::invariants::{Violation, ViolationKind};
+pub use self::journal::{Change, ChangeLog, JournaledNode};
+pub use self::merge::MergeStrategy;
+pub use self::patch::{apply_patch, Patch, PatchOp, PatchValue};
+pub use self::path::{NodePath, PathSegment};
+pub use self::sanitize::{sanitize, SanitizeRules};
+pub(crate) use self::path::{child_error, PathError};
 
 // The attributes argument is very hard to generalize
 fn convert_attributes(attrs: &[(&str, &str)]) -> IndexMap<String, String> {
@@ -20,6 +52,23 @@ fn convert_attributes(attrs: &[(&str, &str)]) -> IndexMap<String, String> {
         .collect()
 }
 
+fn try_convert_attributes(attrs: &[(&str, &str)]) -> Result<IndexMap<String, String>, KbinError> {
+    let mut attributes = IndexMap::with_capacity(attrs.len());
+
+    for (key, value) in attrs {
+        if attributes
+            .insert(String::from(*key), String::from(*value))
+            .is_some()
+        {
+            return Err(KbinError::DuplicateAttribute {
+                key: String::from(*key),
+            });
+        }
+    }
+
+    Ok(attributes)
+}
+
 fn parse_index(s: &str) -> Option<usize> {
     if s.starts_with('+') || (s.starts_with('0') && s.len() != 1) {
         return None;
@@ -27,16 +76,35 @@ fn parse_index(s: &str) -> Option<usize> {
     s.parse().ok()
 }
 
+macro_rules! child_value_accessors {
+    ($($method:ident => $type:ty),*$(,)?) => {
+        $(
+            pub fn $method(&self, key: &str) -> Result<$type, KbinError> {
+                self.child_value_as(key)
+            }
+        )*
+    };
+}
+
 pub struct OptionIterator<T: IntoIterator> {
     inner: Option<T::IntoIter>,
 }
 
+/// Iterator over the descendants of a `Node` in document order. Created by
+/// `Node::descendants`.
+pub struct Descendants<'a> {
+    stack: Vec<&'a Node>,
+}
+
 #[derive(Clone, Default, PartialEq)]
 pub struct Node {
     key: String,
     attributes: Option<IndexMap<String, String>>,
     children: Option<Vec<Node>>,
     value: Option<Value>,
+    namespace: NamespaceTag,
+    annotations: Annotations,
+    id: IdSlot,
 }
 
 impl fmt::Debug for Node {
@@ -53,6 +121,9 @@ impl fmt::Debug for Node {
         if let Some(ref value) = self.value {
             d.field("value", value);
         }
+        if let Some(namespace) = self.namespace() {
+            d.field("namespace", &namespace);
+        }
 
         d.finish()
     }
@@ -68,6 +139,9 @@ impl Node {
             attributes: None,
             children: None,
             value: None,
+            namespace: NamespaceTag::default(),
+            annotations: Annotations::default(),
+            id: IdSlot::default(),
         }
     }
 
@@ -80,9 +154,29 @@ impl Node {
             attributes: Some(convert_attributes(attrs)),
             children: None,
             value: None,
+            namespace: NamespaceTag::default(),
+            annotations: Annotations::default(),
+            id: IdSlot::default(),
         }
     }
 
+    /// Like `with_attrs`, but fails on duplicate attribute keys instead of
+    /// keeping the last value.
+    pub fn try_with_attrs<K>(key: K, attrs: &[(&str, &str)]) -> Result<Self, KbinError>
+    where
+        K: Into<String>,
+    {
+        Ok(Self {
+            key: key.into(),
+            attributes: Some(try_convert_attributes(attrs)?),
+            children: None,
+            value: None,
+            namespace: NamespaceTag::default(),
+            annotations: Annotations::default(),
+            id: IdSlot::default(),
+        })
+    }
+
     pub fn with_value<K>(key: K, value: Value) -> Self
     where
         K: Into<String>,
@@ -92,6 +186,9 @@ impl Node {
             attributes: None,
             children: None,
             value: Some(value),
+            namespace: NamespaceTag::default(),
+            annotations: Annotations::default(),
+            id: IdSlot::default(),
         }
     }
 
@@ -105,6 +202,9 @@ impl Node {
             attributes: None,
             children: Some(nodes.into()),
             value: None,
+            namespace: NamespaceTag::default(),
+            annotations: Annotations::default(),
+            id: IdSlot::default(),
         }
     }
 
@@ -118,6 +218,9 @@ impl Node {
             attributes: Some(convert_attributes(attrs)),
             children: Some(nodes.into()),
             value: None,
+            namespace: NamespaceTag::default(),
+            annotations: Annotations::default(),
+            id: IdSlot::default(),
         }
     }
 
@@ -130,9 +233,19 @@ impl Node {
             attributes: Some(convert_attributes(attrs)),
             children: None,
             value: Some(value),
+            namespace: NamespaceTag::default(),
+            annotations: Annotations::default(),
+            id: IdSlot::default(),
         }
     }
 
+    /// Convert a `NodeCollection`, decoding names and strings with the
+    /// encoding of each definition.
+    #[inline]
+    pub fn from_collection(collection: &NodeCollection) -> Result<Self, KbinError> {
+        collection.as_node()
+    }
+
     #[inline]
     pub fn key(&self) -> &str {
         &self.key
@@ -178,6 +291,14 @@ impl Node {
         OptionIterator::new(self.children_mut())
     }
 
+    /// Iterate over the children of this node, their children, and so on,
+    /// depth first in document order. The node itself is not included.
+    pub fn descendants(&self) -> Descendants<'_> {
+        Descendants {
+            stack: self.children.iter().flatten().rev().collect(),
+        }
+    }
+
     pub fn attr(&self, key: &str) -> Option<&str> {
         self.attributes()
             .and_then(|attributes| attributes.get(key).map(String::as_str))
@@ -188,6 +309,198 @@ impl Node {
             .and_then(|attributes| attributes.get_mut(key))
     }
 
+    /// Parse the value of the attribute with the given key.
+    pub fn attr_parsed<T>(&self, key: &str) -> Result<T, KbinError>
+    where
+        T: FromStr,
+        T::Err: Error + Send + Sync + 'static,
+    {
+        let value = self.attr(key).ok_or_else(|| KbinError::AttributeNotFound {
+            key: key.to_owned(),
+        })?;
+
+        value.parse().map_err(|e| KbinError::AttributeParse {
+            key: key.to_owned(),
+            source: Box::new(e),
+        })
+    }
+
+    /// Parse the value of the attribute with the given key as a value of
+    /// `node_type`, from the same text format as node values in text XML.
+    pub fn attr_value(&self, key: &str, node_type: StandardType) -> Result<Value, KbinError> {
+        let value = self.attr(key).ok_or_else(|| KbinError::AttributeNotFound {
+            key: key.to_owned(),
+        })?;
+
+        Value::from_string(node_type, value, false, 0).map_err(|e| KbinError::AttributeParse {
+            key: key.to_owned(),
+            source: Box::new(e),
+        })
+    }
+
+    /// Convert the value of this node to `T`.
+    pub fn value_as<T>(&self) -> Result<T, KbinError>
+    where
+        T: for<'a> TryFrom<&'a Value, Error = KbinError>,
+    {
+        let value = self.value().ok_or_else(|| KbinError::NoNodeValue {
+            key: self.key.clone(),
+        })?;
+
+        T::try_from(value)
+    }
+
+    /// The value of this node as a string, borrowed from the node.
+    pub fn value_str(&self) -> Result<&str, KbinError> {
+        self.value()
+            .ok_or_else(|| KbinError::NoNodeValue {
+                key: self.key.clone(),
+            })?
+            .as_str()
+    }
+
+    /// Like `value_str`, for the first child node with the given key.
+    pub fn child_value_str(&self, key: &str) -> Result<&str, KbinError> {
+        self.get_child(key)
+            .ok_or_else(|| KbinError::ChildNotFound {
+                key: key.to_owned(),
+            })?
+            .value_str()
+    }
+
+    /// Convert the value of the first child node with the given key to `T`.
+    pub fn child_value_as<T>(&self, key: &str) -> Result<T, KbinError>
+    where
+        T: for<'a> TryFrom<&'a Value, Error = KbinError>,
+    {
+        self.get_child(key)
+            .ok_or_else(|| KbinError::ChildNotFound {
+                key: key.to_owned(),
+            })?
+            .value_as()
+    }
+
+    /// Like `child_value_as`, but a missing child is `None` rather than an
+    /// error.
+    #[inline]
+    pub fn child_value_as_opt<T>(&self, key: &str) -> Result<Option<T>, KbinError>
+    where
+        T: for<'a> TryFrom<&'a Value, Error = KbinError>,
+    {
+        self.child_value_as_opt_with(key, OptionalValuePolicy::Omit)
+    }
+
+    /// Like `child_value_as_opt`, reading `None` as written by
+    /// `append_child_value_with` with the same policy.
+    pub fn child_value_as_opt_with<T>(
+        &self,
+        key: &str,
+        policy: OptionalValuePolicy,
+    ) -> Result<Option<T>, KbinError>
+    where
+        T: for<'a> TryFrom<&'a Value, Error = KbinError>,
+    {
+        match self.get_child(key) {
+            Some(child) if policy == OptionalValuePolicy::Empty && child.value.is_none() => {
+                Ok(None)
+            },
+            child => child.map(Node::value_as).transpose(),
+        }
+    }
+
+    /// Convert every child with the given key with `f`, in document order.
+    /// Repeated siblings, such as the entries of a music database, become one
+    /// element each.
+    ///
+    /// Errors from `f` are wrapped in `KbinError::AtPath` with the path of
+    /// the failing child, so nested calls report the full path.
+    pub fn children_as<T, F>(&self, key: &str, mut f: F) -> Result<Vec<T>, KbinError>
+    where
+        F: FnMut(&Node) -> Result<T, KbinError>,
+    {
+        let total = self
+            .children_iter()
+            .filter(|child| child.key == key)
+            .count();
+
+        self.children_iter()
+            .filter(|child| child.key == key)
+            .enumerate()
+            .map(|(i, child)| {
+                f(child).map_err(|e| {
+                    let index = if total > 1 { Some(i) } else { None };
+                    child_error(&self.key, key, index, e)
+                })
+            })
+            .collect()
+    }
+
+    /// Convert every child with `f`, keyed by the name of the child, for
+    /// nodes whose children model a map with dynamic keys. Children must
+    /// have unique names. Attributes are not included.
+    ///
+    /// Errors from `f` are wrapped in `KbinError::AtPath` like in
+    /// `children_as`.
+    pub fn children_map<T, F>(&self, mut f: F) -> Result<IndexMap<String, T>, KbinError>
+    where
+        F: FnMut(&Node) -> Result<T, KbinError>,
+    {
+        let mut map = IndexMap::with_capacity(self.children().map_or(0, Vec::len));
+        for child in self.children_iter() {
+            if map.contains_key(&child.key) {
+                return Err(KbinError::DuplicateChild {
+                    key: child.key.clone(),
+                });
+            }
+
+            let value = f(child).map_err(|e| child_error(&self.key, &child.key, None, e))?;
+            map.insert(child.key.clone(), value);
+        }
+
+        Ok(map)
+    }
+
+    /// Convert the values of every child with the given key to `T`. A child
+    /// holding an array contributes each element, so a list written as one
+    /// array node and one written as repeated sibling nodes read the same,
+    /// and a single child reads as a list of one.
+    pub fn child_values_as<T>(&self, key: &str) -> Result<Vec<T>, KbinError>
+    where
+        T: for<'a> TryFrom<&'a Value, Error = KbinError>,
+    {
+        let mut output = Vec::new();
+        let result = self.children_as(key, |child| match child.value() {
+            Some(Value::Array(values)) => {
+                for i in 0..values.len() {
+                    let value = values.get(i).ok_or(KbinError::InvalidState)?;
+                    output.push(T::try_from(&value)?);
+                }
+
+                Ok(())
+            },
+            _ => {
+                output.push(child.value_as()?);
+                Ok(())
+            },
+        });
+
+        result.map(|_| output)
+    }
+
+    child_value_accessors! {
+        child_value_i8 => i8,
+        child_value_u8 => u8,
+        child_value_i16 => i16,
+        child_value_u16 => u16,
+        child_value_i32 => i32,
+        child_value_u32 => u32,
+        child_value_i64 => i64,
+        child_value_u64 => u64,
+        child_value_bool => bool,
+        child_value_string => String,
+        child_value_char => char,
+    }
+
     pub fn into_key_and_value(self) -> (String, Option<Value>) {
         (self.key, self.value)
     }
@@ -205,27 +518,242 @@ impl Node {
         attributes.insert(key.into(), value.into())
     }
 
+    /// Set the attribute with the given key to the text of `value`, as it is
+    /// written for node values in text XML. Attributes are always stored and
+    /// written as strings, so read it back with `attr_value` or
+    /// `attr_parsed`.
+    pub fn set_attr_value<K>(&mut self, key: K, value: Value) -> Option<String>
+    where
+        K: Into<String>,
+    {
+        self.set_attr(key, value.to_string())
+    }
+
     pub fn remove_attr(&mut self, key: &str) -> Option<String> {
         self.attributes
             .as_mut()
             .and_then(|attributes| attributes.swap_remove(key))
     }
 
+    /// Like `remove_attr`, but keeps the order of the other attributes
+    /// instead of moving the last one into the place of the removed one.
+    pub fn shift_remove_attr(&mut self, key: &str) -> Option<String> {
+        self.attributes
+            .as_mut()
+            .and_then(|attributes| attributes.shift_remove(key))
+    }
+
     pub fn sort_attrs(&mut self) {
         if let Some(ref mut attributes) = self.attributes {
             attributes.sort_keys();
         }
     }
 
+    pub fn sort_attrs_by<F>(&mut self, mut compare: F)
+    where
+        F: FnMut(&str, &str, &str, &str) -> Ordering,
+    {
+        if let Some(ref mut attributes) = self.attributes {
+            attributes.sort_by(|k1, v1, k2, v2| compare(k1, v1, k2, v2));
+        }
+    }
+
+    /// Sort the children with the given comparison, for example to put the
+    /// children of two trees in the same order before comparing or hashing
+    /// them. The sort is stable, so children that compare equal keep their
+    /// order.
+    pub fn sort_children_by<F>(&mut self, compare: F)
+    where
+        F: FnMut(&Node, &Node) -> Ordering,
+    {
+        if let Some(ref mut children) = self.children {
+            children.sort_by(compare);
+        }
+    }
+
+    /// Sort the children by key.
+    pub fn sort_children_by_key(&mut self) {
+        self.sort_children_by(|a, b| a.key.cmp(&b.key));
+    }
+
+    /// Sort the children by the value of the attribute `key`, such as `id`.
+    /// Values that are integers are compared as numbers and come first, then
+    /// other values as text, then the children without the attribute.
+    pub fn sort_children_by_attr(&mut self, key: &str) {
+        fn sort_key<'a>(node: &'a Node, key: &str) -> (u8, i64, &'a str) {
+            match node.attr(key) {
+                Some(value) => match value.parse() {
+                    Ok(number) => (0, number, value),
+                    Err(_) => (1, 0, value),
+                },
+                None => (2, 0, ""),
+            }
+        }
+
+        self.sort_children_by(|a, b| sort_key(a, key).cmp(&sort_key(b, key)));
+    }
+
+    /// Move the attribute `key` to position `index`, shifting the attributes
+    /// in between. Returns `false` if the attribute does not exist or `index`
+    /// is out of range.
+    pub fn move_attr(&mut self, key: &str, index: usize) -> bool {
+        let attributes = match self.attributes {
+            Some(ref mut attributes) if index < attributes.len() => attributes,
+            _ => return false,
+        };
+
+        match attributes.get_index_of(key) {
+            Some(from) => {
+                attributes.move_index(from, index);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Put the attributes named in `order` first, in that order. Attributes
+    /// not named keep their relative order after them.
+    pub fn reorder_attrs(&mut self, order: &[&str]) {
+        if let Some(ref mut attributes) = self.attributes {
+            let mut index = 0;
+
+            for key in order {
+                if let Some(from) = attributes.get_index_of(*key) {
+                    if from >= index {
+                        attributes.move_index(from, index);
+                        index += 1;
+                    }
+                }
+            }
+        }
+    }
+
     pub fn append_child(&mut self, value: Node) {
         let children = self.children.get_or_insert_with(Default::default);
         children.push(value);
     }
 
+    /// Append a child with the given value if there is one. No node is added
+    /// for `None`, so optional fields can be omitted from the output.
+    #[inline]
+    pub fn append_child_value<K>(&mut self, key: K, value: Option<Value>)
+    where
+        K: Into<String>,
+    {
+        self.append_child_value_with(key, value, OptionalValuePolicy::Omit);
+    }
+
+    /// Like `append_child_value`, with `policy` deciding whether a node is
+    /// added for `None`.
+    pub fn append_child_value_with<K>(
+        &mut self,
+        key: K,
+        value: Option<Value>,
+        policy: OptionalValuePolicy,
+    ) where
+        K: Into<String>,
+    {
+        match (value, policy) {
+            (Some(value), _) => self.append_child(Node::with_value(key, value)),
+            (None, OptionalValuePolicy::Empty) => self.append_child(Node::new(key)),
+            (None, OptionalValuePolicy::Omit) => {},
+        }
+    }
+
+    /// Append one child with the given key per item, filled in by `f`. This
+    /// is the inverse of `children_as`.
+    pub fn append_children<K, T, I, F>(
+        &mut self,
+        key: K,
+        items: I,
+        mut f: F,
+    ) -> Result<(), KbinError>
+    where
+        K: Into<String>,
+        I: IntoIterator<Item = T>,
+        F: FnMut(&mut Node, T) -> Result<(), KbinError>,
+    {
+        let key = key.into();
+        for item in items {
+            let mut child = Node::new(key.clone());
+            f(&mut child, item)?;
+            self.append_child(child);
+        }
+
+        Ok(())
+    }
+
+    /// Append a child for every entry of a map, named by its key and filled
+    /// by `f`. The counterpart of `children_map`.
+    pub fn append_children_map<K, T, I, F>(&mut self, items: I, mut f: F) -> Result<(), KbinError>
+    where
+        K: Into<String>,
+        I: IntoIterator<Item = (K, T)>,
+        F: FnMut(&mut Node, T) -> Result<(), KbinError>,
+    {
+        for (key, item) in items {
+            let mut child = Node::new(key);
+            f(&mut child, item)?;
+            self.append_child(child);
+        }
+
+        Ok(())
+    }
+
     pub fn set_value(&mut self, value: Option<Value>) -> Option<Value> {
         mem::replace(&mut self.value, value)
     }
 
+    /// Convert this node's value to another node type with `Value::retype`.
+    pub fn retype_value(&mut self, node_type: StandardType) -> Result<(), KbinError> {
+        let value = self.value.as_ref().ok_or_else(|| KbinError::NoNodeValue {
+            key: self.key.clone(),
+        })?;
+        self.value = Some(value.retype(node_type)?);
+
+        Ok(())
+    }
+
+    /// Convert every value of type `from` in this node and its descendants to
+    /// type `to`, returning the number of values converted. Arrays of `from`
+    /// are converted to arrays of `to`. Nothing is changed if any conversion
+    /// fails.
+    pub fn retype_values(
+        &mut self,
+        from: StandardType,
+        to: StandardType,
+    ) -> Result<usize, KbinError> {
+        let mut node = self.clone();
+        let count = node.retype_values_inner(from, to)?;
+        *self = node;
+
+        Ok(count)
+    }
+
+    fn retype_values_inner(
+        &mut self,
+        from: StandardType,
+        to: StandardType,
+    ) -> Result<usize, KbinError> {
+        let mut count = 0;
+        if let Some(value) = &self.value {
+            let node_type = match value {
+                Value::Array(values) => values.standard_type(),
+                value => value.standard_type(),
+            };
+            if node_type == from {
+                self.value = Some(value.retype(to)?);
+                count += 1;
+            }
+        }
+
+        for child in self.children_iter_mut() {
+            count += child.retype_values_inner(from, to)?;
+        }
+
+        Ok(count)
+    }
+
     pub fn has(&self, key: &str) -> bool {
         if let Some(ref children) = self.children {
             for node in children {
@@ -299,7 +827,7 @@ impl Node {
             let target_opt = if let Some(index) = parse_index(token) {
                 children.get(index)
             } else {
-                children.iter().find(|ref child| child.key() == *token)
+                children.iter().find(|child| child.key() == *token)
             };
 
             if let Some(t) = target_opt {
@@ -326,7 +854,7 @@ impl Node {
             let target_opt = if let Some(index) = parse_index(token) {
                 children.get_mut(index)
             } else {
-                children.iter_mut().find(|ref child| child.key() == *token)
+                children.iter_mut().find(|child| child.key() == *token)
             };
 
             if let Some(t) = target_opt {
@@ -339,6 +867,15 @@ impl Node {
     }
 }
 
+impl TryFrom<&NodeCollection> for Node {
+    type Error = KbinError;
+
+    #[inline]
+    fn try_from(collection: &NodeCollection) -> Result<Self, Self::Error> {
+        collection.as_node()
+    }
+}
+
 impl<T> OptionIterator<T>
 where
     T: IntoIterator,
@@ -363,3 +900,588 @@ where
         }
     }
 }
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = &'a Node;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.stack.extend(node.children.iter().flatten().rev());
+
+        Some(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indexmap::IndexMap;
+
+    use crate::encoding_type::EncodingType;
+    use crate::error::KbinError;
+    use crate::node_types::StandardType;
+    use crate::options::ReaderOptions;
+    use crate::text_reader::TextReaderError;
+    use crate::value::{Value, ValueArray};
+
+    use super::{Node, NodeCollection};
+
+    #[test]
+    fn test_typed_accessors() {
+        let node = Node::with(
+            "music",
+            &[("id", "42")],
+            vec![
+                Node::with_value("limited", Value::U32(3)),
+                Node::with_value("title", Value::String("song".into())),
+                Node::with_value("grade", Value::String("A".into())),
+            ],
+        );
+
+        assert_eq!(node.attr_parsed::<u32>("id").unwrap(), 42);
+        assert_eq!(
+            node.attr_value("id", StandardType::U16).unwrap(),
+            Value::U16(42)
+        );
+        assert_eq!(node.child_value_u32("limited").unwrap(), 3);
+        assert_eq!(node.child_value_string("title").unwrap(), "song");
+        assert_eq!(node.child_value_str("title").unwrap(), "song");
+        assert_eq!(node.child_value_char("grade").unwrap(), 'A');
+        assert!(node.child_value_char("title").is_err());
+
+        match node.attr_parsed::<u32>("missing") {
+            Err(KbinError::AttributeNotFound { key }) => assert_eq!(key, "missing"),
+            result => panic!("Unexpected result: {:?}", result),
+        };
+        match node.child_value_u8("limited") {
+            Err(KbinError::ValueTypeMismatch { .. }) => {},
+            result => panic!("Unexpected result: {:?}", result),
+        };
+        match node.value_as::<u32>() {
+            Err(KbinError::NoNodeValue { key }) => assert_eq!(key, "music"),
+            result => panic!("Unexpected result: {:?}", result),
+        };
+    }
+
+    #[test]
+    fn test_attr_values() {
+        let mut node = Node::new("pos");
+        node.set_attr_value("xy", Value::S32_2([1, -2]));
+        node.set_attr_value("visible", Value::Boolean(true));
+        assert_eq!(node.attr("xy"), Some("1 -2"));
+        assert_eq!(node.attr("visible"), Some("1"));
+
+        // The attributes are written as plain strings
+        let data = crate::to_binary(&node).unwrap();
+        let (collection, _) = crate::from_binary(data.into()).unwrap();
+        let read = Node::from_collection(&collection).unwrap();
+        assert_eq!(read, node);
+        assert_eq!(
+            read.attr_value("xy", StandardType::S32_2).unwrap(),
+            Value::S32_2([1, -2])
+        );
+        assert_eq!(read.attr_parsed::<u8>("visible").unwrap(), 1);
+        assert!(matches!(
+            read.attr_value("xy", StandardType::U8),
+            Err(KbinError::AttributeParse { .. })
+        ));
+    }
+
+    #[test]
+    fn test_attribute_order_and_duplicates() {
+        assert!(matches!(
+            Node::try_with_attrs("node", &[("a", "1"), ("a", "2")]),
+            Err(KbinError::DuplicateAttribute { .. })
+        ));
+
+        let mut node = Node::with_attrs("node", &[("c", "3"), ("a", "1"), ("b", "2")]);
+        node.shift_remove_attr("c");
+        let keys: Vec<_> = node.attributes().unwrap().keys().cloned().collect();
+        assert_eq!(keys, ["a", "b"]);
+        node.set_attr("c", "3");
+        node.reorder_attrs(&["b", "c"]);
+        assert!(node.move_attr("a", 1));
+        let keys: Vec<_> = node.attributes().unwrap().keys().cloned().collect();
+        assert_eq!(keys, ["b", "a", "c"]);
+
+        // Order survives both the binary and the XML paths
+        let binary = crate::to_binary(&node).unwrap();
+        let (collection, _) = crate::from_slice(&binary).unwrap();
+        assert_eq!(collection.as_node().unwrap(), node);
+        let text = crate::to_text_xml(&node).unwrap();
+        let (collection, _) = crate::from_text_xml(&text).unwrap();
+        assert_eq!(collection.as_node().unwrap(), node);
+
+        // Duplicates are only rejected when requested. Text XML keeps the
+        // attributes before the duplicate.
+        let text = b"<node a=\"1\" a=\"2\"/>";
+        let (collection, _) = crate::from_text_xml(text).unwrap();
+        assert_eq!(collection.as_node().unwrap().attr("a"), Some("1"));
+
+        let mut options = ReaderOptions::builder();
+        options.deny_duplicate_attributes(true);
+        let options = options.build();
+        assert!(matches!(
+            crate::from_text_xml_with_options(options.clone(), text),
+            Err(KbinError::TextReader {
+                source: TextReaderError::AtPosition { ref source, .. },
+            }) if matches!(**source, TextReaderError::DuplicateAttribute { ref key } if key == "a")
+        ));
+
+        let mut collection =
+            NodeCollection::from_node(&Node::with_attrs("node", &[("a", "1")])).unwrap();
+        collection.push_attribute("a", "2").unwrap();
+        let binary = crate::to_binary(&collection).unwrap();
+        assert!(crate::from_slice(&binary).is_ok());
+        assert!(crate::from_binary_with_options(options, binary.into()).is_err());
+    }
+
+    #[test]
+    fn test_collection_conversion() {
+        let node = Node::with(
+            "root",
+            &[("name", "テスト")],
+            vec![
+                Node::with_value("text", Value::String("文字列".into())),
+                Node::with_value("bin", Value::Binary(vec![1, 2, 3])),
+                Node::with_value("arr", Value::Array(ValueArray::S32(vec![-1, 2]))),
+                Node::with_value("pos", Value::Float2([1.5, 2.5])),
+                Node::new("empty"),
+            ],
+        );
+
+        let collection = NodeCollection::from_node(&node).unwrap();
+        assert_eq!(Node::from_collection(&collection).unwrap(), node);
+        assert_eq!(
+            crate::to_binary(&collection).unwrap(),
+            crate::to_binary(&node).unwrap()
+        );
+
+        let collection =
+            NodeCollection::from_node_with_encoding(&node, EncodingType::SHIFT_JIS).unwrap();
+        assert_eq!(Node::from_collection(&collection).unwrap(), node);
+    }
+
+    #[test]
+    fn test_push_attribute() {
+        use super::NodeDefinition;
+
+        let attribute = NodeDefinition::attribute(EncodingType::SHIFT_JIS, "name", "値").unwrap();
+        assert_eq!(attribute.node_type, StandardType::Attribute);
+        assert_eq!(attribute.key().unwrap().as_deref(), Some("name"));
+        assert_eq!(attribute.value().unwrap(), Value::Attribute("値".into()));
+
+        let node = Node::new("root");
+        let mut collection =
+            NodeCollection::from_node_with_encoding(&node, EncodingType::UTF_8).unwrap();
+        collection.push_attribute("a", "1").unwrap();
+        collection.push_attribute("extra", "値").unwrap();
+        assert_eq!(
+            Node::from_collection(&collection).unwrap(),
+            Node::with_attrs("root", &[("a", "1"), ("extra", "値")])
+        );
+    }
+
+    #[test]
+    fn test_retype_values() {
+        let mut node = Node::with_nodes(
+            "root",
+            vec![
+                Node::with_value("a", Value::S32(1)),
+                Node::with_nodes("b", vec![Node::with_value("c", Value::S32(2))]),
+                Node::with_value("d", Value::Array(ValueArray::S32(vec![3, 4]))),
+                Node::with_value("e", Value::String("text".into())),
+            ],
+        );
+
+        let count = node.retype_values(StandardType::S32, StandardType::U16);
+        assert_eq!(count.unwrap(), 3);
+        assert_eq!(
+            node.pointer(&["b", "c"]).unwrap().value(),
+            Some(&Value::U16(2))
+        );
+        assert_eq!(
+            node.get_child("d").unwrap().value(),
+            Some(&Value::Array(ValueArray::U16(vec![3, 4])))
+        );
+
+        let a = node.get_child_mut("a").unwrap();
+        a.set_value(Some(Value::U16(300)));
+        let result = node.retype_values(StandardType::U16, StandardType::U8);
+        assert!(result.is_err());
+        assert_eq!(
+            node.get_child("d").unwrap().value(),
+            Some(&Value::Array(ValueArray::U16(vec![3, 4])))
+        );
+
+        let e = node.get_child_mut("e").unwrap();
+        assert!(e.retype_value(StandardType::S32).is_err());
+        assert!(node.retype_value(StandardType::S32).is_err());
+    }
+
+    #[test]
+    fn test_optional_children() {
+        let mut node = Node::new("root");
+        node.append_child_value("a", Some(Value::S32(1)));
+        node.append_child_value("b", None);
+        assert_eq!(node.children().map(Vec::len), Some(1));
+
+        assert_eq!(node.child_value_as_opt::<i32>("a").unwrap(), Some(1));
+        assert_eq!(node.child_value_as_opt::<i32>("b").unwrap(), None);
+        assert!(node.child_value_as_opt::<String>("a").is_err());
+    }
+
+    #[test]
+    fn test_optional_value_policy() {
+        use crate::options::OptionalValuePolicy;
+
+        let mut node = Node::new("root");
+        node.append_child_value_with("a", None, OptionalValuePolicy::Empty);
+        node.append_child_value_with("b", None, OptionalValuePolicy::Omit);
+        node.append_child_value_with("c", Some(Value::S32(1)), OptionalValuePolicy::Empty);
+        let keys: Vec<_> = node.children().unwrap().iter().map(Node::key).collect();
+        assert_eq!(keys, ["a", "c"]);
+
+        let read = |key, policy| node.child_value_as_opt_with::<i32>(key, policy);
+        assert_eq!(read("a", OptionalValuePolicy::Empty).unwrap(), None);
+        assert_eq!(read("b", OptionalValuePolicy::Empty).unwrap(), None);
+        assert_eq!(read("c", OptionalValuePolicy::Empty).unwrap(), Some(1));
+
+        // Without the policy, an empty node is not a missing value
+        assert!(read("a", OptionalValuePolicy::Omit).is_err());
+    }
+
+    #[test]
+    fn test_repeated_children() {
+        #[derive(Debug, PartialEq)]
+        struct Music {
+            id: u32,
+            title: String,
+        }
+
+        let songs = vec![
+            Music {
+                id: 1,
+                title: "a".into(),
+            },
+            Music {
+                id: 2,
+                title: "b".into(),
+            },
+        ];
+
+        let mut node = Node::new("mdb");
+        node.append_children("music", &songs, |child, music| {
+            child.set_attr("id", music.id.to_string());
+            child.append_child_value("title", Some(Value::String(music.title.clone())));
+            Ok(())
+        })
+        .unwrap();
+
+        let binary = crate::to_binary(&node).unwrap();
+        let (collection, _) = crate::from_slice(&binary).unwrap();
+        let node = collection.as_node().unwrap();
+
+        let read = |node: &Node| {
+            node.children_as("music", |child| {
+                Ok(Music {
+                    id: child.attr_parsed("id")?,
+                    title: child.child_value_as("title")?,
+                })
+            })
+        };
+        assert_eq!(read(&node).unwrap(), songs);
+        assert!(node
+            .children_as("other", Node::value_as::<u32>)
+            .unwrap()
+            .is_empty());
+
+        let mut node = node;
+        node.children_mut().unwrap()[1]
+            .get_child_mut("title")
+            .unwrap()
+            .set_value(Some(Value::U8(0)));
+        match read(&node) {
+            Err(KbinError::AtPath { path, .. }) => assert_eq!(path.to_string(), "/mdb/music[1]"),
+            result => panic!("Unexpected result: {:?}", result),
+        };
+    }
+
+    #[test]
+    fn test_nested_repeated_children() {
+        #[derive(Debug, PartialEq)]
+        struct Chart {
+            difficulty: u8,
+            notes: Vec<u32>,
+        }
+
+        #[derive(Debug, PartialEq)]
+        struct Music {
+            id: u32,
+            charts: Vec<Chart>,
+        }
+
+        let write = |songs: &[Music]| {
+            let mut node = Node::new("mdb");
+            node.append_children("music", songs, |music, song| {
+                music.set_attr("id", song.id.to_string());
+                music.append_children("chart", &song.charts, |chart, data| {
+                    chart.append_child_value("difficulty", Some(Value::U8(data.difficulty)));
+                    chart.append_children("note", &data.notes, |note, value| {
+                        note.set_value(Some(Value::U32(*value)));
+                        Ok(())
+                    })
+                })
+            })
+            .map(|_| node)
+        };
+        let read = |node: &Node| {
+            node.children_as("music", |music| {
+                Ok(Music {
+                    id: music.attr_parsed("id")?,
+                    charts: music.children_as("chart", |chart| {
+                        Ok(Chart {
+                            difficulty: chart.child_value_as("difficulty")?,
+                            notes: chart.children_as("note", Node::value_as)?,
+                        })
+                    })?,
+                })
+            })
+        };
+
+        let songs = vec![
+            Music {
+                id: 1,
+                charts: vec![
+                    Chart {
+                        difficulty: 0,
+                        notes: vec![1, 2, 3],
+                    },
+                    Chart {
+                        difficulty: 1,
+                        notes: vec![4],
+                    },
+                ],
+            },
+            Music {
+                id: 2,
+                charts: vec![Chart {
+                    difficulty: 2,
+                    notes: Vec::new(),
+                }],
+            },
+            Music {
+                id: 3,
+                charts: Vec::new(),
+            },
+        ];
+        let node = write(&songs).unwrap();
+
+        let binary = crate::to_binary(&node).unwrap();
+        let (collection, _) = crate::from_slice(&binary).unwrap();
+        assert_eq!(read(&collection.as_node().unwrap()).unwrap(), songs);
+
+        let text = crate::to_text_xml(&node).unwrap();
+        let (collection, _) = crate::from_text_xml(&text).unwrap();
+        assert_eq!(read(&collection.as_node().unwrap()).unwrap(), songs);
+
+        // Errors point at the innermost failing node
+        let mut node = node;
+        node.children_mut().unwrap()[0].children_mut().unwrap()[1]
+            .get_child_mut("note")
+            .unwrap()
+            .set_value(Some(Value::S8(-1)));
+        match read(&node) {
+            Err(KbinError::AtPath { path, .. }) => {
+                assert_eq!(path.to_string(), "/mdb/music[0]/chart[1]/note")
+            },
+            result => panic!("Unexpected result: {:?}", result),
+        };
+    }
+
+    #[test]
+    fn test_child_values() {
+        let repeated = Node::with_nodes(
+            "root",
+            vec![
+                Node::with_value("id", Value::U32(1)),
+                Node::with_value("id", Value::U32(2)),
+            ],
+        );
+        let array = Node::with_nodes(
+            "root",
+            vec![Node::with_value(
+                "id",
+                Value::Array(ValueArray::U32(vec![1, 2])),
+            )],
+        );
+        assert_eq!(repeated.child_values_as::<u32>("id").unwrap(), [1, 2]);
+        assert_eq!(array.child_values_as::<u32>("id").unwrap(), [1, 2]);
+
+        let single = Node::with_nodes("root", vec![Node::with_value("id", Value::U32(1))]);
+        assert_eq!(single.child_values_as::<u32>("id").unwrap(), [1]);
+        assert!(single.child_values_as::<u32>("other").unwrap().is_empty());
+        assert!(single.child_values_as::<u8>("id").is_err());
+    }
+
+    #[test]
+    fn test_sort_children() {
+        let items = [Some("10"), None, Some("b"), Some("9"), Some("a"), Some("9")];
+        let mut node = Node::new("data");
+        for (i, id) in items.iter().enumerate() {
+            let mut child = Node::with_value("item", Value::U8(i as u8));
+            if let Some(id) = id {
+                child.set_attr("id", *id);
+            }
+            node.append_child(child);
+        }
+        node.append_child(Node::new("header"));
+
+        node.sort_children_by_attr("id");
+        let order = |node: &Node| {
+            node.children_iter()
+                .map(|child| child.value_as::<u8>().unwrap_or(u8::MAX))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(order(&node), [3, 5, 0, 4, 2, 1, u8::MAX]);
+
+        node.sort_children_by_key();
+        assert_eq!(node.children().unwrap()[0].key(), "header");
+        assert_eq!(order(&node)[1..], [3, 5, 0, 4, 2, 1]);
+
+        let mut collection = NodeCollection::from_node(&node).unwrap();
+        collection.sort_children_by(|a, b| b.base().key().unwrap().cmp(&a.base().key().unwrap()));
+        let sorted = collection.as_node().unwrap();
+        assert_eq!(sorted.children().unwrap()[6].key(), "header");
+        assert_eq!(collection.children_named("header").count(), 1);
+    }
+
+    #[test]
+    fn test_children_map() {
+        let mut settings = IndexMap::new();
+        settings.insert("volume", 80u32);
+        settings.insert("speed", 15);
+
+        let mut node = Node::with_attrs("settings", &[("version", "2")]);
+        node.append_children_map(settings.clone(), |child, value| {
+            child.set_value(Some(Value::U32(value)));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(node.attr("version"), Some("2"));
+
+        let map = node.children_map(Node::value_as::<u32>).unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["volume"], 80);
+        assert_eq!(map.keys().collect::<Vec<_>>(), ["volume", "speed"]);
+
+        match node.children_map(Node::value_as::<u8>) {
+            Err(KbinError::AtPath { path, .. }) => {
+                assert_eq!(path.to_string(), "/settings/volume")
+            },
+            result => panic!("Unexpected result: {:?}", result),
+        };
+
+        node.append_child(Node::with_value("speed", Value::U32(1)));
+        assert!(matches!(
+            node.children_map(Node::value_as::<u32>),
+            Err(KbinError::DuplicateChild { ref key }) if key == "speed"
+        ));
+    }
+
+    #[test]
+    fn test_children_named() {
+        let mut children = Vec::new();
+        for i in 0..2000u32 {
+            children.push(Node::with_value("song", Value::U32(i)));
+            if i % 500 == 0 {
+                children.push(Node::new("divider"));
+            }
+        }
+        let node = Node::with_nodes("mdb", children);
+        let mut collection = NodeCollection::from_node(&node).unwrap();
+
+        let songs: Vec<_> = collection.children_named("song").collect();
+        assert_eq!(songs.len(), 2000);
+        assert_eq!(songs[1999].base().value().unwrap(), Value::U32(1999));
+        assert_eq!(collection.children_named("divider").len(), 4);
+        assert_eq!(collection.children_named("missing").next(), None);
+
+        // Editing the children rebuilds the index on the next lookup
+        collection.children_mut().remove(1);
+        let first = collection.children_named("song").next().unwrap();
+        assert_eq!(first.base().value().unwrap(), Value::U32(0));
+        assert_eq!(collection.children_named("divider").len(), 3);
+        assert_eq!(collection.clone(), collection);
+    }
+
+    #[test]
+    fn test_descendants_and_values() {
+        let node = Node::with_nodes(
+            "root",
+            vec![
+                Node::with_nodes(
+                    "a",
+                    vec![
+                        Node::with_value("b", Value::U32(1)),
+                        Node::with_value("c", Value::S32(2)),
+                    ],
+                ),
+                Node::with_value("d", Value::U32(3)),
+            ],
+        );
+
+        let keys: Vec<_> = node.descendants().map(Node::key).collect();
+        assert_eq!(keys, ["a", "b", "c", "d"]);
+
+        let collection = NodeCollection::from_node(&node).unwrap();
+        let values: Vec<u32> = collection
+            .iter_values::<u32>()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(values, [1, 3]);
+        assert_eq!(collection.iter_values::<i32>().count(), 1);
+    }
+
+    #[test]
+    fn test_key_name() {
+        use bytes::Bytes;
+
+        use crate::sixbit::Sixbit;
+
+        use super::definition::{Key, KeyName};
+
+        let key = |encoding: EncodingType, name: &str| {
+            let mut data = encoding.encode_bytes(name).unwrap();
+            data.pop();
+
+            Key::Uncompressed {
+                encoding,
+                data: Bytes::from(data),
+            }
+        };
+
+        // Names that are already valid UTF-8 are borrowed
+        for &encoding in &[EncodingType::UTF_8, EncodingType::ASCII] {
+            let key = key(encoding, "name");
+            let name = key.name().unwrap();
+            assert!(matches!(name, KeyName::Borrowed("name")), "{:?}", name);
+            assert_eq!(name.into_string(), "name");
+        }
+
+        let key = key(EncodingType::SHIFT_JIS, "名前");
+        let name = key.name().unwrap();
+        assert!(matches!(name, KeyName::Owned(_)), "{:?}", name);
+        assert_eq!(&*name, "名前");
+        assert_eq!(name.into_string(), "名前");
+
+        let mut data = Vec::new();
+        Sixbit::pack(&mut data, "name").unwrap();
+        let key = Key::Compressed {
+            size: Sixbit::size(&mut &data[..]).unwrap(),
+            data: Bytes::from(data).slice(1..),
+        };
+        let name = key.name().unwrap();
+        assert!(matches!(name, KeyName::Sixbit(_)), "{:?}", name);
+        assert_eq!(name.to_string(), "name");
+    }
+}
@@ -0,0 +1,106 @@
+use crate::node::Node;
+
+/// The namespace a node is tagged with. Tags are not part of the document,
+/// so they are ignored when comparing nodes.
+#[derive(Clone, Default)]
+pub(crate) struct NamespaceTag(Option<String>);
+
+impl PartialEq for NamespaceTag {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+fn collect_namespace<'a>(node: &'a Node, namespace: &str, nodes: &mut Vec<&'a Node>) {
+    if node.namespace() == Some(namespace) {
+        nodes.push(node);
+    }
+
+    for child in node.children_iter() {
+        collect_namespace(child, namespace, nodes);
+    }
+}
+
+impl Node {
+    /// The namespace this node is tagged with, if any.
+    ///
+    /// Namespaces group nodes while processing a tree, for example to track
+    /// which source document a subtree of a merged document came from. They
+    /// are kept by `clone` and `merge`, but are never written to binary or
+    /// text XML and are ignored when comparing nodes.
+    #[inline]
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.0.as_deref()
+    }
+
+    /// Set the namespace of this node, returning the previous one.
+    pub fn set_namespace(&mut self, namespace: Option<String>) -> Option<String> {
+        std::mem::replace(&mut self.namespace.0, namespace)
+    }
+
+    /// Tag this node and every descendant that has no namespace yet with
+    /// `namespace`.
+    pub fn tag_namespace(&mut self, namespace: &str) {
+        if self.namespace.0.is_none() {
+            self.namespace.0 = Some(namespace.to_owned());
+        }
+
+        for child in self.children_iter_mut() {
+            child.tag_namespace(namespace);
+        }
+    }
+
+    /// This node and its descendants tagged with `namespace`, in document
+    /// order.
+    pub fn nodes_in_namespace(&self, namespace: &str) -> Vec<&Node> {
+        let mut nodes = Vec::new();
+        collect_namespace(self, namespace, &mut nodes);
+
+        nodes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::node::{MergeStrategy, Node};
+    use crate::value::Value;
+
+    #[test]
+    fn test_namespaces() {
+        let mut base = Node::with_nodes("data", vec![Node::with_value("a", Value::U8(1))]);
+        base.tag_namespace("base");
+
+        let mut patch = Node::with_nodes("data", vec![Node::with_value("b", Value::U8(2))]);
+        patch.tag_namespace("patch");
+
+        base.merge(
+            &patch,
+            MergeStrategy::Deep {
+                match_attribute: None,
+            },
+        );
+
+        let keys = |namespace| {
+            base.nodes_in_namespace(namespace)
+                .into_iter()
+                .map(Node::key)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(keys("base"), ["data", "a"]);
+        assert_eq!(keys("patch"), ["b"]);
+
+        // Namespaces are not part of the document
+        let untagged = Node::with_nodes(
+            "data",
+            vec![
+                Node::with_value("a", Value::U8(1)),
+                Node::with_value("b", Value::U8(2)),
+            ],
+        );
+        assert_eq!(base, untagged);
+        assert_eq!(
+            crate::to_binary(&base).unwrap(),
+            crate::to_binary(&untagged).unwrap()
+        );
+    }
+}
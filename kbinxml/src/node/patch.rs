@@ -0,0 +1,442 @@
+use std::fmt;
+
+use crate::error::KbinError;
+use crate::node::Node;
+use crate::value::Value;
+
+/// The payload of an `add` or `replace` operation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PatchValue {
+    Node(Node),
+    Attribute(String),
+
+    /// Replace the value of a node, `None` clears it.
+    Value(Option<Value>),
+}
+
+/// A single patch operation, analogous to the operations of JSON Patch.
+///
+/// Paths are `/` separated child indices starting from the root, optionally
+/// ending in `@key` to address an attribute. The empty path is the root.
+/// For example, `/0/2` is the third child of the root's first child and
+/// `/0/@id` is the `id` attribute of the root's first child.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PatchOp {
+    /// Insert a child node before the index named by the last segment, or set
+    /// the attribute named by the last segment.
+    Add { path: String, value: PatchValue },
+
+    /// Remove the node or attribute at the path.
+    Remove { path: String },
+
+    /// Replace the node at the path, or its value when given a
+    /// `PatchValue::Value`, or the attribute at the path.
+    Replace { path: String, value: PatchValue },
+}
+
+/// An ordered list of operations that transforms one document into another.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Patch {
+    ops: Vec<PatchOp>,
+}
+
+enum Segment<'a> {
+    Child(usize),
+    Attribute(&'a str),
+}
+
+fn invalid_path(path: &str) -> KbinError {
+    KbinError::InvalidNodePath {
+        path: path.to_owned(),
+    }
+}
+
+fn invalid_op(path: &str, op: &'static str) -> KbinError {
+    KbinError::InvalidPatchOperation {
+        path: path.to_owned(),
+        op,
+    }
+}
+
+/// Split a path into the node indices leading to the parent and the final
+/// segment. Returns `None` as the final segment for the root path.
+fn parse_path(path: &str) -> Result<(Vec<usize>, Option<Segment<'_>>), KbinError> {
+    if path.is_empty() {
+        return Ok((Vec::new(), None));
+    }
+
+    let tokens = path
+        .strip_prefix('/')
+        .ok_or_else(|| invalid_path(path))?
+        .split('/')
+        .collect::<Vec<_>>();
+    let (last, parents) = tokens.split_last().ok_or_else(|| invalid_path(path))?;
+
+    let parents = parents
+        .iter()
+        .map(|token| token.parse::<usize>().map_err(|_| invalid_path(path)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let last = match last.strip_prefix('@') {
+        Some(key) if !key.is_empty() => Segment::Attribute(key),
+        Some(_) => return Err(invalid_path(path)),
+        None => Segment::Child(last.parse().map_err(|_| invalid_path(path))?),
+    };
+
+    Ok((parents, Some(last)))
+}
+
+fn resolve_mut<'a>(
+    root: &'a mut Node,
+    path: &str,
+    indices: &[usize],
+) -> Result<&'a mut Node, KbinError> {
+    let mut target = root;
+
+    for &index in indices {
+        target = target
+            .children
+            .as_mut()
+            .and_then(|children| children.get_mut(index))
+            .ok_or_else(|| invalid_path(path))?;
+    }
+
+    Ok(target)
+}
+
+fn apply_op(root: &mut Node, op: &PatchOp) -> Result<(), KbinError> {
+    match op {
+        PatchOp::Add { path, value } => {
+            let (parents, last) = parse_path(path)?;
+            let parent = resolve_mut(root, path, &parents)?;
+
+            match (last, value) {
+                (Some(Segment::Child(index)), PatchValue::Node(node)) => {
+                    let children = parent.children.get_or_insert_with(Default::default);
+                    if index > children.len() {
+                        return Err(invalid_path(path));
+                    }
+                    children.insert(index, node.clone());
+                },
+                (Some(Segment::Attribute(key)), PatchValue::Attribute(value)) => {
+                    parent.set_attr(key, value.as_str());
+                },
+                _ => return Err(invalid_op(path, "add")),
+            };
+        },
+        PatchOp::Remove { path } => {
+            let (parents, last) = parse_path(path)?;
+            let parent = resolve_mut(root, path, &parents)?;
+
+            match last {
+                Some(Segment::Child(index)) => {
+                    match parent.children.as_mut() {
+                        Some(children) if index < children.len() => children.remove(index),
+                        _ => return Err(invalid_path(path)),
+                    };
+                },
+                Some(Segment::Attribute(key)) => {
+                    parent
+                        .attributes
+                        .as_mut()
+                        .and_then(|attributes| attributes.shift_remove(key))
+                        .ok_or_else(|| invalid_path(path))?;
+                },
+                None => return Err(invalid_op(path, "remove")),
+            };
+        },
+        PatchOp::Replace { path, value } => {
+            let (parents, last) = parse_path(path)?;
+            let parent = resolve_mut(root, path, &parents)?;
+
+            let target = match last {
+                Some(Segment::Child(index)) => parent
+                    .children
+                    .as_mut()
+                    .and_then(|children| children.get_mut(index))
+                    .ok_or_else(|| invalid_path(path))?,
+                Some(Segment::Attribute(key)) => {
+                    let attribute = parent
+                        .attributes
+                        .as_mut()
+                        .and_then(|attributes| attributes.get_mut(key))
+                        .ok_or_else(|| invalid_path(path))?;
+
+                    return match value {
+                        PatchValue::Attribute(value) => {
+                            *attribute = value.clone();
+                            Ok(())
+                        },
+                        _ => Err(invalid_op(path, "replace")),
+                    };
+                },
+                None => parent,
+            };
+
+            match value {
+                PatchValue::Node(node) => *target = node.clone(),
+                PatchValue::Value(value) => target.value = value.clone(),
+                PatchValue::Attribute(_) => return Err(invalid_op(path, "replace")),
+            };
+        },
+    };
+
+    Ok(())
+}
+
+/// Apply every operation of `patch` to `node` in order. If any operation
+/// fails, `node` is left unchanged.
+pub fn apply_patch(node: &mut Node, patch: &Patch) -> Result<(), KbinError> {
+    let mut patched = node.clone();
+
+    for op in &patch.ops {
+        apply_op(&mut patched, op)?;
+    }
+    *node = patched;
+
+    Ok(())
+}
+
+fn diff_node(path: &str, a: &Node, b: &Node, ops: &mut Vec<PatchOp>) {
+    if a.key != b.key {
+        ops.push(PatchOp::Replace {
+            path: path.to_owned(),
+            value: PatchValue::Node(b.clone()),
+        });
+        return;
+    }
+
+    if a.value != b.value {
+        ops.push(PatchOp::Replace {
+            path: path.to_owned(),
+            value: PatchValue::Value(b.value.clone()),
+        });
+    }
+
+    if let Some(attributes) = a.attributes() {
+        for key in attributes.keys() {
+            if b.attr(key).is_none() {
+                ops.push(PatchOp::Remove {
+                    path: format!("{}/@{}", path, key),
+                });
+            }
+        }
+    }
+    if let Some(attributes) = b.attributes() {
+        for (key, value) in attributes {
+            match a.attr(key) {
+                Some(old) if old == value => {},
+                Some(_) => ops.push(PatchOp::Replace {
+                    path: format!("{}/@{}", path, key),
+                    value: PatchValue::Attribute(value.clone()),
+                }),
+                None => ops.push(PatchOp::Add {
+                    path: format!("{}/@{}", path, key),
+                    value: PatchValue::Attribute(value.clone()),
+                }),
+            };
+        }
+    }
+
+    let a_children = a.children().map(Vec::as_slice).unwrap_or_default();
+    let b_children = b.children().map(Vec::as_slice).unwrap_or_default();
+
+    for (index, (a, b)) in a_children.iter().zip(b_children).enumerate() {
+        diff_node(&format!("{}/{}", path, index), a, b, ops);
+    }
+    for (index, node) in b_children.iter().enumerate().skip(a_children.len()) {
+        ops.push(PatchOp::Add {
+            path: format!("{}/{}", path, index),
+            value: PatchValue::Node(node.clone()),
+        });
+    }
+    // Remove from the end so the earlier indices stay valid
+    for index in (b_children.len()..a_children.len()).rev() {
+        ops.push(PatchOp::Remove {
+            path: format!("{}/{}", path, index),
+        });
+    }
+}
+
+impl Patch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compute a patch that transforms `a` into `b`.
+    pub fn from_diff(a: &Node, b: &Node) -> Self {
+        let mut ops = Vec::new();
+        diff_node("", a, b, &mut ops);
+
+        Self { ops }
+    }
+
+    #[inline]
+    pub fn ops(&self) -> &[PatchOp] {
+        &self.ops
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn push(&mut self, op: PatchOp) {
+        self.ops.push(op);
+    }
+
+    /// Convert the patch to a `patch` node with one child per operation,
+    /// suitable for writing with `to_binary` or `to_text_xml`.
+    pub fn to_node(&self) -> Node {
+        let ops = self
+            .ops
+            .iter()
+            .map(|op| {
+                let (name, path, value) = match op {
+                    PatchOp::Add { path, value } => ("add", path, Some(value)),
+                    PatchOp::Remove { path } => ("remove", path, None),
+                    PatchOp::Replace { path, value } => ("replace", path, Some(value)),
+                };
+                let mut node = Node::with_attrs(name, &[("path", path)]);
+
+                match value {
+                    Some(PatchValue::Node(child)) => {
+                        node.set_attr("kind", "node");
+                        node.append_child(child.clone());
+                    },
+                    Some(PatchValue::Attribute(value)) => {
+                        node.set_attr("kind", "attribute");
+                        node.set_attr("value", value.as_str());
+                    },
+                    Some(PatchValue::Value(value)) => {
+                        node.set_attr("kind", "value");
+                        node.set_value(value.clone());
+                    },
+                    None => {},
+                };
+
+                node
+            })
+            .collect::<Vec<_>>();
+
+        Node::with_nodes("patch", ops)
+    }
+
+    /// Parse a patch from the node layout produced by `to_node`.
+    pub fn from_node(node: &Node) -> Result<Self, KbinError> {
+        let mut ops = Vec::new();
+
+        for op in node.children_iter() {
+            let path = op
+                .attr("path")
+                .ok_or_else(|| KbinError::AttributeNotFound { key: "path".into() })?
+                .to_owned();
+            let value = match op.attr("kind") {
+                Some("node") => {
+                    let child = op
+                        .children()
+                        .and_then(|children| children.first())
+                        .ok_or_else(|| invalid_op(&path, "add"))?;
+                    Some(PatchValue::Node(child.clone()))
+                },
+                Some("attribute") => {
+                    let value = op
+                        .attr("value")
+                        .ok_or_else(|| KbinError::AttributeNotFound {
+                            key: "value".into(),
+                        })?;
+                    Some(PatchValue::Attribute(value.to_owned()))
+                },
+                Some("value") => Some(PatchValue::Value(op.value().cloned())),
+                _ => None,
+            };
+
+            let op = match (op.key(), value) {
+                ("add", Some(value)) => PatchOp::Add { path, value },
+                ("remove", None) => PatchOp::Remove { path },
+                ("replace", Some(value)) => PatchOp::Replace { path, value },
+                (_, _) => return Err(invalid_op(&path, "unknown")),
+            };
+            ops.push(op);
+        }
+
+        Ok(Self { ops })
+    }
+}
+
+impl fmt::Display for PatchOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchOp::Add { path, .. } => write!(f, "add {}", path),
+            PatchOp::Remove { path } => write!(f, "remove {}", path),
+            PatchOp::Replace { path, .. } => write!(f, "replace {}", path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_and_apply() {
+        let a = Node::with(
+            "root",
+            &[("a", "1"), ("b", "2")],
+            vec![
+                Node::with_value("score", Value::S32(10)),
+                Node::new("old"),
+                Node::new("gone"),
+            ],
+        );
+        let b = Node::with(
+            "root",
+            &[("b", "3"), ("c", "4")],
+            vec![
+                Node::with_value("score", Value::S32(20)),
+                Node::with_value("new", Value::U8(1)),
+            ],
+        );
+
+        let patch = Patch::from_diff(&a, &b);
+        let ops = patch
+            .ops()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ops,
+            [
+                "remove /@a",
+                "replace /@b",
+                "add /@c",
+                "replace /0",
+                "replace /1",
+                "remove /2"
+            ]
+        );
+
+        let mut patched = a.clone();
+        apply_patch(&mut patched, &patch).unwrap();
+        assert_eq!(patched.children(), b.children());
+        assert_eq!(patched.attr("b"), Some("3"));
+        assert_eq!(patched.attr("c"), Some("4"));
+        assert_eq!(patched.attr("a"), None);
+
+        let node = patch.to_node();
+        let collection = crate::from_slice(&crate::to_binary(&node).unwrap())
+            .unwrap()
+            .0;
+        assert_eq!(
+            Patch::from_node(&collection.as_node().unwrap()).unwrap(),
+            patch
+        );
+
+        // Failed patches leave the input untouched
+        let mut bad = Patch::new();
+        bad.push(PatchOp::Remove { path: "/0".into() });
+        bad.push(PatchOp::Remove { path: "/9".into() });
+        let mut unchanged = a.clone();
+        assert!(apply_patch(&mut unchanged, &bad).is_err());
+        assert_eq!(unchanged, a);
+    }
+}
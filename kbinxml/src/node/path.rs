@@ -0,0 +1,477 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::KbinError;
+use crate::node::{Node, NodeCollection, NodeDefinition};
+
+/// One step of a `NodePath`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PathSegment {
+    /// A child node by name. The index selects among siblings sharing the
+    /// name, counting from zero, and is left out when the name is unique.
+    Child { name: String, index: Option<usize> },
+
+    /// An attribute of the node named by the previous segments. Only valid
+    /// as the last segment.
+    Attribute(String),
+}
+
+/// The location of a node or attribute in a document, written as
+/// `/root/child[2]/@attr`.
+///
+/// The first segment names the root node. Siblings sharing a name are
+/// disambiguated with a zero-based index suffix, the same paths produced by
+/// `search`. A segment without an index refers to the first child with that
+/// name.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NodePath {
+    segments: Vec<PathSegment>,
+}
+
+impl NodePath {
+    pub fn root<K>(name: K) -> Self
+    where
+        K: Into<String>,
+    {
+        Self {
+            segments: vec![PathSegment::Child {
+                name: name.into(),
+                index: None,
+            }],
+        }
+    }
+
+    #[inline]
+    pub fn segments(&self) -> &[PathSegment] {
+        &self.segments
+    }
+
+    /// The path of a child of the node at this path.
+    pub fn child<K>(&self, name: K, index: Option<usize>) -> Self
+    where
+        K: Into<String>,
+    {
+        let mut path = self.clone();
+        path.segments.push(PathSegment::Child {
+            name: name.into(),
+            index,
+        });
+
+        path
+    }
+
+    /// The path of an attribute of the node at this path.
+    pub fn attribute<K>(&self, key: K) -> Self
+    where
+        K: Into<String>,
+    {
+        let mut path = self.clone();
+        path.segments.push(PathSegment::Attribute(key.into()));
+
+        path
+    }
+
+    /// The key of the attribute this path refers to, if it refers to one.
+    pub fn attribute_key(&self) -> Option<&str> {
+        match self.segments.last() {
+            Some(PathSegment::Attribute(key)) => Some(key),
+            _ => None,
+        }
+    }
+
+    /// The path with the last segment removed, or `None` for the root.
+    pub fn parent(&self) -> Option<Self> {
+        if self.segments.len() <= 1 {
+            return None;
+        }
+
+        Some(Self {
+            segments: self.segments[..self.segments.len() - 1].to_vec(),
+        })
+    }
+
+    /// Build the path of the node reached by following child `indexes` from
+    /// `collection`.
+    pub(crate) fn from_indexes(
+        collection: &NodeCollection,
+        indexes: &[usize],
+    ) -> Result<Self, KbinError> {
+        let mut path = NodePath::root(collection.base().key()?.unwrap_or_default());
+        let mut current = collection;
+        for &index in indexes {
+            let children = current.children();
+            let child = children.get(index).ok_or(KbinError::ChildIndexOutOfRange {
+                index,
+                len: children.len(),
+            })?;
+            let name = child.base().key()?.unwrap_or_default();
+
+            let mut before = 0;
+            let mut total = 0;
+            for (i, sibling) in children.iter().enumerate() {
+                if sibling.base().key_name()?.as_deref() == Some(name.as_str()) {
+                    total += 1;
+                    if i < index {
+                        before += 1;
+                    }
+                }
+            }
+
+            path = path.child(name, if total > 1 { Some(before) } else { None });
+            current = child;
+        }
+
+        Ok(path)
+    }
+}
+
+impl fmt::Display for NodePath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for segment in &self.segments {
+            match segment {
+                PathSegment::Child { name, index: None } => write!(f, "/{}", name)?,
+                PathSegment::Child {
+                    name,
+                    index: Some(index),
+                } => write!(f, "/{}[{}]", name, index)?,
+                PathSegment::Attribute(key) => write!(f, "/@{}", key)?,
+            };
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for NodePath {
+    type Err = KbinError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let invalid_path = || KbinError::InvalidNodePath {
+            path: input.to_owned(),
+        };
+
+        let tokens = input.strip_prefix('/').ok_or_else(invalid_path)?.split('/');
+        let mut segments = Vec::new();
+        for token in tokens {
+            if let Some(PathSegment::Attribute(_)) = segments.last() {
+                return Err(invalid_path());
+            }
+
+            let segment = if let Some(key) = token.strip_prefix('@') {
+                if segments.is_empty() {
+                    return Err(invalid_path());
+                }
+
+                PathSegment::Attribute(key.to_owned())
+            } else if let Some(rest) = token.strip_suffix(']') {
+                let (name, index) = rest.split_once('[').ok_or_else(invalid_path)?;
+                let index = index.parse().map_err(|_| invalid_path())?;
+
+                PathSegment::Child {
+                    name: name.to_owned(),
+                    index: Some(index),
+                }
+            } else {
+                PathSegment::Child {
+                    name: token.to_owned(),
+                    index: None,
+                }
+            };
+
+            match &segment {
+                PathSegment::Child { name, .. } | PathSegment::Attribute(name)
+                    if name.is_empty() =>
+                {
+                    return Err(invalid_path());
+                },
+                _ => {},
+            };
+            segments.push(segment);
+        }
+
+        Ok(Self { segments })
+    }
+}
+
+/// Split `path` into the root name and the child segments leading to a node,
+/// rejecting attribute paths and indexed roots.
+fn node_segments(path: &NodePath) -> Option<(&str, &[PathSegment])> {
+    if path.attribute_key().is_some() {
+        return None;
+    }
+
+    match path.segments.split_first()? {
+        (PathSegment::Child { name, index: None }, rest) => Some((name, rest)),
+        _ => None,
+    }
+}
+
+/// The position of the child selected by `index` among the children for
+/// which `matches` yields `true`.
+fn child_position<I>(matches: I, index: Option<usize>) -> Option<usize>
+where
+    I: Iterator<Item = bool>,
+{
+    matches
+        .enumerate()
+        .filter(|(_, matches)| *matches)
+        .nth(index.unwrap_or(0))
+        .map(|(i, _)| i)
+}
+
+/// Resolve the child indexes along a node path in a collection.
+fn collection_indexes(collection: &NodeCollection, path: &NodePath) -> Option<Vec<usize>> {
+    let (root, segments) = node_segments(path)?;
+    if &*collection.base().key_name().ok()?? != root {
+        return None;
+    }
+
+    let mut indexes = Vec::with_capacity(segments.len());
+    let mut current = collection;
+    for segment in segments {
+        if let PathSegment::Child { name, index } = segment {
+            // Children with keys that cannot be decoded never match
+            let matches = current
+                .children()
+                .iter()
+                .map(|child| child.base().key_name().ok().flatten().as_deref() == Some(name));
+            let position = child_position(matches, *index)?;
+
+            indexes.push(position);
+            current = &current.children()[position];
+        }
+    }
+
+    Some(indexes)
+}
+
+impl NodeCollection {
+    /// Get the collection at a node path, or `None` if there is none or the
+    /// path refers to an attribute.
+    pub fn get_path(&self, path: &NodePath) -> Option<&NodeCollection> {
+        let mut current = self;
+        for index in collection_indexes(self, path)? {
+            current = &current.children()[index];
+        }
+
+        Some(current)
+    }
+
+    pub fn get_path_mut(&mut self, path: &NodePath) -> Option<&mut NodeCollection> {
+        let mut current = self;
+        for index in collection_indexes(current, path)? {
+            current = &mut current.children_mut()[index];
+        }
+
+        Some(current)
+    }
+
+    /// Get the attribute definition at an attribute path.
+    pub fn get_path_attribute(&self, path: &NodePath) -> Option<&NodeDefinition> {
+        let key = path.attribute_key()?;
+        self.get_path(&path.parent()?)?
+            .attributes()
+            .iter()
+            .find(|attribute| attribute.key_name().ok().flatten().as_deref() == Some(key))
+    }
+}
+
+impl Node {
+    /// Get the node at a node path, or `None` if there is none or the path
+    /// refers to an attribute.
+    pub fn get_path(&self, path: &NodePath) -> Option<&Node> {
+        let (root, segments) = node_segments(path)?;
+        if self.key() != root {
+            return None;
+        }
+
+        let mut current = self;
+        for segment in segments {
+            if let PathSegment::Child { name, index } = segment {
+                let children = current.children()?;
+                let position =
+                    child_position(children.iter().map(|child| child.key() == name), *index)?;
+                current = &children[position];
+            }
+        }
+
+        Some(current)
+    }
+
+    pub fn get_path_mut(&mut self, path: &NodePath) -> Option<&mut Node> {
+        let (root, segments) = node_segments(path)?;
+        if self.key() != root {
+            return None;
+        }
+
+        let mut current = self;
+        for segment in segments {
+            if let PathSegment::Child { name, index } = segment {
+                let children = current.children_mut()?;
+                let position =
+                    child_position(children.iter().map(|child| child.key() == name), *index)?;
+                current = &mut children[position];
+            }
+        }
+
+        Some(current)
+    }
+
+    /// Get the value of the attribute at an attribute path.
+    pub fn get_path_attr(&self, path: &NodePath) -> Option<&str> {
+        let key = path.attribute_key()?;
+        self.get_path(&path.parent()?)?.attr(key)
+    }
+}
+
+/// Attach the path of the child `key` of a node named `parent` to an error
+/// raised while converting that child. An error that already has a path
+/// starting at the child is extended instead of wrapped again.
+pub(crate) fn child_error(
+    parent: &str,
+    key: &str,
+    index: Option<usize>,
+    error: KbinError,
+) -> KbinError {
+    let child = NodePath::root(parent).child(key, index);
+
+    match error {
+        KbinError::AtPath { path, source } => {
+            let mut segments = child.segments;
+            segments.extend(path.segments.into_iter().skip(1));
+
+            KbinError::AtPath {
+                path: NodePath { segments },
+                source,
+            }
+        },
+        error => KbinError::AtPath {
+            path: child,
+            source: Box::new(error),
+        },
+    }
+}
+
+/// An error raised while walking a collection, along with the child indexes
+/// leading from the failing node back up to the root.
+pub(crate) struct PathError {
+    indexes: Vec<usize>,
+    source: KbinError,
+}
+
+impl PathError {
+    /// Record that the error happened in the child at `index`.
+    pub(crate) fn child(mut self, index: usize) -> Self {
+        self.indexes.push(index);
+        self
+    }
+
+    /// Attach the path of the failing node within `root` to the error.
+    pub(crate) fn into_error(self, root: &NodeCollection) -> KbinError {
+        let PathError {
+            mut indexes,
+            source,
+        } = self;
+        indexes.reverse();
+
+        match NodePath::from_indexes(root, &indexes) {
+            Ok(path) => KbinError::AtPath {
+                path,
+                source: Box::new(source),
+            },
+            Err(_) => source,
+        }
+    }
+}
+
+impl<E> From<E> for PathError
+where
+    E: Into<KbinError>,
+{
+    fn from(source: E) -> Self {
+        Self {
+            indexes: Vec::new(),
+            source: source.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::node::NodeData;
+    use crate::value::Value;
+
+    use super::*;
+
+    #[test]
+    fn test_node_path() {
+        let path: NodePath = "/root/music[1]/@id".parse().unwrap();
+        assert_eq!(path.to_string(), "/root/music[1]/@id");
+        assert_eq!(path.attribute_key(), Some("id"));
+        assert_eq!(path.parent().unwrap().to_string(), "/root/music[1]");
+        assert!("root".parse::<NodePath>().is_err());
+        assert!("/root/@id/child".parse::<NodePath>().is_err());
+        assert!("/root/music[x]".parse::<NodePath>().is_err());
+
+        let mut node = Node::with_nodes(
+            "root",
+            vec![
+                Node::with_attrs_value("music", &[("id", "1")], Value::S32(1)),
+                Node::with_attrs_value("music", &[("id", "2")], Value::S32(2)),
+            ],
+        );
+        assert_eq!(node.get_path_attr(&path), Some("2"));
+        node.get_path_mut(&path.parent().unwrap())
+            .unwrap()
+            .set_value(Some(Value::S32(3)));
+
+        let collection = NodeCollection::from_node(&node).unwrap();
+        let found = collection.get_path(&path.parent().unwrap()).unwrap();
+        assert_eq!(found.base().value().unwrap(), Value::S32(3));
+        let attribute = collection.get_path_attribute(&path).unwrap();
+        assert_eq!(attribute.value().unwrap(), Value::Attribute("2".into()));
+        assert_eq!(
+            NodePath::from_indexes(&collection, &[1]).unwrap(),
+            path.parent().unwrap()
+        );
+
+        let first = NodePath::root("root").child("music", None);
+        assert_eq!(node.get_path(&first).unwrap().value(), Some(&Value::S32(1)));
+        assert!(node.get_path(&NodePath::root("other")).is_none());
+    }
+
+    #[test]
+    fn test_error_path() {
+        let node = Node::with_nodes(
+            "root",
+            vec![
+                Node::with_value("music", Value::S32(1)),
+                Node::with_value("music", Value::S32(2)),
+            ],
+        );
+        let mut collection = NodeCollection::from_node(&node).unwrap();
+        let path = "/root/music[1]".parse().unwrap();
+        if let NodeData::Some { value_data, .. } = collection
+            .get_path_mut(&path)
+            .unwrap()
+            .base_mut()
+            .data_mut()
+        {
+            *value_data = Bytes::from_static(&[0, 0]);
+        }
+
+        match collection.as_node() {
+            Err(KbinError::AtPath {
+                path: found,
+                source,
+            }) => {
+                assert_eq!(found, path);
+                assert!(matches!(*source, KbinError::SizeMismatch { .. }));
+            },
+            result => panic!("unexpected result: {:?}", result),
+        };
+        assert!(crate::to_text_xml(&collection).is_err());
+    }
+}
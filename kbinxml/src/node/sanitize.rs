@@ -0,0 +1,279 @@
+use crate::encoding_type::EncodingType;
+use crate::error::KbinError;
+use crate::node::{Node, NodePath};
+use crate::value::{Value, ValueArray};
+
+/// Which values `sanitize` replaces.
+///
+/// A rule that matches a node replaces its value, the values of its
+/// attributes and everything below it. Paths that do not exist in a document
+/// are skipped, so the same rules can be used for every file of a game.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SanitizeRules {
+    paths: Vec<NodePath>,
+    keys: Vec<String>,
+    attributes: Vec<String>,
+}
+
+impl SanitizeRules {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the node or attribute at `path`.
+    pub fn path(&mut self, path: NodePath) -> &mut Self {
+        self.paths.push(path);
+        self
+    }
+
+    /// Replace every node named `key`, such as `email`.
+    pub fn key<K>(&mut self, key: K) -> &mut Self
+    where
+        K: Into<String>,
+    {
+        self.keys.push(key.into());
+        self
+    }
+
+    /// Replace every attribute named `key`, such as `refid`.
+    pub fn attribute<K>(&mut self, key: K) -> &mut Self
+    where
+        K: Into<String>,
+    {
+        self.attributes.push(key.into());
+        self
+    }
+}
+
+/// Replace text with a placeholder of the same length in `encoding`. ASCII
+/// letters become `x` and digits `0`, other ASCII characters such as the `@`
+/// of an email address are kept. Other characters become one `x` for every
+/// byte they take in `encoding`.
+fn placeholder_text(text: &str, encoding: EncodingType) -> Result<String, KbinError> {
+    let mut placeholder = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            c if c.is_ascii_alphabetic() => placeholder.push('x'),
+            c if c.is_ascii_digit() => placeholder.push('0'),
+            c if c.is_ascii() => placeholder.push(c),
+            c => {
+                let len = encoding.encoded_len(c.encode_utf8(&mut [0; 4]))?;
+                placeholder.extend(std::iter::repeat_n('x', len));
+            },
+        };
+    }
+
+    Ok(placeholder)
+}
+
+/// A value of the same type and size as `value` with all data zeroed.
+fn placeholder_value(value: &Value, encoding: EncodingType) -> Result<Value, KbinError> {
+    let value = match value {
+        Value::String(text) => Value::String(placeholder_text(text, encoding)?),
+        Value::Attribute(text) => Value::Attribute(placeholder_text(text, encoding)?),
+        Value::Binary(data) => Value::Binary(vec![0; data.len()]),
+        Value::Unknown { type_id, data } => Value::Unknown {
+            type_id: *type_id,
+            data: vec![0; data.len()],
+        },
+        Value::Array(values) => {
+            let node_type = values.standard_type();
+            let zeroes = vec![0; values.len() * node_type.size * node_type.count];
+            let values = ValueArray::from_standard_type(node_type, &zeroes)?
+                .ok_or(KbinError::InvalidState)?;
+
+            Value::Array(values)
+        },
+        value => {
+            let node_type = value.standard_type();
+            let zeroes = vec![0; node_type.size * node_type.count];
+
+            Value::from_standard_type(node_type, false, &zeroes)?.ok_or(KbinError::InvalidState)?
+        },
+    };
+
+    Ok(value)
+}
+
+/// Replace the value and attributes of `node` and all nodes below it.
+fn sanitize_all(node: &mut Node, encoding: EncodingType) -> Result<(), KbinError> {
+    if let Some(value) = node.value_mut() {
+        *value = placeholder_value(value, encoding)?;
+    }
+    if let Some(attributes) = node.attributes_mut() {
+        for value in attributes.values_mut() {
+            *value = placeholder_text(value, encoding)?;
+        }
+    }
+    if let Some(children) = node.children_mut() {
+        for child in children {
+            sanitize_all(child, encoding)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn sanitize_matching(
+    node: &mut Node,
+    encoding: EncodingType,
+    rules: &SanitizeRules,
+) -> Result<(), KbinError> {
+    if rules.keys.contains(&node.key) {
+        return sanitize_all(node, encoding);
+    }
+
+    if let Some(attributes) = node.attributes_mut() {
+        for (key, value) in attributes.iter_mut() {
+            if rules.attributes.contains(key) {
+                *value = placeholder_text(value, encoding)?;
+            }
+        }
+    }
+    if let Some(children) = node.children_mut() {
+        for child in children {
+            sanitize_matching(child, encoding, rules)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Replace personal data in a tree with placeholders, for example to share a
+/// file in a bug report.
+///
+/// Placeholders have the type and length of the values they replace, so the
+/// structure of the tree and the size of the written document stay the same
+/// when it is written with `encoding`. Numbers become zero and binary data is
+/// zeroed, see `SanitizeRules` for which values are replaced. Fails if text
+/// cannot be encoded with `encoding`.
+pub fn sanitize(
+    node: &mut Node,
+    encoding: EncodingType,
+    rules: &SanitizeRules,
+) -> Result<(), KbinError> {
+    for path in &rules.paths {
+        match path.attribute_key() {
+            Some(key) => {
+                let attribute = path
+                    .parent()
+                    .and_then(|parent| node.get_path_mut(&parent))
+                    .and_then(|parent| parent.attributes_mut())
+                    .and_then(|attributes| attributes.get_mut(key));
+                if let Some(value) = attribute {
+                    *value = placeholder_text(value, encoding)?;
+                }
+            },
+            None => {
+                if let Some(node) = node.get_path_mut(path) {
+                    sanitize_all(node, encoding)?;
+                }
+            },
+        };
+    }
+
+    sanitize_matching(node, encoding, rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::encoding_type::EncodingType;
+    use crate::node::{Node, NodePath};
+    use crate::options::Options;
+    use crate::value::{Value, ValueArray};
+
+    use super::{sanitize, SanitizeRules};
+
+    #[test]
+    fn test_sanitize() {
+        let node = Node::with_nodes(
+            "player",
+            vec![
+                Node::with_attrs("card", &[("refid", "E0040100AB12"), ("type", "1")]),
+                Node::with_value("email", Value::String("me@example.jp".into())),
+                Node::with_value("name", Value::String("名前 2".into())),
+                Node::with_nodes(
+                    "profile",
+                    vec![
+                        Node::with_value("ip", Value::Ip4(Ipv4Addr::new(10, 0, 0, 1))),
+                        Node::with_value("scores", Value::Array(ValueArray::U32(vec![1, 2, 3]))),
+                    ],
+                ),
+                Node::with_value("level", Value::U8(5)),
+            ],
+        );
+
+        let mut rules = SanitizeRules::new();
+        rules
+            .key("email")
+            .attribute("refid")
+            .path(NodePath::root("player").child("name", None))
+            .path(NodePath::root("player").child("profile", None))
+            .path(
+                NodePath::root("player")
+                    .child("missing", None)
+                    .attribute("id"),
+            );
+
+        let mut sanitized = node.clone();
+        sanitize(&mut sanitized, EncodingType::SHIFT_JIS, &rules).unwrap();
+
+        let card = sanitized.get_child("card").unwrap();
+        assert_eq!(card.attr("refid"), Some("x0000000xx00"));
+        assert_eq!(card.attr("type"), Some("1"));
+        assert_eq!(sanitized.child_value_str("email").unwrap(), "xx@xxxxxxx.xx");
+        assert_eq!(sanitized.child_value_str("name").unwrap(), "xxxx 0");
+        let profile = sanitized.get_child("profile").unwrap();
+        assert_eq!(
+            profile.get_child("ip").unwrap().value(),
+            Some(&Value::Ip4(Ipv4Addr::UNSPECIFIED))
+        );
+        assert_eq!(
+            profile.get_child("scores").unwrap().value(),
+            Some(&Value::Array(ValueArray::U32(vec![0, 0, 0])))
+        );
+        assert_eq!(sanitized.get_child("level"), node.get_child("level"));
+
+        assert_eq!(
+            crate::to_binary(&sanitized).unwrap().len(),
+            crate::to_binary(&node).unwrap().len()
+        );
+    }
+
+    #[test]
+    fn test_sanitize_encodings() {
+        let cases = [
+            (EncodingType::SHIFT_JIS, "ｶﾀｶﾅ名", "xxxxxx"),
+            (EncodingType::ISO_8859_1, "café", "xxxx"),
+            (EncodingType::UTF_8, "café Ж〇", "xxxxx xxxxx"),
+        ];
+        for &(encoding, text, placeholder) in &cases {
+            let node = Node::with_attrs_value("name", &[("a", text)], Value::String(text.into()));
+            let mut rules = SanitizeRules::new();
+            rules.key("name");
+
+            let mut sanitized = node.clone();
+            sanitize(&mut sanitized, encoding, &rules).unwrap();
+            assert_eq!(sanitized.value(), Some(&Value::String(placeholder.into())));
+            assert_eq!(sanitized.attr("a"), Some(placeholder));
+
+            let options = || Options::with_encoding(encoding);
+            assert_eq!(
+                crate::to_binary_with_options(options(), &sanitized)
+                    .unwrap()
+                    .len(),
+                crate::to_binary_with_options(options(), &node)
+                    .unwrap()
+                    .len()
+            );
+        }
+
+        let mut node = Node::with_value("name", Value::String("名".into()));
+        let mut rules = SanitizeRules::new();
+        rules.key("name");
+        assert!(sanitize(&mut node, EncodingType::ASCII, &rules).is_err());
+    }
+}
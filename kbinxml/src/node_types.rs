@@ -70,23 +70,21 @@ macro_rules! construct_types {
         }
       }
 
+      /// Look up a type by its name or its alternate name, such as `str` or
+      /// `string`.
       pub fn from_name(input: &str) -> Result<StandardType, UnknownKbinType> {
         match input {
           $(
             $name => Ok(StandardType::$konst),
           )+
-          "binary" => Ok(StandardType::Binary),
-          "string" => Ok(StandardType::String),
-          "f" => Ok(StandardType::Float),
-          "d" => Ok(StandardType::Double),
-          "vs64" => Ok(StandardType::S64_2),
-          "vu64" => Ok(StandardType::U64_2),
-          "vd" => Ok(StandardType::Double2),
-          "vs32" => Ok(StandardType::S32_4),
-          "vu32" => Ok(StandardType::U32_4),
-          "vf" => Ok(StandardType::Float4),
-          "b" => Ok(StandardType::Boolean),
-          _ => Err(UnknownKbinType::Name(String::from(input))),
+          _ => {
+            let types = [$(StandardType::$konst,)+];
+            types
+              .iter()
+              .copied()
+              .find(|node_type| node_type.alt_name == Some(input))
+              .ok_or_else(|| UnknownKbinType::Name(String::from(input)))
+          },
         }
       }
     }
@@ -126,7 +124,7 @@ construct_types! {
   ( 9, U64,      U64,      "u64",    None,           8, 1);
   (10, BINARY,   Binary,   "bin",    Some("binary"), 1, 0);
   (11, STRING,   String,   "str",    Some("string"), 1, 0);
-  (12, IP4,      Ip4,      "ip4",    None,           4, 1); // Using size of 4 rather than count of 4
+  (12, IP4,      Ip4,      "ip4",    Some("ipv4"),   4, 1); // Using size of 4 rather than count of 4
   (13, TIME,     Time,     "time",   None,           4, 1);
   (14, FLOAT,    Float,    "float",  Some("f"),      4, 1);
   (15, DOUBLE,   Double,   "double", Some("d"),      8, 1);
@@ -172,9 +170,51 @@ construct_types! {
   (55, BOOL_4,   Boolean4, "4b",     None,           1, 4);
   (56, VB,       Vb,       "vb",     None,           1, 16);
 
+  // Placeholder for type ids this crate does not know about, only produced when
+  // unknown types are preserved while reading
+  ( 0, UNKNOWN,    Unknown,   "unknown", None, 0, 0);
+
   ( 1, NODE_START, NodeStart, "void", None, 0, 0);
   (46, ATTRIBUTE,  Attribute, "attr", None, 0, 0);
 
   (190, NODE_END, NodeEnd, "nodeEnd", None, 0, 0);
   (191, FILE_END, FileEnd, "fileEnd", None, 0, 0);
 }
+
+impl StandardType {
+    /// The type of each element of a type holding several values, such as
+    /// `S32` for `S32_4` and `Vs8`. Other types are returned as is.
+    pub(crate) fn element_type(self) -> StandardType {
+        match self {
+            StandardType::S8_2 | StandardType::S8_3 | StandardType::S8_4 | StandardType::Vs8 => {
+                StandardType::S8
+            },
+            StandardType::U8_2 | StandardType::U8_3 | StandardType::U8_4 | StandardType::Vu8 => {
+                StandardType::U8
+            },
+            StandardType::S16_2 |
+            StandardType::S16_3 |
+            StandardType::S16_4 |
+            StandardType::Vs16 => StandardType::S16,
+            StandardType::U16_2 |
+            StandardType::U16_3 |
+            StandardType::U16_4 |
+            StandardType::Vu16 => StandardType::U16,
+            StandardType::S32_2 | StandardType::S32_3 | StandardType::S32_4 => StandardType::S32,
+            StandardType::U32_2 | StandardType::U32_3 | StandardType::U32_4 => StandardType::U32,
+            StandardType::S64_2 | StandardType::S64_3 | StandardType::S64_4 => StandardType::S64,
+            StandardType::U64_2 | StandardType::U64_3 | StandardType::U64_4 => StandardType::U64,
+            StandardType::Float2 | StandardType::Float3 | StandardType::Float4 => {
+                StandardType::Float
+            },
+            StandardType::Double2 | StandardType::Double3 | StandardType::Double4 => {
+                StandardType::Double
+            },
+            StandardType::Boolean2 |
+            StandardType::Boolean3 |
+            StandardType::Boolean4 |
+            StandardType::Vb => StandardType::Boolean,
+            node_type => node_type,
+        }
+    }
+}
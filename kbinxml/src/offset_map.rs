@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::node::NodePath;
+use crate::node_types::StandardType;
+
+/// The location of one node or attribute in a binary document.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OffsetEntry {
+    pub path: NodePath,
+    pub node_type: StandardType,
+    pub is_array: bool,
+
+    /// Offset of the type byte in the node buffer, from the start of the file
+    pub node_offset: usize,
+
+    /// Offset of the value in the data buffer, from the start of the file.
+    /// Length prefixes are not included. `None` for nodes without a value.
+    pub data_offset: Option<usize>,
+
+    /// Length of the value in bytes
+    pub len: usize,
+}
+
+/// The locations of every node and attribute read by a `Reader`, in document
+/// order. Created by `Reader::offset_map`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OffsetMap {
+    entries: Vec<OffsetEntry>,
+}
+
+/// A node recorded while reading, before its path is known.
+#[derive(Debug)]
+pub(crate) struct RawOffset {
+    /// Index of the enclosing node in the recorded list
+    pub(crate) parent: Option<usize>,
+    pub(crate) key: String,
+    pub(crate) node_type: StandardType,
+    pub(crate) is_array: bool,
+    pub(crate) node_offset: usize,
+    pub(crate) data: Option<(usize, usize)>,
+}
+
+impl OffsetMap {
+    /// Build the paths of the recorded nodes. Index suffixes are only added
+    /// to names shared by siblings, so the paths match those from `search`.
+    pub(crate) fn from_raw(raw: &[RawOffset]) -> Self {
+        let is_node = |offset: &RawOffset| offset.node_type != StandardType::Attribute;
+
+        let mut totals: HashMap<(Option<usize>, &str), usize> = HashMap::new();
+        for offset in raw.iter().filter(|offset| is_node(offset)) {
+            *totals.entry((offset.parent, &offset.key)).or_default() += 1;
+        }
+
+        let mut seen: HashMap<(Option<usize>, &str), usize> = HashMap::new();
+        let mut paths: Vec<NodePath> = Vec::with_capacity(raw.len());
+        for offset in raw {
+            let parent = offset.parent.map(|parent| &paths[parent]);
+            let path = if is_node(offset) {
+                let id = (offset.parent, offset.key.as_str());
+                let count = seen.entry(id).or_default();
+                let index = if totals[&id] > 1 { Some(*count) } else { None };
+                *count += 1;
+
+                match parent {
+                    Some(parent) => parent.child(offset.key.as_str(), index),
+                    None => NodePath::root(offset.key.as_str()),
+                }
+            } else {
+                match parent {
+                    Some(parent) => parent.attribute(offset.key.as_str()),
+                    None => NodePath::root("").attribute(offset.key.as_str()),
+                }
+            };
+            paths.push(path);
+        }
+
+        let entries = raw
+            .iter()
+            .zip(paths)
+            .map(|(offset, path)| OffsetEntry {
+                path,
+                node_type: offset.node_type,
+                is_array: offset.is_array,
+                node_offset: offset.node_offset,
+                data_offset: offset.data.map(|(data_offset, _)| data_offset),
+                len: offset.data.map(|(_, len)| len).unwrap_or_default(),
+            })
+            .collect();
+
+        Self { entries }
+    }
+
+    #[inline]
+    pub fn entries(&self) -> &[OffsetEntry] {
+        &self.entries
+    }
+
+    #[inline]
+    pub fn into_entries(self) -> Vec<OffsetEntry> {
+        self.entries
+    }
+}
+
+/// Writes one tab separated line per entry after a header line. Offsets are
+/// hexadecimal and a missing data offset is written as `-`.
+impl fmt::Display for OffsetMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "path\tnode_offset\tdata_offset\tlen\ttype")?;
+
+        for entry in &self.entries {
+            write!(f, "{}\t0x{:x}\t", entry.path, entry.node_offset)?;
+            match entry.data_offset {
+                Some(data_offset) => write!(f, "0x{:x}", data_offset)?,
+                None => f.write_str("-")?,
+            };
+            write!(f, "\t{}\t{}", entry.len, entry.node_type.name)?;
+            if entry.is_array {
+                f.write_str("[]")?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,16 +1,340 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
+
 use crate::compression_type::CompressionType;
+use crate::custom_type::CustomType;
 use crate::encoding_type::EncodingType;
+use crate::node_types::StandardType;
 
 #[derive(Clone, Debug, Default)]
 pub struct Options {
     pub(crate) compression: CompressionType,
     pub(crate) encoding: EncodingType,
+    pub(crate) alignment: Alignment,
+    pub(crate) endianness: Endianness,
+    pub(crate) attribute_order: Option<AttributeOrder>,
+    pub(crate) uncompressed_names: Option<NamePolicy>,
+    pub(crate) custom_types: Vec<CustomType>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ReaderOptions {
+    pub(crate) preserve_unknown_types: bool,
+    pub(crate) deny_duplicate_attributes: bool,
+    pub(crate) deny_non_finite_floats: bool,
+    pub(crate) lenient_booleans: bool,
+    pub(crate) max_depth: Option<usize>,
+    pub(crate) max_nodes: Option<usize>,
+    pub(crate) max_data_len: Option<usize>,
+    pub(crate) max_array_len: Option<usize>,
+    pub(crate) max_string_len: Option<usize>,
+    pub(crate) deny_multiple_roots: bool,
+    pub(crate) deny_trailing_data: bool,
+    pub(crate) alignment: Option<Alignment>,
+    pub(crate) detect_alignment: bool,
+    pub(crate) record_offsets: bool,
+    pub(crate) skip_stray_file_ends: bool,
+    pub(crate) normalize_attribute_whitespace: bool,
+    pub(crate) preserve_whitespace: bool,
+    pub(crate) preserve_cdata: bool,
+    pub(crate) preserve_comments: bool,
+    pub(crate) normalize_newlines: bool,
+    pub(crate) endianness: Option<Endianness>,
+    pub(crate) keep_partial_tree: bool,
+    pub(crate) fragment_root: Option<String>,
+    pub(crate) empty_name: Option<String>,
+    pub(crate) array_count_policy: ArrayCountPolicy,
+    pub(crate) custom_types: Vec<CustomType>,
+}
+
+#[derive(Default)]
+pub struct ReaderOptionsBuilder {
+    preserve_unknown_types: bool,
+    deny_duplicate_attributes: bool,
+    deny_non_finite_floats: bool,
+    lenient_booleans: bool,
+    max_depth: Option<usize>,
+    max_nodes: Option<usize>,
+    max_data_len: Option<usize>,
+    max_array_len: Option<usize>,
+    max_string_len: Option<usize>,
+    deny_multiple_roots: bool,
+    deny_trailing_data: bool,
+    alignment: Option<Alignment>,
+    detect_alignment: bool,
+    record_offsets: bool,
+    skip_stray_file_ends: bool,
+    normalize_attribute_whitespace: bool,
+    preserve_whitespace: bool,
+    preserve_cdata: bool,
+    preserve_comments: bool,
+    normalize_newlines: bool,
+    endianness: Option<Endianness>,
+    keep_partial_tree: bool,
+    fragment_root: Option<String>,
+    empty_name: Option<String>,
+    array_count_policy: ArrayCountPolicy,
+    custom_types: Vec<CustomType>,
+}
+
+/// The order attributes are written in by `Writer`.
+#[derive(Clone, Copy, Debug)]
+pub enum AttributeOrder {
+    /// The order they were inserted in
+    Preserve,
+
+    /// Sorted by key
+    Alphabetic,
+
+    /// Sorted by key with the given comparison. The sort is stable, so keys
+    /// that compare equal keep their insertion order.
+    Custom(fn(&str, &str) -> Ordering),
+}
+
+/// A predicate over node and attribute paths, shared by clones of the
+/// options.
+#[derive(Clone)]
+pub(crate) struct NamePolicy(Arc<dyn Fn(&str) -> bool + Send + Sync>);
+
+impl NamePolicy {
+    #[inline]
+    pub(crate) fn matches(&self, path: &str) -> bool {
+        (self.0)(path)
+    }
+}
+
+impl fmt::Debug for NamePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("NamePolicy")
+    }
+}
+
+/// Resource limits for reading documents from untrusted sources, used by
+/// `Document::parse_untrusted`. The defaults leave plenty of room for game
+/// data while bounding the memory and time spent on hostile input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Length of the whole input in bytes
+    pub max_input_len: usize,
+
+    /// Nesting depth of nodes, with the root node at depth 1
+    pub max_depth: usize,
+
+    /// Number of nodes, counting attributes
+    pub max_nodes: usize,
+
+    /// Length of the data buffer, or of the text content of text XML
+    pub max_data_len: usize,
+
+    /// Number of values of an array node
+    pub max_array_len: usize,
+
+    /// Length of a string or attribute value in bytes
+    pub max_string_len: usize,
+}
+
+/// What the text XML reader does when the number of values of an array node
+/// differs from its `__count` attribute.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ArrayCountPolicy {
+    /// Fail with a `MismatchedArrayCount` error
+    #[default]
+    Error,
+
+    /// Drop the values past the count. Too few values are still an error.
+    Truncate,
+
+    /// Add zeroed values up to the count. Too many values are still an
+    /// error.
+    Pad,
+
+    /// Keep the values as written and remember the count in
+    /// `NodeDefinition::declared_count`, so writing the node back to text XML
+    /// reproduces the original `__count`
+    Preserve,
+}
+
+/// How `Node::append_child_value_with` writes a `None` value and how
+/// `Node::child_value_as_opt_with` reads one back.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OptionalValuePolicy {
+    /// Add no child for `None`. Only a missing child is read as `None`.
+    #[default]
+    Omit,
+
+    /// Add a child without a value for `None`, for consumers that expect
+    /// the node to exist. A missing child or a child without a value is read
+    /// as `None`.
+    Empty,
+}
+
+/// Alignment of values in the data buffer of binary documents.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Alignment {
+    /// Four byte alignment, used by almost every document
+    #[default]
+    Four,
+
+    /// Eight byte alignment, used by some variants of the format
+    Eight,
+}
+
+/// Byte order of the length fields and values of binary documents.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Endianness {
+    /// Big endian, used by Konami's tools and almost every document
+    #[default]
+    Big,
+
+    /// Little endian, written by some third party tools. Strings, binary
+    /// data, and `ip4` addresses are stored the same way in both variants.
+    Little,
+}
+
+/// How `Time` values are written in text XML.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimeFormat {
+    /// Seconds since the Unix epoch, as written by other kbin tools
+    #[default]
+    Unix,
+
+    /// An ISO-8601 UTC timestamp, such as `2020-01-02T03:04:05Z`. The text
+    /// reader accepts both formats.
+    Iso8601,
+}
+
+/// Which names of node types are written in the `__type` attribute of text
+/// XML. The text reader accepts every name.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TypeNameDialect {
+    /// The names used by Konami's tools, such as `str`, `bin`, and `2s64`
+    #[default]
+    Standard,
+
+    /// The alternate names used by some other tools, such as `string`,
+    /// `binary`, `ipv4`, `f`, and `vs64`. Types without an alternate name
+    /// use their standard name.
+    Alternate,
+}
+
+/// Options for writing text XML.
+#[derive(Clone, Debug, Default)]
+pub struct TextXmlOptions {
+    pub(crate) time_format: TimeFormat,
+    pub(crate) integer_width: Option<usize>,
+    pub(crate) type_names: TypeNameDialect,
+    pub(crate) custom_types: Vec<CustomType>,
+    pub(crate) encoding: Option<EncodingType>,
+    pub(crate) omit_binary_size: bool,
+    pub(crate) fragment: bool,
+    pub(crate) empty_name: Option<String>,
+}
+
+#[derive(Default)]
+pub struct TextXmlOptionsBuilder {
+    time_format: TimeFormat,
+    integer_width: Option<usize>,
+    type_names: TypeNameDialect,
+    custom_types: Vec<CustomType>,
+    encoding: Option<EncodingType>,
+    omit_binary_size: bool,
+    fragment: bool,
+    empty_name: Option<String>,
 }
 
 #[derive(Default)]
 pub struct OptionsBuilder {
     compression: CompressionType,
     encoding: EncodingType,
+    alignment: Alignment,
+    endianness: Endianness,
+    attribute_order: Option<AttributeOrder>,
+    uncompressed_names: Option<NamePolicy>,
+    custom_types: Vec<CustomType>,
+}
+
+impl AttributeOrder {
+    /// Sort `attributes` by their key in this order.
+    pub(crate) fn sort<K, V>(self, attributes: &mut [(K, V)])
+    where
+        K: AsRef<str>,
+    {
+        match self {
+            AttributeOrder::Preserve => {},
+            AttributeOrder::Alphabetic => {
+                attributes.sort_by(|(a, _), (b, _)| a.as_ref().cmp(b.as_ref()))
+            },
+            AttributeOrder::Custom(compare) => {
+                attributes.sort_by(|(a, _), (b, _)| compare(a.as_ref(), b.as_ref()))
+            },
+        };
+    }
+}
+
+impl Options {
+    /// The attribute order set with the builder, or insertion order.
+    pub(crate) fn attribute_order(&self) -> AttributeOrder {
+        self.attribute_order.unwrap_or(AttributeOrder::Preserve)
+    }
+}
+
+impl TypeNameDialect {
+    pub(crate) fn name(self, node_type: StandardType) -> &'static str {
+        match (self, node_type.alt_name) {
+            (TypeNameDialect::Alternate, Some(alt_name)) => alt_name,
+            _ => node_type.name,
+        }
+    }
+}
+
+impl Alignment {
+    pub(crate) fn size(self) -> u64 {
+        match self {
+            Alignment::Four => 4,
+            Alignment::Eight => 8,
+        }
+    }
+}
+
+impl Endianness {
+    pub(crate) fn read_u32<R: Read>(self, reader: &mut R) -> io::Result<u32> {
+        match self {
+            Endianness::Big => reader.read_u32::<BigEndian>(),
+            Endianness::Little => reader.read_u32::<LittleEndian>(),
+        }
+    }
+
+    pub(crate) fn write_u32<W: Write>(self, writer: &mut W, n: u32) -> io::Result<()> {
+        match self {
+            Endianness::Big => writer.write_u32::<BigEndian>(n),
+            Endianness::Little => writer.write_u32::<LittleEndian>(n),
+        }
+    }
+
+    /// Convert the data of a value of `node_type` between big endian, which
+    /// `Value` uses, and this byte order. The conversion is its own inverse.
+    pub(crate) fn swap_value_bytes(self, node_type: StandardType, data: &mut [u8]) {
+        if self == Endianness::Big || node_type.size < 2 {
+            return;
+        }
+
+        match node_type {
+            StandardType::Binary |
+            StandardType::String |
+            StandardType::Attribute |
+            StandardType::Ip4 |
+            StandardType::Unknown => {},
+            _ => {
+                for element in data.chunks_exact_mut(node_type.size) {
+                    element.reverse();
+                }
+            },
+        };
+    }
 }
 
 impl Options {
@@ -18,6 +342,7 @@ impl Options {
         Self {
             compression,
             encoding,
+            ..Default::default()
         }
     }
 
@@ -34,6 +359,10 @@ impl Options {
 }
 
 impl OptionsBuilder {
+    /// How node names are written. With `CompressionType::Uncompressed`,
+    /// every name is written in the document encoding, even names that fit in
+    /// sixbit, like the files of some official tools. The header has one flag
+    /// for the whole document, so names cannot be mixed.
     pub fn compression(&mut self, compression: CompressionType) -> &mut Self {
         self.compression = compression;
         self
@@ -44,10 +373,422 @@ impl OptionsBuilder {
         self
     }
 
+    /// Align values in the data buffer to `alignment` instead of four bytes.
+    pub fn alignment(&mut self, alignment: Alignment) -> &mut Self {
+        self.alignment = alignment;
+        self
+    }
+
+    /// Write lengths and values in the given byte order. Konami's tools only
+    /// read big endian documents.
+    pub fn endianness(&mut self, endianness: Endianness) -> &mut Self {
+        self.endianness = endianness;
+        self
+    }
+
+    /// Write attributes in the given order instead of the order they were
+    /// inserted in.
+    pub fn attribute_order(&mut self, attribute_order: AttributeOrder) -> &mut Self {
+        self.attribute_order = Some(attribute_order);
+        self
+    }
+
+    /// Write the document with uncompressed names if `keep_uncompressed`
+    /// returns `true` for the path of any node or attribute, for consumers
+    /// that require some names in the document encoding. Paths are the names
+    /// from the root node, such as `/root/music/info` for a node and
+    /// `/root/music/@id` for an attribute, without sibling indexes. The header
+    /// has one flag for the whole document, so a single match makes every
+    /// name uncompressed. `StreamWriter` cannot rewrite the names it has already
+    /// written and fails with `WriterError::UncompressedNameRequired` instead.
+    pub fn uncompressed_names<F>(&mut self, keep_uncompressed: F) -> &mut Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.uncompressed_names = Some(NamePolicy(Arc::new(keep_uncompressed)));
+        self
+    }
+
+    /// Write `Value::Unknown` values of a registered type with its layout.
+    pub fn custom_type(&mut self, custom_type: CustomType) -> &mut Self {
+        self.custom_types.push(custom_type);
+        self
+    }
+
     pub fn build(self) -> Options {
         Options {
             compression: self.compression,
             encoding: self.encoding,
+            alignment: self.alignment,
+            endianness: self.endianness,
+            attribute_order: self.attribute_order,
+            uncompressed_names: self.uncompressed_names,
+            custom_types: self.custom_types,
+        }
+    }
+}
+
+impl ReaderOptions {
+    pub fn builder() -> ReaderOptionsBuilder {
+        ReaderOptionsBuilder::default()
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_input_len: 64 * 1024 * 1024,
+            max_depth: 64,
+            max_nodes: 1_000_000,
+            max_data_len: 64 * 1024 * 1024,
+            max_array_len: 1024 * 1024,
+            max_string_len: 1024 * 1024,
+        }
+    }
+}
+
+impl Limits {
+    /// Reader options enforcing these limits. Documents with more than one
+    /// root node are rejected as well.
+    pub fn reader_options(&self) -> ReaderOptions {
+        let mut options = ReaderOptions::builder();
+        options
+            .max_depth(self.max_depth)
+            .max_nodes(self.max_nodes)
+            .max_data_len(self.max_data_len)
+            .max_array_len(self.max_array_len)
+            .max_string_len(self.max_string_len)
+            .deny_multiple_roots(true);
+
+        options.build()
+    }
+}
+
+impl ReaderOptionsBuilder {
+    /// Read nodes with unknown type ids into `Value::Unknown` rather than
+    /// failing. Their data is assumed to be length-prefixed in the data buffer,
+    /// the same layout used by strings, binary data, and arrays.
+    pub fn preserve_unknown_types(&mut self, preserve_unknown_types: bool) -> &mut Self {
+        self.preserve_unknown_types = preserve_unknown_types;
+        self
+    }
+
+    /// Fail with a `DuplicateAttribute` error when a node has more than one
+    /// attribute with the same key, instead of the last one taking effect.
+    pub fn deny_duplicate_attributes(&mut self, deny_duplicate_attributes: bool) -> &mut Self {
+        self.deny_duplicate_attributes = deny_duplicate_attributes;
+        self
+    }
+
+    /// Fail when a float value in text XML is NaN or infinite. Binary input
+    /// is not affected.
+    pub fn deny_non_finite_floats(&mut self, deny_non_finite_floats: bool) -> &mut Self {
+        self.deny_non_finite_floats = deny_non_finite_floats;
+        self
+    }
+
+    /// Accept `true` and `false` in any letter case for boolean values in text
+    /// XML. Only the lowercase words and numbers are accepted otherwise.
+    pub fn lenient_booleans(&mut self, lenient_booleans: bool) -> &mut Self {
+        self.lenient_booleans = lenient_booleans;
+        self
+    }
+
+    /// Fail when nodes are nested more than `max_depth` levels deep. The root
+    /// node is at depth 1.
+    pub fn max_depth(&mut self, max_depth: usize) -> &mut Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Fail when the document has more than `max_nodes` nodes, counting
+    /// attributes.
+    pub fn max_nodes(&mut self, max_nodes: usize) -> &mut Self {
+        self.max_nodes = Some(max_nodes);
+        self
+    }
+
+    /// Fail when the value data is longer than `max_data_len` bytes. For binary
+    /// input this is the length of the data buffer, for text XML it is the
+    /// total length of the text content.
+    pub fn max_data_len(&mut self, max_data_len: usize) -> &mut Self {
+        self.max_data_len = Some(max_data_len);
+        self
+    }
+
+    /// Fail when an array node has more than `max_array_len` values, or a
+    /// `__count` attribute above it in text XML.
+    pub fn max_array_len(&mut self, max_array_len: usize) -> &mut Self {
+        self.max_array_len = Some(max_array_len);
+        self
+    }
+
+    /// Fail when a string or attribute value is longer than `max_string_len`
+    /// bytes.
+    pub fn max_string_len(&mut self, max_string_len: usize) -> &mut Self {
+        self.max_string_len = Some(max_string_len);
+        self
+    }
+
+    /// Fail when the document has more than one root node instead of
+    /// ignoring everything after the first.
+    pub fn deny_multiple_roots(&mut self, deny_multiple_roots: bool) -> &mut Self {
+        self.deny_multiple_roots = deny_multiple_roots;
+        self
+    }
+
+    /// Fail when the node buffer or data buffer of a binary document has
+    /// bytes left at the file end marker, other than the zeroed padding up to
+    /// the buffer alignment. The root node is checked as soon as it is read.
+    pub fn deny_trailing_data(&mut self, deny_trailing_data: bool) -> &mut Self {
+        self.deny_trailing_data = deny_trailing_data;
+        self
+    }
+
+    /// Read binary documents with the given data buffer alignment. Without
+    /// this, documents are read with four byte alignment, unless
+    /// `detect_alignment` is set.
+    pub fn alignment(&mut self, alignment: Alignment) -> &mut Self {
+        self.alignment = Some(alignment);
+        self
+    }
+
+    /// Detect the data buffer alignment of binary documents when `alignment`
+    /// is not set. Documents whose data buffer length is a multiple of eight
+    /// are parsed once more to check whether four byte alignment fits, and
+    /// again with eight byte alignment if it does not.
+    pub fn detect_alignment(&mut self, detect_alignment: bool) -> &mut Self {
+        self.detect_alignment = detect_alignment;
+        self
+    }
+
+    /// Record the location of every node and attribute in binary input, for
+    /// `Reader::offset_map`.
+    pub fn record_offsets(&mut self, record_offsets: bool) -> &mut Self {
+        self.record_offsets = record_offsets;
+        self
+    }
+
+    /// Ignore file end markers inside a node in binary input, as the games
+    /// do, instead of ending the document there. Each ignored marker is
+    /// logged and listed in `Reader::recovered_errors`.
+    pub fn skip_stray_file_ends(&mut self, skip_stray_file_ends: bool) -> &mut Self {
+        self.skip_stray_file_ends = skip_stray_file_ends;
+        self
+    }
+
+    /// Replace line breaks and tabs written literally in text XML attribute
+    /// values with spaces, as XML parsers are required to. Without this they
+    /// are kept as written. Escaped characters such as `&#10;` are kept either
+    /// way, and are what `to_text_xml` writes.
+    pub fn normalize_attribute_whitespace(
+        &mut self,
+        normalize_attribute_whitespace: bool,
+    ) -> &mut Self {
+        self.normalize_attribute_whitespace = normalize_attribute_whitespace;
+        self
+    }
+
+    /// Keep the whitespace around the text of text XML elements, for string
+    /// values where it is significant. Without this, text is trimmed. Text
+    /// of other types is trimmed either way, and whitespace between child
+    /// elements is ignored.
+    pub fn preserve_whitespace(&mut self, preserve_whitespace: bool) -> &mut Self {
+        self.preserve_whitespace = preserve_whitespace;
+        self
+    }
+
+    /// Read CDATA sections in text XML as part of the text of their element,
+    /// without unescaping them. Without this, they are ignored.
+    pub fn preserve_cdata(&mut self, preserve_cdata: bool) -> &mut Self {
+        self.preserve_cdata = preserve_cdata;
+        self
+    }
+
+    /// Keep comments and processing instructions inside the root element of
+    /// text XML as children of their element, see `NodeDefinition::comment`.
+    /// They are written back to text XML, but not to binary documents or
+    /// `Node` trees. Without this, they are ignored.
+    pub fn preserve_comments(&mut self, preserve_comments: bool) -> &mut Self {
+        self.preserve_comments = preserve_comments;
+        self
+    }
+
+    /// Replace `\r\n` and `\r` line breaks in the text of text XML elements
+    /// with `\n`, as XML parsers are required to. Without this they are kept
+    /// as written.
+    pub fn normalize_newlines(&mut self, normalize_newlines: bool) -> &mut Self {
+        self.normalize_newlines = normalize_newlines;
+        self
+    }
+
+    /// Read binary documents with the given byte order. Without this, the
+    /// byte order is detected from the buffer lengths in the header, falling
+    /// back to big endian. `AsyncReader` cannot look ahead, so it always
+    /// uses big endian unless this is set.
+    pub fn endianness(&mut self, endianness: Endianness) -> &mut Self {
+        self.endianness = Some(endianness);
+        self
+    }
+
+    /// When reading fails after the root node was started, fail with
+    /// `KbinError::PartialTree` holding the nodes read before the error and
+    /// the path of the last one, instead of the error alone. Nodes that were
+    /// still open are closed.
+    pub fn keep_partial_tree(&mut self, keep_partial_tree: bool) -> &mut Self {
+        self.keep_partial_tree = keep_partial_tree;
+        self
+    }
+
+    /// Read text XML fragments, such as those written with
+    /// `TextXmlOptionsBuilder::fragment`, as the children of a root node
+    /// named `name`. Every top-level element becomes a child, and the input
+    /// may have no elements at all.
+    pub fn fragment_root<K>(&mut self, name: K) -> &mut Self
+    where
+        K: Into<String>,
+    {
+        self.fragment_root = Some(name.into());
+        self
+    }
+
+    /// Read text XML elements named `placeholder` as nodes with an empty name,
+    /// the reverse of `TextXmlOptionsBuilder::empty_name`. Binary documents
+    /// store empty names directly.
+    pub fn empty_name<K>(&mut self, placeholder: K) -> &mut Self
+    where
+        K: Into<String>,
+    {
+        self.empty_name = Some(placeholder.into());
+        self
+    }
+
+    /// Handle array nodes in text XML whose number of values differs from
+    /// their `__count` attribute with `policy` instead of failing.
+    pub fn array_count_policy(&mut self, policy: ArrayCountPolicy) -> &mut Self {
+        self.array_count_policy = policy;
+        self
+    }
+
+    /// Read nodes of a registered type into `Value::Unknown`, both from
+    /// binary documents and from text XML with the name of the type.
+    pub fn custom_type(&mut self, custom_type: CustomType) -> &mut Self {
+        self.custom_types.push(custom_type);
+        self
+    }
+
+    pub fn build(self) -> ReaderOptions {
+        ReaderOptions {
+            preserve_unknown_types: self.preserve_unknown_types,
+            deny_duplicate_attributes: self.deny_duplicate_attributes,
+            deny_non_finite_floats: self.deny_non_finite_floats,
+            lenient_booleans: self.lenient_booleans,
+            max_depth: self.max_depth,
+            max_nodes: self.max_nodes,
+            max_data_len: self.max_data_len,
+            max_array_len: self.max_array_len,
+            max_string_len: self.max_string_len,
+            deny_multiple_roots: self.deny_multiple_roots,
+            deny_trailing_data: self.deny_trailing_data,
+            alignment: self.alignment,
+            detect_alignment: self.detect_alignment,
+            record_offsets: self.record_offsets,
+            skip_stray_file_ends: self.skip_stray_file_ends,
+            normalize_attribute_whitespace: self.normalize_attribute_whitespace,
+            preserve_whitespace: self.preserve_whitespace,
+            preserve_cdata: self.preserve_cdata,
+            preserve_comments: self.preserve_comments,
+            normalize_newlines: self.normalize_newlines,
+            endianness: self.endianness,
+            keep_partial_tree: self.keep_partial_tree,
+            fragment_root: self.fragment_root,
+            empty_name: self.empty_name,
+            array_count_policy: self.array_count_policy,
+            custom_types: self.custom_types,
+        }
+    }
+}
+
+impl TextXmlOptions {
+    pub fn builder() -> TextXmlOptionsBuilder {
+        TextXmlOptionsBuilder::default()
+    }
+}
+
+impl TextXmlOptionsBuilder {
+    pub fn time_format(&mut self, time_format: TimeFormat) -> &mut Self {
+        self.time_format = time_format;
+        self
+    }
+
+    /// Pad integer values with leading zeros to at least `width` characters,
+    /// including the sign, so `5` is written as `0005` with a width of 4.
+    /// Every element of tuples and arrays is padded separately.
+    pub fn zero_pad_integers(&mut self, width: usize) -> &mut Self {
+        self.integer_width = Some(width);
+        self
+    }
+
+    /// Write node types with the names of `dialect`.
+    pub fn type_names(&mut self, dialect: TypeNameDialect) -> &mut Self {
+        self.type_names = dialect;
+        self
+    }
+
+    /// Write `Value::Unknown` values of a registered type with its name and
+    /// text format.
+    pub fn custom_type(&mut self, custom_type: CustomType) -> &mut Self {
+        self.custom_types.push(custom_type);
+        self
+    }
+
+    /// Write the document in `encoding` instead of UTF-8. Characters that
+    /// cannot be represented in it are written as numeric character
+    /// references such as `&#8364;`, which the text reader reads back.
+    /// `TextXmlStreamWriter` always writes UTF-8.
+    pub fn encoding(&mut self, encoding: EncodingType) -> &mut Self {
+        self.encoding = Some(encoding);
+        self
+    }
+
+    /// Whether binary nodes get a `__size` attribute with their length in
+    /// bytes, the default. The text reader takes the size from the data when
+    /// the attribute is missing.
+    pub fn binary_size(&mut self, binary_size: bool) -> &mut Self {
+        self.omit_binary_size = !binary_size;
+        self
+    }
+
+    /// Write the children of the root node without the root node itself and
+    /// without the XML declaration, for embedding in other XML documents.
+    /// The attributes and value of the root node are not written.
+    /// `TextXmlStreamWriter` only leaves out the declaration.
+    pub fn fragment(&mut self, fragment: bool) -> &mut Self {
+        self.fragment = fragment;
+        self
+    }
+
+    /// Write nodes with an empty name, which compressed binary documents can
+    /// hold but XML cannot, as elements named `placeholder`. Writing such a
+    /// node fails with `KbinError::EmptyNodeName` without a placeholder.
+    pub fn empty_name<K>(&mut self, placeholder: K) -> &mut Self
+    where
+        K: Into<String>,
+    {
+        self.empty_name = Some(placeholder.into());
+        self
+    }
+
+    pub fn build(self) -> TextXmlOptions {
+        TextXmlOptions {
+            time_format: self.time_format,
+            integer_width: self.integer_width,
+            type_names: self.type_names,
+            custom_types: self.custom_types,
+            encoding: self.encoding,
+            omit_binary_size: self.omit_binary_size,
+            fragment: self.fragment,
+            empty_name: self.empty_name,
         }
     }
 }
@@ -0,0 +1,109 @@
+use std::fmt;
+
+/// The nodes counted by a progress report are reported every this many nodes.
+pub(crate) const REPORT_INTERVAL: usize = 1024;
+
+/// The part of an operation a progress report belongs to. Conversions read
+/// the whole document before writing it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProgressPhase {
+    Read,
+    Write,
+}
+
+/// Receives progress reports from the `_with_progress` functions, such as
+/// `from_binary_with_progress`.
+///
+/// Each phase is reported every 1024 nodes and once more when it ends, with
+/// the number of elements handled so far and the number of bytes read from
+/// the input or written to the output. The last report of a phase has the
+/// final counts. Closures taking the same arguments can be used as sinks.
+pub trait ProgressSink {
+    fn progress(&mut self, phase: ProgressPhase, items: usize, bytes: usize);
+}
+
+impl<F> ProgressSink for F
+where
+    F: FnMut(ProgressPhase, usize, usize),
+{
+    #[inline]
+    fn progress(&mut self, phase: ProgressPhase, items: usize, bytes: usize) {
+        self(phase, items, bytes)
+    }
+}
+
+/// Counts the nodes handled in one phase of an operation and reports them to
+/// an optional `ProgressSink`.
+pub struct Progress<'a> {
+    sink: Option<&'a mut dyn ProgressSink>,
+    phase: ProgressPhase,
+    items: usize,
+}
+
+impl<'a> Progress<'a> {
+    /// A counter that reports nowhere.
+    #[inline]
+    pub fn none() -> Self {
+        Self {
+            sink: None,
+            phase: ProgressPhase::Read,
+            items: 0,
+        }
+    }
+
+    pub fn new(sink: &'a mut dyn ProgressSink, phase: ProgressPhase) -> Self {
+        Self {
+            sink: Some(sink),
+            phase,
+            items: 0,
+        }
+    }
+
+    /// Count a node, reporting every `REPORT_INTERVAL` nodes. `bytes` is only
+    /// called when a report is made.
+    #[inline]
+    pub(crate) fn node<F>(&mut self, bytes: F)
+    where
+        F: FnOnce() -> usize,
+    {
+        self.items += 1;
+        if self.items.is_multiple_of(REPORT_INTERVAL) {
+            self.report(self.items, bytes());
+        }
+    }
+
+    /// Report counts that were kept elsewhere.
+    pub(crate) fn report(&mut self, items: usize, bytes: usize) {
+        if let Some(sink) = self.sink.as_mut() {
+            sink.progress(self.phase, items, bytes);
+        }
+    }
+
+    /// Make the final report of the phase.
+    pub(crate) fn finish(&mut self, bytes: usize) {
+        self.report(self.items, bytes);
+    }
+
+    /// Count from zero again, for a phase that starts over.
+    pub(crate) fn restart(&mut self) {
+        self.items = 0;
+    }
+
+    /// Start counting the next phase, reporting to the same sink.
+    pub(crate) fn into_phase(self, phase: ProgressPhase) -> Self {
+        Self {
+            sink: self.sink,
+            phase,
+            items: 0,
+        }
+    }
+}
+
+impl fmt::Debug for Progress<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Progress")
+            .field("phase", &self.phase)
+            .field("items", &self.items)
+            .finish()
+    }
+}
@@ -1,19 +1,27 @@
-use std::io::{self, Cursor, Seek, SeekFrom};
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
 
-use byteorder::{BigEndian, ReadBytesExt};
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt};
 use bytes::Bytes;
 use snafu::{ResultExt, Snafu};
 
 use crate::byte_buffer::{ByteBufferError, ByteBufferRead};
 use crate::compression_type::{CompressionType, UnknownCompression};
+use crate::custom_type::CustomType;
 use crate::encoding_type::{EncodingError, EncodingType};
 use crate::node::{Key, NodeData, NodeDefinition};
 use crate::node_types::{StandardType, UnknownKbinType};
+use crate::offset_map::{OffsetMap, RawOffset};
+use crate::options::{Alignment, Endianness, ReaderOptions};
+use crate::progress::Progress;
 use crate::sixbit::{Sixbit, SixbitError};
 use crate::{ARRAY_MASK, SIGNATURE};
 
 #[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
 pub enum ReaderError {
+    #[snafu(display("Failed to read header"))]
+    HeaderRead { source: io::Error },
+
     #[snafu(display("Failed to read signature from header"))]
     Signature { source: io::Error },
 
@@ -44,6 +52,12 @@ pub enum ReaderError {
     #[snafu(display("Failed to read data buffer length"))]
     DataBufferLength { source: io::Error },
 
+    #[snafu(display("Failed to read node buffer ({} bytes)", len_node))]
+    NodeBufferRead { len_node: u32, source: io::Error },
+
+    #[snafu(display("Failed to read data buffer ({} bytes)", len_data))]
+    DataBufferRead { len_data: u32, source: io::Error },
+
     #[snafu(display(
         "Failed to seek forward {} bytes in input buffer for data buffer length",
         len_node
@@ -53,6 +67,45 @@ pub enum ReaderError {
     #[snafu(display("Attempted to read past the end of the node buffer"))]
     EndOfNodeBuffer,
 
+    #[snafu(display("Duplicate attribute `{}`", key))]
+    DuplicateAttribute { key: String },
+
+    #[snafu(display("Node depth exceeds the limit of {}", max_depth))]
+    DepthLimit { max_depth: usize },
+
+    #[snafu(display("Node count exceeds the limit of {}", max_nodes))]
+    NodeLimit { max_nodes: usize },
+
+    #[snafu(display(
+        "Data buffer length of {} bytes exceeds the limit of {} bytes",
+        len_data,
+        max_data_len
+    ))]
+    DataLimit { len_data: u32, max_data_len: usize },
+
+    #[snafu(display("Array of {} values exceeds the limit of {}", len, max_array_len))]
+    ArrayLimit { len: usize, max_array_len: usize },
+
+    #[snafu(display(
+        "String of {} bytes exceeds the limit of {} bytes",
+        len,
+        max_string_len
+    ))]
+    StringLimit { len: usize, max_string_len: usize },
+
+    #[snafu(display("Document has more than one root node"))]
+    MultipleRoots,
+
+    #[snafu(display(
+        "{} unused bytes in the {} buffer at the file end marker",
+        unused,
+        buffer
+    ))]
+    TrailingData { buffer: &'static str, unused: usize },
+
+    #[snafu(display("File end marker inside a node at depth {}", depth))]
+    StrayFileEnd { depth: usize },
+
     #[snafu(display("Failed to read node type"))]
     NodeType { source: io::Error },
 
@@ -84,20 +137,279 @@ pub enum ReaderError {
     },
 }
 
+/// The kind of damage skipped by a recovering `Reader`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecoveryKind {
+    /// A node type byte that is not a known type. The node and its children
+    /// are skipped if the rest of the document can be read after them,
+    /// otherwise reading stops and all open nodes are closed.
+    InvalidNodeType,
+
+    /// A node name that could not be read. Nothing after it can be located,
+    /// so reading stops and all open nodes are closed.
+    InvalidName,
+
+    /// A node value that could not be read from the data buffer. The node
+    /// and its children are skipped.
+    InvalidData,
+
+    /// The node buffer ended before the end of the document.
+    Truncated,
+
+    /// A limit set in `ReaderOptions` was exceeded.
+    Limit,
+
+    /// A file end marker inside a node, ignored because of
+    /// `ReaderOptionsBuilder::skip_stray_file_ends`.
+    StrayFileEnd,
+
+    /// An attribute repeated on the same node, with
+    /// `ReaderOptionsBuilder::deny_duplicate_attributes` set. Its value is
+    /// not read, so reading stops and all open nodes are closed.
+    DuplicateAttribute,
+
+    /// Unused bytes after the file end marker, with
+    /// `ReaderOptionsBuilder::deny_trailing_data` set.
+    TrailingData,
+}
+
+/// An error that a recovering `Reader` skipped over.
+#[derive(Debug)]
+pub struct RecoveredError {
+    /// Offset of the damaged node's type byte from the start of the input
+    pub offset: usize,
+    pub kind: RecoveryKind,
+    pub error: ReaderError,
+}
+
+impl RecoveryKind {
+    fn from_error(error: &ReaderError) -> Self {
+        match error {
+            ReaderError::NodeType { .. } | ReaderError::InvalidNodeType { .. } => {
+                RecoveryKind::InvalidNodeType
+            },
+            ReaderError::ArrayLength { .. } |
+            ReaderError::DataRead { .. } |
+            ReaderError::DataBuffer { .. } => RecoveryKind::InvalidData,
+            ReaderError::EndOfNodeBuffer => RecoveryKind::Truncated,
+            ReaderError::DepthLimit { .. } |
+            ReaderError::NodeLimit { .. } |
+            ReaderError::DataLimit { .. } |
+            ReaderError::ArrayLimit { .. } |
+            ReaderError::StringLimit { .. } => RecoveryKind::Limit,
+            ReaderError::StrayFileEnd { .. } => RecoveryKind::StrayFileEnd,
+            ReaderError::DuplicateAttribute { .. } => RecoveryKind::DuplicateAttribute,
+            ReaderError::TrailingData { .. } => RecoveryKind::TrailingData,
+            ReaderError::NodeSixbitName { .. } |
+            ReaderError::NameLength { .. } |
+            ReaderError::NodeBuffer { .. } => RecoveryKind::InvalidName,
+
+            // Raised by the constructors and `from_binary`, never while
+            // reading nodes
+            ReaderError::HeaderRead { .. } |
+            ReaderError::Signature { .. } |
+            ReaderError::InvalidSignature { .. } |
+            ReaderError::Compression { .. } |
+            ReaderError::InvalidCompression { .. } |
+            ReaderError::Encoding { .. } |
+            ReaderError::EncodingNegate { .. } |
+            ReaderError::InvalidEncoding { .. } |
+            ReaderError::MismatchedEncoding |
+            ReaderError::NodeBufferLength { .. } |
+            ReaderError::DataBufferLength { .. } |
+            ReaderError::NodeBufferRead { .. } |
+            ReaderError::DataBufferRead { .. } |
+            ReaderError::DataLengthSeek { .. } |
+            ReaderError::MultipleRoots => RecoveryKind::InvalidName,
+        }
+    }
+}
+
+/// Progress of a recovering `Reader` through a damaged document.
+enum Recovery {
+    Reading,
+
+    /// Discarding definitions until the reader depth returns to this depth
+    Skipping(usize),
+
+    /// Emitting the given number of `NodeEnd` definitions, then `FileEnd`
+    Closing(usize),
+
+    Done,
+}
+
+/// Whether `buf` is a node buffer, which ends with the file end marker
+/// followed by padding.
+fn ends_with_file_end(buf: &[u8]) -> bool {
+    let file_end = StandardType::FileEnd as u8 | ARRAY_MASK;
+    buf.iter().rev().find(|&&b| b != 0) == Some(&file_end)
+}
+
+/// Fail if a data buffer of `len_data` bytes exceeds the limit of `options`,
+/// before it is allocated or sliced.
+pub(crate) fn check_data_len(options: &ReaderOptions, len_data: u32) -> Result<(), ReaderError> {
+    if let Some(max_data_len) = options.max_data_len {
+        if len_data as usize > max_data_len {
+            return Err(ReaderError::DataLimit {
+                len_data,
+                max_data_len,
+            });
+        }
+    }
+
+    Ok(())
+}
+
 pub struct Reader {
     compression: CompressionType,
     encoding: EncodingType,
+    alignment: Alignment,
+    endianness: Endianness,
+    options: ReaderOptions,
 
     pub(crate) node_buf: ByteBufferRead,
     pub(crate) data_buf: ByteBufferRead,
 
+    /// The offsets of the buffers from the start of the input
+    node_buf_start: usize,
     data_buf_start: u64,
+
+    /// Keys of the attributes read since the last non-attribute node, used to
+    /// detect duplicates
+    attribute_keys: Vec<String>,
+
+    /// The current node depth and the number of nodes read, checked against
+    /// the limits in `options`
+    depth: usize,
+    nodes: usize,
+
+    /// The first error encountered by the `Iterator` implementation
+    error: Option<ReaderError>,
+
+    /// Set for readers constructed with `recovering`, along with the errors
+    /// skipped so far and the number of nodes returned that are still open
+    recovery: Option<Recovery>,
+    recovered: Vec<RecoveredError>,
+    open_nodes: usize,
+
+    /// Nodes read so far and the indexes of the open ones, only recorded
+    /// when `record_offsets` is set in `options`
+    offsets: Vec<RawOffset>,
+    open_offsets: Vec<usize>,
+
+    /// The spans of the open nodes, the innermost last
+    #[cfg(feature = "tracing")]
+    spans: Vec<tracing::Span>,
 }
 
 impl Reader {
+    #[inline]
     pub fn new(input: Bytes) -> Result<Self, ReaderError> {
+        Self::with_options(input, ReaderOptions::default())
+    }
+
+    pub fn with_options(input: Bytes, options: ReaderOptions) -> Result<Self, ReaderError> {
         let mut header = Cursor::new(&input);
+        let (compression, encoding, len_node) = Self::read_header(&mut header)?;
 
+        // `read_header` reads the node buffer length as big endian
+        let endianness = match options.endianness {
+            Some(endianness) => endianness,
+            None => Self::detect_endianness(&input, len_node),
+        };
+        let len_node = match endianness {
+            Endianness::Big => len_node,
+            Endianness::Little => len_node.swap_bytes(),
+        };
+
+        // The length of the data buffer is the 4 bytes right after the node buffer.
+        header
+            .seek(SeekFrom::Current(len_node as i64))
+            .context(DataLengthSeek { len_node })?;
+
+        let len_second = endianness.read_u32(&mut header).context(DataBufferLength)?;
+        info!("len_second: {0} (0x{0:x})", len_second);
+
+        // We have read 8 bytes so far, so offset the start of the node buffer from
+        // the start of the input data. After that is the length of the data buffer.
+        // The data buffer is everything after that. Some dumps have the data
+        // buffer first, with the lengths swapped along with the buffers.
+        let first_end = 8 + len_node as usize;
+        let second_start = first_end + 4;
+        let first = input.slice(8..first_end);
+        let second = input.slice(second_start..);
+        let swapped = !ends_with_file_end(&first) && ends_with_file_end(&second);
+        let (node_buf, data_buf, node_buf_start, data_buf_start, len_data) = if swapped {
+            info!("data buffer found before the node buffer");
+            (second, first, second_start, 8, len_node)
+        } else {
+            (first, second, 8, second_start, len_second)
+        };
+
+        check_data_len(&options, len_data)?;
+
+        let mut reader = Self::from_parts(
+            compression,
+            encoding,
+            endianness,
+            options,
+            node_buf,
+            data_buf,
+        );
+        reader.node_buf_start = node_buf_start;
+        reader.data_buf_start = data_buf_start as u64;
+
+        Ok(reader)
+    }
+
+    /// Construct a `Reader` that skips damaged nodes instead of ending
+    /// iteration at the first error. Skipped errors are available from
+    /// `recovered_errors`. Errors in the header are not recoverable.
+    pub fn recovering(input: Bytes) -> Result<Self, ReaderError> {
+        Self::recovering_with_options(input, ReaderOptions::default())
+    }
+
+    /// Like `recovering`, with the limits and other settings of `options`.
+    /// Limits that are exceeded are reported as `RecoveryKind::Limit`.
+    pub fn recovering_with_options(
+        input: Bytes,
+        options: ReaderOptions,
+    ) -> Result<Self, ReaderError> {
+        let mut reader = Self::with_options(input, options)?;
+        reader.recovery = Some(Recovery::Reading);
+
+        Ok(reader)
+    }
+
+    /// Pick the byte order whose node buffer length and data buffer length
+    /// fit in the input, preferring big endian. `len_node` is the node buffer
+    /// length read as big endian.
+    fn detect_endianness(input: &[u8], len_node: u32) -> Endianness {
+        let fits = |len_node: u32, read_u32: fn(&[u8]) -> u32| {
+            let data_len_start = 8 + len_node as usize;
+            match input.get(data_len_start..data_len_start + 4) {
+                Some(len_data) => data_len_start + 4 + read_u32(len_data) as usize <= input.len(),
+                None => false,
+            }
+        };
+
+        if !fits(len_node, BigEndian::read_u32) &&
+            fits(len_node.swap_bytes(), LittleEndian::read_u32)
+        {
+            Endianness::Little
+        } else {
+            Endianness::Big
+        }
+    }
+
+    /// Read the 8 byte header preceding the node buffer, returning the
+    /// compression type, encoding type, and the length of the node buffer.
+    pub(crate) fn read_header<T>(
+        header: &mut T,
+    ) -> Result<(CompressionType, EncodingType, u32), ReaderError>
+    where
+        T: Read,
+    {
         let signature = header.read_u8().context(Signature)?;
         if signature != SIGNATURE {
             return Err(ReaderError::InvalidSignature { signature });
@@ -121,38 +433,148 @@ impl Reader {
         let len_node = header.read_u32::<BigEndian>().context(NodeBufferLength)?;
         info!("len_node: {0} (0x{0:x})", len_node);
 
-        // The length of the data buffer is the 4 bytes right after the node buffer.
-        header
-            .seek(SeekFrom::Current(len_node as i64))
-            .context(DataLengthSeek { len_node })?;
+        Ok((compression, encoding, len_node))
+    }
 
-        let len_data = header.read_u32::<BigEndian>().context(DataBufferLength)?;
-        info!("len_data: {0} (0x{0:x})", len_data);
+    /// Construct a `Reader` from an already split node buffer and data buffer.
+    /// The data buffer alignment is taken from `options`, or detected if
+    /// `detect_alignment` is set.
+    pub(crate) fn from_parts(
+        compression: CompressionType,
+        encoding: EncodingType,
+        endianness: Endianness,
+        options: ReaderOptions,
+        node_buf: Bytes,
+        data_buf: Bytes,
+    ) -> Self {
+        let alignment = match options.alignment {
+            Some(alignment) => alignment,
+            None if !options.detect_alignment => Alignment::Four,
+            None => Self::detect_alignment(
+                compression,
+                encoding,
+                endianness,
+                &options,
+                &node_buf,
+                &data_buf,
+            ),
+        };
 
-        // We have read 8 bytes so far, so offset the start of the node buffer from
-        // the start of the input data. After that is the length of the data buffer.
-        // The data buffer is everything after that.
-        let node_buffer_end = 8 + len_node as usize;
-        let data_buffer_start = node_buffer_end + 4;
-        let node_buf = ByteBufferRead::new(input.slice(8..node_buffer_end));
-        let data_buf = ByteBufferRead::new(input.slice(data_buffer_start..));
+        Self::with_alignment(
+            compression,
+            encoding,
+            alignment,
+            endianness,
+            options,
+            node_buf,
+            data_buf,
+        )
+    }
 
-        Ok(Self {
+    fn with_alignment(
+        compression: CompressionType,
+        encoding: EncodingType,
+        alignment: Alignment,
+        endianness: Endianness,
+        options: ReaderOptions,
+        node_buf: Bytes,
+        data_buf: Bytes,
+    ) -> Self {
+        // The header is 8 bytes and the data buffer length is 4 bytes.
+        let data_buf_start = (8 + node_buf.len() + 4) as u64;
+
+        let mut data_buf = ByteBufferRead::with_alignment(data_buf, alignment.size());
+        data_buf.set_endianness(endianness);
+
+        Self {
             compression,
             encoding,
+            alignment,
+            endianness,
+            options,
 
-            node_buf,
+            node_buf: ByteBufferRead::new(node_buf),
             data_buf,
 
-            data_buf_start: data_buffer_start as u64,
-        })
+            node_buf_start: 8,
+            data_buf_start,
+
+            attribute_keys: Vec::new(),
+            depth: 0,
+            nodes: 0,
+            error: None,
+
+            recovery: None,
+            recovered: Vec::new(),
+            open_nodes: 0,
+
+            offsets: Vec::new(),
+            open_offsets: Vec::new(),
+
+            #[cfg(feature = "tracing")]
+            spans: Vec::new(),
+        }
+    }
+
+    /// Pick the alignment that reads the whole document and consumes exactly
+    /// the data buffer. Eight byte alignment is only chosen when four byte
+    /// alignment does not fit, so damaged documents are read with the default.
+    fn detect_alignment(
+        compression: CompressionType,
+        encoding: EncodingType,
+        endianness: Endianness,
+        options: &ReaderOptions,
+        node_buf: &Bytes,
+        data_buf: &Bytes,
+    ) -> Alignment {
+        // Eight byte aligned data buffers always have a length that is a
+        // multiple of eight
+        if !data_buf.len().is_multiple_of(8) {
+            return Alignment::Four;
+        }
+
+        let mut options = options.clone();
+        options.record_offsets = false;
+        options.deny_trailing_data = false;
+
+        let fits = |alignment| {
+            Self::with_alignment(
+                compression,
+                encoding,
+                alignment,
+                endianness,
+                options.clone(),
+                node_buf.clone(),
+                data_buf.clone(),
+            )
+            .reads_to_end()
+        };
+
+        if !fits(Alignment::Four) && fits(Alignment::Eight) {
+            Alignment::Eight
+        } else {
+            Alignment::Four
+        }
+    }
+
+    /// Whether reading reaches the file end without errors and consumes
+    /// exactly the data buffer.
+    fn reads_to_end(mut self) -> bool {
+        let file_end = self.any(|def| def.node_type == StandardType::FileEnd);
+
+        file_end && self.error.is_none() && self.data_buf.is_empty()
     }
 
     fn parse_node_type(raw_node_type: u8) -> Result<(StandardType, bool), ReaderError> {
         let is_array = raw_node_type & ARRAY_MASK == ARRAY_MASK;
         let node_type = raw_node_type & !ARRAY_MASK;
 
-        let xml_type = StandardType::from_u8(node_type).context(InvalidNodeType)?;
+        let xml_type = match StandardType::from_u8(node_type) {
+            // `Unknown` is a placeholder and never a valid type id on its own
+            Ok(StandardType::Unknown) => Err(UnknownKbinType::Byte(node_type)),
+            result => result,
+        }
+        .context(InvalidNodeType)?;
         debug!(
             "Reader::parse_node_type() => raw_node_type: {}, node_type: {:?} ({}), is_array: {}",
             raw_node_type, xml_type, node_type, is_array
@@ -161,13 +583,88 @@ impl Reader {
         Ok((xml_type, is_array))
     }
 
+    #[inline]
+    pub fn compression(&self) -> CompressionType {
+        self.compression
+    }
+
     #[inline]
     pub fn encoding(&self) -> EncodingType {
         self.encoding
     }
 
+    #[inline]
+    pub(crate) fn options(&self) -> &ReaderOptions {
+        &self.options
+    }
+
+    /// The data buffer alignment, either from the options or detected.
+    #[inline]
+    pub fn alignment(&self) -> Alignment {
+        self.alignment
+    }
+
+    /// The byte order, either from the options or detected.
+    #[inline]
+    pub fn endianness(&self) -> Endianness {
+        self.endianness
+    }
+
+    /// Take the error that ended iteration early, if any. The `Iterator`
+    /// implementation stops at the first error, which would otherwise be
+    /// indistinguishable from the end of the document.
+    #[inline]
+    pub fn take_error(&mut self) -> Option<ReaderError> {
+        self.error.take()
+    }
+
+    /// The errors skipped by a reader constructed with `recovering`, and the
+    /// file end markers ignored because of `skip_stray_file_ends`.
+    #[inline]
+    pub fn recovered_errors(&self) -> &[RecoveredError] {
+        &self.recovered
+    }
+
+    #[inline]
+    pub fn take_recovered_errors(&mut self) -> Vec<RecoveredError> {
+        std::mem::take(&mut self.recovered)
+    }
+
+    /// The number of bytes of the input read so far, counting the header and
+    /// both buffers.
+    #[inline]
+    pub(crate) fn bytes_read(&self) -> usize {
+        self.node_buf_start + self.node_buf.position() as usize + self.data_buf.position() as usize
+    }
+
+    /// Check that nothing but zeroed padding is left in either buffer after
+    /// the root node. The file end marker is skipped if it has not been read
+    /// yet. Fails with `ReaderError::TrailingData` otherwise.
+    pub fn check_trailing_data(&self) -> Result<(), ReaderError> {
+        let file_end = StandardType::FileEnd as u8 | ARRAY_MASK;
+        let node_buf = &self.node_buf.get_ref()[self.node_buf.position() as usize..];
+        let node_buf = node_buf.strip_prefix(&[file_end]).unwrap_or(node_buf);
+        let data_buf = self.data_buf.get_ref();
+        let data_buf = data_buf.get(self.data_buf.position() as usize..).unwrap_or_default();
+
+        let buffers = [
+            ("node", node_buf, 4),
+            ("data", data_buf, self.alignment.size() as usize),
+        ];
+        for (buffer, rest, alignment) in buffers {
+            if rest.len() >= alignment || rest.iter().any(|&b| b != 0) {
+                return Err(ReaderError::TrailingData {
+                    buffer,
+                    unused: rest.len(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn check_if_node_buffer_end(&self) -> Result<(), ReaderError> {
-        if self.node_buf.position() >= self.data_buf_start {
+        if self.node_buf.position() >= self.node_buf.get_ref().len() as u64 {
             Err(ReaderError::EndOfNodeBuffer)
         } else {
             Ok(())
@@ -175,12 +672,33 @@ impl Reader {
     }
 
     pub fn read_node_type(&mut self) -> Result<(StandardType, bool), ReaderError> {
+        let (_, node_type, is_array) = self.read_raw_node_type()?;
+
+        Ok((node_type, is_array))
+    }
+
+    fn read_raw_node_type(&mut self) -> Result<(u8, StandardType, bool), ReaderError> {
         self.check_if_node_buffer_end()?;
 
         let raw_node_type = self.node_buf.read_u8().context(NodeType)?;
-        let value = Self::parse_node_type(raw_node_type)?;
+        let (node_type, is_array) = match Self::parse_node_type(raw_node_type) {
+            Ok(value) => value,
+            Err(ReaderError::InvalidNodeType { .. })
+                if CustomType::find(&self.options.custom_types, raw_node_type).is_some() =>
+            {
+                let is_array = raw_node_type & ARRAY_MASK == ARRAY_MASK;
+                (StandardType::Unknown, is_array)
+            },
+            Err(ReaderError::InvalidNodeType { .. }) if self.options.preserve_unknown_types => {
+                warn!("Preserving unknown node type: {}", raw_node_type);
 
-        Ok(value)
+                let is_array = raw_node_type & ARRAY_MASK == ARRAY_MASK;
+                (StandardType::Unknown, is_array)
+            },
+            Err(e) => return Err(e),
+        };
+
+        Ok((raw_node_type, node_type, is_array))
     }
 
     pub fn read_node_data(
@@ -196,12 +714,33 @@ impl Reader {
 
         let value = match node_type {
             StandardType::Attribute | StandardType::String => {
-                self.data_buf.buf_read().context(DataBuffer { node_type })?
+                let data = self.data_buf.buf_read().context(DataBuffer { node_type })?;
+                if let Some(max_string_len) = self.options.max_string_len {
+                    if data.len() > max_string_len {
+                        return Err(ReaderError::StringLimit {
+                            len: data.len(),
+                            max_string_len,
+                        });
+                    }
+                }
+
+                data
+            },
+            StandardType::Binary | StandardType::Unknown => {
+                self.read_bytes().context(DataBuffer { node_type })?
             },
-            StandardType::Binary => self.read_bytes().context(DataBuffer { node_type })?,
             StandardType::NodeStart | StandardType::NodeEnd | StandardType::FileEnd => Bytes::new(),
             node_type if is_array => {
-                let arr_size = self.data_buf.read_u32::<BigEndian>().context(ArrayLength)?;
+                let arr_size = self
+                    .endianness
+                    .read_u32(&mut *self.data_buf)
+                    .context(ArrayLength)?;
+                if let Some(max_array_len) = self.options.max_array_len {
+                    let len = arr_size as usize / (node_type.size * node_type.count);
+                    if len > max_array_len {
+                        return Err(ReaderError::ArrayLimit { len, max_array_len });
+                    }
+                }
                 let data = self
                     .data_buf
                     .get(arr_size)
@@ -217,6 +756,17 @@ impl Reader {
                 .get_aligned(node_type)
                 .context(DataBuffer { node_type })?,
         };
+
+        // Values are decoded as big endian
+        let value = match self.endianness {
+            Endianness::Little if !value.is_empty() => {
+                let mut data = value.to_vec();
+                self.endianness.swap_value_bytes(node_type, &mut data);
+
+                Bytes::from(data)
+            },
+            _ => value,
+        };
         debug!(
             "Reader::read_node_data(node_type: {:?}, is_array: {}) => value: 0x{:02x?}",
             node_type,
@@ -227,52 +777,268 @@ impl Reader {
         Ok(value)
     }
 
+    /// Read the name of a definition of type `node_type` from the node
+    /// buffer.
+    fn read_key(&mut self, node_type: StandardType) -> Result<Key, ReaderError> {
+        let key = match self.compression {
+            CompressionType::Compressed => {
+                let size = Sixbit::size(&mut *self.node_buf).context(NodeSixbitName)?;
+                let data = self
+                    .node_buf
+                    .get(size.real_len as u32)
+                    .context(NodeBuffer { node_type })?;
+
+                Key::Compressed { size, data }
+            },
+            CompressionType::Uncompressed => {
+                let encoding = self.encoding;
+                let length = (self.node_buf.read_u8().context(NameLength)? & !ARRAY_MASK) + 1;
+                let data = self
+                    .node_buf
+                    .get(length as u32)
+                    .context(NodeBuffer { node_type })?;
+
+                Key::Uncompressed { encoding, data }
+            },
+        };
+
+        Ok(key)
+    }
+
     pub fn read_node_definition(&mut self) -> Result<NodeDefinition, ReaderError> {
-        let (node_type, is_array) = self.read_node_type()?;
+        #[cfg(feature = "tracing")]
+        let span = self.spans.last().cloned().unwrap_or_else(tracing::Span::current);
+        #[cfg(feature = "tracing")]
+        let _entered = span.enter();
+
+        let (node_offset, raw_node_type, node_type, is_array) = loop {
+            let node_offset = self.node_buf_start + self.node_buf.position() as usize;
+            let (raw_node_type, node_type, is_array) = self.read_raw_node_type()?;
+
+            if node_type == StandardType::FileEnd &&
+                self.depth > 0 &&
+                self.options.skip_stray_file_ends
+            {
+                self.skip_stray_file_end(node_offset);
+                continue;
+            }
+
+            break (node_offset, raw_node_type, node_type, is_array);
+        };
+        self.check_limits(node_type)?;
+        if node_type == StandardType::FileEnd && self.options.deny_trailing_data {
+            self.check_trailing_data()?;
+        }
 
         match node_type {
             StandardType::NodeEnd | StandardType::FileEnd => {
+                if self.options.record_offsets && node_type == StandardType::NodeEnd {
+                    self.open_offsets.pop();
+                }
+                #[cfg(feature = "tracing")]
+                {
+                    if node_type == StandardType::NodeEnd {
+                        self.spans.pop();
+                    }
+                }
+
                 Ok(NodeDefinition::new(self.encoding, node_type, is_array))
             },
             _ => {
-                let key = match self.compression {
-                    CompressionType::Compressed => {
-                        let size = Sixbit::size(&mut *self.node_buf).context(NodeSixbitName)?;
-                        let data = self
-                            .node_buf
-                            .get(size.real_len as u32)
-                            .context(NodeBuffer { node_type })?;
-
-                        Key::Compressed { size, data }
-                    },
-                    CompressionType::Uncompressed => {
-                        let encoding = self.encoding;
-                        let length =
-                            (self.node_buf.read_u8().context(NameLength)? & !ARRAY_MASK) + 1;
-                        let data = self
-                            .node_buf
-                            .get(length as u32)
-                            .context(NodeBuffer { node_type })?;
-
-                        Key::Uncompressed { encoding, data }
+                let key = self.read_key(node_type)?;
+                if self.options.deny_duplicate_attributes {
+                    self.check_duplicate_attribute(node_type, &key)?;
+                }
+
+                let custom_type = match node_type {
+                    StandardType::Unknown if !is_array => {
+                        CustomType::find(&self.options.custom_types, raw_node_type)
                     },
+                    _ => None,
                 };
-                let value_data = self.read_node_data(node_type, is_array)?;
+                let value_data = match custom_type {
+                    Some(custom_type) => self
+                        .data_buf
+                        .get_aligned_size(custom_type.value_size())
+                        .context(DataBuffer { node_type })?,
+                    None => self.read_node_data(node_type, is_array)?,
+                };
+                if self.options.record_offsets {
+                    self.record_offset(node_type, is_array, node_offset, &key, &value_data);
+                }
+                #[cfg(feature = "tracing")]
+                self.trace_node(node_type, is_array, node_offset, &key, &value_data);
+                let data = NodeData::Some { key, value_data };
 
-                Ok(NodeDefinition::with_data(
-                    self.encoding,
-                    node_type,
-                    is_array,
-                    NodeData::Some { key, value_data },
-                ))
+                if node_type == StandardType::Unknown {
+                    Ok(NodeDefinition::unknown(self.encoding, raw_node_type, data))
+                } else {
+                    Ok(NodeDefinition::with_data(
+                        self.encoding,
+                        node_type,
+                        is_array,
+                        data,
+                    ))
+                }
+            },
+        }
+    }
+
+    /// Open the span of a node, or record an event for an attribute. The
+    /// nesting of the spans gives the path of the node.
+    #[cfg(feature = "tracing")]
+    fn trace_node(
+        &mut self,
+        node_type: StandardType,
+        is_array: bool,
+        node_offset: usize,
+        key: &Key,
+        value_data: &Bytes,
+    ) {
+        let data_offset = match node_type {
+            StandardType::NodeStart => None,
+            _ => Some(self.last_data_offset()),
+        };
+
+        if node_type == StandardType::Attribute {
+            trace!(
+                key = key.name().as_deref().unwrap_or_default(),
+                node_offset,
+                data_offset,
+                data_len = value_data.len(),
+                "attribute"
+            );
+        } else {
+            let span = debug_span!(
+                "node",
+                key = key.name().as_deref().unwrap_or_default(),
+                ?node_type,
+                is_array,
+                node_offset,
+                data_offset,
+                data_len = value_data.len()
+            );
+            self.spans.push(span);
+        }
+    }
+
+    fn skip_stray_file_end(&mut self, offset: usize) {
+        let error = ReaderError::StrayFileEnd { depth: self.depth };
+        warn!("Ignoring file end marker at offset {}: {}", offset, error);
+
+        self.recovered.push(RecoveredError {
+            offset,
+            kind: RecoveryKind::StrayFileEnd,
+            error,
+        });
+    }
+
+    fn record_offset(
+        &mut self,
+        node_type: StandardType,
+        is_array: bool,
+        node_offset: usize,
+        key: &Key,
+        value_data: &Bytes,
+    ) {
+        let data = match node_type {
+            StandardType::NodeStart => None,
+            _ => Some((self.last_data_offset(), value_data.len())),
+        };
+
+        let parent = self.open_offsets.last().copied();
+        if node_type != StandardType::Attribute {
+            self.open_offsets.push(self.offsets.len());
+        }
+        self.offsets.push(RawOffset {
+            parent,
+            key: key.to_string().unwrap_or_default(),
+            node_type,
+            is_array,
+            node_offset,
+            data,
+        });
+    }
+
+    /// The offset from the start of the file of the next definition in the
+    /// node buffer.
+    pub(crate) fn node_offset(&self) -> usize {
+        self.node_buf_start + self.node_buf.position() as usize
+    }
+
+    /// Whether the data buffer comes before the node buffer in the input.
+    pub(crate) fn is_data_first(&self) -> bool {
+        self.data_buf_start < self.node_buf_start as u64
+    }
+
+    /// The offset from the start of the file of the value data returned by
+    /// the last read.
+    pub(crate) fn last_data_offset(&self) -> usize {
+        self.data_buf_start as usize + self.data_buf.last_offset()
+    }
+
+    /// The location of every node and attribute read so far, which covers
+    /// the whole document after a full parse. Empty unless `record_offsets`
+    /// was set in the options.
+    pub fn offset_map(&self) -> OffsetMap {
+        OffsetMap::from_raw(&self.offsets)
+    }
+
+    fn check_limits(&mut self, node_type: StandardType) -> Result<(), ReaderError> {
+        match node_type {
+            StandardType::NodeEnd => {
+                self.depth = self.depth.saturating_sub(1);
+                return Ok(());
             },
+            StandardType::FileEnd => return Ok(()),
+            StandardType::Attribute => {},
+            _ => {
+                self.depth += 1;
+                if let Some(max_depth) = self.options.max_depth {
+                    if self.depth > max_depth {
+                        return Err(ReaderError::DepthLimit { max_depth });
+                    }
+                }
+            },
+        };
+
+        self.nodes += 1;
+        if let Some(max_nodes) = self.options.max_nodes {
+            if self.nodes > max_nodes {
+                return Err(ReaderError::NodeLimit { max_nodes });
+            }
         }
+
+        Ok(())
+    }
+
+    fn check_duplicate_attribute(
+        &mut self,
+        node_type: StandardType,
+        key: &Key,
+    ) -> Result<(), ReaderError> {
+        if node_type != StandardType::Attribute {
+            self.attribute_keys.clear();
+            return Ok(());
+        }
+
+        // Keys that cannot be decoded fail later when the value is converted
+        if let Ok(key) = key.name() {
+            if self.attribute_keys.iter().any(|seen| *seen == *key) {
+                return Err(ReaderError::DuplicateAttribute {
+                    key: key.into_string(),
+                });
+            }
+            self.attribute_keys.push(key.into_string());
+        }
+
+        Ok(())
     }
 
     pub fn read_u32(&mut self) -> Result<u32, ReaderError> {
         let value = self
-            .data_buf
-            .read_u32::<BigEndian>()
+            .endianness
+            .read_u32(&mut *self.data_buf)
             .context(DataRead { size: 4usize })?;
         debug!("Reader::read_u32() => result: {}", value);
 
@@ -285,16 +1051,756 @@ impl Reader {
     }
 }
 
+impl Reader {
+    /// The position in the node buffer after the definition whose name
+    /// starts at the current position and the attributes and children of
+    /// the node, ignoring their types and values. `None` if the node buffer
+    /// ends or a name cannot be read first.
+    fn skip_subtree(&mut self) -> Option<u64> {
+        let mut depth = 0;
+        self.read_key(StandardType::NodeStart).ok()?;
+
+        loop {
+            self.check_if_node_buffer_end().ok()?;
+            let node_type = self.node_buf.read_u8().ok()? & !ARRAY_MASK;
+
+            if node_type == StandardType::NodeEnd as u8 {
+                if depth == 0 {
+                    return Some(self.node_buf.position());
+                }
+                depth -= 1;
+            } else if node_type == StandardType::FileEnd as u8 {
+                return None;
+            } else {
+                self.read_key(StandardType::NodeStart).ok()?;
+                if node_type != StandardType::Attribute as u8 {
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    /// Continue after a definition with an invalid type, whose name starts at
+    /// the current position of the node buffer.
+    ///
+    /// The definition is taken to be a node, skipped up to its end, or else
+    /// an attribute. Its value is somewhere in the data buffer, so reading
+    /// continues at the first aligned position from which the rest of the
+    /// document can be read to the file end, using up the data buffer.
+    /// Returns `false` without moving if there is no such position.
+    fn resynchronize(&mut self) -> bool {
+        let name_start = self.node_buf.position();
+        let node_end = self.skip_subtree();
+        self.node_buf.set_position(name_start);
+        let name_end = match self.read_key(StandardType::Attribute) {
+            Ok(_) => self.node_buf.position(),
+            Err(_) => {
+                self.node_buf.set_position(name_start);
+                return false;
+            },
+        };
+
+        let mut options = self.options.clone();
+        options.record_offsets = false;
+        options.deny_trailing_data = false;
+
+        let node_buf = self.node_buf.get_ref().clone();
+        let data_buf = self.data_buf.get_ref().clone();
+        let alignment = self.alignment.size();
+        let data_start = self.data_buf.position().next_multiple_of(alignment);
+
+        for node_pos in node_end.into_iter().chain(Some(name_end)) {
+            let mut data_pos = data_start;
+            while data_pos <= data_buf.len() as u64 {
+                let rest = Self::with_alignment(
+                    self.compression,
+                    self.encoding,
+                    self.alignment,
+                    self.endianness,
+                    options.clone(),
+                    node_buf.slice(node_pos as usize..),
+                    data_buf.slice(data_pos as usize..),
+                );
+                if rest.reads_to_end() {
+                    self.node_buf.set_position(node_pos);
+                    self.data_buf.reset_position(data_pos);
+                    return true;
+                }
+
+                data_pos += alignment;
+            }
+        }
+
+        self.node_buf.set_position(name_start);
+        false
+    }
+
+    fn next_recovering(&mut self) -> Option<NodeDefinition> {
+        loop {
+            match self.recovery {
+                Some(Recovery::Closing(0)) => {
+                    self.recovery = Some(Recovery::Done);
+                    return Some(NodeDefinition::new(
+                        self.encoding,
+                        StandardType::FileEnd,
+                        false,
+                    ));
+                },
+                Some(Recovery::Closing(remaining)) => {
+                    self.recovery = Some(Recovery::Closing(remaining - 1));
+                    return Some(NodeDefinition::new(
+                        self.encoding,
+                        StandardType::NodeEnd,
+                        false,
+                    ));
+                },
+                Some(Recovery::Done) => return None,
+                _ => {},
+            };
+
+            let offset = self.node_buf_start + self.node_buf.position() as usize;
+            let depth = self.depth;
+            match self.read_node_definition() {
+                Ok(definition) => {
+                    if let Some(Recovery::Skipping(target)) = self.recovery {
+                        if self.depth <= target {
+                            self.recovery = Some(Recovery::Reading);
+                        }
+                        continue;
+                    }
+
+                    match definition.node_type {
+                        StandardType::Attribute => {},
+                        StandardType::NodeEnd => {
+                            self.open_nodes = self.open_nodes.saturating_sub(1)
+                        },
+                        StandardType::FileEnd => self.recovery = Some(Recovery::Done),
+                        _ => self.open_nodes += 1,
+                    };
+
+                    return Some(definition);
+                },
+                Err(error) => {
+                    let kind = RecoveryKind::from_error(&error);
+                    warn!("Recovering from error at offset {}: {}", offset, error);
+                    self.recovered.push(RecoveredError {
+                        offset,
+                        kind,
+                        error,
+                    });
+
+                    let skipping = matches!(self.recovery, Some(Recovery::Skipping(_)));
+                    self.recovery = Some(match kind {
+                        // An element opened by the damaged node has to be skipped
+                        RecoveryKind::InvalidData if skipping => continue,
+                        RecoveryKind::InvalidData if self.depth > depth => {
+                            Recovery::Skipping(depth)
+                        },
+                        RecoveryKind::InvalidData => Recovery::Reading,
+                        RecoveryKind::InvalidNodeType if !skipping && self.resynchronize() => {
+                            Recovery::Reading
+                        },
+                        _ => Recovery::Closing(self.open_nodes),
+                    });
+                },
+            };
+        }
+    }
+}
+
 impl Iterator for Reader {
     type Item = NodeDefinition;
 
     fn next(&mut self) -> Option<NodeDefinition> {
+        if self.recovery.is_some() {
+            return self.next_recovering();
+        }
+
         match self.read_node_definition() {
             Ok(v) => Some(v),
             Err(e) => {
                 error!("Error reading node definition in `next()`: {}", e);
+                self.error = Some(e);
                 None
             },
         }
     }
 }
+
+/// Iterates a `Reader`, counting every element it returns in a `Progress`.
+pub(crate) struct ProgressReader<'r, 'p> {
+    reader: &'r mut Reader,
+    progress: &'r mut Progress<'p>,
+}
+
+impl<'r, 'p> ProgressReader<'r, 'p> {
+    #[inline]
+    pub(crate) fn new(reader: &'r mut Reader, progress: &'r mut Progress<'p>) -> Self {
+        Self { reader, progress }
+    }
+}
+
+impl Iterator for ProgressReader<'_, '_> {
+    type Item = NodeDefinition;
+
+    fn next(&mut self) -> Option<NodeDefinition> {
+        let definition = self.reader.next()?;
+        match definition.node_type {
+            StandardType::Attribute | StandardType::NodeEnd | StandardType::FileEnd => {},
+            _ => {
+                let reader = &self.reader;
+                self.progress.node(|| reader.bytes_read());
+            },
+        };
+
+        Some(definition)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{BigEndian, ByteOrder};
+    use bytes::Bytes;
+
+    use crate::node::Node;
+    use crate::node_types::StandardType;
+    use crate::options::ReaderOptions;
+    use crate::value::Value;
+
+    use super::RecoveryKind;
+
+    /// A document with a single `Binary` child whose type byte has been
+    /// replaced with an unused type id.
+    fn unknown_type_document() -> Vec<u8> {
+        let node = Node::with_nodes(
+            "root",
+            vec![Node::with_value("data", Value::Binary(vec![1, 2, 3]))],
+        );
+        let mut data = crate::to_binary(&node).unwrap();
+
+        // header (8) + root type (1) + sixbit "root" (4)
+        assert_eq!(data[13], 10);
+        data[13] = 60;
+
+        data
+    }
+
+    #[test]
+    fn test_unknown_type_round_trip() {
+        let data = unknown_type_document();
+
+        // Reading stops at the unknown node by default
+        let (collection, _) = crate::from_slice(&data).unwrap();
+        assert!(collection.children().is_empty());
+
+        let mut options = ReaderOptions::builder();
+        options.preserve_unknown_types(true);
+        let (collection, _) =
+            crate::from_binary_with_options(options.build(), Bytes::from(data.clone())).unwrap();
+
+        let node = collection.as_node().unwrap();
+        assert_eq!(
+            node.get_child("data").and_then(Node::value),
+            Some(&Value::Unknown {
+                type_id: 60,
+                data: vec![1, 2, 3],
+            })
+        );
+        assert_eq!(crate::to_binary(&collection).unwrap(), data);
+        assert_eq!(crate::to_binary(&node).unwrap(), data);
+
+        let text = crate::to_text_xml(&collection).unwrap();
+        let (collection, _) = crate::from_text_xml(&text).unwrap();
+        assert_eq!(
+            crate::to_binary(&collection.as_node().unwrap()).unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn test_limits() {
+        let node = Node::with_nodes(
+            "root",
+            vec![Node::with_nodes(
+                "a",
+                vec![Node::with_value("b", Value::String("text".into()))],
+            )],
+        );
+        let data = Bytes::from(crate::to_binary(&node).unwrap());
+        let text = crate::to_text_xml(&node).unwrap();
+
+        let limited = |f: &dyn Fn(&mut crate::options::ReaderOptionsBuilder)| {
+            let mut options = ReaderOptions::builder();
+            f(&mut options);
+            let options = options.build();
+
+            let binary = crate::from_binary_with_options(options.clone(), data.clone());
+            let text = crate::from_text_xml_with_options(options, &text);
+            assert_eq!(binary.is_ok(), text.is_ok());
+            binary.is_ok()
+        };
+
+        assert!(limited(&|options| {
+            options.max_depth(3).max_nodes(3);
+        }));
+        assert!(!limited(&|options| {
+            options.max_depth(2);
+        }));
+        assert!(!limited(&|options| {
+            options.max_nodes(2);
+        }));
+        assert!(!limited(&|options| {
+            options.max_data_len(3);
+        }));
+    }
+
+    #[test]
+    fn test_recovering() {
+        let node = Node::with_nodes(
+            "root",
+            vec![
+                Node::with_value("a", Value::S32(1)),
+                Node::with_nodes("b", vec![Node::with_value("c", Value::U8(2))]),
+                Node::with_value("d", Value::S32(3)),
+            ],
+        );
+        let data = crate::to_binary(&node).unwrap();
+
+        // Replace the type of `d` with an unused type id
+        let mut damaged = data.clone();
+        let offset = damaged.iter().rposition(|&b| b == 6).unwrap();
+        damaged[offset] = 60;
+        let result =
+            crate::from_binary_with_options(ReaderOptions::default(), Bytes::from(damaged.clone()));
+        assert!(result.is_err());
+
+        let (collection, _, errors) = crate::from_binary_recovering(Bytes::from(damaged)).unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].offset, offset);
+        assert_eq!(errors[0].kind, RecoveryKind::InvalidNodeType);
+
+        let recovered = collection.as_node().unwrap();
+        assert_eq!(recovered.children().unwrap().len(), 2);
+        assert_eq!(recovered.pointer(&["b", "c"]), node.pointer(&["b", "c"]));
+
+        // Undamaged documents read the same as with a normal reader
+        let (collection, _, errors) = crate::from_binary_recovering(Bytes::from(data)).unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(collection.as_node().unwrap(), node);
+    }
+
+    #[test]
+    fn test_recovering_continues() {
+        use crate::tokens::Tokens;
+
+        let node = Node::with_nodes(
+            "root",
+            vec![
+                Node::with_value("a", Value::S32(1)),
+                Node::with(
+                    "b",
+                    &[("id", "2")],
+                    vec![Node::with_value("c", Value::String("text".into()))],
+                ),
+                Node::with_value("d", Value::U8(3)),
+                Node::with_value("e", Value::String("after".into())),
+                Node::with_value("f", Value::S16(-4)),
+            ],
+        );
+        let data = crate::to_binary(&node).unwrap();
+        let tokens = Tokens::new(Bytes::from(data.clone()))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        // Damage `a`, which has a value, and `b`, which has an attribute and
+        // a child with a value
+        for &(token, skipped) in &[(1, "a"), (3, "b")] {
+            let mut damaged = data.clone();
+            damaged[tokens[token].offset] = 60;
+
+            let (collection, _, errors) =
+                crate::from_binary_recovering(Bytes::from(damaged)).unwrap();
+            assert_eq!(errors.len(), 1);
+            assert_eq!(errors[0].kind, RecoveryKind::InvalidNodeType);
+
+            let mut expected = node.clone();
+            let children = expected.children_mut().unwrap();
+            children.retain(|child| child.key() != skipped);
+            assert_eq!(collection.as_node().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_stray_file_end() {
+        use crate::tokens::{TokenWriter, Tokens};
+
+        let node = Node::with_nodes(
+            "root",
+            vec![
+                Node::with_value("a", Value::S32(1)),
+                Node::with_value("b", Value::S32(2)),
+            ],
+        );
+        let data = Bytes::from(crate::to_binary(&node).unwrap());
+
+        // Insert a file end marker after the end of `a`
+        let tokens = Tokens::new(data).unwrap();
+        let mut writer = TokenWriter::new(tokens.compression(), tokens.encoding());
+        let mut tokens = tokens.collect::<Result<Vec<_>, _>>().unwrap();
+        let offset = tokens[3].offset;
+        tokens.insert(3, tokens.last().unwrap().clone());
+        for token in &tokens {
+            writer.push(token).unwrap();
+        }
+        let damaged = Bytes::from(writer.finish().unwrap());
+
+        let (collection, _) = crate::from_binary(damaged.clone()).unwrap();
+        assert_eq!(collection.children().len(), 1);
+
+        let mut options = ReaderOptions::builder();
+        options.skip_stray_file_ends(true);
+        let mut reader = super::Reader::with_options(damaged, options.build()).unwrap();
+        let collection = crate::NodeCollection::from_iter(&mut reader).unwrap();
+        assert_eq!(collection.as_node().unwrap(), node);
+
+        let errors = reader.recovered_errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].offset, offset);
+        assert_eq!(errors[0].kind, RecoveryKind::StrayFileEnd);
+    }
+
+    #[test]
+    fn test_recovering_with_options() {
+        use super::ReaderError;
+        use crate::tokens::{TokenWriter, Tokens};
+
+        let node = Node::with_nodes(
+            "root",
+            vec![
+                Node::with_value("a", Value::S32(1)),
+                Node::with_value("b", Value::S32(2)),
+                Node::with_value("c", Value::String("text".into())),
+            ],
+        );
+        let data = Bytes::from(crate::to_binary(&node).unwrap());
+
+        // Insert a file end marker after the end of `a`
+        let tokens = Tokens::new(data).unwrap();
+        let mut writer = TokenWriter::new(tokens.compression(), tokens.encoding());
+        let mut tokens = tokens.collect::<Result<Vec<_>, _>>().unwrap();
+        let stray_offset = tokens[3].offset;
+        tokens.insert(3, tokens.last().unwrap().clone());
+        for token in &tokens {
+            writer.push(token).unwrap();
+        }
+        let damaged = Bytes::from(writer.finish().unwrap());
+
+        // The string in `c` is over the limit
+        let mut options = ReaderOptions::builder();
+        options.skip_stray_file_ends(true).max_string_len(3);
+        let mut reader = super::Reader::recovering_with_options(damaged, options.build()).unwrap();
+        let collection = crate::NodeCollection::from_iter(&mut reader).unwrap();
+        let mut expected = node.clone();
+        expected.children_mut().unwrap().pop();
+        assert_eq!(collection.as_node().unwrap(), expected);
+
+        let errors = reader.recovered_errors();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].offset, stray_offset);
+        assert_eq!(errors[0].kind, RecoveryKind::StrayFileEnd);
+        assert_eq!(errors[1].kind, RecoveryKind::Limit);
+        assert!(matches!(
+            errors[1].error,
+            ReaderError::StringLimit { len: 5, .. }
+        ));
+
+        let error = ReaderError::ArrayLimit {
+            len: 2,
+            max_array_len: 1,
+        };
+        assert_eq!(RecoveryKind::from_error(&error), RecoveryKind::Limit);
+        let error = ReaderError::DuplicateAttribute { key: "id".into() };
+        assert_eq!(
+            RecoveryKind::from_error(&error),
+            RecoveryKind::DuplicateAttribute
+        );
+    }
+
+    #[test]
+    fn test_offset_map() {
+        let node = Node::with_nodes(
+            "root",
+            vec![
+                Node::with_attrs_value("item", &[("id", "1")], Value::U8(7)),
+                Node::with_value("item", Value::S32(-1)),
+                Node::with_value("name", Value::String("abc".into())),
+            ],
+        );
+        let data = crate::to_binary(&node).unwrap();
+
+        let mut options = ReaderOptions::builder();
+        options.record_offsets(true);
+        let mut reader =
+            super::Reader::with_options(Bytes::from(data.clone()), options.build()).unwrap();
+        for _ in &mut reader {}
+
+        let map = reader.offset_map();
+        let paths: Vec<_> = map
+            .entries()
+            .iter()
+            .map(|entry| entry.path.to_string())
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                "/root",
+                "/root/item[0]",
+                "/root/item[0]/@id",
+                "/root/item[1]",
+                "/root/name",
+            ]
+        );
+
+        let entries = map.entries();
+        assert_eq!(entries[0].data_offset, None);
+        assert_eq!(data[entries[1].node_offset], StandardType::U8 as u8);
+        let value = |entry: &crate::OffsetEntry| {
+            let start = entry.data_offset.unwrap();
+            &data[start..start + entry.len]
+        };
+        assert_eq!(value(&entries[1]), &[7]);
+        assert_eq!(value(&entries[2]), b"1\0");
+        assert_eq!(value(&entries[3]), &(-1i32).to_be_bytes());
+        assert_eq!(value(&entries[4]), b"abc\0");
+        assert!(map.to_string().starts_with("path\tnode_offset"));
+    }
+
+    #[test]
+    fn test_swapped_sections() {
+        let node = Node::with_nodes(
+            "root",
+            vec![
+                Node::with_attrs_value("item", &[("id", "1")], Value::U8(7)),
+                Node::with_value("name", Value::String("abc".into())),
+            ],
+        );
+        let data = crate::to_binary(&node).unwrap();
+
+        // Move the data buffer and its length before the node buffer
+        let len_node = BigEndian::read_u32(&data[4..8]) as usize;
+        let (node_section, data_section) = data[4..].split_at(4 + len_node);
+        let swapped = [&data[..4], data_section, node_section].concat();
+
+        let mut options = ReaderOptions::builder();
+        options.record_offsets(true);
+        let mut reader =
+            super::Reader::with_options(Bytes::from(swapped.clone()), options.build()).unwrap();
+        for _ in &mut reader {}
+        let entries = reader.offset_map().entries().to_vec();
+        assert_eq!(swapped[entries[1].node_offset], StandardType::U8 as u8);
+        let start = entries[1].data_offset.unwrap();
+        assert_eq!(swapped[start], 7);
+
+        // Written back in the standard layout
+        let (collection, _) = crate::from_slice(&swapped).unwrap();
+        assert_eq!(Node::from_collection(&collection).unwrap(), node);
+        assert_eq!(crate::to_binary(&collection).unwrap(), data);
+    }
+
+    #[test]
+    fn test_trailing_data() {
+        use super::ReaderError;
+        use crate::error::KbinError;
+
+        let node = Node::with_nodes(
+            "root",
+            vec![
+                Node::with_value("a", Value::U8(1)),
+                Node::with_value("name", Value::String("abc".into())),
+            ],
+        );
+        let data = crate::to_binary(&node).unwrap();
+        let mut options = ReaderOptions::builder();
+        options.deny_trailing_data(true);
+        let options = options.build();
+        let read = |data: &[u8]| {
+            crate::from_binary_with_options(options.clone(), Bytes::from(data.to_vec()))
+        };
+        assert!(read(&data).is_ok());
+
+        // Garbage after the data buffer, counted in its length
+        let len_node = BigEndian::read_u32(&data[4..8]) as usize;
+        let mut garbage = data.clone();
+        let len_data = BigEndian::read_u32(&data[8 + len_node..]) + 8;
+        BigEndian::write_u32(&mut garbage[8 + len_node..], len_data);
+        garbage.extend_from_slice(&[0xab; 8]);
+        assert!(crate::from_binary(Bytes::from(garbage.clone())).is_ok());
+        assert!(matches!(
+            read(&garbage),
+            Err(KbinError::Reader {
+                source: ReaderError::TrailingData {
+                    buffer: "data",
+                    unused: 8,
+                },
+            })
+        ));
+
+        // Garbage after the file end marker of the node buffer
+        let mut garbage = data[..8 + len_node].to_vec();
+        garbage.extend_from_slice(&[1, 2, 3, 4]);
+        garbage.extend_from_slice(&data[8 + len_node..]);
+        BigEndian::write_u32(&mut garbage[4..8], len_node as u32 + 4);
+        let mut reader = super::Reader::with_options(Bytes::from(garbage), options).unwrap();
+        for _ in &mut reader {}
+        assert!(matches!(
+            reader.take_error(),
+            Some(ReaderError::TrailingData { buffer: "node", .. })
+        ));
+    }
+
+    #[test]
+    fn test_partial_tree() {
+        use crate::error::KbinError;
+
+        let node = Node::with_nodes(
+            "root",
+            vec![
+                Node::with_value("a", Value::S32(1)),
+                Node::with_nodes("b", vec![Node::with_value("c", Value::U8(2))]),
+                Node::with_value("d", Value::S32(3)),
+            ],
+        );
+        let mut damaged = crate::to_binary(&node).unwrap();
+        let offset = damaged.iter().rposition(|&b| b == 6).unwrap();
+        damaged[offset] = 60;
+
+        let mut options = ReaderOptions::builder();
+        options.keep_partial_tree(true);
+        match crate::from_binary_with_options(options.build(), Bytes::from(damaged)) {
+            Err(KbinError::PartialTree {
+                collection,
+                last_path,
+                source,
+            }) => {
+                assert!(matches!(*source, KbinError::Reader { .. }));
+                assert_eq!(collection.children().len(), 2);
+                assert_eq!(last_path.unwrap().to_string(), "/root/b/c");
+            },
+            result => panic!("unexpected result: {:?}", result),
+        };
+
+        // Text XML closes the nodes that were still open
+        let input = br#"<root><a __type="s32">1</a><b><c __type="u8">300</c></b></root>"#;
+        let mut options = ReaderOptions::builder();
+        options.keep_partial_tree(true);
+        match crate::from_text_xml_with_options(options.build(), input) {
+            Err(KbinError::PartialTree {
+                collection,
+                last_path,
+                ..
+            }) => {
+                assert_eq!(collection.children().len(), 2);
+                assert_eq!(last_path.unwrap().to_string(), "/root/b/c");
+            },
+            result => panic!("unexpected result: {:?}", result),
+        };
+
+        assert!(matches!(
+            crate::from_text_xml(input),
+            Err(KbinError::TextReader { .. })
+        ));
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_tracing_spans() {
+        use std::fmt;
+        use std::sync::{Arc, Mutex};
+
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata, Subscriber};
+
+        /// The path of every node span, built from the `key` fields of the
+        /// span and the spans entered when it was created.
+        #[derive(Clone, Default)]
+        struct Paths {
+            spans: Arc<Mutex<Vec<String>>>,
+            entered: Arc<Mutex<Vec<u64>>>,
+        }
+
+        struct KeyVisitor(String);
+
+        impl Visit for KeyVisitor {
+            fn record_str(&mut self, field: &Field, value: &str) {
+                if field.name() == "key" {
+                    self.0 = value.to_owned();
+                }
+            }
+
+            fn record_debug(&mut self, _field: &Field, _value: &dyn fmt::Debug) {}
+        }
+
+        impl Subscriber for Paths {
+            fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut visitor = KeyVisitor(String::new());
+                span.record(&mut visitor);
+
+                let mut spans = self.spans.lock().unwrap();
+                let path = match self.entered.lock().unwrap().last() {
+                    Some(&parent) => format!("{}/{}", spans[parent as usize - 1], visitor.0),
+                    None => visitor.0,
+                };
+                spans.push(path);
+
+                Id::from_u64(spans.len() as u64)
+            }
+
+            fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+            fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+            fn event(&self, _event: &Event<'_>) {}
+
+            fn enter(&self, span: &Id) {
+                self.entered.lock().unwrap().push(span.into_u64());
+            }
+
+            fn exit(&self, _span: &Id) {
+                self.entered.lock().unwrap().pop();
+            }
+        }
+
+        let node = Node::with_nodes(
+            "root",
+            vec![
+                Node::with_nodes("a", vec![Node::with_value("b", Value::U8(1))]),
+                Node::new("c"),
+            ],
+        );
+        let data = crate::to_binary(&node).unwrap();
+
+        let paths = Paths::default();
+        tracing::subscriber::with_default(paths.clone(), || {
+            crate::from_binary(data.into()).unwrap();
+        });
+        assert_eq!(
+            *paths.spans.lock().unwrap(),
+            ["root", "root/a", "root/a/b", "root/c"]
+        );
+
+        let paths = Paths::default();
+        tracing::subscriber::with_default(paths.clone(), || {
+            crate::to_binary(&node).unwrap();
+        });
+        assert_eq!(
+            *paths.spans.lock().unwrap(),
+            ["root", "root/a", "root/a/b", "root/c"]
+        );
+    }
+}
@@ -0,0 +1,478 @@
+//! Search node names, attribute values, and values of a `NodeCollection`
+//! without converting it to XML first.
+
+use std::collections::VecDeque;
+use std::ops::Range;
+
+#[cfg(feature = "regex")]
+use regex::{Regex, RegexBuilder};
+
+use crate::error::KbinError;
+use crate::node::{NodeCollection, NodeDefinition};
+use crate::node_types::StandardType;
+
+/// What part of a node a `SearchMatch` was found in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MatchKind {
+    /// The name of the node
+    Name,
+
+    /// The value of the attribute with the given key
+    Attribute(String),
+
+    /// The value of the node, formatted the same way as the XML output
+    Value,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchMatch {
+    /// Slash separated path of node names from the root to the matching node.
+    /// Siblings sharing a name are disambiguated with a zero-based index
+    /// suffix, e.g. `/root/music[2]/title`.
+    pub path: String,
+    pub kind: MatchKind,
+
+    /// The text that was searched
+    pub text: String,
+
+    /// The byte range of the match within `text`
+    pub span: Range<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchOptions {
+    pub(crate) names: bool,
+    pub(crate) attributes: bool,
+    pub(crate) values: bool,
+    pub(crate) case_insensitive: bool,
+    #[cfg(feature = "regex")]
+    pub(crate) regex: bool,
+}
+
+pub struct SearchOptionsBuilder {
+    names: bool,
+    attributes: bool,
+    values: bool,
+    case_insensitive: bool,
+    #[cfg(feature = "regex")]
+    regex: bool,
+}
+
+enum Matcher {
+    Substring {
+        pattern: String,
+        case_insensitive: bool,
+    },
+    #[cfg(feature = "regex")]
+    Regex(Regex),
+}
+
+impl SearchOptions {
+    pub fn builder() -> SearchOptionsBuilder {
+        SearchOptionsBuilder::default()
+    }
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptionsBuilder::default().build()
+    }
+}
+
+impl Default for SearchOptionsBuilder {
+    fn default() -> Self {
+        Self {
+            names: true,
+            attributes: true,
+            values: true,
+            case_insensitive: false,
+            #[cfg(feature = "regex")]
+            regex: false,
+        }
+    }
+}
+
+impl SearchOptionsBuilder {
+    pub fn names(&mut self, names: bool) -> &mut Self {
+        self.names = names;
+        self
+    }
+
+    pub fn attributes(&mut self, attributes: bool) -> &mut Self {
+        self.attributes = attributes;
+        self
+    }
+
+    pub fn values(&mut self, values: bool) -> &mut Self {
+        self.values = values;
+        self
+    }
+
+    /// Match ASCII characters regardless of case.
+    pub fn case_insensitive(&mut self, case_insensitive: bool) -> &mut Self {
+        self.case_insensitive = case_insensitive;
+        self
+    }
+
+    /// Interpret the pattern as a regular expression rather than a literal
+    /// substring.
+    #[cfg(feature = "regex")]
+    pub fn regex(&mut self, regex: bool) -> &mut Self {
+        self.regex = regex;
+        self
+    }
+
+    pub fn build(&self) -> SearchOptions {
+        SearchOptions {
+            names: self.names,
+            attributes: self.attributes,
+            values: self.values,
+            case_insensitive: self.case_insensitive,
+            #[cfg(feature = "regex")]
+            regex: self.regex,
+        }
+    }
+}
+
+impl Matcher {
+    fn new(pattern: &str, options: &SearchOptions) -> Result<Self, KbinError> {
+        #[cfg(feature = "regex")]
+        {
+            if options.regex {
+                let regex = RegexBuilder::new(pattern)
+                    .case_insensitive(options.case_insensitive)
+                    .build()
+                    .map_err(|source| KbinError::InvalidRegex { source })?;
+
+                return Ok(Matcher::Regex(regex));
+            }
+        }
+
+        let pattern = if options.case_insensitive {
+            pattern.to_ascii_lowercase()
+        } else {
+            pattern.to_owned()
+        };
+
+        Ok(Matcher::Substring {
+            pattern,
+            case_insensitive: options.case_insensitive,
+        })
+    }
+
+    fn find_all(&self, text: &str) -> Vec<Range<usize>> {
+        match self {
+            Matcher::Substring {
+                pattern,
+                case_insensitive,
+            } => {
+                // An empty pattern would otherwise match at every position
+                if pattern.is_empty() {
+                    return Vec::new();
+                }
+
+                // ASCII lowercasing keeps byte offsets identical to `text`
+                let haystack = if *case_insensitive {
+                    text.to_ascii_lowercase()
+                } else {
+                    text.to_owned()
+                };
+
+                haystack
+                    .match_indices(pattern.as_str())
+                    .map(|(start, m)| start..start + m.len())
+                    .collect()
+            },
+            #[cfg(feature = "regex")]
+            Matcher::Regex(regex) => regex.find_iter(text).map(|m| m.range()).collect(),
+        }
+    }
+}
+
+/// Build the path segment for each child of a collection, appending an index
+/// suffix to names that appear more than once. Comments and processing
+/// instructions are left out and do not count as siblings.
+pub(crate) fn child_segments(
+    collection: &NodeCollection,
+) -> Result<Vec<(String, &NodeCollection)>, KbinError> {
+    let mut children = Vec::with_capacity(collection.children().len());
+    for child in collection.children() {
+        if !child.base().is_markup() {
+            children.push((child.base().key()?.unwrap_or_default(), child));
+        }
+    }
+
+    let segments = children
+        .iter()
+        .enumerate()
+        .map(|(i, (key, child))| {
+            let same_key = |(other, _): &&(String, &NodeCollection)| other == key;
+            let total = children.iter().filter(same_key).count();
+
+            if total > 1 {
+                let index = children[..i].iter().filter(same_key).count();
+                (format!("{}[{}]", key, index), *child)
+            } else {
+                (key.clone(), *child)
+            }
+        })
+        .collect();
+
+    Ok(segments)
+}
+
+fn push_matches(
+    matcher: &Matcher,
+    matches: &mut Vec<SearchMatch>,
+    path: &str,
+    kind: MatchKind,
+    text: String,
+) {
+    for span in matcher.find_all(&text) {
+        matches.push(SearchMatch {
+            path: path.to_owned(),
+            kind: kind.clone(),
+            text: text.clone(),
+            span,
+        });
+    }
+}
+
+fn search_collection(
+    collection: &NodeCollection,
+    path: &str,
+    matcher: &Matcher,
+    options: &SearchOptions,
+    matches: &mut Vec<SearchMatch>,
+) -> Result<(), KbinError> {
+    let base = collection.base();
+
+    if options.names {
+        let key = base.key()?.unwrap_or_default();
+        push_matches(matcher, matches, path, MatchKind::Name, key);
+    }
+
+    if options.attributes {
+        for attribute in collection.attributes() {
+            let key = attribute.key()?.unwrap_or_default();
+            let value = attribute.value()?.to_string();
+            push_matches(matcher, matches, path, MatchKind::Attribute(key), value);
+        }
+    }
+
+    if options.values {
+        match base.value() {
+            Ok(value) => push_matches(matcher, matches, path, MatchKind::Value, value.to_string()),
+            Err(KbinError::InvalidNodeType { .. }) => {},
+            Err(e) => return Err(e),
+        };
+    }
+
+    for (segment, child) in child_segments(collection)? {
+        let path = format!("{}/{}", path, segment);
+        search_collection(child, &path, matcher, options, matches)?;
+    }
+
+    Ok(())
+}
+
+/// Search a collection for `pattern`, returning every match in document order.
+pub fn grep(
+    collection: &NodeCollection,
+    pattern: &str,
+    options: SearchOptions,
+) -> Result<Vec<SearchMatch>, KbinError> {
+    let matcher = Matcher::new(pattern, &options)?;
+    let path = format!("/{}", collection.base().key()?.unwrap_or_default());
+
+    let mut matches = Vec::new();
+    search_collection(collection, &path, &matcher, &options, &mut matches)?;
+
+    Ok(matches)
+}
+
+/// Iterator over the definitions of one node type in a collection, in
+/// document order, along with their paths. Attributes are only visited when
+/// searching for `StandardType::Attribute`, with paths such as `/root/@id`.
+///
+/// Created by `NodeCollection::filter_by_type`.
+pub struct FilterByType<'a> {
+    node_type: StandardType,
+    stack: Vec<(String, &'a NodeCollection)>,
+    pending: VecDeque<Result<(String, &'a NodeDefinition), KbinError>>,
+}
+
+impl<'a> FilterByType<'a> {
+    pub(crate) fn new(collection: &'a NodeCollection, node_type: StandardType) -> Self {
+        let mut stack = Vec::new();
+        let mut pending = VecDeque::new();
+        match collection.base().key() {
+            Ok(key) => stack.push((format!("/{}", key.unwrap_or_default()), collection)),
+            Err(e) => pending.push_back(Err(e)),
+        };
+
+        Self {
+            node_type,
+            stack,
+            pending,
+        }
+    }
+
+    fn visit(&mut self, path: String, collection: &'a NodeCollection) {
+        if self.node_type == StandardType::Attribute {
+            for attribute in collection.attributes() {
+                let item = attribute
+                    .key()
+                    .map(|key| (format!("{}/@{}", path, key.unwrap_or_default()), attribute));
+                self.pending.push_back(item);
+            }
+        }
+
+        match child_segments(collection) {
+            Ok(segments) => {
+                for (segment, child) in segments.into_iter().rev() {
+                    self.stack.push((format!("{}/{}", path, segment), child));
+                }
+            },
+            Err(e) => self.pending.push_back(Err(e)),
+        };
+
+        if collection.base().node_type == self.node_type {
+            self.pending.push_front(Ok((path, collection.base())));
+        }
+    }
+}
+
+impl<'a> Iterator for FilterByType<'a> {
+    type Item = Result<(String, &'a NodeDefinition), KbinError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.pending.pop_front() {
+                return Some(item);
+            }
+
+            let (path, collection) = self.stack.pop()?;
+            self.visit(path, collection);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::node::Node;
+    use crate::options::ReaderOptions;
+    use crate::value::Value;
+
+    use super::*;
+
+    fn collection() -> NodeCollection {
+        let node = Node::with_nodes(
+            "root",
+            vec![
+                Node::with_attrs_value("music", &[("id", "1")], Value::String("Alpha".into())),
+                Node::with_attrs_value("music", &[("id", "2")], Value::String("beta".into())),
+            ],
+        );
+        let data = crate::to_binary(&node).unwrap();
+
+        crate::from_slice(&data).unwrap().0
+    }
+
+    #[test]
+    fn test_filter_by_type() {
+        let collection = collection();
+
+        let strings = collection
+            .filter_by_type(StandardType::String)
+            .map(|item| item.map(|(path, definition)| (path, definition.value().unwrap())))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            strings,
+            vec![
+                ("/root/music[0]".to_owned(), Value::String("Alpha".into())),
+                ("/root/music[1]".to_owned(), Value::String("beta".into())),
+            ]
+        );
+
+        let paths = collection
+            .filter_by_type(StandardType::Attribute)
+            .map(|item| item.map(|(path, _)| path))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(paths, vec!["/root/music[0]/@id", "/root/music[1]/@id"]);
+
+        assert_eq!(collection.filter_by_type(StandardType::Binary).count(), 0);
+    }
+
+    #[test]
+    fn test_grep() {
+        let matches = grep(&collection(), "a", SearchOptions::default()).unwrap();
+        let found: Vec<_> = matches
+            .iter()
+            .map(|m| (m.path.as_str(), &m.kind, m.span.clone()))
+            .collect();
+
+        assert_eq!(
+            found,
+            vec![
+                ("/root/music[0]", &MatchKind::Value, 4..5),
+                ("/root/music[1]", &MatchKind::Value, 3..4),
+            ]
+        );
+
+        let mut options = SearchOptions::builder();
+        options.case_insensitive(true).names(false);
+        let matches = grep(&collection(), "A", options.build()).unwrap();
+        assert_eq!(matches.len(), 3);
+        assert_eq!(matches[0].span, 0..1);
+
+        let mut options = SearchOptions::builder();
+        options.values(false);
+        let matches = grep(&collection(), "2", options.build()).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].kind, MatchKind::Attribute("id".into()));
+        assert_eq!(matches[0].path, "/root/music[1]");
+    }
+
+    #[test]
+    fn test_skips_comments() {
+        let text = "<root><!-- a --><music><b __type=\"u8\">1</b></music><?pi data?>\
+            <music><!-- b --><b __type=\"u8\">2</b></music></root>";
+        let mut options = ReaderOptions::builder();
+        options.preserve_comments(true);
+        let (collection, _) =
+            crate::from_text_xml_with_options(options.build(), text.as_bytes()).unwrap();
+        assert_eq!(collection.children().len(), 4);
+
+        let paths = collection
+            .filter_by_type(StandardType::NodeStart)
+            .map(|item| item.map(|(path, _)| path))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(paths, ["/root", "/root/music[0]", "/root/music[1]"]);
+
+        let matches = grep(&collection, "2", SearchOptions::default()).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].path, "/root/music[1]/b");
+
+        let values: Result<Vec<u8>, _> = collection.iter_values().collect();
+        assert_eq!(values.unwrap(), [1, 2]);
+        let b = collection.pointer(&["1", "b"]).unwrap();
+        assert_eq!(b.base().value().unwrap(), Value::U8(2));
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_grep_regex() {
+        let mut options = SearchOptions::builder();
+        options.regex(true).attributes(false);
+        let matches = grep(&collection(), "^[A-Z]\\w+", options.build()).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].text, "Alpha");
+        assert_eq!(matches[0].span, 0..5);
+
+        assert!(grep(&collection(), "(", options.build()).is_err());
+    }
+}
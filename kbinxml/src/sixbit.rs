@@ -1,20 +1,35 @@
-use std::collections::HashMap;
+use std::fmt;
 use std::io::{self, Read, Write};
+use std::ops::Deref;
+use std::str;
 
 use byteorder::{ReadBytesExt, WriteBytesExt};
 use snafu::{ResultExt, Snafu};
 
-static CHAR_MAP: &'static [u8] =
-    b"0123456789:ABCDEFGHIJKLMNOPQRSTUVWXYZ_abcdefghijklmnopqrstuvwxyz";
-
-lazy_static! {
-    static ref BYTE_MAP: HashMap<u8, u8> = {
-        CHAR_MAP
-            .iter()
-            .enumerate()
-            .map(|(i, value)| (*value, i as u8))
-            .collect()
-    };
+const CHAR_MAP: &[u8; 64] = b"0123456789:ABCDEFGHIJKLMNOPQRSTUVWXYZ_abcdefghijklmnopqrstuvwxyz";
+
+/// Marks bytes in `BYTE_MAP` that are not sixbit characters
+const INVALID: u8 = 0xff;
+
+/// The sixbit value of every byte, the inverse of `CHAR_MAP`
+const BYTE_MAP: [u8; 256] = {
+    let mut map = [INVALID; 256];
+    let mut i = 0;
+    while i < CHAR_MAP.len() {
+        map[CHAR_MAP[i] as usize] = i as u8;
+        i += 1;
+    }
+
+    map
+};
+
+/// The longest name the sixbit length byte can describe
+const MAX_LEN: usize = u8::MAX as usize;
+
+/// The number of bytes needed to pack `len` sixbit characters
+#[inline]
+fn packed_len(len: usize) -> usize {
+    (len * 6).div_ceil(8)
 }
 
 #[derive(Debug, Snafu)]
@@ -34,6 +49,20 @@ pub enum SixbitError {
 
     #[snafu(display("Failed to write sixbit string data"))]
     DataWrite { source: io::Error },
+
+    #[snafu(display(
+        "Invalid sixbit character at index {} (byte: 0x{:02x})",
+        index,
+        ch
+    ))]
+    InvalidCharacter { index: usize, ch: u8 },
+
+    #[snafu(display(
+        "Sixbit string is too long (length: {}, maximum: {})",
+        len,
+        MAX_LEN
+    ))]
+    TooLong { len: usize },
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -42,6 +71,43 @@ pub struct SixbitSize {
     pub real_len: usize,
 }
 
+/// A sixbit name decoded into a fixed size buffer, so reading a name does
+/// not allocate. Allocate a `String` only when the name has to be kept.
+#[derive(Clone, Copy)]
+pub struct SixbitName {
+    len: u8,
+    buf: [u8; MAX_LEN],
+}
+
+impl SixbitName {
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        // Every character in `CHAR_MAP` is ASCII
+        str::from_utf8(&self.buf[..self.len as usize]).unwrap_or_default()
+    }
+}
+
+impl Deref for SixbitName {
+    type Target = str;
+
+    #[inline]
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Debug for SixbitName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for SixbitName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 pub struct Sixbit;
 
 impl Sixbit {
@@ -50,8 +116,7 @@ impl Sixbit {
         T: Read,
     {
         let sixbit_len = reader.read_u8().context(LengthRead)?;
-        let real_len = (f32::from(sixbit_len * 6) / 8f32).ceil();
-        let real_len = (real_len as u32) as usize;
+        let real_len = packed_len(sixbit_len as usize);
         debug!("sixbit_len: {}, real_len: {}", sixbit_len, real_len);
 
         Ok(SixbitSize {
@@ -64,34 +129,47 @@ impl Sixbit {
     where
         T: Write,
     {
-        let sixbit_chars = input.bytes().map(|ch| {
-            *BYTE_MAP
-                .get(&ch)
-                .expect("Character must be a valid sixbit character")
-        });
-
         let len = input.len();
-        let real_len = (f64::from(len as u32 * 6) / 8f64).ceil() as usize;
+        if len > MAX_LEN {
+            return Err(SixbitError::TooLong { len });
+        }
+
+        let real_len = packed_len(len);
         debug!("sixbit_len: {}, real_len: {}", len, real_len);
 
-        let mut i = 0;
-        let mut bytes = vec![0; real_len];
-        for ch in sixbit_chars {
-            for _ in 0..6 {
-                // Some crazy math that works on a single bit at a time, but
-                // it still performs better than a `BigUint` calculation
-                bytes[i / 8] |= (ch >> (5 - (i % 6)) & 1) << (7 - (i % 8));
-                i += 1;
+        // Four characters fill three bytes, so the input is packed in chunks
+        // of four, with the last chunk padded with zero bits
+        let mut bytes = [0; MAX_LEN];
+        for (i, chunk) in input.as_bytes().chunks(4).enumerate() {
+            let mut packed = 0u32;
+            for (j, &ch) in chunk.iter().enumerate() {
+                let value = BYTE_MAP[ch as usize];
+                if value == INVALID {
+                    return Err(SixbitError::InvalidCharacter {
+                        index: i * 4 + j,
+                        ch,
+                    });
+                }
+                packed |= u32::from(value) << (18 - j * 6);
+            }
+
+            if let Some(out) = bytes.get_mut(i * 3..i * 3 + 3) {
+                out.copy_from_slice(&packed.to_be_bytes()[1..]);
             }
         }
 
         writer.write_u8(len as u8).context(LengthWrite)?;
-        writer.write_all(&bytes).context(DataWrite)?;
+        writer.write_all(&bytes[..real_len]).context(DataWrite)?;
 
         Ok(())
     }
 
     pub fn unpack(buf: &[u8], size: SixbitSize) -> Result<String, SixbitError> {
+        Self::decode(buf, size).map(|name| name.as_str().to_owned())
+    }
+
+    /// Like `unpack`, but decodes into a `SixbitName` without allocating.
+    pub fn decode(buf: &[u8], size: SixbitSize) -> Result<SixbitName, SixbitError> {
         let SixbitSize {
             sixbit_len,
             real_len,
@@ -104,33 +182,36 @@ impl Sixbit {
             });
         }
 
-        let sixbit_len = sixbit_len as usize;
-        let mut result = String::with_capacity(sixbit_len);
-        for i in 0..sixbit_len {
-            let mut current = 0u8;
-            for j in 0..6 {
-                let k = (i * 6) + j;
-                current |= (buf[k / 8] >> (7 - (k % 8)) & 1) << (5 - (k % 6));
+        let mut name = SixbitName {
+            len: sixbit_len,
+            buf: [0; MAX_LEN],
+        };
+
+        // Every three bytes hold four characters
+        let chars = &mut name.buf[..sixbit_len as usize];
+        for (chunk, out) in buf[..real_len].chunks(3).zip(chars.chunks_mut(4)) {
+            let mut packed = [0; 4];
+            packed[1..=chunk.len()].copy_from_slice(chunk);
+            let packed = u32::from_be_bytes(packed);
+
+            for (j, ch) in out.iter_mut().enumerate() {
+                *ch = CHAR_MAP[(packed >> (18 - j * 6)) as usize & 0x3f];
             }
-            result.push(CHAR_MAP[current as usize] as char);
         }
 
-        Ok(result)
+        Ok(name)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    extern crate test;
-
-    use std::io::{Cursor, Seek, SeekFrom};
-
-    use test::{black_box, Bencher};
+    use std::io::Cursor;
+    use std::str;
 
-    use super::Sixbit;
+    use super::{Sixbit, SixbitError};
 
-    const TEST1_STR: &str = "hello";
-    const TEST1_BYTES: &[u8] = &[5, 182, 172, 113, 208];
+    pub(super) const TEST1_STR: &str = "hello";
+    pub(super) const TEST1_BYTES: &[u8] = &[5, 182, 172, 113, 208];
 
     #[test]
     fn test_pack() {
@@ -148,6 +229,76 @@ mod tests {
         assert_eq!(result, TEST1_STR);
     }
 
+    #[test]
+    fn test_decode() {
+        let size = Sixbit::size(&mut Cursor::new(TEST1_BYTES))
+            .expect("Failed to get size of sixbit string");
+        let result =
+            Sixbit::decode(&TEST1_BYTES[1..], size).expect("Failed to decode sixbit string");
+        assert_eq!(&*result, TEST1_STR);
+        assert_eq!(result.to_string(), TEST1_STR);
+    }
+
+    #[test]
+    fn test_pack_invalid_character() {
+        let mut data: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        match Sixbit::pack(&mut data, "bad-name") {
+            Err(SixbitError::InvalidCharacter { index, ch }) => {
+                assert_eq!(index, 3);
+                assert_eq!(ch, b'-');
+            },
+            result => panic!("Unexpected result: {:?}", result),
+        };
+        assert!(data.into_inner().is_empty());
+    }
+
+    #[test]
+    fn test_pack_too_long() {
+        let mut data: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        let input = "a".repeat(super::MAX_LEN);
+        Sixbit::pack(&mut data, &input).unwrap();
+        let size = Sixbit::size(&mut &data.get_ref()[..]).unwrap();
+        assert_eq!(Sixbit::unpack(&data.get_ref()[1..], size).unwrap(), input);
+
+        // Longer names would wrap the length byte or overflow the buffer
+        for len in &[super::MAX_LEN + 1, 341, 1000] {
+            let mut data: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+            match Sixbit::pack(&mut data, &"a".repeat(*len)) {
+                Err(SixbitError::TooLong { len: actual }) => assert_eq!(actual, *len),
+                result => panic!("Unexpected result: {:?}", result),
+            };
+            assert!(data.into_inner().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let chars = str::from_utf8(super::CHAR_MAP).unwrap();
+
+        // Cover every length of the last chunk of four characters
+        for len in (0..8).chain(Some(chars.len())) {
+            let input = &chars[chars.len() - len..];
+            let mut data = Vec::new();
+            Sixbit::pack(&mut data, input).unwrap();
+            assert_eq!(data.len(), 1 + (len * 6).div_ceil(8));
+
+            let size = Sixbit::size(&mut &data[..]).unwrap();
+            assert_eq!(&*Sixbit::decode(&data[1..], size).unwrap(), input);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "nightly"))]
+mod benches {
+    extern crate test;
+
+    use std::io::{Cursor, Seek, SeekFrom};
+
+    use test::{black_box, Bencher};
+
+    use super::tests::{TEST1_BYTES, TEST1_STR};
+    use super::Sixbit;
+
     #[bench]
     fn bench_pack(b: &mut Bencher) {
         let mut data: Cursor<Vec<u8>> = Cursor::new(Vec::with_capacity(10));
@@ -174,4 +325,17 @@ mod tests {
             }
         });
     }
+
+    #[bench]
+    fn bench_decode(b: &mut Bencher) {
+        b.iter(|| {
+            for _ in 0..100 {
+                let size = Sixbit::size(&mut Cursor::new(TEST1_BYTES))
+                    .expect("Failed to get size of sixbit string");
+                let result = Sixbit::decode(&TEST1_BYTES[1..], size)
+                    .expect("Failed to decode sixbit string");
+                black_box(result);
+            }
+        });
+    }
 }
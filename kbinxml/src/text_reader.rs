@@ -1,17 +1,23 @@
+use std::borrow::Cow;
+use std::mem;
 use std::num::ParseIntError;
+use std::ops::ControlFlow;
 use std::str::{self, Utf8Error};
 
 use bytes::{BufMut, Bytes, BytesMut};
-use quick_xml::events::attributes::Attributes;
 use quick_xml::events::{BytesStart, BytesText, Event};
 use quick_xml::Error as QuickXmlError;
 use quick_xml::Reader;
 use snafu::{ResultExt, Snafu};
 
+use crate::custom_type::CustomType;
 use crate::encoding_type::{EncodingError, EncodingType};
 use crate::node::{Key, NodeCollection, NodeData, NodeDefinition};
 use crate::node_types::{StandardType, UnknownKbinType};
+use crate::options::{ArrayCountPolicy, ReaderOptions};
+use crate::progress::Progress;
 use crate::value::Value;
+use crate::ARRAY_MASK;
 
 const EMPTY_STRING_DATA: &[u8] = &[0];
 
@@ -23,12 +29,24 @@ pub enum TextReaderError {
     #[snafu(display("Invalid encoding type read from declaration"))]
     InvalidEncoding { source: EncodingError },
 
+    #[snafu(display("Failed to decode text with the encoding of the document"))]
+    DecodeText { source: EncodingError },
+
     #[snafu(display("Failed to parse array count from attribute"))]
     ParseArrayCount { source: ParseIntError },
 
     #[snafu(display("Failed to parse binary node size from attribute"))]
     ParseBinarySize { source: ParseIntError },
 
+    #[snafu(display("Failed to parse unknown node type id from attribute"))]
+    ParseTypeId { source: ParseIntError },
+
+    #[snafu(display("Unknown node type is missing the type id attribute"))]
+    MissingTypeId,
+
+    #[snafu(display("Invalid value for custom node type `{}`", name))]
+    InvalidCustomValue { name: &'static str },
+
     #[snafu(display(
         "Mismatched binary node length and size attribute value (value length: {}, size attribute: {})",
         len,
@@ -36,6 +54,48 @@ pub enum TextReaderError {
     ))]
     MismatchedBinaryNodeLength { len: usize, size: usize },
 
+    #[snafu(display(
+        "Array node `{}` has {} values, but a count attribute of {}",
+        key,
+        len,
+        count
+    ))]
+    MismatchedArrayCount { key: String, len: usize, count: usize },
+
+    #[snafu(display("Empty node signals an array (count attribute: {})", count))]
+    EmptyArrayNode { count: usize },
+
+    #[snafu(display("Empty node signals binary data (size attribute: {})", size))]
+    EmptyBinaryNode { size: usize },
+
+    #[snafu(display("Duplicate attribute `{}`", key))]
+    DuplicateAttribute { key: String },
+
+    #[snafu(display("Non-finite value for node type {}", node_type))]
+    NonFiniteFloat { node_type: StandardType },
+
+    #[snafu(display("Node depth exceeds the limit of {}", max_depth))]
+    DepthLimit { max_depth: usize },
+
+    #[snafu(display("Node count exceeds the limit of {}", max_nodes))]
+    NodeLimit { max_nodes: usize },
+
+    #[snafu(display("Text content exceeds the limit of {} bytes", max_data_len))]
+    DataLimit { max_data_len: usize },
+
+    #[snafu(display("Array of {} values exceeds the limit of {}", len, max_array_len))]
+    ArrayLimit { len: usize, max_array_len: usize },
+
+    #[snafu(display(
+        "String of {} bytes exceeds the limit of {} bytes",
+        len,
+        max_string_len
+    ))]
+    StringLimit { len: usize, max_string_len: usize },
+
+    #[snafu(display("Document has more than one root element"))]
+    MultipleRoots,
+
     #[snafu(display("No node data found"))]
     NoNodeData,
 
@@ -58,6 +118,20 @@ pub enum TextReaderError {
 
     #[snafu(display("Failed to handle XML operation"))]
     Xml { source: QuickXmlError },
+
+    #[snafu(display(
+        "Error on line {}, column {} (offset {}): {}",
+        line,
+        column,
+        offset,
+        source
+    ))]
+    AtPosition {
+        offset: usize,
+        line: usize,
+        column: usize,
+        source: Box<TextReaderError>,
+    },
 }
 
 impl From<Utf8Error> for TextReaderError {
@@ -74,34 +148,229 @@ impl From<QuickXmlError> for TextReaderError {
     }
 }
 
+fn check_array_len(options: &ReaderOptions, len: usize) -> Result<(), TextReaderError> {
+    match options.max_array_len {
+        Some(max_array_len) if len > max_array_len => {
+            Err(TextReaderError::ArrayLimit { len, max_array_len })
+        },
+        _ => Ok(()),
+    }
+}
+
+fn check_string_len(options: &ReaderOptions, len: usize) -> Result<(), TextReaderError> {
+    match options.max_string_len {
+        Some(max_string_len) if len > max_string_len => {
+            Err(TextReaderError::StringLimit {
+                len,
+                max_string_len,
+            })
+        },
+        _ => Ok(()),
+    }
+}
+
+struct ParsedAttributes {
+    node_type: StandardType,
+    count: usize,
+    size: Option<usize>,
+    type_id: Option<u8>,
+    attributes: Vec<NodeDefinition>,
+}
+
+/// Attribute value normalization from the XML specification: line breaks
+/// and tabs written literally become spaces, with `\r\n` becoming a single
+/// space. Character references such as `&#10;` are expanded afterwards, so
+/// they are kept.
+fn normalize_attribute_whitespace(value: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(value.len());
+    let mut iter = value.iter().peekable();
+    while let Some(&ch) = iter.next() {
+        match ch {
+            b'\r' => {
+                iter.next_if_eq(&&b'\n');
+                output.push(b' ');
+            },
+            b'\n' | b'\t' => output.push(b' '),
+            ch => output.push(ch),
+        };
+    }
+
+    output
+}
+
+/// Check if an attribute key is one of the reserved `__type`, `__count`,
+/// `__size`, and `__type_id` keys, or one of them with more leading
+/// underscores added.
+pub(crate) fn is_reserved_attribute_key(key: &[u8]) -> bool {
+    let name = match key.iter().position(|&ch| ch != b'_') {
+        Some(start) if start >= 2 => &key[start..],
+        _ => return false,
+    };
+
+    matches!(name, b"type" | b"count" | b"size" | b"type_id")
+}
+
+/// Remove the underscore added to attribute keys that collide with the
+/// reserved keys when writing text XML. Other keys are kept as is.
+fn unescape_attribute_key(key: &[u8]) -> &[u8] {
+    if key.starts_with(b"___") && is_reserved_attribute_key(key) {
+        &key[1..]
+    } else {
+        key
+    }
+}
+
+/// Append the text of an event to `output`, replacing `\r\n` and `\r` with
+/// `\n` if `normalize_newlines` is set.
+fn push_text(output: &mut Vec<u8>, text: &[u8], normalize_newlines: bool) {
+    if !normalize_newlines {
+        output.extend_from_slice(text);
+        return;
+    }
+
+    let mut iter = text.iter().peekable();
+    while let Some(&ch) = iter.next() {
+        if ch == b'\r' {
+            iter.next_if_eq(&&b'\n');
+            output.push(b'\n');
+        } else {
+            output.push(ch);
+        }
+    }
+}
+
+/// A node whose end has not been read yet.
+struct OpenNode {
+    collection: NodeCollection,
+    count: usize,
+    size: Option<usize>,
+
+    /// The text and CDATA read so far, handled at the end of the node
+    text: Vec<u8>,
+
+    /// The position of the first text event, if there was one
+    text_start: Option<usize>,
+}
+
+impl OpenNode {
+    fn new((collection, count, size): (NodeCollection, usize, Option<usize>)) -> Self {
+        Self {
+            collection,
+            count,
+            size,
+            text: Vec::new(),
+            text_start: None,
+        }
+    }
+}
+
 pub struct TextXmlReader<'a> {
+    input: &'a [u8],
     xml_reader: Reader<&'a [u8]>,
     encoding: EncodingType,
+    options: ReaderOptions,
+
+    stack: Vec<OpenNode>,
+
+    /// The number of nodes and bytes of text read, checked against the limits
+    /// in `options`
+    nodes: usize,
+    data_len: usize,
 
-    stack: Vec<(NodeCollection, usize, Option<usize>)>,
+    progress: Progress<'a>,
 }
 
 impl<'a> TextXmlReader<'a> {
+    #[inline]
     pub fn new(input: &'a [u8]) -> Self {
+        Self::with_options(input, ReaderOptions::default())
+    }
+
+    pub fn with_options(input: &'a [u8], options: ReaderOptions) -> Self {
         let mut xml_reader = Reader::from_reader(input);
-        xml_reader.trim_text(true);
+        xml_reader.trim_text(!options.preserve_whitespace);
 
         Self {
+            input,
             xml_reader,
             encoding: EncodingType::UTF_8,
+            options,
 
             // Most kbinxml files that I have come across do not have too
             // many inner layers.
             stack: Vec::with_capacity(6),
+
+            nodes: 0,
+            data_len: 0,
+
+            progress: Progress::none(),
         }
     }
 
+    /// Count the elements read in `progress`, reported with the position in
+    /// the input.
+    #[inline]
+    pub(crate) fn set_progress(&mut self, progress: Progress<'a>) {
+        self.progress = progress;
+    }
+
+    /// Make the final report of the read phase and return the counter.
+    pub(crate) fn finish_progress(&mut self) -> Progress<'a> {
+        let mut progress = mem::replace(&mut self.progress, Progress::none());
+        progress.finish(self.xml_reader.buffer_position());
+
+        progress
+    }
+
     #[inline]
     pub fn encoding(&self) -> EncodingType {
         self.encoding
     }
 
+    /// Whether text is converted from the encoding of the document to UTF-8
+    /// before character references are expanded, so references to
+    /// characters the encoding cannot represent are read correctly. Values
+    /// are then stored as UTF-8.
+    fn transcodes(&self) -> bool {
+        !matches!(self.encoding, EncodingType::None | EncodingType::UTF_8)
+    }
+
+    /// The encoding of the values of read nodes.
+    fn value_encoding(&self) -> EncodingType {
+        if self.transcodes() {
+            EncodingType::UTF_8
+        } else {
+            self.encoding
+        }
+    }
+
+    /// Convert text to the encoding of values and expand character
+    /// references in it if it is `escaped`.
+    fn decode_text<'b>(
+        &self,
+        raw: &'b [u8],
+        escaped: bool,
+    ) -> Result<Cow<'b, [u8]>, TextReaderError> {
+        let text = if self.transcodes() {
+            match self.encoding.decode_borrowed(raw).context(DecodeText)? {
+                Cow::Borrowed(text) => Cow::Borrowed(text.as_bytes()),
+                Cow::Owned(text) => Cow::Owned(text.into_bytes()),
+            }
+        } else {
+            Cow::Borrowed(raw)
+        };
+
+        if !escaped || !text.contains(&b'&') {
+            return Ok(text);
+        }
+
+        let unescaped = BytesText::from_escaped(&*text).unescaped()?.into_owned();
+        Ok(Cow::Owned(unescaped))
+    }
+
     fn parse_attribute(&self, key: &[u8], value: &[u8]) -> Result<NodeDefinition, TextReaderError> {
+        check_string_len(&self.options, value.len())?;
+
         let mut value = BytesMut::from(value);
 
         // Add the trailing null byte that kbin has at the end of strings
@@ -118,7 +387,7 @@ impl<'a> TextXmlReader<'a> {
 
         // `Attribute` nodes do not have the `is_array` flag set
         Ok(NodeDefinition::with_data(
-            self.encoding,
+            self.value_encoding(),
             StandardType::Attribute,
             false,
             data,
@@ -127,18 +396,25 @@ impl<'a> TextXmlReader<'a> {
 
     fn parse_attributes(
         &self,
-        attrs: Attributes<'a>,
-    ) -> Result<(StandardType, usize, Option<usize>, Vec<NodeDefinition>), TextReaderError> {
+        start: &BytesStart<'_>,
+    ) -> Result<ParsedAttributes, TextReaderError> {
         let mut node_type = None;
         let mut count = 0;
         let mut size = None;
+        let mut type_id = None;
+        let mut custom_type = None;
         let mut attributes = Vec::new();
 
-        for attr in attrs {
+        for attr in start.attributes() {
             match attr {
-                Ok(attr) => {
-                    let value = match attr.unescaped_value() {
+                Ok(mut attr) => {
+                    if self.options.normalize_attribute_whitespace {
+                        attr.value = Cow::Owned(normalize_attribute_whitespace(&attr.value));
+                    }
+
+                    let value = match self.decode_text(&attr.value, true) {
                         Ok(v) => v,
+                        Err(e @ TextReaderError::DecodeText { .. }) => return Err(e),
                         Err(e) => {
                             error!("Error decoding attribute value: {:?}", e);
                             attr.value.clone()
@@ -146,31 +422,64 @@ impl<'a> TextXmlReader<'a> {
                     };
 
                     if attr.key == b"__type" {
-                        let value = str::from_utf8(&*value)?;
+                        let value = str::from_utf8(&value)?;
 
-                        node_type = Some(StandardType::from_name(value).context(InvalidKbinType)?);
+                        match CustomType::find_by_name(&self.options.custom_types, value) {
+                            Some(custom) => {
+                                node_type = Some(StandardType::Unknown);
+                                custom_type = Some(custom.id);
+                            },
+                            None => {
+                                node_type =
+                                    Some(StandardType::from_name(value).context(InvalidKbinType)?);
+                            },
+                        };
                     } else if attr.key == b"__count" {
-                        let value = str::from_utf8(&*value)?;
+                        let value = str::from_utf8(&value)?;
                         let num_count = value.parse::<u32>().context(ParseArrayCount)?;
 
                         count = num_count as usize;
                     } else if attr.key == b"__size" {
-                        let value = str::from_utf8(&*value)?
+                        let value = str::from_utf8(&value)?
                             .parse::<usize>()
                             .context(ParseBinarySize)?;
 
                         size = Some(value);
+                    } else if attr.key == b"__type_id" {
+                        let value = str::from_utf8(&value)?
+                            .parse::<u8>()
+                            .context(ParseTypeId)?;
+
+                        type_id = Some(value);
                     } else {
-                        let definition = self.parse_attribute(attr.key, &value)?;
+                        let key = unescape_attribute_key(attr.key);
+                        let definition = self.parse_attribute(key, &value)?;
                         attributes.push(definition);
                     }
                 },
+                Err(QuickXmlError::DuplicatedAttribute(position, _))
+                    if self.options.deny_duplicate_attributes =>
+                {
+                    let key = start[position..]
+                        .split(|&b| b == b'=' || b.is_ascii_whitespace())
+                        .next()
+                        .unwrap_or_default();
+
+                    return Err(TextReaderError::DuplicateAttribute {
+                        key: String::from_utf8_lossy(key).into_owned(),
+                    });
+                },
                 Err(e) => {
                     error!("Error reading attribute: {:?}", e);
                 },
             };
         }
 
+        // Custom types are arrays if they have a count
+        if let Some(id) = custom_type {
+            type_id = Some(if count > 0 { id | ARRAY_MASK } else { id });
+        }
+
         let node_type = match node_type {
             Some(node_type) => node_type,
             None => {
@@ -180,14 +489,44 @@ impl<'a> TextXmlReader<'a> {
             },
         };
 
-        Ok((node_type, count, size, attributes))
+        Ok(ParsedAttributes {
+            node_type,
+            count,
+            size,
+            type_id,
+            attributes,
+        })
+    }
+
+    fn check_limits(&mut self, collection: &NodeCollection) -> Result<(), TextReaderError> {
+        if let Some(max_depth) = self.options.max_depth {
+            if self.stack.len() + 1 > max_depth {
+                return Err(TextReaderError::DepthLimit { max_depth });
+            }
+        }
+
+        self.nodes += 1 + collection.attributes().len();
+        if let Some(max_nodes) = self.options.max_nodes {
+            if self.nodes > max_nodes {
+                return Err(TextReaderError::NodeLimit { max_nodes });
+            }
+        }
+
+        Ok(())
     }
 
     fn handle_start(
         &self,
         e: BytesStart,
     ) -> Result<(NodeCollection, usize, Option<usize>), TextReaderError> {
-        let (node_type, count, size, attributes) = self.parse_attributes(e.attributes())?;
+        let ParsedAttributes {
+            node_type,
+            count,
+            size,
+            type_id,
+            attributes,
+        } = self.parse_attributes(&e)?;
+        check_array_len(&self.options, count)?;
         let is_array = count > 0;
 
         // Stub the value for now, handle with `Event::Text`.
@@ -195,30 +534,66 @@ impl<'a> TextXmlReader<'a> {
             StandardType::String => Bytes::from(EMPTY_STRING_DATA),
             _ => Bytes::new(),
         };
+        let name = match &self.options.empty_name {
+            Some(placeholder) if e.name() == placeholder.as_bytes() => Bytes::new(),
+            _ => Bytes::from(e.name().to_vec()),
+        };
         let data = NodeData::Some {
             key: Key::Uncompressed {
                 encoding: self.encoding,
-                data: Bytes::from(e.name().to_vec()),
+                data: name,
             },
             value_data,
         };
 
-        let base = NodeDefinition::with_data(self.encoding, node_type, is_array, data);
+        let base = if node_type == StandardType::Unknown {
+            let type_id = type_id.ok_or(TextReaderError::MissingTypeId)?;
+
+            NodeDefinition::unknown(self.value_encoding(), type_id, data)
+        } else {
+            NodeDefinition::with_data(self.value_encoding(), node_type, is_array, data)
+        };
         let collection = NodeCollection::with_attributes(base, attributes.into());
 
         Ok((collection, count, size))
     }
 
+    /// Wrap an error with the line and column of the event read from `start`
+    /// in the input.
+    fn at_position(&self, start: usize, source: TextReaderError) -> TextReaderError {
+        // Whitespace before events is trimmed, so the event starts at the
+        // first other byte
+        let start = start.min(self.input.len());
+        let offset = self.input[start..]
+            .iter()
+            .position(|b| !b.is_ascii_whitespace())
+            .map_or(start, |skipped| start + skipped);
+        let before = &self.input[..offset];
+        let line_start = before
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1);
+
+        TextReaderError::AtPosition {
+            offset,
+            line: before.iter().filter(|&&b| b == b'\n').count() + 1,
+            column: offset - line_start + 1,
+            source: Box::new(source),
+        }
+    }
+
     fn handle_text(
-        event: BytesText,
+        data: &[u8],
         definition: &mut NodeDefinition,
         count: usize,
         size: Option<usize>,
+        options: &ReaderOptions,
     ) -> Result<(), TextReaderError> {
-        let data = event.unescaped()?;
         let data = match definition.node_type {
             StandardType::String | StandardType::NodeStart => {
-                let mut data = BytesMut::from(&*data);
+                check_string_len(options, data.len())?;
+
+                let mut data = BytesMut::from(data);
 
                 // Add the trailing null byte that kbin has at the end of strings
                 data.reserve(1);
@@ -226,11 +601,41 @@ impl<'a> TextXmlReader<'a> {
 
                 data.freeze()
             },
+            StandardType::Unknown => {
+                let text = str::from_utf8(data)?;
+
+                match CustomType::find(&options.custom_types, definition.type_id()) {
+                    Some(custom) => Bytes::from(
+                        (custom.parse)(text.trim())
+                            .ok_or(TextReaderError::InvalidCustomValue { name: custom.name })?,
+                    ),
+                    None => {
+                        // Other unknown node data is written as hex, like binary data
+                        let value = Value::from_string(StandardType::Binary, text, false, 0)
+                            .context(ValueDecode {
+                                node_type: StandardType::Unknown,
+                            })?;
+
+                        Bytes::from(value.into_binary().context(ValueDecode {
+                            node_type: StandardType::Unknown,
+                        })?)
+                    },
+                }
+            },
             node_type => {
-                let text = str::from_utf8(&*data)?;
-                let value = Value::from_string(node_type, text, definition.is_array, count)
+                let text = str::from_utf8(data)?.trim();
+                let from_string = if options.lenient_booleans {
+                    Value::from_string_lenient
+                } else {
+                    Value::from_string
+                };
+                let value = from_string(node_type, text, definition.is_array, count)
                     .context(ValueDecode { node_type })?;
 
+                if options.deny_non_finite_floats && !value.is_finite() {
+                    return Err(TextReaderError::NonFiniteFloat { node_type });
+                }
+
                 // The read number of bytes must match the size attribute, if set
                 if let Value::Binary(data) = &value {
                     if let Some(size) = size {
@@ -243,7 +648,33 @@ impl<'a> TextXmlReader<'a> {
                     }
                 }
 
-                Bytes::from(value.to_bytes().context(ValueEncode { node_type })?)
+                let mut data = value.to_bytes().context(ValueEncode { node_type })?;
+
+                if let Value::Array(values) = &value {
+                    let len = values.len();
+                    check_array_len(options, len)?;
+                    let element_size = node_type.size * node_type.count;
+
+                    match options.array_count_policy {
+                        ArrayCountPolicy::Truncate if len > count => {
+                            data.truncate(count * element_size)
+                        },
+                        ArrayCountPolicy::Pad if len < count => data.resize(count * element_size, 0),
+                        ArrayCountPolicy::Preserve if len != count => {
+                            definition.set_declared_count(Some(count))
+                        },
+                        _ if len != count => {
+                            return Err(TextReaderError::MismatchedArrayCount {
+                                key: definition.key().ok().flatten().unwrap_or_default(),
+                                len,
+                                count,
+                            });
+                        },
+                        _ => {},
+                    };
+                }
+
+                Bytes::from(data)
             },
         };
 
@@ -257,7 +688,7 @@ impl<'a> TextXmlReader<'a> {
             } => {
                 *value_data = data;
             },
-            NodeData::None => {
+            _ => {
                 // There should be a valid `NodeData` structure from the `Event::Start` handler
                 return Err(TextReaderError::NoNodeData);
             },
@@ -266,61 +697,404 @@ impl<'a> TextXmlReader<'a> {
         Ok(())
     }
 
+    /// Read the first top-level element. Any elements after it are ignored
+    /// unless the `deny_multiple_roots` option is set. With the
+    /// `fragment_root` option, every top-level element is read into a new
+    /// root node instead.
     pub fn as_node_collection(&mut self) -> Result<Option<NodeCollection>, TextReaderError> {
+        if let Some(name) = &self.options.fragment_root {
+            let data = NodeData::Some {
+                key: Key::Uncompressed {
+                    encoding: EncodingType::UTF_8,
+                    data: Bytes::from(name.clone().into_bytes()),
+                },
+                value_data: Bytes::new(),
+            };
+            let base =
+                NodeDefinition::with_data(self.value_encoding(), StandardType::NodeStart, false, data);
+            let mut collection = NodeCollection::new(base);
+            collection
+                .children_mut()
+                .extend(self.as_node_collections()?);
+
+            return Ok(Some(collection));
+        }
+
+        let collection = self.read_root()?;
+        if collection.is_some() && self.options.deny_multiple_roots && self.read_root()?.is_some() {
+            return Err(TextReaderError::MultipleRoots);
+        }
+
+        Ok(collection)
+    }
+
+    /// Close the nodes that were still open when reading failed and return
+    /// their root, so the nodes read before the error can be inspected.
+    pub fn take_partial_tree(&mut self) -> Option<NodeCollection> {
+        let mut collection = None;
+        while let Some(OpenNode {
+            collection: mut parent,
+            ..
+        }) = self.stack.pop()
+        {
+            if let Some(child) = collection.take() {
+                parent.children_mut().push_back(child);
+            }
+            collection = Some(parent);
+        }
+
+        collection
+    }
+
+    /// Read every top-level element, for documents that have more than one
+    /// root sibling.
+    pub fn as_node_collections(&mut self) -> Result<Vec<NodeCollection>, TextReaderError> {
+        let mut collections = Vec::new();
+        while let Some(collection) = self.read_root()? {
+            collections.push(collection);
+        }
+
+        Ok(collections)
+    }
+
+    /// Read events until the end of the next root element. Errors are
+    /// wrapped in `AtPosition` with the position of the event that failed.
+    fn read_root(&mut self) -> Result<Option<NodeCollection>, TextReaderError> {
         // A buffer size for reading a `quick_xml::events::Event` that I pulled
         // out of my head.
         let mut buf = Vec::with_capacity(1024);
 
         loop {
-            match self.xml_reader.read_event(&mut buf)? {
-                Event::Start(e) => {
-                    let start = self.handle_start(e)?;
-                    self.stack.push(start);
-                },
-                Event::Text(e) => {
-                    if let Some((ref mut collection, ref count, ref size)) = self.stack.last_mut() {
-                        let base = collection.base_mut();
-                        Self::handle_text(e, base, *count, *size)?;
-                    }
-                },
-                Event::End(_) => {
-                    if let Some((collection, _count, _size)) = self.stack.pop() {
-                        if let Some((parent_collection, _count, _size)) = self.stack.last_mut() {
-                            parent_collection.children_mut().push_back(collection);
-                        } else {
-                            // The end of the structure has been reached.
-                            return Ok(Some(collection));
-                        }
-                    }
-                },
-                Event::Empty(e) => {
-                    let (collection, count, size) = self.handle_start(e)?;
-                    assert!(count == 0, "empty node should not signal an array");
-                    assert!(
-                        size.is_none() || size == Some(0),
-                        "empty node should not signal binary data"
-                    );
-
-                    if let Some((ref mut parent_collection, _count, _size)) = self.stack.last_mut()
-                    {
-                        parent_collection.children_mut().push_back(collection);
-                    } else {
-                        return Ok(Some(collection));
-                    }
-                },
-                Event::Decl(e) => {
-                    if let Some(encoding) = e.encoding() {
-                        self.encoding =
-                            EncodingType::from_label(&encoding?).context(InvalidEncoding)?;
-                    }
-                },
-                Event::Eof => break,
-                _ => {},
+            let start = self.xml_reader.buffer_position();
+            let event = match self.xml_reader.read_event(&mut buf) {
+                Ok(event) => event,
+                Err(e) => return Err(self.at_position(start, e.into())),
+            };
+
+            match self.handle_event(event, start) {
+                Ok(ControlFlow::Continue(())) => {},
+                Ok(ControlFlow::Break(collection)) => return Ok(collection),
+                Err(e @ TextReaderError::AtPosition { .. }) => return Err(e),
+                Err(e) => return Err(self.at_position(start, e)),
             };
 
             buf.clear();
         }
+    }
+
+    /// Add the unescaped text of a text or CDATA event read from `start` to
+    /// the open node.
+    fn push_text(&mut self, text: &[u8], start: usize) -> Result<(), TextReaderError> {
+        self.data_len += text.len();
+        if let Some(max_data_len) = self.options.max_data_len {
+            if self.data_len > max_data_len {
+                return Err(TextReaderError::DataLimit { max_data_len });
+            }
+        }
+
+        if let Some(open) = self.stack.last_mut() {
+            push_text(&mut open.text, text, self.options.normalize_newlines);
+            open.text_start.get_or_insert(start);
+        }
+
+        Ok(())
+    }
+
+    /// Handle the text of the innermost open node when its end is read.
+    fn handle_end_text(&mut self) -> Result<(), TextReaderError> {
+        let open = match self.stack.last_mut() {
+            Some(open) => open,
+            None => return Ok(()),
+        };
+        let text_start = match open.text_start.take() {
+            Some(text_start) => text_start,
+            None => return Ok(()),
+        };
+
+        // Whitespace between child elements is not text
+        if !open.collection.children().is_empty() &&
+            open.text.iter().all(u8::is_ascii_whitespace)
+        {
+            return Ok(());
+        }
+
+        let result = Self::handle_text(
+            &open.text,
+            open.collection.base_mut(),
+            open.count,
+            open.size,
+            &self.options,
+        );
+        result.map_err(|e| self.at_position(text_start, e))
+    }
+
+    /// Add a comment or processing instruction to the open node. Those
+    /// outside the root element are dropped.
+    fn push_markup(&mut self, definition: NodeDefinition) {
+        if let Some(parent) = self.stack.last_mut() {
+            parent
+                .collection
+                .children_mut()
+                .push_back(NodeCollection::new(definition));
+        }
+    }
+
+    fn handle_event(
+        &mut self,
+        event: Event,
+        start: usize,
+    ) -> Result<ControlFlow<Option<NodeCollection>>, TextReaderError> {
+        match event {
+            Event::Start(e) => {
+                let open = self.handle_start(e)?;
+                self.check_limits(&open.0)?;
+                self.progress.node(|| start);
+                self.stack.push(OpenNode::new(open));
+            },
+            Event::Text(e) => {
+                let text = self.decode_text(&e, true)?;
+                self.push_text(&text, start)?
+            },
+
+            // The content of CDATA sections is not escaped
+            Event::CData(e) if self.options.preserve_cdata => {
+                let text = self.decode_text(&e, false)?;
+                self.push_text(&text, start)?
+            },
+            Event::End(_) => {
+                // The node stays open if its text is invalid, for
+                // `take_partial_tree`
+                self.handle_end_text()?;
+
+                if let Some(OpenNode { collection, .. }) = self.stack.pop() {
+                    if let Some(parent) = self.stack.last_mut() {
+                        parent.collection.children_mut().push_back(collection);
+                    } else {
+                        // The end of the structure has been reached.
+                        return Ok(ControlFlow::Break(Some(collection)));
+                    }
+                }
+            },
+            Event::Empty(e) => {
+                let (collection, count, size) = self.handle_start(e)?;
+                self.check_limits(&collection)?;
+                self.progress.node(|| start);
+                if count != 0 {
+                    return Err(TextReaderError::EmptyArrayNode { count });
+                }
+                match size {
+                    None | Some(0) => {},
+                    Some(size) => return Err(TextReaderError::EmptyBinaryNode { size }),
+                };
+
+                if let Some(parent) = self.stack.last_mut() {
+                    parent.collection.children_mut().push_back(collection);
+                } else {
+                    return Ok(ControlFlow::Break(Some(collection)));
+                }
+            },
+            Event::Comment(e) if self.options.preserve_comments => {
+                let text = self.encoding.decode_bytes(&e).context(DecodeText)?;
+                self.push_markup(NodeDefinition::comment(text));
+            },
+            Event::PI(e) if self.options.preserve_comments => {
+                let content = self.encoding.decode_bytes(&e).context(DecodeText)?;
+                self.push_markup(NodeDefinition::processing_instruction(content));
+            },
+            Event::Decl(e) => {
+                if let Some(encoding) = e.encoding() {
+                    self.encoding =
+                        EncodingType::from_label(&encoding?).context(InvalidEncoding)?;
+                }
+            },
+            Event::Eof => return Ok(ControlFlow::Break(None)),
+            _ => {},
+        };
+
+        Ok(ControlFlow::Continue(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::node::NodeData;
+    use crate::options::ReaderOptions;
+
+    #[test]
+    fn test_multiple_roots() {
+        let text = br#"<a __type="s32">1</a><b/>"#;
+
+        let (collection, _) = crate::from_text_xml(text).unwrap();
+        assert_eq!(collection.as_node().unwrap().key(), "a");
+
+        let (collections, _) = crate::from_text_xml_roots(text).unwrap();
+        assert_eq!(collections.len(), 2);
+        assert_eq!(collections[1].as_node().unwrap().key(), "b");
+
+        let mut options = ReaderOptions::builder();
+        options.deny_multiple_roots(true);
+        let options = options.build();
+        assert!(crate::from_text_xml_with_options(options.clone(), text).is_err());
+        assert!(crate::from_text_xml_with_options(options.clone(), b"<a/>").is_ok());
+
+        let data = crate::to_binary(&collections[0]).unwrap();
+        assert!(crate::from_binary_with_options(options, data.into()).is_ok());
+    }
+
+    #[test]
+    fn test_array_count_policy() {
+        use std::error::Error;
+
+        use crate::options::ArrayCountPolicy;
+        use crate::value::{Value, ValueArray};
+
+        let read = |input: &[u8], policy| {
+            let mut options = ReaderOptions::builder();
+            options.array_count_policy(policy);
+            crate::from_text_xml_with_options(options.build(), input)
+                .map(|(collection, _)| collection.children()[0].base().value().unwrap())
+        };
+
+        let long = b"<root>\n  <a __type=\"u32\" __count=\"2\">1 2 3</a>\n</root>";
+        let short = b"<root>\n\n  <a __type=\"2u8\" __count=\"3\">1 2 3 4</a>\n</root>";
+
+        let error = read(long, ArrayCountPolicy::Error).unwrap_err();
+        let error = error.source().unwrap().to_string();
+        assert!(error.starts_with("Error on line 2, column 31"), "{}", error);
+        assert!(error.contains("`a` has 3 values"), "{}", error);
+        let error = read(short, ArrayCountPolicy::Truncate).unwrap_err();
+        let error = error.source().unwrap().to_string();
+        assert!(error.starts_with("Error on line 3, column 31"), "{}", error);
+        assert!(error.contains("`a` has 2 values"), "{}", error);
+        assert!(read(long, ArrayCountPolicy::Pad).is_err());
+
+        assert_eq!(
+            read(long, ArrayCountPolicy::Truncate).unwrap(),
+            Value::Array(ValueArray::U32(vec![1, 2]))
+        );
+        assert_eq!(
+            read(short, ArrayCountPolicy::Pad).unwrap(),
+            Value::Array(ValueArray::U8_2(vec![[1, 2], [3, 4], [0, 0]]))
+        );
+    }
+
+    #[test]
+    fn test_preserve_array_count() {
+        use crate::options::ArrayCountPolicy;
+        use crate::value::{Value, ValueArray};
+
+        let input = b"<root><a __type=\"u32\" __count=\"2\">1 2 3</a></root>";
+        let mut options = ReaderOptions::builder();
+        options.array_count_policy(ArrayCountPolicy::Preserve);
+        let options = options.build();
+        let (collection, _) = crate::from_text_xml_with_options(options.clone(), input).unwrap();
+        let child = &collection.children()[0];
+        assert_eq!(child.base().declared_count(), Some(2));
+        assert_eq!(
+            child.base().value().unwrap(),
+            Value::Array(ValueArray::U32(vec![1, 2, 3]))
+        );
+
+        // The count is written back, but is not part of the comparison
+        let text = crate::to_text_xml(&collection).unwrap();
+        assert!(String::from_utf8_lossy(&text).contains("__count=\"2\""));
+        let (reread, _) = crate::from_text_xml_with_options(options, &text).unwrap();
+        assert_eq!(reread.children()[0].base().declared_count(), Some(2));
+
+        let mut without_count = collection.clone();
+        let child = &mut without_count.children_mut()[0];
+        child.base_mut().set_declared_count(None);
+        assert_eq!(without_count, collection);
+
+        // Other policies cannot read the text back
+        assert!(crate::from_text_xml(&text).is_err());
+    }
+
+    #[test]
+    fn test_error_position() {
+        use super::TextReaderError;
+
+        let read = |input: &[u8]| {
+            super::TextXmlReader::new(input)
+                .as_node_collection()
+                .unwrap_err()
+        };
+
+        match read(b"<root>\n  <a/>\n  <b __type=\"nope\"/>\n</root>") {
+            TextReaderError::AtPosition {
+                offset,
+                line,
+                column,
+                source,
+            } => {
+                assert_eq!((offset, line, column), (16, 3, 3));
+                assert!(matches!(*source, TextReaderError::InvalidKbinType { .. }));
+            },
+            e => panic!("unexpected error: {:?}", e),
+        };
+
+        let error = read(b"<root>\n<a __type=\"u8\">\n  256\n</a></root>");
+        assert!(error.to_string().starts_with("Error on line 3, column 3"), "{}", error);
+    }
+
+    #[test]
+    fn test_whitespace_and_cdata() {
+        use crate::node::Node;
+
+        let input = b"<root>\n  <a __type=\"str\">  x\r\n y </a>\n  \
+            <b __type=\"str\"><![CDATA[<&>]]></b>\n  <c __type=\"u8\"> 1 </c>\n</root>";
+        let read = |options: ReaderOptions| {
+            let (collection, _) = crate::from_text_xml_with_options(options, input).unwrap();
+            Node::from_collection(&collection).unwrap()
+        };
+
+        let node = read(ReaderOptions::default());
+        assert_eq!(node.child_value_str("a").unwrap(), "x\r\n y");
+        assert_eq!(node.child_value_str("b").unwrap(), "");
+
+        let mut options = ReaderOptions::builder();
+        options
+            .preserve_whitespace(true)
+            .preserve_cdata(true)
+            .normalize_newlines(true);
+        let node = read(options.build());
+        assert_eq!(node.child_value_str("a").unwrap(), "  x\n y ");
+        assert_eq!(node.child_value_str("b").unwrap(), "<&>");
+        assert_eq!(node.child_value_u8("c").unwrap(), 1);
+        assert_eq!(node.value(), None);
+    }
+
+    #[test]
+    fn test_comments() {
+        let text = "<?xml version=\"1.0\" encoding=\"UTF-8\"?><!-- outside --><root>\
+            <!-- a & b --><?target data?><a __type=\"s32\">1</a><!--end--></root>";
+
+        let (collection, _) = crate::from_text_xml(text.as_bytes()).unwrap();
+        assert_eq!(collection.children().len(), 1);
+
+        let mut options = ReaderOptions::builder();
+        options.preserve_comments(true);
+        let (collection, _) =
+            crate::from_text_xml_with_options(options.build(), text.as_bytes()).unwrap();
+        let children = collection.children();
+        assert_eq!(children.len(), 4);
+        assert_eq!(
+            children[0].base().data(),
+            &NodeData::Comment(" a & b ".into())
+        );
+        assert_eq!(
+            children[1].base().data(),
+            &NodeData::ProcessingInstruction("target data".into())
+        );
 
-        Ok(None)
+        // Only text XML keeps them
+        let output = crate::to_text_xml(&collection).unwrap();
+        assert!(output.ends_with(
+            b"<root><!-- a & b --><?target data?><a __type=\"s32\">1</a><!--end--></root>"
+        ));
+        let node = collection.as_node().unwrap();
+        assert_eq!(node.children().unwrap().len(), 1);
+        let data = crate::to_binary(&collection).unwrap();
+        assert_eq!(data, crate::to_binary(&node).unwrap());
     }
 }
@@ -1,43 +1,623 @@
+use std::borrow::Cow;
 use std::io::{Cursor, Write};
 
-use quick_xml::events::{BytesDecl, Event};
+use quick_xml::events::attributes::Attribute;
+use quick_xml::events::{BytesDecl, BytesStart, Event};
 use quick_xml::Writer;
 
-use crate::encoding_type::EncodingType;
+use crate::custom_type::CustomType;
+use crate::encoding_type::{EncodingError, EncodingType};
 use crate::error::KbinError;
+use crate::node_types::StandardType;
+use crate::options::{TextXmlOptions, TimeFormat};
+use crate::text_reader::is_reserved_attribute_key;
+use crate::value::Value;
+use crate::ARRAY_MASK;
 
 mod node;
 mod node_collection;
+mod stream;
+
+pub use self::stream::TextXmlStreamWriter;
+
+/// Escape an attribute key. The `__type`, `__count`, `__size`, and
+/// `__type_id` keys are reserved for the node definition, so attributes of
+/// the node with such keys, or with such keys after removing leading
+/// underscores, get one more leading underscore. The readers remove it again.
+fn escape_attribute_key(key: &str) -> Cow<'_, [u8]> {
+    if is_reserved_attribute_key(key.as_bytes()) {
+        let mut output = Vec::with_capacity(key.len() + 1);
+        output.push(b'_');
+        output.extend_from_slice(key.as_bytes());
+
+        Cow::Owned(output)
+    } else {
+        Cow::Borrowed(key.as_bytes())
+    }
+}
+
+/// Escape an attribute value. Tabs and line breaks are written as character
+/// references as well, since XML parsers replace them with spaces when they
+/// are written literally.
+fn escape_attribute(value: &str) -> Cow<'_, [u8]> {
+    let needs_escape = |ch: &u8| {
+        matches!(
+            ch,
+            b'<' | b'>' | b'&' | b'\'' | b'"' | b'\t' | b'\n' | b'\r'
+        )
+    };
+    if !value.as_bytes().iter().any(needs_escape) {
+        return Cow::Borrowed(value.as_bytes());
+    }
+
+    let mut output = Vec::with_capacity(value.len() + 8);
+    for &ch in value.as_bytes() {
+        match ch {
+            b'<' => output.extend_from_slice(b"&lt;"),
+            b'>' => output.extend_from_slice(b"&gt;"),
+            b'&' => output.extend_from_slice(b"&amp;"),
+            b'\'' => output.extend_from_slice(b"&apos;"),
+            b'"' => output.extend_from_slice(b"&quot;"),
+            b'\t' => output.extend_from_slice(b"&#9;"),
+            b'\n' => output.extend_from_slice(b"&#10;"),
+            b'\r' => output.extend_from_slice(b"&#13;"),
+            ch => output.push(ch),
+        };
+    }
+
+    Cow::Owned(output)
+}
+
+/// The element name for a node named `key`, the placeholder from the
+/// options for an empty name.
+fn element_name<'a>(key: &'a str, options: &'a TextXmlOptions) -> Result<&'a str, KbinError> {
+    match &options.empty_name {
+        _ if !key.is_empty() => Ok(key),
+        Some(placeholder) => Ok(placeholder),
+        None => Err(KbinError::EmptyNodeName),
+    }
+}
+
+/// Push the attributes describing the type of an unknown node: the name of
+/// a registered custom type and, for arrays, the number of values, or
+/// `__type_id` and `__type="unknown"` otherwise.
+fn push_unknown_type(
+    elem: &mut BytesStart,
+    type_id: u8,
+    data: &[u8],
+    options: &TextXmlOptions,
+) {
+    let (name, count) = match CustomType::find(&options.custom_types, type_id) {
+        Some(custom_type) => {
+            let count = if type_id & ARRAY_MASK == ARRAY_MASK {
+                Some(data.len() / custom_type.value_size().max(1))
+            } else {
+                None
+            };
+
+            (custom_type.name, count)
+        },
+        None => {
+            elem.push_attribute(Attribute {
+                key: b"__type_id",
+                value: Cow::Owned(type_id.to_string().into_bytes()),
+            });
+
+            (options.type_names.name(StandardType::Unknown), None)
+        },
+    };
+
+    if let Some(count) = count {
+        elem.push_attribute(Attribute {
+            key: b"__count",
+            value: Cow::Owned(count.to_string().into_bytes()),
+        });
+    }
+    elem.push_attribute(Attribute {
+        key: b"__type",
+        value: Cow::Borrowed(name.as_bytes()),
+    });
+}
+
+/// Format a node value as the text content of its element.
+///
+/// Formatting never depends on the system locale, floats always use `.` as
+/// the decimal separator and integers have no grouping separators.
+fn format_value(value: &Value, options: &TextXmlOptions) -> Result<String, KbinError> {
+    match (value, options.time_format, options.integer_width) {
+        (Value::Unknown { type_id, data }, _, _) => {
+            match CustomType::find(&options.custom_types, *type_id) {
+                Some(custom_type) => Ok((custom_type.emit)(data)),
+                None => format_value_inner(value),
+            }
+        },
+        (Value::Time(secs), TimeFormat::Iso8601, _) => Ok(crate::value::format_iso8601(*secs)),
+        (value, _, Some(width)) if is_integer_type(value.standard_type()) => {
+            Ok(zero_pad_integers(&value.to_string(), width))
+        },
+        (value, _, _) => format_value_inner(value),
+    }
+}
+
+/// Returns `true` for the signed and unsigned integer types, including their
+/// tuple and vector forms such as `2s8` and `vu16`.
+fn is_integer_type(node_type: StandardType) -> bool {
+    matches!(
+        node_type.element_type(),
+        StandardType::S8 |
+            StandardType::U8 |
+            StandardType::S16 |
+            StandardType::U16 |
+            StandardType::S32 |
+            StandardType::U32 |
+            StandardType::S64 |
+            StandardType::U64
+    )
+}
+
+fn zero_pad_integers(text: &str, width: usize) -> String {
+    let mut output = String::with_capacity(text.len());
+    for (i, number) in text.split(' ').enumerate() {
+        if i > 0 {
+            output.push(' ');
+        }
+
+        let (sign, digits) = match number.strip_prefix('-') {
+            Some(digits) => ("-", digits),
+            None => ("", number),
+        };
+        output.push_str(sign);
+        for _ in (sign.len() + digits.len())..width {
+            output.push('0');
+        }
+        output.push_str(digits);
+    }
+
+    output
+}
+
+#[cfg(not(feature = "exact-floats"))]
+#[inline]
+fn format_value_inner(value: &Value) -> Result<String, KbinError> {
+    Ok(value.to_string())
+}
+
+/// Format a node value as the text content of its element, failing if a
+/// floating point value would not parse back to the same bits, such as a NaN
+/// with a non-standard payload.
+#[cfg(feature = "exact-floats")]
+fn format_value_inner(value: &Value) -> Result<String, KbinError> {
+    let text = value.to_string();
+    let node_type = value.standard_type();
+
+    match node_type {
+        StandardType::Float |
+        StandardType::Float2 |
+        StandardType::Float3 |
+        StandardType::Float4 |
+        StandardType::Double |
+        StandardType::Double2 |
+        StandardType::Double3 |
+        StandardType::Double4 => {
+            let (is_array, count) = match value {
+                Value::Array(values) => (true, values.len()),
+                _ => (false, 0),
+            };
+            let parsed = Value::from_string(node_type, &text, is_array, count)?;
+
+            if parsed.to_bytes()? != value.to_bytes()? {
+                return Err(KbinError::InexactFloat { node_type, text });
+            }
+        },
+        _ => {},
+    };
+
+    Ok(text)
+}
 
 pub trait ToTextXml {
     fn encoding(&self) -> EncodingType;
     fn write<W: Write>(&self, writer: &mut Writer<W>) -> Result<(), KbinError>;
+
+    /// Write with formatting options. Implementations that do not support
+    /// any options can rely on the default, which ignores them.
+    fn write_with_options<W: Write>(
+        &self,
+        writer: &mut Writer<W>,
+        options: &TextXmlOptions,
+    ) -> Result<(), KbinError> {
+        let _ = options;
+        self.write(writer)
+    }
+
+    /// Write the children of this node without the node itself, for
+    /// `TextXmlOptionsBuilder::fragment`. Implementations that cannot write
+    /// fragments can rely on the default, which returns an error.
+    fn write_children<W: Write>(
+        &self,
+        writer: &mut Writer<W>,
+        options: &TextXmlOptions,
+    ) -> Result<(), KbinError> {
+        let _ = (writer, options);
+        Err(KbinError::FragmentUnsupported)
+    }
 }
 
 pub struct TextXmlWriter {
     xml_writer: Writer<Cursor<Vec<u8>>>,
+    options: TextXmlOptions,
 }
 
 impl TextXmlWriter {
+    #[inline]
     pub fn new() -> Self {
+        Self::with_options(TextXmlOptions::default())
+    }
+
+    pub fn with_options(options: TextXmlOptions) -> Self {
         let inner = Cursor::new(Vec::new());
         let xml_writer = Writer::new(inner);
 
-        Self { xml_writer }
+        Self {
+            xml_writer,
+            options,
+        }
     }
 
-    pub fn to_text_xml<T>(mut self, value: &T) -> Result<Vec<u8>, KbinError>
+    pub fn into_text_xml<T>(mut self, value: &T) -> Result<Vec<u8>, KbinError>
         where
             T: ToTextXml,
     {
-        if let Some(encoding) = value.encoding().name() {
-            let header = BytesDecl::new(b"1.0", Some(encoding.as_bytes()), None);
+        let encoding = self.options.encoding.unwrap_or_else(|| value.encoding());
+        match encoding.name() {
+            Some(name) if !self.options.fragment => {
+                let header = BytesDecl::new(b"1.0", Some(name.as_bytes()), None);
+
+                self.xml_writer.write_event(Event::Decl(header))?;
+            },
+            _ => {},
+        };
+
+        if self.options.fragment {
+            value.write_children(&mut self.xml_writer, &self.options)?;
+        } else {
+            value.write_with_options(&mut self.xml_writer, &self.options)?;
+        }
+
+        let output = self.xml_writer.into_inner().into_inner();
+        match encoding {
+            EncodingType::None | EncodingType::UTF_8 => Ok(output),
 
-            self.xml_writer.write_event(Event::Decl(header))?;
+            // The document is written as UTF-8 by `ToTextXml`
+            _ => {
+                let output = String::from_utf8(output)
+                    .map_err(|source| EncodingError::InvalidUtf8 { source })?;
+                Ok(encoding.encode_with_char_refs(&output))
+            },
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use quick_xml::Writer;
 
-        value.write(&mut self.xml_writer)?;
+    use crate::encoding_type::EncodingType;
+    use crate::error::KbinError;
+    use crate::node::Node;
+    use crate::node_types::StandardType;
+    use crate::options::{ReaderOptions, TextXmlOptions, TimeFormat};
+    use crate::value::Value;
 
-        Ok(self.xml_writer.into_inner().into_inner())
+    use super::ToTextXml;
+
+    #[cfg(feature = "exact-floats")]
+    #[test]
+    fn test_exact_floats() {
+        let value = Value::Float2([0.1, 16_777_217.0]);
+        let node = Node::with_value("f", value.clone());
+        let text = crate::to_text_xml(&node).unwrap();
+        let (collection, _) = crate::from_text_xml(&text).unwrap();
+        assert_eq!(collection.base().value().unwrap(), value);
+
+        let node = Node::with_value("f", Value::Float(f32::from_bits(0x7fc0_0001)));
+        assert!(crate::to_text_xml(&node).is_err());
+    }
+
+    #[test]
+    fn test_iso8601_time() {
+        let node = Node::with_value("t", Value::Time(1_577_934_245));
+
+        let mut options = TextXmlOptions::builder();
+        options.time_format(TimeFormat::Iso8601);
+        let text = crate::to_text_xml_with_options(options.build(), &node).unwrap();
+        assert!(String::from_utf8_lossy(&text).contains(">2020-01-02T03:04:05Z<"));
+
+        let (collection, _) = crate::from_text_xml(&text).unwrap();
+        assert_eq!(collection.as_node().unwrap(), node);
+    }
+
+    #[test]
+    fn test_zero_pad_integers() {
+        let node = Node::with_nodes(
+            "root",
+            vec![
+                Node::with_value("a", Value::S32(5)),
+                Node::with_value("b", Value::S16_2([-5, 12345])),
+                Node::with_value("c", Value::Double(1.5)),
+                Node::with_value("d", Value::Vu16([1, 2, 3, 4, 5, 6, 7, 8])),
+                Node::with_value("e", Value::Float2([1.5, 2.5])),
+            ],
+        );
+
+        let mut options = TextXmlOptions::builder();
+        options.zero_pad_integers(4);
+        let text = crate::to_text_xml_with_options(options.build(), &node).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains(">0005<"));
+        assert!(text.contains(">-005 12345<"));
+        assert!(text.contains(">1.5"));
+        assert!(text.contains(">0001 0002 0003 0004 0005 0006 0007 0008<"));
+        assert!(!text.contains(">01.5"));
+
+        let (collection, _) = crate::from_text_xml(text.as_bytes()).unwrap();
+        assert_eq!(collection.as_node().unwrap(), node);
+    }
+
+    #[test]
+    fn test_binary_size() {
+        let node = Node::with_value("bin", Value::Binary(vec![1, 2, 0xff]));
+        let text = crate::to_text_xml(&node).unwrap();
+        assert!(String::from_utf8(text).unwrap().contains("__size=\"3\""));
+
+        let mut options = TextXmlOptions::builder();
+        options.binary_size(false);
+        let options = options.build();
+        let text = crate::to_text_xml_with_options(options.clone(), &node).unwrap();
+        assert!(String::from_utf8(text.clone())
+            .unwrap()
+            .ends_with("<bin __type=\"bin\">0102ff</bin>"));
+
+        // The size is taken from the data
+        let (collection, _) = crate::from_text_xml(&text).unwrap();
+        assert_eq!(collection.as_node().unwrap(), node);
+        let text = crate::to_text_xml_with_options(options, &collection).unwrap();
+        assert!(!String::from_utf8(text).unwrap().contains("__size"));
+    }
+
+    #[test]
+    fn test_fragment() {
+        let node = Node::with_nodes(
+            "root",
+            vec![
+                Node::with_value("a", Value::U8(1)),
+                Node::with_nodes("b", vec![Node::with_value("c", Value::S32(-1))]),
+            ],
+        );
+
+        let mut options = TextXmlOptions::builder();
+        options.fragment(true);
+        let options = options.build();
+        let text = crate::to_text_xml_with_options(options.clone(), &node).unwrap();
+        assert_eq!(
+            String::from_utf8(text.clone()).unwrap(),
+            "<a __type=\"u8\">1</a><b><c __type=\"s32\">-1</c></b>"
+        );
+
+        // The fragment is wrapped in a new root node when read
+        let mut reader_options = ReaderOptions::builder();
+        reader_options.fragment_root("root");
+        let reader_options = reader_options.build();
+        let (collection, _) =
+            crate::from_text_xml_with_options(reader_options.clone(), &text).unwrap();
+        assert_eq!(collection.as_node().unwrap(), node);
+        let output = crate::to_text_xml_with_options(options, &collection).unwrap();
+        assert_eq!(output, text);
+
+        let (collection, _) = crate::from_text_xml_with_options(reader_options, b"").unwrap();
+        assert_eq!(collection.as_node().unwrap(), Node::new("root"));
+    }
+
+    #[test]
+    fn test_fragment_unsupported() {
+        struct Document(Node);
+
+        impl ToTextXml for Document {
+            fn encoding(&self) -> EncodingType {
+                self.0.encoding()
+            }
+
+            fn write<W: Write>(&self, writer: &mut Writer<W>) -> Result<(), KbinError> {
+                self.0.write(writer)
+            }
+        }
+
+        let document = Document(Node::with_value("a", Value::U8(1)));
+        let text = crate::to_text_xml(&document).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.ends_with("<a __type=\"u8\">1</a>"));
+
+        let mut options = TextXmlOptions::builder();
+        options.fragment(true);
+        match crate::to_text_xml_with_options(options.build(), &document) {
+            Err(KbinError::FragmentUnsupported) => {},
+            result => panic!("Unexpected result: {:?}", result),
+        };
+    }
+
+    #[test]
+    fn test_empty_names() {
+        let node = Node::with_nodes(
+            "root",
+            vec![Node::with_value("", Value::U8(1)), Node::new("")],
+        );
+
+        // Compressed binary documents store empty names directly
+        let binary = crate::to_binary(&node).unwrap();
+        let (collection, _) = crate::from_slice(&binary).unwrap();
+        assert_eq!(collection.as_node().unwrap(), node);
+        assert_eq!(crate::to_binary(&collection).unwrap(), binary);
+
+        assert!(matches!(
+            crate::to_text_xml(&node),
+            Err(KbinError::EmptyNodeName)
+        ));
+        match crate::to_text_xml(&collection) {
+            Err(KbinError::AtPath { source, .. }) => {
+                assert!(matches!(*source, KbinError::EmptyNodeName))
+            },
+            result => panic!("unexpected result: {:?}", result),
+        };
+
+        let mut options = TextXmlOptions::builder();
+        options.empty_name("_");
+        let options = options.build();
+        let text = crate::to_text_xml_with_options(options.clone(), &node).unwrap();
+        assert!(text.ends_with(b"<root><_ __type=\"u8\">1</_><_/></root>"));
+        let output = crate::to_text_xml_with_options(options, &collection).unwrap();
+        assert_eq!(output, text);
+
+        let mut reader_options = ReaderOptions::builder();
+        reader_options.empty_name("_");
+        let (collection, _) =
+            crate::from_text_xml_with_options(reader_options.build(), &text).unwrap();
+        assert_eq!(collection.as_node().unwrap(), node);
+        assert_eq!(crate::to_binary(&collection).unwrap(), binary);
+    }
+
+    #[test]
+    fn test_locale_independent() {
+        assert_eq!(Value::U32(1_234_567).to_string(), "1234567");
+        assert!(!Value::Double(1234.5).to_string().contains(','));
+        assert_eq!(
+            Value::from_string(StandardType::Float, "1.5", false, 0).unwrap(),
+            Value::Float(1.5)
+        );
+        assert!(Value::from_string(StandardType::Float, "1,5", false, 0).is_err());
+        assert!(Value::from_string(StandardType::U32, "1,000", false, 0).is_err());
+    }
+
+    #[test]
+    fn test_encoding_char_refs() {
+        use crate::encoding_type::EncodingType;
+
+        let node = Node::with_attrs_value(
+            "root",
+            &[("name", "한 & 国")],
+            Value::String("テスト 한국어 <&>".into()),
+        );
+
+        let mut options = TextXmlOptions::builder();
+        options.encoding(EncodingType::SHIFT_JIS);
+        let text = crate::to_text_xml_with_options(options.build(), &node).unwrap();
+        assert!(text.starts_with(b"<?xml version=\"1.0\" encoding=\"Shift_JIS\"?>"));
+
+        // Characters Shift-JIS cannot represent are written as references
+        let decoded = EncodingType::SHIFT_JIS.decode(&text).unwrap();
+        assert!(decoded.contains("name=\"&#54620; &amp; 国\""), "{}", decoded);
+        assert!(decoded.contains(">テスト &#54620;&#44397;&#50612; &lt;&amp;&gt;<"));
+
+        let (collection, encoding) = crate::from_text_xml(&text).unwrap();
+        assert_eq!(encoding, EncodingType::SHIFT_JIS);
+        assert_eq!(collection.as_node().unwrap(), node);
+    }
+
+    #[test]
+    fn test_attribute_escaping() {
+        let value = "a &amp; b\r\n\tc <\"'>";
+        let node = Node::with_attrs("root", &[("a", value)]);
+
+        let text = crate::to_text_xml(&node).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains("a=\"a &amp;amp; b&#13;&#10;&#9;c &lt;&quot;&apos;&gt;\""));
+
+        let (collection, _) = crate::from_text_xml(text.as_bytes()).unwrap();
+        assert_eq!(collection.as_node().unwrap(), node);
+        assert_eq!(crate::to_text_xml(&collection).unwrap(), text.as_bytes());
+
+        let binary = crate::to_binary(&collection).unwrap();
+        let (collection, _) = crate::from_slice(&binary).unwrap();
+        assert_eq!(crate::to_text_xml(&collection).unwrap(), text.as_bytes());
+
+        // Literal whitespace is only normalized when asked to
+        let input = b"<root a=\"1\r\n2\t3&#10;4\"/>";
+        let (collection, _) = crate::from_text_xml(input).unwrap();
+        assert_eq!(
+            collection.as_node().unwrap().attr("a"),
+            Some("1\r\n2\t3\n4")
+        );
+
+        let mut options = ReaderOptions::builder();
+        options.normalize_attribute_whitespace(true);
+        let (collection, _) = crate::from_text_xml_with_options(options.build(), input).unwrap();
+        assert_eq!(collection.as_node().unwrap().attr("a"), Some("1 2 3\n4"));
+    }
+    #[test]
+    fn test_reserved_attribute_keys() {
+        let node = Node::with_attrs_value(
+            "root",
+            &[
+                ("__type", "user"),
+                ("___count", "c"),
+                ("__foo", "a"),
+                ("_bar", "b"),
+            ],
+            Value::S32(1),
+        );
+
+        let text = crate::to_text_xml(&node).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        let attrs = r#" __type="s32" ___type="user" ____count="c" __foo="a" _bar="b""#;
+        assert!(text.contains(attrs), "{}", text);
+
+        let mut options = ReaderOptions::builder();
+        options.deny_duplicate_attributes(true);
+        let (collection, _) =
+            crate::from_text_xml_with_options(options.build(), text.as_bytes()).unwrap();
+        assert_eq!(collection.as_node().unwrap(), node);
+        assert_eq!(crate::to_text_xml(&collection).unwrap(), text.as_bytes());
+
+        // Repeated escaped keys and repeated reserved keys are reported
+        for input in &[
+            &br#"<root ___size="1" ___size="2"/>"#[..],
+            &br#"<root __type="s32" __type="u8">1</root>"#[..],
+        ] {
+            let mut options = ReaderOptions::builder();
+            options.deny_duplicate_attributes(true);
+            assert!(crate::from_text_xml_with_options(options.build(), input).is_err());
+        }
+    }
+
+    #[test]
+    fn test_type_name_dialect() {
+        use crate::options::TypeNameDialect;
+        use crate::value::ValueArray;
+
+        let node = Node::with_nodes(
+            "root",
+            vec![
+                Node::with_value("s", Value::String("a".into())),
+                Node::with_value("ip", Value::Ip4("127.0.0.1".parse().unwrap())),
+                Node::with_value("v", Value::Array(ValueArray::S32_4(vec![[1, 2, 3, 4]]))),
+                Node::with_value("n", Value::U8(1)),
+            ],
+        );
+
+        let mut options = TextXmlOptions::builder();
+        options.type_names(TypeNameDialect::Alternate);
+        let text = crate::to_text_xml_with_options(options.build(), &node).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        for name in &["string", "ipv4", "vs32", "u8"] {
+            assert!(text.contains(&format!("__type=\"{}\"", name)), "{}", text);
+        }
+
+        let (collection, _) = crate::from_text_xml(text.as_bytes()).unwrap();
+        assert_eq!(collection.as_node().unwrap(), node);
+        for name in &["str", "string", "ip4", "ipv4", "4s32", "vs32", "b", "bool"] {
+            let node_type = StandardType::from_name(name).unwrap();
+            assert!(node_type.name == *name || node_type.alt_name == Some(*name));
+        }
     }
 }
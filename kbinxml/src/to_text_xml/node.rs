@@ -9,7 +9,11 @@ use crate::encoding_type::EncodingType;
 use crate::error::KbinError;
 use crate::node::Node;
 use crate::node_types::StandardType;
-use crate::to_text_xml::ToTextXml;
+use crate::options::TextXmlOptions;
+use crate::to_text_xml::{
+    element_name, escape_attribute, escape_attribute_key, format_value, push_unknown_type,
+    ToTextXml,
+};
 use crate::value::Value;
 
 impl ToTextXml for Node {
@@ -18,16 +22,25 @@ impl ToTextXml for Node {
         EncodingType::UTF_8
     }
 
+    #[inline]
     fn write<W: Write>(&self, writer: &mut Writer<W>) -> Result<(), KbinError> {
-        let key = self.key();
-        let mut elem = BytesStart::borrowed(key.as_bytes(), key.as_bytes().len());
+        self.write_with_options(writer, &TextXmlOptions::default())
+    }
+
+    fn write_with_options<W: Write>(
+        &self,
+        writer: &mut Writer<W>,
+        options: &TextXmlOptions,
+    ) -> Result<(), KbinError> {
+        let key = element_name(self.key(), options)?;
+        let mut elem = BytesStart::borrowed(key.as_bytes(), key.len());
 
         // Write the attributes for the value, but not the value contents.
         if let Some(value) = self.value() {
             let node_type = value.standard_type();
 
             match value {
-                Value::Binary(ref data) => {
+                Value::Binary(ref data) if !options.omit_binary_size => {
                     elem.push_attribute(Attribute {
                         key: b"__size",
                         value: Cow::Owned(data.len().to_string().into_bytes()),
@@ -39,25 +52,27 @@ impl ToTextXml for Node {
                         value: Cow::Owned(values.len().to_string().into_bytes()),
                     });
                 },
+                Value::Unknown { type_id, ref data } => {
+                    push_unknown_type(&mut elem, *type_id, data, options);
+                },
                 _ => {},
             };
 
-            // Only add a `__type` attribute if this is not a `NodeStart` node
-            if node_type != StandardType::NodeStart {
+            // Only add a `__type` attribute if this is not a `NodeStart` node,
+            // unknown nodes have theirs already
+            if node_type != StandardType::NodeStart && node_type != StandardType::Unknown {
                 elem.push_attribute(Attribute {
                     key: b"__type",
-                    value: Cow::Borrowed(node_type.name.as_bytes()),
+                    value: Cow::Borrowed(options.type_names.name(node_type).as_bytes()),
                 });
             }
         }
 
         if let Some(attributes) = self.attributes() {
             for (key, value) in attributes {
-                let value = BytesText::from_plain_str(&value);
-
                 elem.push_attribute(Attribute {
-                    key: key.as_bytes(),
-                    value: Cow::Borrowed(value.escaped()),
+                    key: &escape_attribute_key(key),
+                    value: escape_attribute(value),
                 });
             }
         }
@@ -66,7 +81,7 @@ impl ToTextXml for Node {
         let start_elem = if let Some(value) = self.value() {
             writer.write_event(Event::Start(elem))?;
 
-            let value = value.to_string();
+            let value = format_value(value, options)?;
             let elem = BytesText::from_plain_str(&value);
             writer.write_event(Event::Text(elem))?;
 
@@ -90,11 +105,7 @@ impl ToTextXml for Node {
             }
         }
 
-        if let Some(children) = self.children() {
-            for child in children {
-                child.write(writer)?;
-            }
-        }
+        self.write_children(writer, options)?;
 
         if has_value || has_children {
             let end_elem = BytesEnd::borrowed(key.as_bytes());
@@ -103,4 +114,18 @@ impl ToTextXml for Node {
 
         Ok(())
     }
+
+    fn write_children<W: Write>(
+        &self,
+        writer: &mut Writer<W>,
+        options: &TextXmlOptions,
+    ) -> Result<(), KbinError> {
+        if let Some(children) = self.children() {
+            for child in children {
+                child.write_with_options(writer, options)?;
+            }
+        }
+
+        Ok(())
+    }
 }
@@ -7,9 +7,13 @@ use quick_xml::Writer;
 
 use crate::encoding_type::EncodingType;
 use crate::error::KbinError;
-use crate::node::NodeCollection;
+use crate::node::{NodeCollection, NodeData, PathError};
 use crate::node_types::StandardType;
-use crate::to_text_xml::ToTextXml;
+use crate::options::TextXmlOptions;
+use crate::to_text_xml::{
+    element_name, escape_attribute, escape_attribute_key, format_value, push_unknown_type,
+    ToTextXml,
+};
 
 impl ToTextXml for NodeCollection {
     /// At the moment, decoding the value of a `NodeDefinition` will decode
@@ -18,29 +22,109 @@ impl ToTextXml for NodeCollection {
         EncodingType::UTF_8
     }
 
+    #[inline]
     fn write<W: Write>(&self, writer: &mut Writer<W>) -> Result<(), KbinError> {
+        self.write_with_options(writer, &TextXmlOptions::default())
+    }
+
+    /// Errors are wrapped in `KbinError::AtPath` with the path of the node
+    /// that failed.
+    fn write_with_options<W: Write>(
+        &self,
+        writer: &mut Writer<W>,
+        options: &TextXmlOptions,
+    ) -> Result<(), KbinError> {
+        self.write_collection(writer, options)
+            .map_err(|e| e.into_error(self))
+    }
+
+    fn write_children<W: Write>(
+        &self,
+        writer: &mut Writer<W>,
+        options: &TextXmlOptions,
+    ) -> Result<(), KbinError> {
+        for (i, child) in self.children().iter().enumerate() {
+            child
+                .write_collection(writer, options)
+                .map_err(|e| e.child(i).into_error(self))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl NodeCollection {
+    fn write_collection<W: Write>(
+        &self,
+        writer: &mut Writer<W>,
+        options: &TextXmlOptions,
+    ) -> Result<(), PathError> {
+        let end_key = self.write_start(writer, options)?;
+
+        for (i, child) in self.children().iter().enumerate() {
+            child
+                .write_collection(writer, options)
+                .map_err(|e| e.child(i))?;
+        }
+
+        if let Some(key) = end_key {
+            write_end(writer, &key)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the start of the element for this node, including its value but
+    /// not its children. Returns the element name if the element has to be
+    /// closed with `write_end` after the children are written.
+    pub(crate) fn write_start<W: Write>(
+        &self,
+        writer: &mut Writer<W>,
+        options: &TextXmlOptions,
+    ) -> Result<Option<String>, PathError> {
         let base = self.base();
-        let key = base.key()?.ok_or(KbinError::InvalidState)?;
+        match base.data() {
+            NodeData::Comment(text) => {
+                let text = BytesText::from_escaped_str(text.as_str());
+                writer.write_event(Event::Comment(text))?;
+                return Ok(None);
+            },
+            NodeData::ProcessingInstruction(content) => {
+                let content = BytesText::from_escaped_str(content.as_str());
+                writer.write_event(Event::PI(content))?;
+                return Ok(None);
+            },
+            _ => {},
+        };
+
+        let mut key = base.key()?.ok_or(KbinError::InvalidState)?;
+        if key.is_empty() {
+            key = element_name(&key, options)?.to_owned();
+        }
         let value = match base.value() {
             Ok(value) => Some(value),
             Err(e) => match e {
                 KbinError::InvalidNodeType { .. } => None,
-                _ => return Err(e),
+                _ => return Err(e.into()),
             },
         };
 
-        let mut elem = BytesStart::borrowed(key.as_bytes(), key.as_bytes().len());
+        let mut elem = BytesStart::borrowed(key.as_bytes(), key.len());
 
-        if base.is_array {
+        if base.node_type == StandardType::Unknown {
+            let data = base.value_bytes().ok_or(KbinError::InvalidState)?;
+            push_unknown_type(&mut elem, base.type_id(), data, options);
+        } else if base.is_array {
             let values = value.as_ref().ok_or(KbinError::InvalidState)?.as_array()?;
+            let count = base.declared_count().unwrap_or_else(|| values.len());
 
             elem.push_attribute(Attribute {
                 key: b"__count",
-                value: Cow::Owned(values.len().to_string().into_bytes()),
+                value: Cow::Owned(count.to_string().into_bytes()),
             });
         }
 
-        if base.node_type == StandardType::Binary {
+        if base.node_type == StandardType::Binary && !options.omit_binary_size {
             let value = value.as_ref().ok_or(KbinError::InvalidState)?.as_slice()?;
 
             elem.push_attribute(Attribute {
@@ -49,25 +133,22 @@ impl ToTextXml for NodeCollection {
             });
         }
 
-        // Only add a `__type` attribute if this is not a `NodeStart` node
-        if base.node_type != StandardType::NodeStart {
+        // Only add a `__type` attribute if this is not a `NodeStart` node,
+        // unknown nodes have theirs already
+        if base.node_type != StandardType::NodeStart && base.node_type != StandardType::Unknown {
             elem.push_attribute(Attribute {
                 key: b"__type",
-                value: Cow::Borrowed(base.node_type.name.as_bytes()),
+                value: Cow::Borrowed(options.type_names.name(base.node_type).as_bytes()),
             });
         }
 
         for attribute in self.attributes() {
-            let key = attribute
-                .key()?
-                .ok_or(KbinError::InvalidState)?
-                .into_bytes();
+            let key = attribute.key()?.ok_or(KbinError::InvalidState)?;
             let value = attribute.value()?.to_string();
-            let value = BytesText::from_plain_str(&value);
 
             elem.push_attribute(Attribute {
-                key: &key,
-                value: Cow::Borrowed(value.escaped()),
+                key: &escape_attribute_key(&key),
+                value: escape_attribute(&value),
             });
         }
 
@@ -75,7 +156,7 @@ impl ToTextXml for NodeCollection {
             Some(value) => {
                 writer.write_event(Event::Start(elem))?;
 
-                let value = value.to_string();
+                let value = format_value(&value, options)?;
                 let elem = BytesText::from_plain_str(&value);
                 writer.write_event(Event::Text(elem))?;
 
@@ -96,15 +177,17 @@ impl ToTextXml for NodeCollection {
             }
         }
 
-        for child in self.children() {
-            child.write(writer)?;
-        }
-
         if has_value || has_children {
-            let end_elem = BytesEnd::borrowed(key.as_bytes());
-            writer.write_event(Event::End(end_elem))?;
+            Ok(Some(key))
+        } else {
+            Ok(None)
         }
-
-        Ok(())
     }
 }
+
+pub(crate) fn write_end<W: Write>(writer: &mut Writer<W>, key: &str) -> Result<(), KbinError> {
+    let end_elem = BytesEnd::borrowed(key.as_bytes());
+    writer.write_event(Event::End(end_elem))?;
+
+    Ok(())
+}
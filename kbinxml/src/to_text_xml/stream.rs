@@ -0,0 +1,215 @@
+use std::io::Write;
+
+use quick_xml::events::{BytesDecl, Event};
+use quick_xml::{Error as QuickXmlError, Writer};
+
+use crate::error::KbinError;
+use crate::node::NodeCollection;
+use crate::options::TextXmlOptions;
+use crate::to_text_xml::node_collection::write_end;
+use crate::to_text_xml::ToTextXml;
+
+struct Frame<'a> {
+    collection: &'a NodeCollection,
+    next_child: usize,
+    end_key: Option<String>,
+}
+
+/// Writes a `NodeCollection` as text XML a few nodes at a time.
+///
+/// Each call to `write_nodes` writes up to the given number of elements and
+/// flushes the output, so a large export can be spread over many calls and
+/// report how far it got in between.
+pub struct TextXmlStreamWriter<'a, W: Write> {
+    root: &'a NodeCollection,
+    writer: Writer<W>,
+    options: TextXmlOptions,
+    stack: Vec<Frame<'a>>,
+    started: bool,
+    written: usize,
+    total: usize,
+}
+
+fn count_nodes(collection: &NodeCollection) -> usize {
+    1 + collection.children().iter().map(count_nodes).sum::<usize>()
+}
+
+impl<'a, W: Write> TextXmlStreamWriter<'a, W> {
+    #[inline]
+    pub fn new(root: &'a NodeCollection, output: W) -> Result<Self, KbinError> {
+        Self::with_options(root, output, TextXmlOptions::default())
+    }
+
+    /// Start the document by writing the XML declaration.
+    pub fn with_options(
+        root: &'a NodeCollection,
+        output: W,
+        options: TextXmlOptions,
+    ) -> Result<Self, KbinError> {
+        let mut writer = Writer::new(output);
+
+        if let Some(encoding) = root.encoding().name().filter(|_| !options.fragment) {
+            let header = BytesDecl::new(b"1.0", Some(encoding.as_bytes()), None);
+
+            writer.write_event(Event::Decl(header))?;
+        }
+
+        Ok(Self {
+            root,
+            writer,
+            options,
+            stack: Vec::new(),
+            started: false,
+            written: 0,
+            total: count_nodes(root),
+        })
+    }
+
+    /// The number of elements written so far.
+    #[inline]
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// The number of elements in the document.
+    #[inline]
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// The output, holding everything written so far once `write_nodes`
+    /// returns.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut W {
+        self.writer.inner()
+    }
+
+    #[inline]
+    pub fn is_finished(&self) -> bool {
+        self.started && self.stack.is_empty()
+    }
+
+    /// Write up to `count` more elements, closing every element whose
+    /// children are all written, then flush the output. Returns `true` once
+    /// the whole document is written.
+    ///
+    /// Errors are wrapped in `KbinError::AtPath` with the path of the node
+    /// that failed.
+    pub fn write_nodes(&mut self, count: usize) -> Result<bool, KbinError> {
+        let mut remaining = count;
+
+        if !self.started && remaining > 0 {
+            self.start_node(self.root)?;
+            self.started = true;
+            remaining -= 1;
+        }
+
+        while let Some(frame) = self.stack.last_mut() {
+            match frame.collection.children().get(frame.next_child) {
+                Some(_) if remaining == 0 => break,
+                Some(child) => {
+                    frame.next_child += 1;
+                    self.start_node(child)?;
+                    remaining -= 1;
+                },
+                None => {
+                    if let Some(key) = frame.end_key.take() {
+                        write_end(&mut self.writer, &key)?;
+                    }
+                    self.stack.pop();
+                },
+            };
+        }
+
+        self.writer.inner().flush().map_err(QuickXmlError::Io)?;
+
+        Ok(self.is_finished())
+    }
+
+    fn start_node(&mut self, collection: &'a NodeCollection) -> Result<(), KbinError> {
+        match collection.write_start(&mut self.writer, &self.options) {
+            Ok(end_key) => {
+                self.stack.push(Frame {
+                    collection,
+                    next_child: 0,
+                    end_key,
+                });
+                self.written += 1;
+
+                Ok(())
+            },
+            Err(e) => {
+                let e = self
+                    .stack
+                    .iter()
+                    .rev()
+                    .fold(e, |e, frame| e.child(frame.next_child - 1));
+
+                Err(e.into_error(self.root))
+            },
+        }
+    }
+
+    /// Write the rest of the document in chunks of `chunk_size` elements,
+    /// calling `progress` with the number of elements written and the total
+    /// after each chunk.
+    pub fn finish_with_progress<F>(
+        mut self,
+        chunk_size: usize,
+        mut progress: F,
+    ) -> Result<W, KbinError>
+    where
+        F: FnMut(usize, usize),
+    {
+        let chunk_size = chunk_size.max(1);
+        while !self.write_nodes(chunk_size)? {
+            progress(self.written, self.total);
+        }
+        progress(self.written, self.total);
+
+        Ok(self.writer.into_inner())
+    }
+
+    /// Write the rest of the document and return the output.
+    pub fn finish(mut self) -> Result<W, KbinError> {
+        self.write_nodes(usize::MAX)?;
+
+        Ok(self.writer.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::node::Node;
+    use crate::value::Value;
+
+    #[test]
+    fn test_stream_writer() {
+        let node = Node::with_nodes(
+            "root",
+            vec![
+                Node::with_nodes("a", vec![Node::with_value("b", Value::S32(1))]),
+                Node::new("c"),
+                Node::with_value("d", Value::String("text".into())),
+            ],
+        );
+        let collection = NodeCollection::from_node(&node).unwrap();
+        let expected = crate::to_text_xml(&collection).unwrap();
+
+        let mut updates = Vec::new();
+        let writer = TextXmlStreamWriter::new(&collection, Vec::new()).unwrap();
+        let output = writer
+            .finish_with_progress(2, |written, total| updates.push((written, total)))
+            .unwrap();
+        assert_eq!(output, expected);
+        assert_eq!(updates, vec![(2, 5), (4, 5), (5, 5)]);
+
+        let mut writer = TextXmlStreamWriter::new(&collection, Vec::new()).unwrap();
+        assert!(!writer.write_nodes(0).unwrap());
+        assert!(!writer.write_nodes(4).unwrap());
+        assert!(writer.write_nodes(1).unwrap());
+        assert_eq!(writer.finish().unwrap(), expected);
+    }
+}
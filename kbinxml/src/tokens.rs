@@ -0,0 +1,429 @@
+//! The raw token sequence of a binary document, without decoding names or
+//! values.
+//!
+//! Every node, attribute, node end, and file end marker is one `Token` with
+//! the type byte, the name as stored in the node buffer, and the slice of the
+//! data buffer holding its value. `TokenWriter` lays tokens out again using
+//! the same alignment rules as the reader. A writer created with
+//! `TokenWriter::for_tokens` also keeps the alignment, byte order, and
+//! section order of the document, so reading and writing the tokens of a
+//! document reproduces it byte for byte.
+
+use std::io::Write;
+
+use byteorder::WriteBytesExt;
+use bytes::Bytes;
+use snafu::ResultExt;
+
+use crate::byte_buffer::ByteBufferWrite;
+use crate::compression_type::CompressionType;
+use crate::encoding_type::EncodingType;
+use crate::error::KbinError;
+use crate::node::{Key, NodeData};
+use crate::node_types::StandardType;
+use crate::options::{Alignment, Endianness, Options, ReaderOptions};
+use crate::reader::Reader;
+use crate::writer::{DataBuffer, DataWrite, NodeBuffer, NodeSize, NodeType, WriterError};
+use crate::ARRAY_MASK;
+
+/// A node name as stored in the node buffer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TokenName {
+    /// A sixbit packed name with `len` characters
+    Sixbit { len: u8, bits: Bytes },
+
+    /// A name in the document encoding, without the length byte
+    Encoded(Bytes),
+}
+
+/// A value as stored in the data buffer. Length prefixes and padding are not
+/// included. Values of little endian documents are converted to big endian,
+/// like the values of a `NodeDefinition`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenData {
+    /// Offset of the value from the start of the file
+    pub offset: usize,
+    pub bytes: Bytes,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Token {
+    /// Offset of the type byte from the start of the file
+    pub offset: usize,
+
+    /// The type byte, including the array flag
+    pub type_byte: u8,
+
+    /// The name, for every token except node ends and the file end
+    pub name: Option<TokenName>,
+
+    /// The value, for tokens other than `NodeStart`, node ends, and the file
+    /// end
+    pub data: Option<TokenData>,
+}
+
+impl Token {
+    /// The node type of the type byte, or `None` for unknown type ids.
+    pub fn node_type(&self) -> Option<StandardType> {
+        StandardType::from_u8(self.type_byte & !ARRAY_MASK).ok()
+    }
+
+    pub fn is_array(&self) -> bool {
+        self.type_byte & ARRAY_MASK == ARRAY_MASK
+    }
+}
+
+/// Iterator over the tokens of a binary document, ending after the file end
+/// token or the first error.
+pub struct Tokens {
+    reader: Reader,
+    done: bool,
+}
+
+impl Tokens {
+    pub fn new(input: Bytes) -> Result<Self, KbinError> {
+        let mut options = ReaderOptions::builder();
+        options.preserve_unknown_types(true).detect_alignment(true);
+        let reader = Reader::with_options(input, options.build())?;
+
+        Ok(Self {
+            reader,
+            done: false,
+        })
+    }
+
+    #[inline]
+    pub fn compression(&self) -> CompressionType {
+        self.reader.compression()
+    }
+
+    #[inline]
+    pub fn encoding(&self) -> EncodingType {
+        self.reader.encoding()
+    }
+
+    #[inline]
+    pub fn alignment(&self) -> Alignment {
+        self.reader.alignment()
+    }
+
+    #[inline]
+    pub fn endianness(&self) -> Endianness {
+        self.reader.endianness()
+    }
+
+    /// Whether the data buffer comes before the node buffer in the document.
+    #[inline]
+    pub fn is_data_first(&self) -> bool {
+        self.reader.is_data_first()
+    }
+
+    fn read_token(&mut self) -> Result<Token, KbinError> {
+        let offset = self.reader.node_offset();
+        let definition = self.reader.read_node_definition()?;
+
+        let (name, data) = match definition.data() {
+            NodeData::Some { key, value_data } => {
+                let name = match key {
+                    Key::Compressed { size, data } => TokenName::Sixbit {
+                        len: size.sixbit_len,
+                        bits: data.clone(),
+                    },
+                    Key::Uncompressed { data, .. } => TokenName::Encoded(data.clone()),
+                };
+                let data = match definition.node_type {
+                    StandardType::NodeStart => None,
+                    _ => Some(TokenData {
+                        offset: self.reader.last_data_offset(),
+                        bytes: value_data.clone(),
+                    }),
+                };
+
+                (Some(name), data)
+            },
+            _ => (None, None),
+        };
+
+        Ok(Token {
+            offset,
+            type_byte: definition.type_id(),
+            name,
+            data,
+        })
+    }
+}
+
+impl Iterator for Tokens {
+    type Item = Result<Token, KbinError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let token = self.read_token();
+        self.done = match &token {
+            Ok(token) => token.type_byte & !ARRAY_MASK == StandardType::FileEnd as u8,
+            Err(_) => true,
+        };
+
+        Some(token)
+    }
+}
+
+/// Builds a binary document from tokens. The offsets in the tokens are
+/// ignored.
+pub struct TokenWriter {
+    compression: CompressionType,
+    encoding: EncodingType,
+    endianness: Endianness,
+    data_first: bool,
+    node_buf: ByteBufferWrite,
+    data_buf: ByteBufferWrite,
+}
+
+impl TokenWriter {
+    /// `compression` and `encoding` are only written to the header, names
+    /// are written as given in the tokens.
+    pub fn new(compression: CompressionType, encoding: EncodingType) -> Self {
+        Self::with_options(&Options::new(compression, encoding))
+    }
+
+    /// Like `new`, also writing with the alignment and byte order of
+    /// `options`. Other options do not apply to tokens.
+    pub fn with_options(options: &Options) -> Self {
+        let mut data_buf = ByteBufferWrite::with_alignment(Vec::new(), options.alignment.size());
+        data_buf.set_endianness(options.endianness);
+
+        Self {
+            compression: options.compression,
+            encoding: options.encoding,
+            endianness: options.endianness,
+            data_first: false,
+            node_buf: ByteBufferWrite::new(Vec::new()),
+            data_buf,
+        }
+    }
+
+    /// A writer with the header, alignment, byte order, and section order of
+    /// the document read by `tokens`.
+    pub fn for_tokens(tokens: &Tokens) -> Self {
+        let mut options = Options::builder();
+        options
+            .compression(tokens.compression())
+            .encoding(tokens.encoding())
+            .alignment(tokens.alignment())
+            .endianness(tokens.endianness());
+
+        let mut writer = Self::with_options(&options.build());
+        writer.data_first = tokens.is_data_first();
+
+        writer
+    }
+
+    pub fn push(&mut self, token: &Token) -> Result<(), KbinError> {
+        self.write_token(token).map_err(Into::into)
+    }
+
+    fn write_token(&mut self, token: &Token) -> Result<(), WriterError> {
+        let node_type = token.node_type().unwrap_or(StandardType::Unknown);
+        self.node_buf
+            .write_u8(token.type_byte)
+            .context(NodeType { node_type })?;
+
+        match &token.name {
+            Some(TokenName::Sixbit { len, bits }) => {
+                self.node_buf
+                    .write_u8(*len)
+                    .context(DataWrite { node_type })?;
+                self.node_buf
+                    .write_all(bits)
+                    .context(DataWrite { node_type })?;
+            },
+            Some(TokenName::Encoded(bytes)) => {
+                let len = bytes.len().saturating_sub(1) as u8;
+                self.node_buf
+                    .write_u8(len | ARRAY_MASK)
+                    .context(DataWrite { node_type })?;
+                self.node_buf
+                    .write_all(bytes)
+                    .context(DataWrite { node_type })?;
+            },
+            None => {},
+        };
+
+        if let Some(data) = &token.data {
+            self.write_data(node_type, token.is_array(), &data.bytes)?;
+        }
+
+        Ok(())
+    }
+
+    /// Mirrors `Reader::read_node_data`.
+    fn write_data(
+        &mut self,
+        node_type: StandardType,
+        is_array: bool,
+        data: &[u8],
+    ) -> Result<(), WriterError> {
+        let mut swapped;
+        let data = match self.endianness {
+            Endianness::Little => {
+                swapped = data.to_vec();
+                self.endianness.swap_value_bytes(node_type, &mut swapped);
+                &swapped[..]
+            },
+            Endianness::Big => data,
+        };
+
+        match node_type {
+            StandardType::Attribute |
+            StandardType::String |
+            StandardType::Binary |
+            StandardType::Unknown => {
+                self.data_buf
+                    .buf_write(data)
+                    .context(DataBuffer { node_type })?;
+            },
+            StandardType::NodeStart | StandardType::NodeEnd | StandardType::FileEnd => {},
+            node_type if is_array => {
+                let size = data.len() as u32;
+                self.endianness
+                    .write_u32(&mut *self.data_buf, size)
+                    .context(NodeSize { node_type, size })?;
+                self.data_buf
+                    .write_all(data)
+                    .context(DataWrite { node_type })?;
+                self.data_buf
+                    .realign_writes(None)
+                    .context(DataBuffer { node_type })?;
+            },
+            node_type => {
+                self.data_buf
+                    .write_aligned(node_type, data)
+                    .context(DataBuffer { node_type })?;
+            },
+        };
+
+        Ok(())
+    }
+
+    /// Finish the document. The tokens should end with a file end token.
+    pub fn finish(mut self) -> Result<Vec<u8>, KbinError> {
+        self.node_buf.realign_writes(None).context(NodeBuffer {
+            node_type: StandardType::FileEnd,
+        })?;
+
+        let node_buf = self.node_buf.into_inner();
+        let data_buf = self.data_buf.into_inner();
+
+        // The sections are laid out the same way with their lengths, so the
+        // data buffer is written first by swapping them
+        let (first, second) = if self.data_first {
+            (&data_buf, &node_buf)
+        } else {
+            (&node_buf, &data_buf)
+        };
+        crate::writer::assemble(
+            self.compression,
+            self.encoding,
+            self.endianness,
+            first,
+            second,
+        )
+        .map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{BigEndian, ByteOrder};
+
+    use crate::node::Node;
+    use crate::value::{Value, ValueArray};
+
+    use super::*;
+
+    #[test]
+    fn test_token_round_trip() {
+        let data = include_bytes!("../../testcases_out.kbin");
+        let tokens = Tokens::new(Bytes::from(&data[..])).unwrap();
+        let mut writer = TokenWriter::new(tokens.compression(), tokens.encoding());
+
+        let tokens = tokens.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(tokens[0].offset, 8);
+        assert_eq!(tokens[0].node_type(), Some(StandardType::NodeStart));
+        assert_eq!(
+            tokens.last().unwrap().node_type(),
+            Some(StandardType::FileEnd)
+        );
+
+        for token in &tokens {
+            if let Some(token_data) = &token.data {
+                let start = token_data.offset;
+                assert_eq!(
+                    &data[start..start + token_data.bytes.len()],
+                    &token_data.bytes[..]
+                );
+            }
+            writer.push(token).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), &data[..]);
+    }
+
+    fn rewrite(data: &[u8]) -> Vec<u8> {
+        let tokens = Tokens::new(Bytes::from(data.to_vec())).unwrap();
+        let mut writer = TokenWriter::for_tokens(&tokens);
+        for token in tokens {
+            let token = token.unwrap();
+            assert_eq!(
+                data[token.offset] & !ARRAY_MASK,
+                token.type_byte & !ARRAY_MASK
+            );
+            writer.push(&token).unwrap();
+        }
+
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_token_layouts() {
+        let node = Node::with(
+            "root",
+            &[("id", "1")],
+            vec![
+                Node::with_value("u8", Value::U8(7)),
+                Node::with_value("s16", Value::S16(-2)),
+                Node::with_value("str", Value::String("abc".into())),
+                Node::with_value("arr", Value::Array(ValueArray::U32(vec![1, 2, 3]))),
+                Node::with_value("bin", Value::Binary(vec![1, 2, 3])),
+            ],
+        );
+
+        for &(alignment, endianness) in &[
+            (Alignment::Eight, Endianness::Big),
+            (Alignment::Four, Endianness::Little),
+            (Alignment::Eight, Endianness::Little),
+        ] {
+            let mut options = Options::builder();
+            options.alignment(alignment).endianness(endianness);
+            let data = crate::to_binary_with_options(options.build(), &node).unwrap();
+
+            let tokens = Tokens::new(Bytes::from(data.clone())).unwrap();
+            assert_eq!(tokens.alignment(), alignment);
+            assert_eq!(tokens.endianness(), endianness);
+            assert_eq!(rewrite(&data), data);
+        }
+
+        // Move the data buffer and its length before the node buffer
+        let data = crate::to_binary(&node).unwrap();
+        let len_node = BigEndian::read_u32(&data[4..8]) as usize;
+        let (node_section, data_section) = data[4..].split_at(4 + len_node);
+        let swapped = [&data[..4], data_section, node_section].concat();
+
+        assert!(Tokens::new(Bytes::from(swapped.clone()))
+            .unwrap()
+            .is_data_first());
+        assert_eq!(rewrite(&swapped), swapped);
+    }
+}
@@ -1,6 +1,5 @@
-use std::error::Error;
+use std::borrow::Cow;
 use std::net::Ipv4Addr;
-use std::str::FromStr;
 
 use snafu::ResultExt;
 
@@ -13,16 +12,59 @@ pub trait FromKbinString: Sized {
 fn space_check(input: &str) -> Result<()> {
     // check for space character
     if input.find(' ').is_some() {
-        return Err(KbinError::InvalidState.into());
+        return Err(KbinError::InvalidState);
     }
 
     Ok(())
 }
 
+/// Parse the textual forms of NaN and infinity. Besides the `nan`, `inf`, and
+/// `infinity` tokens understood by `FromStr`, this accepts the forms written by
+/// the Microsoft C runtime, such as `1.#INF00` and `-1.#IND00`.
+fn parse_non_finite(input: &str) -> Option<f64> {
+    let input = input.to_ascii_lowercase();
+    let (negative, rest) = match input.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, input.strip_prefix('+').unwrap_or(&input)),
+    };
+
+    let value = match rest.trim_end_matches('0') {
+        "nan" | "1.#qnan" | "1.#snan" | "1.#ind" => f64::NAN,
+        "inf" | "infinity" | "1.#inf" => f64::INFINITY,
+        _ => return None,
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+/// Replace `true` and `false` words, in any letter case, with `1` and `0` so
+/// hand-written boolean values can be parsed like generated ones.
+pub(crate) fn normalize_bool_words(input: &str) -> Cow<'_, str> {
+    fn number(part: &str) -> Option<&'static str> {
+        if part.eq_ignore_ascii_case("true") {
+            Some("1")
+        } else if part.eq_ignore_ascii_case("false") {
+            Some("0")
+        } else {
+            None
+        }
+    }
+
+    if input.split(' ').all(|part| number(part).is_none()) {
+        return Cow::Borrowed(input);
+    }
+
+    let parts: Vec<&str> = input
+        .split(' ')
+        .map(|part| number(part).unwrap_or(part))
+        .collect();
+
+    Cow::Owned(parts.join(" "))
+}
+
 fn parse_tuple<T>(node_type: &'static str, input: &str, output: &mut [T]) -> Result<()>
 where
-    T: FromStr,
-    T::Err: Error + Send + Sync + 'static,
+    T: FromKbinString,
 {
     let count = input.split(' ').count();
     if count != output.len() {
@@ -34,10 +76,7 @@ where
     }
 
     for (i, part) in input.split(' ').enumerate() {
-        output[i] = part
-            .parse::<T>()
-            .map_err(|e| Box::new(e) as Box<(dyn Error + Send + Sync + 'static)>)
-            .context(StringParse { node_type })?;
+        output[i] = T::from_kbin_string(part)?;
     }
 
     Ok(())
@@ -89,8 +128,8 @@ macro_rules! basic_int_parse {
         fn from_kbin_string(input: &str) -> Result<Self> {
           space_check(input)?;
 
-          if input.starts_with("0x") {
-            <$type>::from_str_radix(&input[2..], 16)
+          if let Some(hex) = input.strip_prefix("0x") {
+            <$type>::from_str_radix(hex, 16)
               .context(StringParseInt { node_type: stringify!($type) })
           } else {
             input.parse::<$type>()
@@ -111,6 +150,10 @@ macro_rules! basic_float_parse {
         fn from_kbin_string(input: &str) -> Result<Self> {
           space_check(input)?;
 
+          if let Some(value) = parse_non_finite(input) {
+            return Ok(value as $type);
+          }
+
           input.parse::<$type>()
             .context(StringParseFloat { node_type: stringify!($type) })
         }
@@ -0,0 +1,128 @@
+use std::cmp::Ordering;
+
+use crate::error::{KbinError, Result};
+use crate::value::Value;
+
+macro_rules! compare_values {
+  (
+    $lhs:expr, $rhs:expr;
+    $($konst:ident),*$(,)?
+  ) => {
+    match ($lhs, $rhs) {
+      $(
+        (Value::$konst(a), Value::$konst(b)) => a.partial_cmp(b),
+      )*
+      (Value::Array(a), Value::Array(b)) if a.standard_type() == b.standard_type() => a.partial_cmp(b),
+      (
+        Value::Unknown { type_id: a_type, data: a_data },
+        Value::Unknown { type_id: b_type, data: b_data },
+      ) if a_type == b_type => a_data.partial_cmp(b_data),
+      _ => None,
+    }
+  };
+}
+
+macro_rules! checked_op {
+  (
+    $method:ident, $checked:ident, $float:tt;
+    integer: [$($int:ident),*],
+    float: [$($flt:ident),*]
+  ) => {
+    /// Combine two scalar numeric values of the same type, failing on a type
+    /// mismatch or integer overflow.
+    pub fn $method(&self, rhs: &Value) -> Result<Value> {
+      let node_type = self.standard_type();
+
+      let value = match (self, rhs) {
+        $(
+          (Value::$int(a), Value::$int(b)) => a.$checked(*b).map(Value::$int),
+        )*
+        $(
+          (Value::$flt(a), Value::$flt(b)) => Some(Value::$flt(*a $float *b)),
+        )*
+        (Value::S8(_), _) | (Value::U8(_), _) |
+        (Value::S16(_), _) | (Value::U16(_), _) |
+        (Value::S32(_), _) | (Value::U32(_), _) |
+        (Value::S64(_), _) | (Value::U64(_), _) |
+        (Value::Time(_), _) |
+        (Value::Float(_), _) | (Value::Double(_), _) => {
+          return Err(KbinError::ValueTypeMismatch {
+            node_type,
+            value: rhs.clone(),
+          });
+        },
+        _ => return Err(KbinError::InvalidNodeType { node_type }),
+      };
+
+      value.ok_or(KbinError::ArithmeticOverflow { node_type })
+    }
+  };
+}
+
+/// Values are only ordered against values of the same type. Tuple and array
+/// values compare element by element.
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Value) -> Option<Ordering> {
+        compare_values! {
+            self, other;
+            S8, U8, S16, U16, S32, U32, S64, U64,
+            String, Ip4, Float, Double, Binary, Time, Attribute, Boolean,
+            S8_2, U8_2, S16_2, U16_2, S32_2, U32_2, S64_2, U64_2, Float2, Double2,
+            S8_3, U8_3, S16_3, U16_3, S32_3, U32_3, S64_3, U64_3, Float3, Double3,
+            S8_4, U8_4, S16_4, U16_4, S32_4, U32_4, S64_4, U64_4, Float4, Double4,
+            Vs8, Vu8, Vs16, Vu16,
+            Boolean2, Boolean3, Boolean4, Vb,
+        }
+    }
+}
+
+impl Value {
+    checked_op! {
+        checked_add, checked_add, +;
+        integer: [S8, U8, S16, U16, S32, U32, S64, U64, Time],
+        float: [Float, Double]
+    }
+
+    checked_op! {
+        checked_sub, checked_sub, -;
+        integer: [S8, U8, S16, U16, S32, U32, S64, U64, Time],
+        float: [Float, Double]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_and_arithmetic() {
+        assert!(Value::S32(1) < Value::S32(2));
+        assert_eq!(Value::S32(1).partial_cmp(&Value::U32(2)), None);
+        assert!(Value::U8_2([1, 2]) < Value::U8_2([1, 3]));
+
+        assert_eq!(
+            Value::S32(5).checked_add(&Value::S32(10)).unwrap(),
+            Value::S32(15)
+        );
+        assert_eq!(
+            Value::U8(5).checked_sub(&Value::U8(2)).unwrap(),
+            Value::U8(3)
+        );
+        assert_eq!(
+            Value::Float(1.5).checked_add(&Value::Float(1.0)).unwrap(),
+            Value::Float(2.5)
+        );
+
+        assert!(matches!(
+            Value::U8(0).checked_sub(&Value::U8(1)),
+            Err(KbinError::ArithmeticOverflow { .. })
+        ));
+        assert!(matches!(
+            Value::S32(0).checked_add(&Value::U32(1)),
+            Err(KbinError::ValueTypeMismatch { .. })
+        ));
+        assert!(Value::String("a".into())
+            .checked_add(&Value::String("b".into()))
+            .is_err());
+    }
+}
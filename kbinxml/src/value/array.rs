@@ -6,8 +6,9 @@ use crate::error::KbinError;
 use crate::node_types::StandardType;
 use crate::types::FromKbinString;
 use crate::types::{FromKbinBytes, IntoKbinBytes};
+use crate::value::{FloatText, Value};
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub enum ValueArray {
     S8(Vec<i8>),
     U8(Vec<u8>),
@@ -77,6 +78,7 @@ macro_rules! type_impl {
       let mut reader = Cursor::new(input);
 
       let value = match node_type {
+        StandardType::Unknown |
         StandardType::NodeStart |
         StandardType::NodeEnd |
         StandardType::FileEnd |
@@ -126,6 +128,7 @@ macro_rules! type_impl {
       });
 
       let value = match node_type {
+        StandardType::Unknown |
         StandardType::NodeStart |
         StandardType::NodeEnd |
         StandardType::FileEnd |
@@ -181,6 +184,107 @@ macro_rules! type_impl {
         )*
       }
     }
+
+    pub fn is_empty(&self) -> bool {
+      self.len() == 0
+    }
+
+    /// Collect single values of `node_type` into an array. Fails if any value
+    /// has a different type, or if `node_type` cannot be stored in arrays.
+    pub fn from_values(node_type: StandardType, values: Vec<Value>) -> Result<Self, KbinError> {
+      match node_type {
+        $(
+          StandardType::$konst => {
+            let values = values
+              .into_iter()
+              .map(|value| match value {
+                Value::$konst(v) => Ok(v),
+                value => Err(KbinError::ValueTypeMismatch { node_type, value }),
+              })
+              .collect::<Result<Vec<_>, _>>()?;
+
+            Ok(ValueArray::$konst(values))
+          },
+        )*
+        node_type => Err(KbinError::InvalidNodeType { node_type }),
+      }
+    }
+
+    /// The element at `index` as a single value.
+    pub fn get(&self, index: usize) -> Option<Value> {
+      match self {
+        $(
+          ValueArray::$konst(values) => values.get(index).cloned().map(Value::$konst),
+        )*
+      }
+    }
+
+    /// Replace the element at `index`. Fails if `value` is not a single value
+    /// of the array type or `index` is out of range.
+    pub fn set(&mut self, index: usize, value: Value) -> Result<(), KbinError> {
+      let len = self.len();
+
+      match self {
+        $(
+          ValueArray::$konst(values) => {
+            let element = values
+              .get_mut(index)
+              .ok_or(KbinError::ArrayIndexOutOfRange { index, len })?;
+            *element = match value {
+              Value::$konst(v) => v,
+              value => return Err(KbinError::ValueTypeMismatch {
+                node_type: StandardType::$konst,
+                value,
+              }),
+            };
+          },
+        )*
+      };
+
+      Ok(())
+    }
+
+    /// Shorten the array to `len` elements. Does nothing if the array is
+    /// already shorter.
+    pub fn truncate(&mut self, len: usize) {
+      match self {
+        $(
+          ValueArray::$konst(values) => values.truncate(len),
+        )*
+      }
+    }
+
+    /// Resize the array to `len` elements, filling new elements with values
+    /// returned by `f`. Fails without changing the array if `f` returns a
+    /// value that is not a single value of the array type.
+    pub fn resize_with<F>(&mut self, len: usize, mut f: F) -> Result<(), KbinError>
+    where
+      F: FnMut() -> Value,
+    {
+      match self {
+        $(
+          ValueArray::$konst(values) => {
+            if len <= values.len() {
+              values.truncate(len);
+              return Ok(());
+            }
+
+            let added = (values.len()..len)
+              .map(|_| match f() {
+                Value::$konst(v) => Ok(v),
+                value => Err(KbinError::ValueTypeMismatch {
+                  node_type: StandardType::$konst,
+                  value,
+                }),
+              })
+              .collect::<Result<Vec<_>, _>>()?;
+            values.extend(added);
+          },
+        )*
+      };
+
+      Ok(())
+    }
   };
 }
 
@@ -206,14 +310,33 @@ impl ValueArray {
       Float2, Float3, Float4,
       Double2, Double3, Double4,
     }
+
+    /// Returns `false` if any floating point element is NaN or infinite.
+    pub fn is_finite(&self) -> bool {
+        match self {
+            ValueArray::Float(v) => v.iter().all(|n| n.is_finite()),
+            ValueArray::Double(v) => v.iter().all(|n| n.is_finite()),
+            ValueArray::Float2(v) => v.iter().flatten().all(|n| n.is_finite()),
+            ValueArray::Float3(v) => v.iter().flatten().all(|n| n.is_finite()),
+            ValueArray::Float4(v) => v.iter().flatten().all(|n| n.is_finite()),
+            ValueArray::Double2(v) => v.iter().flatten().all(|n| n.is_finite()),
+            ValueArray::Double3(v) => v.iter().flatten().all(|n| n.is_finite()),
+            ValueArray::Double4(v) => v.iter().flatten().all(|n| n.is_finite()),
+            _ => true,
+        }
+    }
 }
 
-fn write_values<T: fmt::Display>(f: &mut fmt::Formatter, values: &[T]) -> fmt::Result {
-    for (i, v) in values.iter().enumerate() {
+fn write_values<I>(f: &mut fmt::Formatter, values: I) -> fmt::Result
+where
+    I: IntoIterator,
+    I::Item: fmt::Display,
+{
+    for (i, v) in values.into_iter().enumerate() {
         if i > 0 {
             f.write_str(" ")?;
         }
-        fmt::Display::fmt(v, f)?;
+        fmt::Display::fmt(&v, f)?;
     }
     Ok(())
 }
@@ -250,8 +373,8 @@ impl fmt::Display for ValueArray {
             ValueArray::S64(v) => write_values(f, v),
             ValueArray::U64(v) => write_values(f, v),
             ValueArray::Ip4(v) => write_values(f, v),
-            ValueArray::Float(v) => write_values(f, v),
-            ValueArray::Double(v) => write_values(f, v),
+            ValueArray::Float(v) => write_values(f, v.iter().copied().map(FloatText)),
+            ValueArray::Double(v) => write_values(f, v.iter().copied().map(FloatText)),
             ValueArray::S8_2(v) => write_array_2(f, v),
             ValueArray::U8_2(v) => write_array_2(f, v),
             ValueArray::S16_2(v) => write_array_2(f, v),
@@ -260,8 +383,8 @@ impl fmt::Display for ValueArray {
             ValueArray::U32_2(v) => write_array_2(f, v),
             ValueArray::S64_2(v) => write_array_2(f, v),
             ValueArray::U64_2(v) => write_array_2(f, v),
-            ValueArray::Float2(v) => write_array_2(f, v),
-            ValueArray::Double2(v) => write_array_2(f, v),
+            ValueArray::Float2(v) => write_values(f, v.iter().flatten().copied().map(FloatText)),
+            ValueArray::Double2(v) => write_values(f, v.iter().flatten().copied().map(FloatText)),
             ValueArray::S8_3(v) => write_array_3(f, v),
             ValueArray::U8_3(v) => write_array_3(f, v),
             ValueArray::S16_3(v) => write_array_3(f, v),
@@ -270,8 +393,8 @@ impl fmt::Display for ValueArray {
             ValueArray::U32_3(v) => write_array_3(f, v),
             ValueArray::S64_3(v) => write_array_3(f, v),
             ValueArray::U64_3(v) => write_array_3(f, v),
-            ValueArray::Float3(v) => write_array_3(f, v),
-            ValueArray::Double3(v) => write_array_3(f, v),
+            ValueArray::Float3(v) => write_values(f, v.iter().flatten().copied().map(FloatText)),
+            ValueArray::Double3(v) => write_values(f, v.iter().flatten().copied().map(FloatText)),
             ValueArray::S8_4(v) => write_array_4(f, v),
             ValueArray::U8_4(v) => write_array_4(f, v),
             ValueArray::S16_4(v) => write_array_4(f, v),
@@ -280,8 +403,8 @@ impl fmt::Display for ValueArray {
             ValueArray::U32_4(v) => write_array_4(f, v),
             ValueArray::S64_4(v) => write_array_4(f, v),
             ValueArray::U64_4(v) => write_array_4(f, v),
-            ValueArray::Float4(v) => write_array_4(f, v),
-            ValueArray::Double4(v) => write_array_4(f, v),
+            ValueArray::Float4(v) => write_values(f, v.iter().flatten().copied().map(FloatText)),
+            ValueArray::Double4(v) => write_values(f, v.iter().flatten().copied().map(FloatText)),
             ValueArray::Vs8(v) => write_array_16(f, v),
             ValueArray::Vu8(v) => write_array_16(f, v),
             ValueArray::Vs16(v) => write_array_8(f, v),
@@ -296,7 +419,7 @@ impl fmt::Display for ValueArray {
                 Ok(())
             },
             ValueArray::Boolean2(values) => {
-                for (i, v) in values.iter().flat_map(|v| v.into_iter()).enumerate() {
+                for (i, v) in values.iter().flat_map(|v| v.iter()).enumerate() {
                     if i > 0 {
                         f.write_str(" ")?;
                     }
@@ -305,7 +428,7 @@ impl fmt::Display for ValueArray {
                 Ok(())
             },
             ValueArray::Boolean3(values) => {
-                for (i, v) in values.iter().flat_map(|v| v.into_iter()).enumerate() {
+                for (i, v) in values.iter().flat_map(|v| v.iter()).enumerate() {
                     if i > 0 {
                         f.write_str(" ")?;
                     }
@@ -314,7 +437,7 @@ impl fmt::Display for ValueArray {
                 Ok(())
             },
             ValueArray::Boolean4(values) => {
-                for (i, v) in values.iter().flat_map(|v| v.into_iter()).enumerate() {
+                for (i, v) in values.iter().flat_map(|v| v.iter()).enumerate() {
                     if i > 0 {
                         f.write_str(" ")?;
                     }
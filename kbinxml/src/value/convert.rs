@@ -0,0 +1,268 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+
+use crate::error::{KbinError, Result};
+use crate::node_types::StandardType;
+use crate::value::Value;
+
+/// A scalar numeric value widened to a type that holds every kbin integer or
+/// float without loss.
+enum Number {
+    Integer(i128),
+    Float(f64),
+}
+
+impl Number {
+    fn from_value(value: &Value) -> Option<Self> {
+        let number = match *value {
+            Value::S8(n) => Number::Integer(n.into()),
+            Value::U8(n) => Number::Integer(n.into()),
+            Value::S16(n) => Number::Integer(n.into()),
+            Value::U16(n) => Number::Integer(n.into()),
+            Value::S32(n) => Number::Integer(n.into()),
+            Value::U32(n) => Number::Integer(n.into()),
+            Value::S64(n) => Number::Integer(n.into()),
+            Value::U64(n) => Number::Integer(n.into()),
+            Value::Time(n) => Number::Integer(n.into()),
+            Value::Boolean(n) => Number::Integer(n.into()),
+            Value::Float(n) => Number::Float(n.into()),
+            Value::Double(n) => Number::Float(n),
+            _ => return None,
+        };
+
+        Some(number)
+    }
+
+    fn to_integer(&self) -> Option<i128> {
+        match *self {
+            Number::Integer(n) => Some(n),
+            Number::Float(n) if n.is_finite() && n.fract() == 0.0 => {
+                // Out of range floats saturate, which the target range check rejects
+                Some(n as i128)
+            },
+            Number::Float(_) => None,
+        }
+    }
+
+    fn partial_cmp(&self, other: &Number) -> Option<Ordering> {
+        match (self, other) {
+            (Number::Integer(a), Number::Integer(b)) => Some(a.cmp(b)),
+            _ => self.to_float().partial_cmp(&other.to_float()),
+        }
+    }
+
+    fn to_float(&self) -> f64 {
+        match *self {
+            Number::Integer(n) => n as f64,
+            Number::Float(n) => n,
+        }
+    }
+
+    /// Convert to a numeric node type, returning `None` if the number is out
+    /// of range.
+    fn to_value(&self, node_type: StandardType) -> Option<Value> {
+        let value = match node_type {
+            StandardType::S8 => Value::S8(i8::try_from(self.to_integer()?).ok()?),
+            StandardType::U8 => Value::U8(u8::try_from(self.to_integer()?).ok()?),
+            StandardType::S16 => Value::S16(i16::try_from(self.to_integer()?).ok()?),
+            StandardType::U16 => Value::U16(u16::try_from(self.to_integer()?).ok()?),
+            StandardType::S32 => Value::S32(i32::try_from(self.to_integer()?).ok()?),
+            StandardType::U32 => Value::U32(u32::try_from(self.to_integer()?).ok()?),
+            StandardType::S64 => Value::S64(i64::try_from(self.to_integer()?).ok()?),
+            StandardType::U64 => Value::U64(u64::try_from(self.to_integer()?).ok()?),
+            StandardType::Time => Value::Time(u32::try_from(self.to_integer()?).ok()?),
+            StandardType::Boolean => match self.to_integer()? {
+                0 => Value::Boolean(false),
+                1 => Value::Boolean(true),
+                _ => return None,
+            },
+            StandardType::Float => {
+                let n = self.to_float();
+                if n.is_finite() && n.abs() > f64::from(f32::MAX) {
+                    return None;
+                }
+
+                Value::Float(n as f32)
+            },
+            StandardType::Double => Value::Double(self.to_float()),
+            _ => return None,
+        };
+
+        Some(value)
+    }
+}
+
+impl Value {
+    /// Convert the value to another node type, keeping arrays as arrays.
+    ///
+    /// Scalar numbers, times, and booleans convert directly and fail if the
+    /// value is out of range for the new type or, for floats converted to
+    /// integers, has a fractional part. Every other conversion goes through
+    /// the text representation used by text XML, so a string holding `"12"`
+    /// can become an `S32`, and tuples and arrays convert element by element.
+    pub fn retype(&self, node_type: StandardType) -> Result<Value> {
+        let conversion_error = || KbinError::InvalidValueConversion {
+            node_type,
+            value: self.clone(),
+        };
+
+        match node_type {
+            StandardType::Unknown |
+            StandardType::NodeStart |
+            StandardType::NodeEnd |
+            StandardType::FileEnd => return Err(KbinError::InvalidNodeType { node_type }),
+            _ => {},
+        };
+
+        if let Value::Array(values) = self {
+            if values.standard_type() == node_type {
+                return Ok(self.clone());
+            }
+
+            let count = values.len() * values.standard_type().count;
+            if node_type.count == 0 || count % node_type.count != 0 {
+                return Err(conversion_error());
+            }
+
+            let text = self.to_string();
+            return Value::from_string(node_type, &text, true, count / node_type.count)
+                .map_err(|_| conversion_error());
+        }
+
+        if self.standard_type() == node_type {
+            return Ok(self.clone());
+        }
+
+        if let Some(number) = Number::from_value(self) {
+            if is_numeric(node_type) {
+                return number.to_value(node_type).ok_or_else(conversion_error);
+            }
+        }
+
+        let text = match self {
+            Value::String(text) | Value::Attribute(text) => text.clone(),
+            value => value.to_string(),
+        };
+
+        Value::from_string(node_type, &text, false, 0).map_err(|_| conversion_error())
+    }
+
+    /// Compare two values by what they hold rather than by their node type.
+    ///
+    /// Scalar numbers, times, and booleans compare numerically whatever their
+    /// types, so a `U8` of 3 is less than an `S32` of 10, and strings compare
+    /// with attributes by their text. Other values only compare with values of
+    /// the same type, as with `partial_cmp`. Returns `None` for values that
+    /// cannot be compared, including NaN floats.
+    pub fn partial_cmp_value(&self, other: &Value) -> Option<Ordering> {
+        if let (Some(a), Some(b)) = (Number::from_value(self), Number::from_value(other)) {
+            return a.partial_cmp(&b);
+        }
+
+        match (self, other) {
+            (Value::String(a), Value::String(b)) |
+            (Value::String(a), Value::Attribute(b)) |
+            (Value::Attribute(a), Value::String(b)) |
+            (Value::Attribute(a), Value::Attribute(b)) => Some(a.cmp(b)),
+            _ => self.partial_cmp(other),
+        }
+    }
+}
+
+fn is_numeric(node_type: StandardType) -> bool {
+    matches!(
+        node_type,
+        StandardType::S8 |
+            StandardType::U8 |
+            StandardType::S16 |
+            StandardType::U16 |
+            StandardType::S32 |
+            StandardType::U32 |
+            StandardType::S64 |
+            StandardType::U64 |
+            StandardType::Time |
+            StandardType::Boolean |
+            StandardType::Float |
+            StandardType::Double
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::ValueArray;
+
+    #[test]
+    fn test_retype() {
+        assert_eq!(
+            Value::S32(300).retype(StandardType::U16).unwrap(),
+            Value::U16(300)
+        );
+        assert!(Value::S32(-1).retype(StandardType::U16).is_err());
+        assert!(Value::S32(70000).retype(StandardType::U16).is_err());
+        assert_eq!(
+            Value::Double(2.0).retype(StandardType::S8).unwrap(),
+            Value::S8(2)
+        );
+        assert!(Value::Double(2.5).retype(StandardType::S8).is_err());
+        assert_eq!(
+            Value::U8(1).retype(StandardType::Boolean).unwrap(),
+            Value::Boolean(true)
+        );
+
+        assert_eq!(
+            Value::String("12".into()).retype(StandardType::S32).unwrap(),
+            Value::S32(12)
+        );
+        assert_eq!(
+            Value::S32(12).retype(StandardType::String).unwrap(),
+            Value::String("12".into())
+        );
+        assert_eq!(
+            Value::S32_2([1, 2]).retype(StandardType::U8_2).unwrap(),
+            Value::U8_2([1, 2])
+        );
+
+        assert_eq!(
+            Value::Array(ValueArray::S32(vec![1, 2, 3, 4]))
+                .retype(StandardType::U16_2)
+                .unwrap(),
+            Value::Array(ValueArray::U16_2(vec![[1, 2], [3, 4]]))
+        );
+        assert!(Value::Array(ValueArray::S32(vec![1, 2, 3]))
+            .retype(StandardType::U16_2)
+            .is_err());
+    }
+
+    #[test]
+    fn test_partial_cmp_value() {
+        assert_eq!(
+            Value::U8(3).partial_cmp_value(&Value::S32(10)),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            Value::U64(u64::MAX).partial_cmp_value(&Value::S64(-1)),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(
+            Value::Double(2.0).partial_cmp_value(&Value::U16(2)),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(
+            Value::Float(f32::NAN).partial_cmp_value(&Value::U8(0)),
+            None
+        );
+        assert_eq!(
+            Value::String("b".into()).partial_cmp_value(&Value::Attribute("a".into())),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(
+            Value::String("1".into()).partial_cmp_value(&Value::S32(1)),
+            None
+        );
+
+        let mut ids = vec![Value::S32(20), Value::U8(3), Value::U16(7)];
+        ids.sort_by(|a, b| a.partial_cmp_value(b).unwrap());
+        assert_eq!(ids, vec![Value::U8(3), Value::U16(7), Value::S32(20)]);
+    }
+}
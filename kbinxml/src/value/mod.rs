@@ -7,13 +7,41 @@ use std::net::Ipv4Addr;
 use rustc_hex::FromHex;
 use snafu::ResultExt;
 
+use crate::encoding_type::EncodingType;
 use crate::error::*;
 use crate::node_types::StandardType;
-use crate::types::{FromKbinBytes, FromKbinString, IntoKbinBytes};
+use crate::types::{normalize_bool_words, FromKbinBytes, FromKbinString, IntoKbinBytes};
 
+mod arithmetic;
 mod array;
+mod convert;
+mod time;
 
 pub use self::array::ValueArray;
+pub(crate) use self::time::format_iso8601;
+
+/// Formats a float like its `Display` implementation, except NaN and infinity
+/// are written as the `nan`, `inf`, and `-inf` tokens used by C's `printf`.
+pub(crate) struct FloatText<T>(pub(crate) T);
+
+impl<T> fmt::Display for FloatText<T>
+where
+    T: fmt::Display + Copy + Into<f64>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let value: f64 = self.0.into();
+
+        if value.is_nan() {
+            f.write_str("nan")
+        } else if value == f64::INFINITY {
+            f.write_str("inf")
+        } else if value == f64::NEG_INFINITY {
+            f.write_str("-inf")
+        } else {
+            fmt::Display::fmt(&self.0, f)
+        }
+    }
+}
 
 macro_rules! construct_types {
   (
@@ -21,6 +49,9 @@ macro_rules! construct_types {
       ($konst:ident, $($value_type:tt)*);
     )+
   ) => {
+    /// The value of a node or attribute. Values never hold other nodes, a
+    /// subtree is always made of the children of a `Node` or
+    /// `NodeCollection`, so every value can be displayed and encoded.
     #[derive(Clone, PartialEq)]
     pub enum Value {
       $(
@@ -31,6 +62,10 @@ macro_rules! construct_types {
       Attribute(String),
 
       Array(ValueArray),
+
+      /// Raw data for a node type this crate does not know about. `type_id`
+      /// is the raw type byte, including the array flag.
+      Unknown { type_id: u8, data: Vec<u8> },
     }
 
     $(
@@ -83,6 +118,7 @@ macro_rules! construct_types {
           Value::Time(_) => StandardType::Time,
           Value::Attribute(_) => StandardType::Attribute,
           Value::Array(ref value) => value.standard_type(),
+          Value::Unknown { .. } => StandardType::Unknown,
         }
       }
     }
@@ -119,6 +155,7 @@ macro_rules! tuple {
       let mut reader = Cursor::new(input);
 
       let value = match node_type {
+        StandardType::Unknown |
         StandardType::NodeStart |
         StandardType::NodeEnd |
         StandardType::FileEnd |
@@ -163,6 +200,7 @@ macro_rules! tuple {
       }
 
       let value = match node_type {
+        StandardType::Unknown |
         StandardType::NodeStart |
         StandardType::NodeEnd |
         StandardType::FileEnd => return Err(KbinError::InvalidNodeType { node_type }),
@@ -181,7 +219,10 @@ macro_rules! tuple {
         StandardType::String => Value::String(input.to_owned()),
         StandardType::Attribute => Value::Attribute(input.to_owned()),
         StandardType::Ip4 => Ipv4Addr::from_kbin_string(input).map(Value::Ip4)?,
-        StandardType::Time => u32::from_kbin_string(input).map(Value::Time)?,
+        StandardType::Time => match time::parse_iso8601(input) {
+          Some(secs) => Value::Time(secs),
+          None => u32::from_kbin_string(input).map(Value::Time)?,
+        },
         StandardType::Float => f32::from_kbin_string(input).map(Value::Float)?,
         StandardType::Double => f64::from_kbin_string(input).map(Value::Double)?,
         StandardType::Boolean => bool::from_kbin_string(input).map(Value::Boolean)?,
@@ -194,6 +235,21 @@ macro_rules! tuple {
       Ok(value)
     }
 
+    /// Like `from_string`, also accepting `true` and `false` in any letter case
+    /// for boolean types, as used by hand-written documents.
+    pub fn from_string_lenient(node_type: StandardType, input: &str, is_array: bool, arr_count: usize) -> Result<Value> {
+      let input = match node_type {
+        StandardType::Boolean |
+        StandardType::Boolean2 |
+        StandardType::Boolean3 |
+        StandardType::Boolean4 |
+        StandardType::Vb => normalize_bool_words(input),
+        _ => Cow::Borrowed(input),
+      };
+
+      Self::from_string(node_type, &input, is_array, arr_count)
+    }
+
     fn to_bytes_inner(&self, output: &mut Vec<u8>) -> Result<()> {
       debug!("Value::to_bytes_inner(self: {:?})", self);
 
@@ -206,7 +262,8 @@ macro_rules! tuple {
         Value::U32(n) => n.write_kbin_bytes(output),
         Value::S64(n) => n.write_kbin_bytes(output),
         Value::U64(n) => n.write_kbin_bytes(output),
-        Value::Binary(data) => output.extend_from_slice(data),
+        Value::Binary(data) |
+        Value::Unknown { data, .. } => output.extend_from_slice(data),
         Value::Time(n) => n.write_kbin_bytes(output),
         Value::Ip4(addr) => addr.write_kbin_bytes(output),
         Value::Float(n) => n.write_kbin_bytes(output),
@@ -243,6 +300,13 @@ impl Value {
         Double2, Double3, Double4,
     }
 
+    /// Construct an array of `node_type` from single values, checking that
+    /// every value has that type. Arrays, strings, binary data, and
+    /// attributes cannot be elements.
+    pub fn array(node_type: StandardType, values: Vec<Value>) -> Result<Value> {
+        ValueArray::from_values(node_type, values).map(Value::Array)
+    }
+
     pub fn to_bytes(&self) -> Result<Vec<u8>> {
         let mut output = Vec::new();
         self.to_bytes_inner(&mut output)?;
@@ -385,6 +449,82 @@ impl Value {
         }
     }
 
+    /// The number of bytes of the value in the data buffer of a binary
+    /// document with `encoding`, without the length prefix of strings,
+    /// binary data, and arrays, or any padding. Strings include their
+    /// trailing null byte.
+    pub fn encoded_len(&self, encoding: EncodingType) -> Result<usize> {
+        match self {
+            Value::String(text) | Value::Attribute(text) => Ok(encoding.encoded_len(text)? + 1),
+            Value::Binary(data) | Value::Unknown { data, .. } => Ok(data.len()),
+            Value::Array(values) => {
+                let node_type = values.standard_type();
+                Ok(values.len() * node_type.size * node_type.count)
+            },
+            value => {
+                let node_type = value.standard_type();
+                Ok(node_type.size * node_type.count)
+            },
+        }
+    }
+
+    /// Shorten a string or attribute value so its `encoded_len` in `encoding`,
+    /// including the trailing null byte, is at most `max_bytes`. The text is
+    /// cut between characters, so a multi-byte character is never split.
+    /// Returns whether the text was shortened.
+    pub fn truncate_string_to_bytes(
+        &mut self,
+        max_bytes: usize,
+        encoding: EncodingType,
+    ) -> Result<bool> {
+        let text = match self {
+            Value::String(text) | Value::Attribute(text) => text,
+            value => {
+                return Err(KbinError::ValueTypeMismatch {
+                    node_type: StandardType::String,
+                    value: value.clone(),
+                })
+            },
+        };
+
+        // Leave room for the null byte
+        let max_bytes = max_bytes.saturating_sub(1);
+        if encoding.encoded_len(text)? <= max_bytes {
+            return Ok(false);
+        }
+
+        let mut len = 0;
+        let mut end = 0;
+        let mut buf = [0; 4];
+        for (i, ch) in text.char_indices() {
+            len += encoding.encoded_len(ch.encode_utf8(&mut buf))?;
+            if len > max_bytes {
+                break;
+            }
+            end = i + ch.len_utf8();
+        }
+        text.truncate(end);
+
+        Ok(true)
+    }
+
+    /// Returns `false` if any floating point component of the value is NaN or
+    /// infinite.
+    pub fn is_finite(&self) -> bool {
+        match self {
+            Value::Float(n) => n.is_finite(),
+            Value::Double(n) => n.is_finite(),
+            Value::Float2(v) => v.iter().all(|n| n.is_finite()),
+            Value::Float3(v) => v.iter().all(|n| n.is_finite()),
+            Value::Float4(v) => v.iter().all(|n| n.is_finite()),
+            Value::Double2(v) => v.iter().all(|n| n.is_finite()),
+            Value::Double3(v) => v.iter().all(|n| n.is_finite()),
+            Value::Double4(v) => v.iter().all(|n| n.is_finite()),
+            Value::Array(values) => values.is_finite(),
+            _ => true,
+        }
+    }
+
     pub fn as_array(&self) -> Result<&ValueArray> {
         match self {
             Value::Array(ref values) => Ok(values),
@@ -477,6 +617,26 @@ impl TryFrom<&Value> for Cow<'_, str> {
     }
 }
 
+impl TryFrom<Value> for char {
+    type Error = KbinError;
+
+    fn try_from(value: Value) -> Result<Self> {
+        Self::try_from(&value)
+    }
+}
+
+/// A string holding exactly one character.
+impl TryFrom<&Value> for char {
+    type Error = KbinError;
+
+    fn try_from(value: &Value) -> Result<Self> {
+        value.as_str()?.parse().map_err(|e| KbinError::StringParse {
+            node_type: "char",
+            source: Box::new(e),
+        })
+    }
+}
+
 impl From<Vec<u8>> for Value {
     fn from(value: Vec<u8>) -> Value {
         Value::Binary(value)
@@ -498,6 +658,7 @@ impl fmt::Debug for Value {
                         Value::$konst_debug(ref v) => write!(f, concat!(stringify!($konst_debug), "({:?})"), v),
                     )*
                     Value::Binary(ref v) => write!(f, "Binary(0x{:02x?})", v),
+                    Value::Unknown { type_id, ref data } => write!(f, "Unknown({}, 0x{:02x?})", type_id, data),
                     Value::Array(ref value) => if f.alternate() {
                         write!(f, "Array({:#?})", value)
                     } else {
@@ -562,14 +723,22 @@ impl fmt::Display for Value {
                             },
                         )*
                     )*
-                    Value::Binary(buf) => {
+                    Value::Binary(buf) |
+                    Value::Unknown { data: buf, .. } => {
                         for n in buf {
                             write!(f, "{:02x}", n)?;
                         }
                         Ok(())
                     },
-                    Value::Float(n) => write!(f, "{:.6}", n),
-                    Value::Double(n) => write!(f, "{:.6}", n),
+                    #[cfg(not(feature = "exact-floats"))]
+                    Value::Float(n) => write!(f, "{:.6}", FloatText(*n)),
+                    #[cfg(not(feature = "exact-floats"))]
+                    Value::Double(n) => write!(f, "{:.6}", FloatText(*n)),
+                    // Shortest representation that parses back to the same value
+                    #[cfg(feature = "exact-floats")]
+                    Value::Float(n) => fmt::Display::fmt(&FloatText(*n), f),
+                    #[cfg(feature = "exact-floats")]
+                    Value::Double(n) => fmt::Display::fmt(&FloatText(*n), f),
                     Value::Boolean(b) => match b {
                         true => f.write_str("1"),
                         false => f.write_str("0"),
@@ -656,3 +825,166 @@ construct_types! {
     (Boolean4, [bool; 4]);
     (Vb,       [bool; 16]);
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::node::Node;
+    use crate::options::ReaderOptions;
+
+    use super::*;
+
+    #[test]
+    fn test_array_constructor() {
+        let value = Value::array(StandardType::U16, vec![Value::U16(1), Value::U16(2)]).unwrap();
+        assert_eq!(value, Value::Array(ValueArray::U16(vec![1, 2])));
+
+        let value = Value::array(StandardType::Float2, Vec::new()).unwrap();
+        assert_eq!(value, Value::Array(ValueArray::Float2(Vec::new())));
+
+        assert!(matches!(
+            Value::array(StandardType::U16, vec![Value::U16(1), Value::U32(2)]),
+            Err(KbinError::ValueTypeMismatch { .. })
+        ));
+        assert!(matches!(
+            Value::array(StandardType::U16, vec![value]),
+            Err(KbinError::ValueTypeMismatch { .. })
+        ));
+        assert!(matches!(
+            Value::array(StandardType::Attribute, vec![Value::Attribute("a".into())]),
+            Err(KbinError::InvalidNodeType { .. })
+        ));
+    }
+
+    #[test]
+    fn test_array_editing() {
+        let mut array = ValueArray::U8_2(vec![[1, 2], [3, 4], [5, 6]]);
+
+        array.set(1, Value::U8_2([7, 8])).unwrap();
+        assert!(matches!(
+            array.set(1, Value::U8(7)),
+            Err(KbinError::ValueTypeMismatch { .. })
+        ));
+        assert!(matches!(
+            array.set(3, Value::U8_2([0, 0])),
+            Err(KbinError::ArrayIndexOutOfRange { index: 3, len: 3 })
+        ));
+
+        array.truncate(2);
+        assert_eq!(array, ValueArray::U8_2(vec![[1, 2], [7, 8]]));
+
+        array.resize_with(4, || Value::U8_2([0, 0])).unwrap();
+        assert_eq!(
+            array,
+            ValueArray::U8_2(vec![[1, 2], [7, 8], [0, 0], [0, 0]])
+        );
+        assert!(array.resize_with(5, || Value::U16_2([0, 0])).is_err());
+        assert_eq!(array.len(), 4);
+
+        array.resize_with(1, || unreachable!()).unwrap();
+        assert_eq!(array, ValueArray::U8_2(vec![[1, 2]]));
+    }
+
+    #[test]
+    fn test_non_finite_floats() {
+        let value = Value::Double3([f64::NAN, f64::INFINITY, f64::NEG_INFINITY]);
+        assert_eq!(value.to_string(), "nan inf -inf");
+        assert!(!value.is_finite());
+
+        let value =
+            Value::from_string(StandardType::Float2, "1.#INF00 -1.#IND00", false, 0).unwrap();
+        match value {
+            Value::Float2([a, b]) => assert!(a == f32::INFINITY && b.is_nan()),
+            value => panic!("Unexpected value: {:?}", value),
+        };
+
+        let node = Node::with_value("f", Value::Float(f32::NEG_INFINITY));
+        let text = crate::to_text_xml(&node).unwrap();
+        let (collection, _) = crate::from_text_xml(&text).unwrap();
+        assert_eq!(collection.as_node().unwrap(), node);
+
+        let mut options = ReaderOptions::builder();
+        options.deny_non_finite_floats(true);
+        assert!(crate::from_text_xml_with_options(options.build(), &text).is_err());
+    }
+
+    #[test]
+    fn test_lenient_booleans() {
+        let text = br#"<root><a __type="bool">TRUE</a><b __type="2b">False true</b></root>"#;
+        assert!(crate::from_text_xml(text).is_err());
+
+        let mut options = ReaderOptions::builder();
+        options.lenient_booleans(true);
+        let (collection, _) = crate::from_text_xml_with_options(options.build(), text).unwrap();
+        let node = collection.as_node().unwrap();
+        assert_eq!(
+            node.get_child("a").unwrap().value(),
+            Some(&Value::Boolean(true))
+        );
+        assert_eq!(
+            node.get_child("b").unwrap().value(),
+            Some(&Value::Boolean2([false, true]))
+        );
+
+        let from_string = Value::from_string_lenient;
+        assert_eq!(
+            from_string(StandardType::Boolean, "False", false, 0).unwrap(),
+            Value::Boolean(false)
+        );
+        assert_eq!(
+            from_string(StandardType::Boolean, "TRUE false 1", true, 3).unwrap(),
+            Value::Array(ValueArray::Boolean(vec![true, false, true]))
+        );
+        assert!(Value::from_string(StandardType::Boolean, "TRUE", false, 0).is_err());
+        assert!(from_string(StandardType::U8, "true", false, 0).is_err());
+    }
+
+    #[test]
+    fn test_encoded_len() {
+        for value in &[
+            Value::U8(1),
+            Value::S64(-1),
+            Value::Ip4(Ipv4Addr::LOCALHOST),
+            Value::Float3([1.0, 2.0, 3.0]),
+            Value::Binary(vec![1, 2, 3]),
+            Value::array(StandardType::U16, vec![Value::U16(1), Value::U16(2)]).unwrap(),
+        ] {
+            let len = value.to_bytes().unwrap().len();
+            assert_eq!(value.encoded_len(EncodingType::UTF_8).unwrap(), len);
+        }
+
+        let text = Value::String("\u{30c6}st".into());
+        assert_eq!(text.encoded_len(EncodingType::UTF_8).unwrap(), 6);
+        assert_eq!(text.encoded_len(EncodingType::SHIFT_JIS).unwrap(), 5);
+        assert!(text.encoded_len(EncodingType::ASCII).is_err());
+    }
+
+    #[test]
+    fn test_truncate_string_to_bytes() {
+        // Two bytes per character in Shift-JIS, three in UTF-8
+        let text = Value::String("\u{30c6}\u{30b9}\u{30c8}".into());
+
+        let mut value = text.clone();
+        assert!(value
+            .truncate_string_to_bytes(6, EncodingType::SHIFT_JIS)
+            .unwrap());
+        assert_eq!(value, Value::String("\u{30c6}\u{30b9}".into()));
+        assert_eq!(value.encoded_len(EncodingType::SHIFT_JIS).unwrap(), 5);
+
+        let mut value = text.clone();
+        assert!(value.truncate_string_to_bytes(6, EncodingType::UTF_8).unwrap());
+        assert_eq!(value, Value::String("\u{30c6}".into()));
+
+        let mut value = text.clone();
+        assert!(!value
+            .truncate_string_to_bytes(7, EncodingType::SHIFT_JIS)
+            .unwrap());
+        assert_eq!(value, text);
+
+        let mut value = Value::Attribute("abc".into());
+        assert!(value.truncate_string_to_bytes(0, EncodingType::ASCII).unwrap());
+        assert_eq!(value, Value::Attribute(String::new()));
+        assert!(Value::U8(1)
+            .truncate_string_to_bytes(1, EncodingType::UTF_8)
+            .is_err());
+    }
+}
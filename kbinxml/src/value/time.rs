@@ -0,0 +1,192 @@
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::{KbinError, Result};
+use crate::node_types::StandardType;
+use crate::value::Value;
+
+// Conversions between days since the Unix epoch and proleptic Gregorian
+// dates, using Howard Hinnant's `civil_from_days` and `days_from_civil`.
+
+fn days_to_civil(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+fn civil_to_days(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146_097 + doe - 719_468
+}
+
+/// Format seconds since the Unix epoch as an ISO-8601 UTC timestamp, such as
+/// `2020-01-02T03:04:05Z`.
+pub(crate) fn format_iso8601(secs: u32) -> String {
+    let secs = i64::from(secs);
+    let (year, month, day) = days_to_civil(secs.div_euclid(86400));
+    let time = secs.rem_euclid(86400);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// Parse a UTC timestamp in the form written by `format_iso8601`. A space is
+/// accepted in place of the `T` separator and the trailing `Z` is optional.
+pub(crate) fn parse_iso8601(input: &str) -> Option<u32> {
+    let input = input.strip_suffix('Z').unwrap_or(input);
+    if input.len() != 19 || !input.is_char_boundary(10) || !input.is_char_boundary(11) {
+        return None;
+    }
+    let (date, time) = (&input[..10], &input[11..]);
+    if !matches!(&input[10..11], "T" | " ") {
+        return None;
+    }
+
+    let mut date = date.splitn(3, '-');
+    let year: i64 = date.next()?.parse().ok()?;
+    let month: u32 = date.next()?.parse().ok()?;
+    let day: u32 = date.next()?.parse().ok()?;
+
+    let mut time = time.splitn(3, ':');
+    let hour: i64 = time.next()?.parse().ok()?;
+    let minute: i64 = time.next()?.parse().ok()?;
+    let second: i64 = time.next()?.parse().ok()?;
+
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    if hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+
+    // Reject days past the end of the month by checking the date round-trips
+    let days = civil_to_days(year, month, day);
+    if days_to_civil(days) != (year, month, day) {
+        return None;
+    }
+
+    let secs = days * 86400 + hour * 3600 + minute * 60 + second;
+    u32::try_from(secs).ok()
+}
+
+impl TryFrom<SystemTime> for Value {
+    type Error = KbinError;
+
+    /// Convert to a `Time` value, failing for times before the Unix epoch or
+    /// after the range of a `u32` number of seconds. Fractional seconds are
+    /// truncated.
+    fn try_from(time: SystemTime) -> Result<Self> {
+        time.duration_since(UNIX_EPOCH)
+            .ok()
+            .and_then(|duration| u32::try_from(duration.as_secs()).ok())
+            .map(Value::Time)
+            .ok_or(KbinError::TimeOutOfRange)
+    }
+}
+
+impl TryFrom<&Value> for SystemTime {
+    type Error = KbinError;
+
+    fn try_from(value: &Value) -> Result<Self> {
+        match value {
+            Value::Time(secs) => Ok(UNIX_EPOCH + Duration::from_secs(u64::from(*secs))),
+            value => Err(KbinError::ValueTypeMismatch {
+                node_type: StandardType::Time,
+                value: value.clone(),
+            }),
+        }
+    }
+}
+
+#[cfg(feature = "chrono")]
+mod chrono_impl {
+    use std::convert::TryFrom;
+
+    use chrono::{DateTime, TimeZone, Utc};
+
+    use crate::error::{KbinError, Result};
+    use crate::node_types::StandardType;
+    use crate::value::Value;
+
+    impl<Tz: TimeZone> TryFrom<DateTime<Tz>> for Value {
+        type Error = KbinError;
+
+        fn try_from(time: DateTime<Tz>) -> Result<Self> {
+            u32::try_from(time.timestamp())
+                .map(Value::Time)
+                .map_err(|_| KbinError::TimeOutOfRange)
+        }
+    }
+
+    impl TryFrom<&Value> for DateTime<Utc> {
+        type Error = KbinError;
+
+        fn try_from(value: &Value) -> Result<Self> {
+            match value {
+                Value::Time(secs) => Utc
+                    .timestamp_opt(i64::from(*secs), 0)
+                    .single()
+                    .ok_or(KbinError::TimeOutOfRange),
+                value => Err(KbinError::ValueTypeMismatch {
+                    node_type: StandardType::Time,
+                    value: value.clone(),
+                }),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_iso8601() {
+        assert_eq!(format_iso8601(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_iso8601(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_iso8601(u32::MAX), "2106-02-07T06:28:15Z");
+
+        for &secs in &[0, 951_782_400, 1_577_934_245, u32::MAX] {
+            assert_eq!(parse_iso8601(&format_iso8601(secs)), Some(secs));
+        }
+        assert_eq!(parse_iso8601("2020-01-02 03:04:05"), Some(1_577_934_245));
+        assert_eq!(parse_iso8601("2021-02-29T00:00:00Z"), None);
+        assert_eq!(parse_iso8601("1969-12-31T23:59:59Z"), None);
+        assert_eq!(parse_iso8601("1577934245"), None);
+
+        let time = SystemTime::try_from(&Value::Time(10)).unwrap();
+        assert_eq!(Value::try_from(time).unwrap(), Value::Time(10));
+        assert!(Value::try_from(UNIX_EPOCH - Duration::from_secs(1)).is_err());
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_chrono() {
+        use chrono::{DateTime, Utc};
+
+        let time = DateTime::<Utc>::try_from(&Value::Time(1_577_934_245)).unwrap();
+        assert_eq!(time.to_rfc3339(), "2020-01-02T03:04:05+00:00");
+        assert_eq!(Value::try_from(time).unwrap(), Value::Time(1_577_934_245));
+    }
+}
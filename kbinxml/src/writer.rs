@@ -1,20 +1,25 @@
-use std::io::{self, Cursor, Write};
+use std::io::{self, Write};
+use std::mem;
 
-use byteorder::{BigEndian, WriteBytesExt};
+use byteorder::WriteBytesExt;
 use snafu::{ResultExt, Snafu};
 
 use crate::byte_buffer::{ByteBufferError, ByteBufferWrite};
 use crate::compression_type::CompressionType;
+use crate::custom_type::CustomType;
 use crate::encoding_type::{EncodingError, EncodingType};
+use crate::events::Event;
 use crate::node::{Node, NodeCollection};
 use crate::node_types::StandardType;
-use crate::options::Options;
+use crate::options::{Endianness, Options};
+use crate::progress::{Progress, ProgressPhase, ProgressSink};
 use crate::sixbit::{Sixbit, SixbitError};
 use crate::value::Value;
 
 use super::{ARRAY_MASK, SIGNATURE};
 
 #[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
 pub enum WriterError {
     #[snafu(display("Failed to write signature to header"))]
     Signature { source: io::Error },
@@ -54,12 +59,21 @@ pub enum WriterError {
     #[snafu(display("Failed to write sixbit node name"))]
     NodeSixbitName { source: SixbitError },
 
+    #[snafu(display("Name at `{}` must be written uncompressed", path))]
+    UncompressedNameRequired { path: String },
+
     #[snafu(display("Failed to encode uncompressed node name to {:?}", encoding))]
     NodeUncompressedNameEncode {
         encoding: EncodingType,
         source: EncodingError,
     },
 
+    #[snafu(display(
+        "Uncompressed node name of {} bytes is not between 1 and 64 bytes long",
+        len
+    ))]
+    UncompressedNameLength { len: usize },
+
     #[snafu(display("Failed to write uncompressed node name length"))]
     NodeUncompressedNameLength { source: io::Error },
 
@@ -108,11 +122,32 @@ pub enum WriterError {
         source: Box<crate::KbinError>,
     },
 
+    #[snafu(display(
+        "Attempted to write value array for node type {} but the node was not marked as an array",
+        node_type
+    ))]
+    UnexpectedValueArray { node_type: StandardType },
+
+    #[snafu(display(
+        "Attempted to write non-array value for node type {} but the node was marked as an array",
+        node_type
+    ))]
+    ExpectedValueArray { node_type: StandardType },
+
     #[snafu(display("Attempted to write node definition without key data"))]
     NoNodeKey,
 
     #[snafu(display("Attempted to write node definition without value data"))]
     NoNodeValue,
+
+    #[snafu(display("Attempted to write {} outside of a node", node_type))]
+    NoOpenNode { node_type: StandardType },
+
+    #[snafu(display("Attempted to write more than one root node"))]
+    MultipleRoots,
+
+    #[snafu(display("Attempted to finish the document with {} open node(s)", count))]
+    UnclosedNodes { count: usize },
 }
 
 fn write_value(
@@ -128,22 +163,31 @@ fn write_value(
 
             // TODO: add overflow check
             let size = (data.len() * node_type.size) as u32;
-            data_buf
-                .write_u32::<BigEndian>(size)
+            options
+                .endianness
+                .write_u32(&mut **data_buf, size)
                 .context(NodeSize { node_type, size })?;
-            data_buf.write_all(&data).context(DataWrite { node_type })?;
+            data_buf.write_all(data).context(DataWrite { node_type })?;
             data_buf
                 .realign_writes(None)
                 .context(DataBuffer { node_type })?;
         },
         Value::String(text) => {
             data_buf
-                .write_str(options.encoding, &text)
+                .write_str(options.encoding, text)
                 .context(DataBuffer { node_type })?;
         },
+        Value::Unknown { type_id, data } => {
+            match CustomType::find(&options.custom_types, *type_id) {
+                Some(custom_type) if !is_array => data_buf
+                    .write_aligned_size(node_type, custom_type.value_size(), data)
+                    .context(DataBuffer { node_type })?,
+                _ => data_buf.buf_write(data).context(DataBuffer { node_type })?,
+            };
+        },
         Value::Array(values) => {
             if !is_array {
-                panic!("Attempted to write value array but was not marked as array");
+                return Err(WriterError::UnexpectedValueArray { node_type });
             }
 
             let total_size = values.len() * node_type.count * node_type.size;
@@ -152,9 +196,11 @@ fn write_value(
             values
                 .to_bytes_into(&mut data)
                 .context(ValueEncode { node_type })?;
+            options.endianness.swap_value_bytes(node_type, &mut data);
 
-            data_buf
-                .write_u32::<BigEndian>(total_size as u32)
+            options
+                .endianness
+                .write_u32(&mut **data_buf, total_size as u32)
                 .context(NodeSize {
                     node_type,
                     size: total_size as u32,
@@ -166,10 +212,11 @@ fn write_value(
         },
         value => {
             if is_array {
-                panic!("Attempted to write non-array value but was marked as array");
+                return Err(WriterError::ExpectedValueArray { node_type });
             }
 
-            let data = value.to_bytes().context(ValueEncode { node_type })?;
+            let mut data = value.to_bytes().context(ValueEncode { node_type })?;
+            options.endianness.swap_value_bytes(node_type, &mut data);
             data_buf
                 .write_aligned(node_type, &data)
                 .context(DataBuffer { node_type })?;
@@ -179,6 +226,107 @@ fn write_value(
     Ok(())
 }
 
+/// Write a node name to the node buffer, as sixbit or in the document
+/// encoding depending on the compression. `path` is the path of the node or
+/// attribute named `key`, for the `uncompressed_names` option.
+fn write_key(
+    options: &Options,
+    node_buf: &mut ByteBufferWrite,
+    path: &str,
+    key: &str,
+) -> Result<(), WriterError> {
+    match options.compression {
+        CompressionType::Compressed
+            if options
+                .uncompressed_names
+                .as_ref()
+                .is_some_and(|keep| keep.matches(path)) =>
+        {
+            return Err(WriterError::UncompressedNameRequired {
+                path: path.to_owned(),
+            });
+        },
+        CompressionType::Compressed => {
+            Sixbit::pack(&mut **node_buf, key).context(NodeSixbitName)?
+        },
+        CompressionType::Uncompressed => {
+            // Names are written without the trailing null byte of strings
+            let mut data = options
+                .encoding
+                .encode_bytes(key)
+                .context(NodeUncompressedNameEncode {
+                    encoding: options.encoding,
+                })?;
+            data.pop();
+
+            // The length is stored minus one in the six bits below the array
+            // flag
+            if data.is_empty() || data.len() > 64 {
+                return Err(WriterError::UncompressedNameLength { len: data.len() });
+            }
+            let len = (data.len() - 1) as u8;
+            node_buf
+                .write_u8(len | ARRAY_MASK)
+                .context(NodeUncompressedNameLength)?;
+            node_buf
+                .write_all(&data)
+                .context(NodeUncompressedNameData)?;
+        },
+    };
+
+    Ok(())
+}
+
+/// The node type, array flag, and type byte of a node holding `value`.
+fn value_type(value: Option<&Value>) -> (StandardType, bool, u8) {
+    let (node_type, is_array) = match value {
+        Some(Value::Array(ref values)) => (values.standard_type(), true),
+        Some(Value::Unknown { type_id, .. }) => {
+            (StandardType::Unknown, type_id & ARRAY_MASK == ARRAY_MASK)
+        },
+        Some(value) => (value.standard_type(), false),
+        None => (StandardType::NodeStart, false),
+    };
+    let type_id = match value {
+        Some(Value::Unknown { type_id, .. }) => *type_id,
+        _ if is_array => node_type as u8 | ARRAY_MASK,
+        _ => node_type as u8,
+    };
+
+    (node_type, is_array, type_id)
+}
+
+/// Join the header, node buffer, and data buffer into a document.
+pub(crate) fn assemble(
+    compression: CompressionType,
+    encoding: EncodingType,
+    endianness: Endianness,
+    node_buf: &[u8],
+    data_buf: &[u8],
+) -> Result<Vec<u8>, WriterError> {
+    let mut output = Vec::with_capacity(12 + node_buf.len() + data_buf.len());
+    output.write_u8(SIGNATURE).context(Signature)?;
+    output
+        .write_u8(compression.to_byte())
+        .context(Compression)?;
+
+    let encoding = encoding.to_byte();
+    output.write_u8(encoding).context(Encoding)?;
+    output.write_u8(0xFF ^ encoding).context(EncodingNegate)?;
+
+    endianness
+        .write_u32(&mut output, node_buf.len() as u32)
+        .context(NodeBufferLength)?;
+    output.extend_from_slice(node_buf);
+
+    endianness
+        .write_u32(&mut output, data_buf.len() as u32)
+        .context(DataBufferLength)?;
+    output.extend_from_slice(data_buf);
+
+    Ok(output)
+}
+
 pub trait Writeable {
     fn write_node(
         &self,
@@ -186,23 +334,78 @@ pub trait Writeable {
         node_buf: &mut ByteBufferWrite,
         data_buf: &mut ByteBufferWrite,
     ) -> Result<(), WriterError>;
+
+    /// Like `write_node`, also counting every node written in `progress`.
+    fn write_node_with_progress(
+        &self,
+        options: &Options,
+        node_buf: &mut ByteBufferWrite,
+        data_buf: &mut ByteBufferWrite,
+        _progress: &mut Progress<'_>,
+    ) -> Result<(), WriterError> {
+        self.write_node(options, node_buf, data_buf)
+    }
+}
+
+/// The number of bytes written to the buffers so far.
+fn buffers_len(node_buf: &ByteBufferWrite, data_buf: &ByteBufferWrite) -> usize {
+    node_buf.get_ref().len() + data_buf.get_ref().len()
 }
 
 impl Writeable for NodeCollection {
+    #[inline]
     fn write_node(
         &self,
         options: &Options,
         node_buf: &mut ByteBufferWrite,
         data_buf: &mut ByteBufferWrite,
+    ) -> Result<(), WriterError> {
+        self.write_node_with_progress(options, node_buf, data_buf, &mut Progress::none())
+    }
+
+    #[inline]
+    fn write_node_with_progress(
+        &self,
+        options: &Options,
+        node_buf: &mut ByteBufferWrite,
+        data_buf: &mut ByteBufferWrite,
+        progress: &mut Progress<'_>,
+    ) -> Result<(), WriterError> {
+        self.write_node_at(options, &mut String::new(), node_buf, data_buf, progress)
+    }
+}
+
+impl NodeCollection {
+    /// Write this collection as a child of the node at `path`. The names of
+    /// this collection and its children are added to `path` while they are
+    /// written.
+    fn write_node_at(
+        &self,
+        options: &Options,
+        path: &mut String,
+        node_buf: &mut ByteBufferWrite,
+        data_buf: &mut ByteBufferWrite,
+        progress: &mut Progress<'_>,
     ) -> Result<(), WriterError> {
         let (node_type, is_array) = self.base().node_type_tuple();
-        let array_mask = if is_array { ARRAY_MASK } else { 0 };
         let name = self
             .base()
             .key()
             .context(DefinitionValue { node_type })?
             .ok_or(WriterError::NoNodeKey)?;
 
+        // Offsets are relative to the start of each buffer
+        #[cfg(feature = "tracing")]
+        let _span = debug_span!(
+            "node",
+            key = name.as_str(),
+            ?node_type,
+            is_array,
+            node_offset = node_buf.position(),
+            data_offset = data_buf.position()
+        )
+        .entered();
+
         debug!("NodeCollection write_node => name: {}, type: {:?}, type_size: {}, type_count: {}, is_array: {}",
             name,
             node_type,
@@ -211,82 +414,70 @@ impl Writeable for NodeCollection {
             is_array);
 
         node_buf
-            .write_u8(node_type as u8 | array_mask)
+            .write_u8(self.base().type_id())
             .context(DataWrite { node_type })?;
 
-        match options.compression {
-            CompressionType::Compressed => {
-                Sixbit::pack(&mut **node_buf, &name).context(NodeSixbitName)?
-            },
-            CompressionType::Uncompressed => {
-                let data =
-                    options
-                        .encoding
-                        .encode_bytes(&name)
-                        .context(NodeUncompressedNameEncode {
-                            encoding: options.encoding,
-                        })?;
-                let len = (data.len() - 1) as u8;
-                node_buf
-                    .write_u8(len | ARRAY_MASK)
-                    .context(NodeUncompressedNameLength)?;
-                node_buf
-                    .write_all(&data)
-                    .context(NodeUncompressedNameData)?;
-            },
-        };
+        let parent_len = path.len();
+        path.push('/');
+        path.push_str(&name);
+        write_key(options, node_buf, path, &name)?;
 
         if node_type != StandardType::NodeStart {
             let value = self.base().value().context(DefinitionValue { node_type })?;
             write_value(options, data_buf, node_type, is_array, &value)?;
         }
 
+        let mut attributes = Vec::with_capacity(self.attributes().len());
         for attr in self.attributes() {
-            let node_type = StandardType::Attribute;
             let key = attr
                 .key()
-                .context(DefinitionKey { node_type })?
+                .context(DefinitionKey {
+                    node_type: StandardType::Attribute,
+                })?
                 .ok_or(WriterError::NoNodeKey)?;
-            let value = attr.value_bytes().ok_or(WriterError::NoNodeValue)?;
+            let value = match attr.value().context(DefinitionValue {
+                node_type: StandardType::Attribute,
+            })? {
+                Value::Attribute(value) => value,
+                _ => return Err(WriterError::NoNodeValue),
+            };
+
+            attributes.push((key, value));
+        }
+        options.attribute_order().sort(&mut attributes);
+
+        for (key, value) in attributes {
+            let node_type = StandardType::Attribute;
 
             trace!(
-                "NodeCollection write_node => attr: {}, value: 0x{:02x?}",
+                "NodeCollection write_node => attr: {}, value: {}",
                 key,
                 value
             );
 
+            // Values read from text are stored as UTF-8, so re-encode them
             data_buf
-                .buf_write(value)
+                .write_str(options.encoding, &value)
                 .context(DataBuffer { node_type })?;
 
             node_buf
                 .write_u8(StandardType::Attribute as u8)
                 .context(DataWrite { node_type })?;
 
-            match options.compression {
-                CompressionType::Compressed => {
-                    Sixbit::pack(&mut **node_buf, &key).context(NodeSixbitName)?
-                },
-                CompressionType::Uncompressed => {
-                    let data = options.encoding.encode_bytes(&key).context(
-                        NodeUncompressedNameEncode {
-                            encoding: options.encoding,
-                        },
-                    )?;
-                    let len = (data.len() - 1) as u8;
-                    node_buf
-                        .write_u8(len | ARRAY_MASK)
-                        .context(NodeUncompressedNameLength)?;
-                    node_buf
-                        .write_all(&data)
-                        .context(NodeUncompressedNameData)?;
-                },
-            };
+            let node_len = path.len();
+            path.push_str("/@");
+            path.push_str(&key);
+            write_key(options, node_buf, path, &key)?;
+            path.truncate(node_len);
         }
 
+        // Binary documents cannot hold comments
         for child in self.children() {
-            child.write_node(options, node_buf, data_buf)?;
+            if !child.base().is_markup() {
+                child.write_node_at(options, path, node_buf, data_buf, progress)?;
+            }
         }
+        path.truncate(parent_len);
 
         // node end always has the array bit set
         node_buf
@@ -294,24 +485,58 @@ impl Writeable for NodeCollection {
             .context(NodeType {
                 node_type: StandardType::NodeEnd,
             })?;
+        progress.node(|| buffers_len(node_buf, data_buf));
 
         Ok(())
     }
 }
 
 impl Writeable for Node {
+    #[inline]
     fn write_node(
         &self,
         options: &Options,
         node_buf: &mut ByteBufferWrite,
         data_buf: &mut ByteBufferWrite,
     ) -> Result<(), WriterError> {
-        let (node_type, is_array) = match self.value() {
-            Some(Value::Array(ref values)) => (values.standard_type(), true),
-            Some(ref value) => (value.standard_type(), false),
-            None => (StandardType::NodeStart, false),
-        };
-        let array_mask = if is_array { ARRAY_MASK } else { 0 };
+        self.write_node_with_progress(options, node_buf, data_buf, &mut Progress::none())
+    }
+
+    #[inline]
+    fn write_node_with_progress(
+        &self,
+        options: &Options,
+        node_buf: &mut ByteBufferWrite,
+        data_buf: &mut ByteBufferWrite,
+        progress: &mut Progress<'_>,
+    ) -> Result<(), WriterError> {
+        self.write_node_at(options, &mut String::new(), node_buf, data_buf, progress)
+    }
+}
+
+impl Node {
+    /// Write this node as a child of the node at `path`, like
+    /// `NodeCollection::write_node_at`.
+    fn write_node_at(
+        &self,
+        options: &Options,
+        path: &mut String,
+        node_buf: &mut ByteBufferWrite,
+        data_buf: &mut ByteBufferWrite,
+        progress: &mut Progress<'_>,
+    ) -> Result<(), WriterError> {
+        let (node_type, is_array, type_id) = value_type(self.value());
+
+        #[cfg(feature = "tracing")]
+        let _span = debug_span!(
+            "node",
+            key = self.key(),
+            ?node_type,
+            is_array,
+            node_offset = node_buf.position(),
+            data_offset = data_buf.position()
+        )
+        .entered();
 
         debug!(
             "Node::write_node => name: {}, type: {:?}, type_size: {}, type_count: {}, is_array: {}",
@@ -323,35 +548,21 @@ impl Writeable for Node {
         );
 
         node_buf
-            .write_u8(node_type as u8 | array_mask)
-            .context(DataWrite {
-                node_type: node_type,
-            })?;
-        match options.compression {
-            CompressionType::Compressed => {
-                Sixbit::pack(&mut **node_buf, &self.key()).context(NodeSixbitName)?
-            },
-            CompressionType::Uncompressed => {
-                let data = options.encoding.encode_bytes(&self.key()).context(
-                    NodeUncompressedNameEncode {
-                        encoding: options.encoding,
-                    },
-                )?;
-                let len = (data.len() - 1) as u8;
-                node_buf
-                    .write_u8(len | ARRAY_MASK)
-                    .context(NodeUncompressedNameLength)?;
-                node_buf
-                    .write_all(&data)
-                    .context(NodeUncompressedNameData)?;
-            },
-        };
+            .write_u8(type_id)
+            .context(DataWrite { node_type })?;
+        let parent_len = path.len();
+        path.push('/');
+        path.push_str(self.key());
+        write_key(options, node_buf, path, self.key())?;
 
         if let Some(value) = self.value() {
             write_value(options, data_buf, node_type, is_array, value)?;
         }
 
         if let Some(attributes) = self.attributes() {
+            let mut attributes: Vec<_> = attributes.iter().collect();
+            options.attribute_order().sort(&mut attributes);
+
             for (key, value) in attributes {
                 trace!("Node write_node => attr: {}, value: {}", key, value);
 
@@ -365,33 +576,20 @@ impl Writeable for Node {
                         node_type: StandardType::Attribute,
                     })?;
 
-                match options.compression {
-                    CompressionType::Compressed => {
-                        Sixbit::pack(&mut **node_buf, &key).context(NodeSixbitName)?
-                    },
-                    CompressionType::Uncompressed => {
-                        let data = options.encoding.encode_bytes(&key).context(
-                            NodeUncompressedNameEncode {
-                                encoding: options.encoding,
-                            },
-                        )?;
-                        let len = (data.len() - 1) as u8;
-                        node_buf
-                            .write_u8(len | ARRAY_MASK)
-                            .context(NodeUncompressedNameLength)?;
-                        node_buf
-                            .write_all(&data)
-                            .context(NodeUncompressedNameData)?;
-                    },
-                };
+                let node_len = path.len();
+                path.push_str("/@");
+                path.push_str(key);
+                write_key(options, node_buf, path, key)?;
+                path.truncate(node_len);
             }
         }
 
         if let Some(children) = self.children() {
             for child in children {
-                child.write_node(options, node_buf, data_buf)?;
+                child.write_node_at(options, path, node_buf, data_buf, progress)?;
             }
         }
+        path.truncate(parent_len);
 
         // node end always has the array bit set
         node_buf
@@ -399,44 +597,193 @@ impl Writeable for Node {
             .context(NodeType {
                 node_type: StandardType::NodeEnd,
             })?;
+        progress.node(|| buffers_len(node_buf, data_buf));
 
         Ok(())
     }
 }
 
+/// Node and data buffers that are kept between calls to
+/// `Writer::to_binary_with_context`, so writing many documents does not
+/// allocate and grow new buffers each time.
+#[derive(Clone, Debug, Default)]
+pub struct WriteContext {
+    node_buf: Vec<u8>,
+    data_buf: Vec<u8>,
+}
+
+impl WriteContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a context with `capacity` bytes reserved for each of the node
+    /// and data buffers.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            node_buf: Vec::with_capacity(capacity),
+            data_buf: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Combined capacity of the node and data buffers.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.node_buf.capacity() + self.data_buf.capacity()
+    }
+}
+
 pub struct Writer {
     options: Options,
+    capacity_hint: usize,
+}
+
+impl Default for Writer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Writer {
     pub fn new() -> Self {
+        Self::with_options(Options::default())
+    }
+
+    pub fn with_options(options: Options) -> Self {
         Self {
-            options: Options::default(),
+            options,
+            capacity_hint: 0,
         }
     }
 
-    pub fn with_options(options: Options) -> Self {
-        Self { options }
+    /// Reserve `capacity` bytes for each of the node and data buffers when
+    /// writing with `to_binary`.
+    pub fn with_capacity_hint(mut self, capacity: usize) -> Self {
+        self.capacity_hint = capacity;
+        self
     }
 
     pub fn to_binary<T>(&mut self, input: &T) -> Result<Vec<u8>, WriterError>
     where
         T: Writeable,
     {
-        let mut header = Cursor::new(Vec::with_capacity(8));
-        header.write_u8(SIGNATURE).context(Signature)?;
+        let mut context = WriteContext::with_capacity(self.capacity_hint);
+        self.to_binary_with_context(&mut context, input)
+    }
+
+    /// Write `input` using the buffers in `context`. The buffers are cleared
+    /// first and returned to the context afterwards, even if writing fails.
+    pub fn to_binary_with_context<T>(
+        &mut self,
+        context: &mut WriteContext,
+        input: &T,
+    ) -> Result<Vec<u8>, WriterError>
+    where
+        T: Writeable,
+    {
+        self.write_with_context(context, input, &mut Progress::none())
+    }
+
+    /// Like `to_binary`, also reporting the nodes written and the size of the
+    /// buffers to `progress`. The last report includes the header.
+    pub fn to_binary_with_progress<T>(
+        &mut self,
+        input: &T,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<Vec<u8>, WriterError>
+    where
+        T: Writeable,
+    {
+        let mut context = WriteContext::with_capacity(self.capacity_hint);
+        let mut progress = Progress::new(progress, ProgressPhase::Write);
+        let output = self.write_with_context(&mut context, input, &mut progress)?;
+        progress.finish(output.len());
+
+        Ok(output)
+    }
+
+    /// Write a document, starting over with uncompressed names if a name
+    /// selected by `OptionsBuilder::uncompressed_names` is found.
+    pub(crate) fn write_with_context<T>(
+        &mut self,
+        context: &mut WriteContext,
+        input: &T,
+        progress: &mut Progress<'_>,
+    ) -> Result<Vec<u8>, WriterError>
+    where
+        T: Writeable,
+    {
+        match self.write_document(context, input, progress) {
+            Err(WriterError::UncompressedNameRequired { path }) => {
+                info!("`{}` is kept uncompressed, writing every name uncompressed", path);
+
+                // The nodes of the first pass are counted again
+                progress.restart();
+                let compression =
+                    mem::replace(&mut self.options.compression, CompressionType::Uncompressed);
+                let result = self.write_document(context, input, progress);
+                self.options.compression = compression;
+
+                result
+            },
+            result => result,
+        }
+    }
 
-        let compression = self.options.compression.to_byte();
-        header.write_u8(compression).context(Compression)?;
+    fn write_document<T>(
+        &mut self,
+        context: &mut WriteContext,
+        input: &T,
+        progress: &mut Progress<'_>,
+    ) -> Result<Vec<u8>, WriterError>
+    where
+        T: Writeable,
+    {
+        context.node_buf.clear();
+        context.data_buf.clear();
 
-        let encoding = self.options.encoding.to_byte();
-        header.write_u8(encoding).context(Encoding)?;
-        header.write_u8(0xFF ^ encoding).context(EncodingNegate)?;
+        let mut node_buf = ByteBufferWrite::new(mem::take(&mut context.node_buf));
+        let mut data_buf = ByteBufferWrite::with_alignment(
+            mem::take(&mut context.data_buf),
+            self.options.alignment.size(),
+        );
+        data_buf.set_endianness(self.options.endianness);
+        let result = self.write_buffers(input, &mut node_buf, &mut data_buf, progress);
 
-        let mut node_buf = ByteBufferWrite::new(Vec::new());
-        let mut data_buf = ByteBufferWrite::new(Vec::new());
+        context.node_buf = node_buf.into_inner();
+        context.data_buf = data_buf.into_inner();
+        result?;
 
-        input.write_node(&self.options, &mut node_buf, &mut data_buf)?;
+        let (node_buf, data_buf) = (&context.node_buf, &context.data_buf);
+        debug!(
+            "to_binary_internal => node_buf len: {0} (0x{0:x})",
+            node_buf.len()
+        );
+        debug!(
+            "to_binary_internal => data_buf len: {0} (0x{0:x})",
+            data_buf.len()
+        );
+
+        assemble(
+            self.options.compression,
+            self.options.encoding,
+            self.options.endianness,
+            node_buf,
+            data_buf,
+        )
+    }
+
+    fn write_buffers<T>(
+        &self,
+        input: &T,
+        node_buf: &mut ByteBufferWrite,
+        data_buf: &mut ByteBufferWrite,
+        progress: &mut Progress<'_>,
+    ) -> Result<(), WriterError>
+    where
+        T: Writeable,
+    {
+        input.write_node_with_progress(&self.options, node_buf, data_buf, progress)?;
 
         node_buf
             .write_u8(StandardType::FileEnd as u8 | ARRAY_MASK)
@@ -447,28 +794,493 @@ impl Writer {
             node_type: StandardType::FileEnd,
         })?;
 
-        let mut output = header.into_inner();
+        Ok(())
+    }
+}
 
-        let node_buf = node_buf.into_inner();
-        debug!(
-            "to_binary_internal => node_buf len: {0} (0x{0:x})",
-            node_buf.len()
+/// Writes a document node by node, straight into the node and data buffers,
+/// without building a `Node` or `NodeCollection` first.
+///
+/// Attributes are written in the order they are given, so the attribute order
+/// of the options does not apply. Writing a node's value, then its
+/// attributes, then its children produces the same bytes as `Writer` does for
+/// the equivalent `Node`.
+pub struct StreamWriter {
+    options: Options,
+    node_buf: ByteBufferWrite,
+    data_buf: ByteBufferWrite,
+
+    /// The number of open nodes and whether the root node has been started
+    depth: usize,
+    has_root: bool,
+
+    /// The path of the most recently opened node that is still open
+    path: String,
+}
+
+impl Default for StreamWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StreamWriter {
+    pub fn new() -> Self {
+        Self::with_options(Options::default())
+    }
+
+    pub fn with_options(options: Options) -> Self {
+        let mut data_buf = ByteBufferWrite::with_alignment(Vec::new(), options.alignment.size());
+        data_buf.set_endianness(options.endianness);
+
+        Self {
+            options,
+            node_buf: ByteBufferWrite::new(Vec::new()),
+            data_buf,
+            depth: 0,
+            has_root: false,
+            path: String::new(),
+        }
+    }
+
+    /// Open a node without a value.
+    #[inline]
+    pub fn start_node(&mut self, key: &str) -> Result<(), WriterError> {
+        self.start(key, None)
+    }
+
+    /// Open a node holding `value`.
+    #[inline]
+    pub fn start_value_node(&mut self, key: &str, value: &Value) -> Result<(), WriterError> {
+        self.start(key, Some(value))
+    }
+
+    fn start(&mut self, key: &str, value: Option<&Value>) -> Result<(), WriterError> {
+        if self.depth == 0 && self.has_root {
+            return Err(WriterError::MultipleRoots);
+        }
+
+        let (node_type, is_array, type_id) = value_type(value);
+        self.node_buf
+            .write_u8(type_id)
+            .context(DataWrite { node_type })?;
+        self.path.push('/');
+        self.path.push_str(key);
+        write_key(&self.options, &mut self.node_buf, &self.path, key)?;
+
+        if let Some(value) = value {
+            write_value(
+                &self.options,
+                &mut self.data_buf,
+                node_type,
+                is_array,
+                value,
+            )?;
+        }
+
+        self.depth += 1;
+        self.has_root = true;
+
+        Ok(())
+    }
+
+    /// Add an attribute to the most recently opened node that is still open.
+    pub fn attribute(&mut self, key: &str, value: &str) -> Result<(), WriterError> {
+        let node_type = StandardType::Attribute;
+        if self.depth == 0 {
+            return Err(WriterError::NoOpenNode { node_type });
+        }
+
+        self.data_buf
+            .write_str(self.options.encoding, value)
+            .context(DataBuffer { node_type })?;
+        self.node_buf
+            .write_u8(node_type as u8)
+            .context(DataWrite { node_type })?;
+        let node_len = self.path.len();
+        self.path.push_str("/@");
+        self.path.push_str(key);
+        let result = write_key(&self.options, &mut self.node_buf, &self.path, key);
+        self.path.truncate(node_len);
+
+        result
+    }
+
+    /// Close the most recently opened node that is still open.
+    pub fn end_node(&mut self) -> Result<(), WriterError> {
+        let node_type = StandardType::NodeEnd;
+        if self.depth == 0 {
+            return Err(WriterError::NoOpenNode { node_type });
+        }
+
+        // node end always has the array bit set
+        self.node_buf
+            .write_u8(node_type as u8 | ARRAY_MASK)
+            .context(NodeType { node_type })?;
+        self.depth -= 1;
+        let parent_len = self.path.rfind('/').unwrap_or(0);
+        self.path.truncate(parent_len);
+
+        Ok(())
+    }
+
+    /// Write a node holding `value` without attributes or children.
+    pub fn value_node(&mut self, key: &str, value: &Value) -> Result<(), WriterError> {
+        self.start_value_node(key, value)?;
+        self.end_node()
+    }
+
+    /// Write a sequence of events, such as the events of a `Node` changed
+    /// with the adapters in `events`.
+    pub fn write_events<'a, I>(&mut self, events: I) -> Result<(), WriterError>
+    where
+        I: IntoIterator<Item = Event<'a>>,
+    {
+        for event in events {
+            match event {
+                Event::Start { key, value } => self.start(&key, value)?,
+                Event::Attribute { key, value } => self.attribute(&key, &value)?,
+                Event::End => self.end_node()?,
+            };
+        }
+
+        Ok(())
+    }
+
+    /// Finish the document. Every node must have been closed.
+    pub fn finish(mut self) -> Result<Vec<u8>, WriterError> {
+        if self.depth > 0 {
+            return Err(WriterError::UnclosedNodes { count: self.depth });
+        }
+
+        let node_type = StandardType::FileEnd;
+        self.node_buf
+            .write_u8(node_type as u8 | ARRAY_MASK)
+            .context(NodeType { node_type })?;
+        self.node_buf
+            .realign_writes(None)
+            .context(NodeBuffer { node_type })?;
+
+        let node_buf = self.node_buf.into_inner();
+        let data_buf = self.data_buf.into_inner();
+        assemble(
+            self.options.compression,
+            self.options.encoding,
+            self.options.endianness,
+            &node_buf,
+            &data_buf,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use bytes::Bytes;
+
+    use crate::options::{Alignment, ReaderOptions};
+    use crate::reader::Reader;
+    use crate::value::ValueArray;
+
+    use super::*;
+
+    /// Asserts that writing `input`, a document written by this crate, again
+    /// with the same header settings gives identical bytes, from both the
+    /// `NodeCollection` and the `Node` forms.
+    fn assert_stable_rewrite(input: &[u8]) {
+        let mut reader = Reader::new(Bytes::from(input.to_vec())).unwrap();
+        let collection = NodeCollection::from_iter(&mut reader).unwrap();
+
+        let mut options = Options::builder();
+        options
+            .compression(reader.compression())
+            .encoding(reader.encoding());
+        let options = options.build();
+
+        let output = Writer::with_options(options.clone())
+            .to_binary(&collection)
+            .unwrap();
+        assert_eq!(output, input);
+
+        let node = collection.as_node().unwrap();
+        let output = Writer::with_options(options).to_binary(&node).unwrap();
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn test_stable_rewrite() {
+        assert_stable_rewrite(include_bytes!("../../testcases_out.kbin"));
+
+        // Interleave 1, 2, and 4 byte values so the shared alignment slots
+        // are exercised
+        let node = Node::with(
+            "root",
+            &[("b", "2"), ("a", "1")],
+            vec![
+                Node::with_value("u8", Value::U8(1)),
+                Node::with_value("s16", Value::S16(-2)),
+                Node::with_value("str", Value::String("テスト".into())),
+                Node::with_value("u8", Value::U8(3)),
+                Node::with_value("bin", Value::Binary(vec![1, 2, 3])),
+                Node::with_value("s16", Value::S16(4)),
+                Node::with_value("arr", Value::Array(ValueArray::U8(vec![5, 6, 7]))),
+                Node::with_value("bool", Value::Boolean(true)),
+            ],
         );
-        output
-            .write_u32::<BigEndian>(node_buf.len() as u32)
-            .context(NodeBufferLength)?;
-        output.extend_from_slice(&node_buf);
+        let mut options = Options::builder();
+        options
+            .compression(CompressionType::Compressed)
+            .encoding(EncodingType::SHIFT_JIS);
+        let input = Writer::with_options(options.build())
+            .to_binary(&node)
+            .unwrap();
+        assert_stable_rewrite(&input);
+    }
 
-        let data_buf = data_buf.into_inner();
-        debug!(
-            "to_binary_internal => data_buf len: {0} (0x{0:x})",
-            data_buf.len()
+    #[test]
+    fn test_uncompressed_names() {
+        // Names that fit in sixbit are still written in the document encoding
+        let node = Node::with_nodes("a", vec![Node::with_value("b", Value::U8(1))]);
+        let mut options = Options::builder();
+        options.compression(CompressionType::Uncompressed);
+        let binary = crate::to_binary_with_options(options.build(), &node).unwrap();
+        assert_eq!(binary[1], CompressionType::Uncompressed.to_byte());
+        assert_eq!(&binary[8..14], &[0x01, 0x40, b'a', 0x03, 0x40, b'b']);
+
+        assert_stable_rewrite(&binary);
+    }
+
+    #[test]
+    fn test_uncompressed_name_policy() {
+        let children = (0..2000).map(|i| Node::with_value("b", Value::U16(i)));
+        let mut children: Vec<_> = children.collect();
+        children.push(Node::with_attrs("c", &[("raw_id", "1")]));
+        let node = Node::with_nodes("a", children);
+        let mut options = Options::builder();
+        options.uncompressed_names(|path| path == "/a/c/@raw_id");
+        let options = options.build();
+
+        // The nodes written before the match are not counted twice
+        let mut reports = Vec::new();
+        let mut sink = |_, items, _| reports.push(items);
+        let mut writer = Writer::with_options(options.clone());
+        let binary = writer.to_binary_with_progress(&node, &mut sink).unwrap();
+        assert_eq!(binary[1], CompressionType::Uncompressed.to_byte());
+        assert_eq!(reports, [1024, 1024, 2002]);
+        let (collection, _) = crate::from_slice(&binary).unwrap();
+        assert_eq!(collection.as_node().unwrap(), node);
+
+        // Only the name at the selected path counts
+        let node = Node::with_nodes("a", vec![Node::with_attrs("b", &[("raw_id", "1")])]);
+        let binary = crate::to_binary_with_options(options.clone(), &node).unwrap();
+        assert_eq!(binary[1], CompressionType::Compressed.to_byte());
+
+        let mut writer = StreamWriter::with_options(options);
+        writer.start_node("a").unwrap();
+        writer.attribute("raw_id", "1").unwrap();
+        writer.start_node("c").unwrap();
+        assert!(matches!(
+            writer.attribute("raw_id", "1"),
+            Err(WriterError::UncompressedNameRequired { ref path }) if path == "/a/c/@raw_id"
+        ));
+    }
+
+    #[test]
+    fn test_uncompressed_name_set() {
+        // The policy can be built from data, such as paths from a config file
+        let paths: HashSet<String> = ["/a/b/@raw_id", "/a/d"]
+            .iter()
+            .map(|path| path.to_string())
+            .collect();
+        let mut options = Options::builder();
+        options.uncompressed_names(move |path| paths.contains(path));
+        let options = options.build();
+
+        let node = Node::with_nodes("a", vec![Node::with_attrs("c", &[("raw_id", "1")])]);
+        let binary = crate::to_binary_with_options(options.clone(), &node).unwrap();
+        assert_eq!(binary[1], CompressionType::Compressed.to_byte());
+
+        let node = Node::with_nodes("a", vec![Node::with_value("d", Value::U8(1))]);
+        let binary = crate::to_binary_with_options(options, &node).unwrap();
+        assert_eq!(binary[1], CompressionType::Uncompressed.to_byte());
+        let (collection, _) = crate::from_slice(&binary).unwrap();
+        assert_eq!(collection.as_node().unwrap(), node);
+    }
+
+    #[test]
+    fn test_encoded_attributes() {
+        let encoding = EncodingType::SHIFT_JIS;
+        let text = "<?xml version=\"1.0\" encoding=\"Shift_JIS\"?><root name=\"日本\"/>";
+        let input = encoding.encode(text).unwrap();
+
+        let (collection, _) = crate::from_text_xml(&input).unwrap();
+        let options = Options::with_encoding(encoding);
+        let output = Writer::with_options(options)
+            .to_binary(&collection)
+            .unwrap();
+
+        let (collection, read_encoding) = crate::from_binary(Bytes::from(output)).unwrap();
+        assert_eq!(read_encoding, encoding);
+        let node = collection.as_node().unwrap();
+        assert_eq!(node.attr("name"), Some("日本"));
+    }
+
+    #[test]
+    fn test_attribute_order() {
+        use crate::options::AttributeOrder;
+
+        let node = Node::with_attrs("root", &[("b", "2"), ("a", "1"), ("C", "3")]);
+        let keys = |order: Option<AttributeOrder>| {
+            let mut options = Options::builder();
+            if let Some(order) = order {
+                options.attribute_order(order);
+            }
+            let binary = crate::to_binary_with_options(options.build(), &node).unwrap();
+            let (collection, _) = crate::from_slice(&binary).unwrap();
+            let node = collection.as_node().unwrap();
+            node.attributes().unwrap().keys().cloned().collect::<Vec<_>>()
+        };
+
+        assert_eq!(keys(None), ["b", "a", "C"]);
+        assert_eq!(keys(Some(AttributeOrder::Preserve)), ["b", "a", "C"]);
+        assert_eq!(keys(Some(AttributeOrder::Alphabetic)), ["C", "a", "b"]);
+        let case_insensitive = AttributeOrder::Custom(|a, b| {
+            a.to_ascii_lowercase().cmp(&b.to_ascii_lowercase())
+        });
+        assert_eq!(keys(Some(case_insensitive)), ["a", "b", "C"]);
+    }
+
+    #[test]
+    fn test_alignment() {
+        let node = Node::with(
+            "root",
+            &[("a", "1")],
+            vec![
+                Node::with_value("u8", Value::U8(1)),
+                Node::with_value("str", Value::String("test".into())),
+                Node::with_value("s16", Value::S16(-2)),
+                Node::with_value("s32", Value::S32(3)),
+                Node::with_value("arr", Value::Array(ValueArray::U8(vec![5, 6, 7]))),
+            ],
         );
-        output
-            .write_u32::<BigEndian>(data_buf.len() as u32)
-            .context(DataBufferLength)?;
-        output.extend_from_slice(&data_buf);
 
-        Ok(output)
+        for &alignment in &[Alignment::Four, Alignment::Eight] {
+            let mut options = Options::builder();
+            options.alignment(alignment);
+            let output = Writer::with_options(options.build())
+                .to_binary(&node)
+                .unwrap();
+
+            let reader = Reader::new(Bytes::from(output.clone())).unwrap();
+            assert_eq!(reader.alignment(), Alignment::Four);
+
+            let mut reader_options = ReaderOptions::builder();
+            reader_options.detect_alignment(true);
+            let reader_options = reader_options.build();
+            let reader =
+                Reader::with_options(Bytes::from(output.clone()), reader_options.clone()).unwrap();
+            assert_eq!(reader.alignment(), alignment);
+
+            let (collection, _) =
+                crate::from_binary_with_options(reader_options, Bytes::from(output)).unwrap();
+            assert_eq!(collection.as_node().unwrap(), node);
+        }
+    }
+
+    #[test]
+    fn test_endianness() {
+        let node = Node::with(
+            "root",
+            &[("a", "1")],
+            vec![
+                Node::with_value("s16", Value::S16(-2)),
+                Node::with_value("str", Value::String("test".into())),
+                Node::with_value("u32_2", Value::U32_2([3, 4])),
+                Node::with_value("ip4", Value::Ip4([127, 0, 0, 1].into())),
+                Node::with_value("bin", Value::Binary(vec![1, 2, 3])),
+                Node::with_value("arr", Value::Array(ValueArray::Double(vec![0.5, 1.5]))),
+            ],
+        );
+        let big = crate::to_binary(&node).unwrap();
+
+        let mut options = Options::builder();
+        options.endianness(Endianness::Little);
+        let little = Writer::with_options(options.build())
+            .to_binary(&node)
+            .unwrap();
+        assert_ne!(little, big);
+        assert_eq!(little.len(), big.len());
+        assert_eq!(
+            u32::from_le_bytes([little[4], little[5], little[6], little[7]]),
+            u32::from_be_bytes([big[4], big[5], big[6], big[7]])
+        );
+
+        let reader = Reader::new(Bytes::from(little.clone())).unwrap();
+        assert_eq!(reader.endianness(), Endianness::Little);
+        let (collection, _) = crate::from_slice(&little).unwrap();
+        assert_eq!(collection.as_node().unwrap(), node);
+
+        // Writing the collection read from a little endian document as big
+        // endian gives the same output as writing the node
+        assert_eq!(crate::to_binary(&collection).unwrap(), big);
+
+        let reader = Reader::new(Bytes::from(big)).unwrap();
+        assert_eq!(reader.endianness(), Endianness::Big);
+    }
+
+    #[test]
+    fn test_write_context() {
+        let node = Node::with(
+            "root",
+            &[("a", "1")],
+            vec![Node::with_value("str", Value::String("test".into()))],
+        );
+        let expected = Writer::new().to_binary(&node).unwrap();
+
+        let mut writer = Writer::new().with_capacity_hint(64);
+        assert_eq!(writer.to_binary(&node).unwrap(), expected);
+
+        let mut context = WriteContext::new();
+        for _ in 0..2 {
+            let output = writer.to_binary_with_context(&mut context, &node).unwrap();
+            assert_eq!(output, expected);
+        }
+        assert!(!context.data_buf.is_empty());
+    }
+
+    #[test]
+    fn test_stream_writer() {
+        let node = Node::with(
+            "root",
+            &[("a", "1")],
+            vec![
+                Node::with_attrs_value("item", &[("id", "2")], Value::U8(3)),
+                Node::with_nodes(
+                    "list",
+                    vec![Node::with_value("s", Value::String("x".into()))],
+                ),
+            ],
+        );
+        let expected = Writer::new().to_binary(&node).unwrap();
+
+        let mut writer = StreamWriter::new();
+        writer.start_node("root").unwrap();
+        writer.attribute("a", "1").unwrap();
+        writer.start_value_node("item", &Value::U8(3)).unwrap();
+        writer.attribute("id", "2").unwrap();
+        writer.end_node().unwrap();
+        writer.start_node("list").unwrap();
+        writer.value_node("s", &Value::String("x".into())).unwrap();
+        writer.end_node().unwrap();
+        writer.end_node().unwrap();
+
+        assert!(writer.start_node("other").is_err());
+        assert!(writer.end_node().is_err());
+        assert_eq!(writer.finish().unwrap(), expected);
+
+        let mut writer = StreamWriter::new();
+        writer.start_node("root").unwrap();
+        assert!(writer.finish().is_err());
     }
 }
@@ -0,0 +1,83 @@
+//! Round-trips every file in a directory and reports the result per file.
+//!
+//! ```text
+//! cargo test -p kbinxml --features corpus --test corpus -- --corpus-dir <dir>
+//! ```
+//!
+//! The directory is searched recursively. Without `--corpus-dir`, or with the
+//! `KBINXML_CORPUS_DIR` environment variable unset, nothing is tested.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use kbinxml::compare::round_trip;
+
+fn corpus_dir() -> Option<PathBuf> {
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--corpus-dir" {
+            return args.next().map(PathBuf::from);
+        } else if let Some(dir) = arg.strip_prefix("--corpus-dir=") {
+            return Some(PathBuf::from(dir));
+        }
+    }
+
+    env::var_os("KBINXML_CORPUS_DIR").map(PathBuf::from)
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let dir = match corpus_dir() {
+        Some(dir) => dir,
+        None => {
+            println!("corpus: no --corpus-dir given, skipping");
+            return;
+        },
+    };
+
+    let mut files = Vec::new();
+    if let Err(e) = collect_files(&dir, &mut files) {
+        eprintln!("corpus: failed to read {}: {}", dir.display(), e);
+        process::exit(2);
+    }
+    files.sort();
+
+    let mut failed = 0;
+    for path in &files {
+        let result = fs::read(path)
+            .map_err(|e| e.to_string())
+            .and_then(|data| round_trip(&data).map_err(|e| e.to_string()));
+
+        match result {
+            Ok(report) if report.is_ok() => println!("ok   {}: {}", path.display(), report),
+            Ok(report) => {
+                failed += 1;
+                println!("FAIL {}: {}", path.display(), report);
+            },
+            Err(e) => {
+                failed += 1;
+                println!("FAIL {}: {}", path.display(), e);
+            },
+        };
+    }
+
+    println!("corpus: {} passed, {} failed", files.len() - failed, failed);
+    if failed > 0 {
+        process::exit(1);
+    }
+}